@@ -9,7 +9,11 @@ shopt -s nullglob
 # Constants
 TEMP_PATH="/tmp/segmented_archive" # Temporary path to extract tar files
 EXT=".tar.gz"                   # Extension of the tar files
-PATH_FILE=".seg_arc.path"       # Path file used to place extracted files
+LIST_EXT=".parts"               # Extension of part listing files
+PARTIAL_EXT=".partial"          # Extension of files left by a failed or interrupted run
+INFO_FILE=".seg_arc.json"       # Info file used to place extracted files
+PATH_FILE=".seg_arc.path"       # Legacy path file (Older archives)
+MANIFEST_FILE=".seg_arc.manifest.json" # File hashes (Only checked by the restore command)
 REMOVE_TAR_FILES=true           # Whether to remove tar files after extraction
 
 # Arguments
@@ -31,8 +35,12 @@ combine_parts() {
 
         # Collect all part files for the base file
         base_file="${first_part%.part001}"
-        parts=("$base_file".part*)
+        parts=()
+        for part in "$base_file".part[0-9]*; do
+            if [[ "$part" != *"$PARTIAL_EXT" ]]; then parts+=("$part"); fi
+        done
         IFS=$'\n' sorted_parts=($(printf '%s\n' "${parts[@]}" | sort -V))
+        check_part_list "$base_file"
 
         echo "> Combining ${#sorted_parts[@]} files into $base_file"
         # Append parts one-by-one to avoid excess disk usage
@@ -42,9 +50,52 @@ combine_parts() {
             echo "  Moved $part >> $base_file"
             if [ "$REMOVE_TAR_FILES" = true ]; then rm -f "$part"; fi
         done
+        if [ "$REMOVE_TAR_FILES" = true ]; then rm -f "$base_file$LIST_EXT"; fi
     done
 }
 
+# Panic if any part in the listing (if present) is missing or the wrong size
+check_part_list() {
+    local base_file="$1"
+    local list_file="$base_file$LIST_EXT"
+    if [ ! -f "$list_file" ]; then return; fi
+
+    local dir=$(dirname "$base_file")
+    while read -r size name; do
+        if [ ! -f "$dir/$name" ]; then
+            echo "  ERROR: Missing part listed in $list_file: $name" > /dev/stderr
+            exit -1
+        fi
+        local actual=$(wc -c < "$dir/$name")
+        if [ "$actual" -ne "$size" ]; then
+            echo "  ERROR: Part $name is $actual bytes, expected $size" > /dev/stderr
+            exit -1
+        fi
+    done < "$list_file"
+    echo "  Found all $(wc -l < "$list_file") parts listed in $list_file"
+}
+
+# Print the name of the info/path file found in an extracted archive
+find_info_file() {
+    local folder="$1"
+    if [ -f "$folder/$INFO_FILE" ]; then
+        echo "$INFO_FILE"
+    elif [ -f "$folder/$PATH_FILE" ]; then
+        echo "$PATH_FILE"
+    fi
+}
+
+# Print the restore path stored in an info/path file
+read_restore_path() {
+    local file="$1"
+    if [ "$(basename "$file")" = "$INFO_FILE" ]; then
+        # Info file is pretty-printed JSON (One key per line)
+        sed -n 's/^  "path": "\(.*\)",\{0,1\}$/\1/p' "$file" | sed 's/\\"/"/g; s/\\\\/\\/g'
+    else
+        cat "$file"
+    fi
+}
+
 # Extract the tar files to the RESTORE_PATH
 extract_tars() {
     local src_path="$1"
@@ -58,21 +109,29 @@ extract_tars() {
         rm -Rf "$temp_folder"
         mkdir -p "$temp_folder"
         echo "  Created temp folder: $temp_folder"
-        tar -xvf "$tar_file" -C "$temp_folder"
+        # (-i: Ignore end-of-archive blocks, so combined independent parts extract fully)
+        tar -xivf "$tar_file" -C "$temp_folder"
 
-        # Panic if the path file does not exist
-        if [ ! -f "$temp_folder/$PATH_FILE" ]; then
-            echo "  ERROR: Path file ($PATH_FILE) not found in archive: $tar_file" > /dev/stderr
+        # Panic if the info file does not exist
+        local info_file=$(find_info_file "$temp_folder")
+        if [ -z "$info_file" ]; then
+            echo "  ERROR: Info file ($INFO_FILE or $PATH_FILE) not found in archive: $tar_file" > /dev/stderr
             rm -Rf "$temp_folder"
             echo "  Removed temp folder: $temp_folder"
             exit -1
         fi
         
         # Determine if this is a "file" or "directory" segment
-        # File segments contain 1 file (besides the path file) and its name matches the path file's content
-        local dest_path="$dest_root/$(cat "$temp_folder/$PATH_FILE")"
-        local files_in_archive=$(find "$temp_folder" -type f ! -name "$PATH_FILE" | wc -l)
+        # File segments contain 1 file (besides the info file and manifest) and its name matches the stored restore path
+        local dest_path="$dest_root/$(read_restore_path "$temp_folder/$info_file")"
+        local files_in_archive=$(find "$temp_folder" -type f ! -name "$info_file" ! -name "$MANIFEST_FILE" | wc -l)
         local path_filename=$(basename "$dest_path")
+
+        # Remove the manifest before restoring (It is only read by the restore command)
+        if [ -f "$temp_folder/$MANIFEST_FILE" ]; then
+            rm "$temp_folder/$MANIFEST_FILE"
+            echo "  Removed manifest: $temp_folder/$MANIFEST_FILE"
+        fi
         
         if [ "$files_in_archive" -eq 1 ] && [ -f "$temp_folder/$path_filename" ]; then
             # File segment: restore the single file
@@ -80,15 +139,15 @@ extract_tars() {
             echo "  Restoring file: $dest_path"
             mkdir -p "$dest_dir"
             rsync -av --remove-source-files "${RSYNC_OPTS[@]}" "$temp_folder/$path_filename" "$dest_path"
-            rm "$temp_folder/$PATH_FILE" # Not in the rsync call, unlike a directory segment
+            rm "$temp_folder/$info_file" # Not in the rsync call, unlike a directory segment
 
         else
             # Directory segment: restore the directory structure
             echo "  Restoring directory: $dest_path"
             mkdir -p "$dest_path"
             rsync -av --remove-source-files "${RSYNC_OPTS[@]}" "$temp_folder/" "$dest_path/"
-            rm "$dest_path/$PATH_FILE"
-            echo "  Removed path file: $dest_path/$PATH_FILE"
+            rm "$dest_path/$info_file"
+            echo "  Removed info file: $dest_path/$info_file"
         fi
 
         rm -Rf "$temp_folder"
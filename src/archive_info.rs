@@ -0,0 +1,166 @@
+use anyhow::{Context, Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::io::{BufRead, BufReader, Read};
+use std::fs;
+use chrono::Local;
+use flate2::read::GzDecoder;
+use crate::helpers::strip_root;
+use crate::manifest::{Manifest, ManifestFiles};
+
+/// Info entry injected at the root of every archive
+pub const INFO_FILE: &str = ".seg_arc.json";
+/// Path entry of archives written by older versions (Holding only the restore path)
+pub const LEGACY_PATH_FILE: &str = ".seg_arc.path";
+/// Manifest entry following the info entry (Only with embed_manifest)
+pub const MANIFEST_ENTRY: &str = ".seg_arc.manifest.json";
+/// Increment when the layout of ArchiveInfo changes
+pub const INFO_FORMAT_VERSION: u32 = 1;
+
+/// Describes where an archive came from and how to restore it
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ArchiveInfo {
+    pub format_version: u32,
+    pub segment: String,
+    /// Restore path (source_path relative to root_path)
+    pub path: String,
+    pub source_path: PathBuf,
+    pub root_path: Option<PathBuf>,
+    /// Missing if the segment could not be hashed
+    pub hash: Option<String>,
+    /// Time the archive was created (RFC 3339)
+    pub created: String,
+    /// True if the segment is a single file, restored into the parent of its path (Missing in older archives)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_file: Option<bool>,
+    /// File hashes to embed as the manifest entry (Not part of the info entry)
+    #[serde(skip)]
+    pub manifest: Option<ManifestFiles>,
+}
+
+impl ArchiveInfo {
+    /// Build the info for a segment, stamped with the current time
+    pub fn new(segment: &str, source_path: &Path, root_path: &Option<PathBuf>, hash: Option<String>) -> Result<Self> {
+        Ok(Self {
+            format_version: INFO_FORMAT_VERSION,
+            segment: segment.to_string(),
+            path: strip_root(source_path, root_path)?,
+            source_path: source_path.to_path_buf(),
+            root_path: root_path.to_owned(),
+            hash,
+            created: Local::now().to_rfc3339(),
+            is_file: Some(source_path.is_file()),
+            manifest: None,
+        })
+    }
+
+    /// Serialize as pretty-printed JSON (One key per line, so restore.sh can read it without a JSON parser)
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("Failed to serialize archive info")
+    }
+
+    /// Serialize the manifest entry (None unless there are file hashes to embed, along with the segment hash)
+    pub fn manifest_json(&self) -> Result<Option<String>> {
+        let (Some(files), Some(hash)) = (&self.manifest, &self.hash) else {
+            return Ok(None);
+        };
+        let manifest = Manifest { segment: self.segment.clone(), hash: hash.clone(), created: self.created.clone(), files: files.clone() };
+        Ok(Some(serde_json::to_string(&manifest).context("Failed to serialize manifest entry")?))
+    }
+}
+
+/// Read the info entry of an existing archive (Or of its first part)
+pub fn read_archive_info(path: &Path) -> Result<ArchiveInfo> {
+    let file = fs::File::open(path)
+        .context(format!("Failed to open archive: {:?}", path))?;
+    let mut reader = BufReader::new(file);
+    // Multi-volume archives are not compressed
+    let is_gzip = reader.fill_buf()
+        .context(format!("Failed to read archive: {:?}", path))?
+        .starts_with(&[0x1f, 0x8b]);
+    let reader: Box<dyn Read> = if is_gzip { Box::new(GzDecoder::new(reader)) } else { Box::new(reader) };
+
+    let mut archive = tar::Archive::new(reader);
+    let mut entry = archive.entries()
+        .context(format!("Failed to read archive: {:?}", path))?
+        .next()
+        .ok_or_else(|| anyhow!("Archive is empty: {:?}", path))?
+        .context(format!("Failed to read archive: {:?}", path))?;
+    if entry.path().ok().is_none_or(|entry_path| entry_path != Path::new(INFO_FILE)) {
+        return Err(anyhow!("Archive has no info entry: {:?}", path));
+    }
+    let mut contents = String::new();
+    entry.read_to_string(&mut contents)
+        .context(format!("Failed to read info entry: {:?}", path))?;
+    serde_json::from_str(&contents)
+        .context(format!("Failed to parse info entry: {:?}", path))
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_info_new_with_root() {
+        let root_path = Some(PathBuf::from("/tmp/files"));
+        let info = ArchiveInfo::new("docs", Path::new("/tmp/files/docs"), &root_path, Some("abc".to_string())).unwrap();
+
+        assert_eq!(info.format_version, INFO_FORMAT_VERSION);
+        assert_eq!(info.segment, "docs");
+        assert_eq!(info.path, "docs");
+        assert_eq!(info.source_path, PathBuf::from("/tmp/files/docs"));
+        assert_eq!(info.root_path, root_path);
+        assert_eq!(info.hash, Some("abc".to_string()));
+    }
+
+    #[test]
+    fn test_archive_info_new_invalid_root() {
+        let root_path = Some(PathBuf::from("/var/other"));
+        let result = ArchiveInfo::new("docs", Path::new("/tmp/files/docs"), &root_path, None);
+        assert!(result.is_err(), "Source outside of root_path should return error");
+    }
+
+    #[test]
+    fn test_archive_info_json_round_trip() {
+        let info = ArchiveInfo::new("docs", Path::new("/tmp/files/\"quoted\" docs"), &None, None).unwrap();
+        let json = info.to_json().unwrap();
+
+        // Each key should be on its own line
+        assert!(json.lines().any(|line| line.starts_with("  \"path\": ")));
+
+        let read: ArchiveInfo = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.path, "/tmp/files/\"quoted\" docs");
+        assert_eq!(read.segment, "docs");
+        assert!(read.hash.is_none());
+    }
+
+    #[test]
+    fn test_read_archive_info() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+
+        let test_dir = PathBuf::from("/tmp/archive_info_test_read");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+
+        let info = ArchiveInfo::new("docs", Path::new("/tmp/files/docs"), &None, Some("xxh3:0123456789abcdef".to_string())).unwrap();
+        let json = info.to_json().unwrap();
+        let archive_path = test_dir.join("docs.tar.gz");
+        let mut builder = tar::Builder::new(GzEncoder::new(fs::File::create(&archive_path).unwrap(), Compression::default()));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(json.len() as u64);
+        builder.append_data(&mut header, INFO_FILE, json.as_bytes()).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let read = read_archive_info(&archive_path).unwrap();
+        assert_eq!(read.segment, "docs");
+        assert_eq!(read.hash, info.hash);
+
+        // Not an archive
+        fs::write(&archive_path, b"not an archive").unwrap();
+        assert!(read_archive_info(&archive_path).is_err());
+
+        let _ = fs::remove_dir_all(&test_dir);
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::{Context, Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::fs;
+use flate2::read::MultiGzDecoder;
+use crate::rolling_writer::list_outputs;
+use crate::sidecar::{read_sidecar, sidecar_path};
+
+/// Extensions tried when an archive is given without one
+const ARCHIVE_EXTENSIONS: [&str; 2] = ["tar.gz", "tar"];
+
+/// Find the files of an archive, given its path, its first part, or its path without an extension
+/// (i.e. `docs`, `docs.tar.gz` or `docs.tar.gz.part001`).
+/// Returns the archive file, or its parts in order.
+pub fn find_archive_parts(archive: &Path) -> Result<Vec<PathBuf>> {
+    let base = archive_base(archive);
+    let mut candidates = vec![base.clone()];
+    candidates.extend(ARCHIVE_EXTENSIONS.iter().map(|ext| PathBuf::from(format!("{}.{}", base.display(), ext))));
+    for candidate in candidates {
+        if candidate.is_file() {
+            return Ok(vec![candidate]);
+        }
+        let mut parts: Vec<(u32, PathBuf)> = list_outputs(&candidate)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|path| Some((part_number(&path.file_name()?.to_string_lossy())?, path)))
+            .collect();
+        if !parts.is_empty() {
+            // (Sorted by number, since names only sort correctly up to part999)
+            parts.sort();
+            return Ok(parts.into_iter().map(|(_, path)| path).collect());
+        }
+    }
+    Err(anyhow!("No archive or parts found for {:?}", archive))
+}
+
+/// Path of an archive without its part number (i.e. `docs.tar.gz.part001` is `docs.tar.gz`)
+pub fn archive_base(path: &Path) -> PathBuf {
+    match path.file_name().map(|name| name.to_string_lossy()) {
+        Some(name) if part_number(&name).is_some() => path.with_file_name(name.rsplit_once(".part").unwrap_or_default().0),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Parts of a segment's latest archive in `output_dir` (As listed in its metadata file, or found by name)
+///
+/// # Errors
+/// Returns an error naming the first listed part that is missing
+pub fn latest_archive_parts(output_dir: &Path, segment: &str) -> Result<Vec<PathBuf>> {
+    let parts = match read_sidecar(&sidecar_path(output_dir, segment)) {
+        Ok(sidecar) if !sidecar.parts.is_empty() => sidecar.parts.iter().map(|part| output_dir.join(&part.name)).collect(),
+        _ => find_archive_parts(&output_dir.join(segment))?,
+    };
+    if let Some(missing) = parts.iter().find(|part| !part.is_file()) {
+        return Err(anyhow!("Part is missing: {:?}", missing));
+    }
+    Ok(parts)
+}
+
+/// Open the parts of an archive as one tar stream (Decompressing it, if compressed)
+///
+/// # Errors
+/// Multi-volume archives can't be read as one stream (Their parts must be given to `tar -xM`)
+pub fn open_archive(parts: &[PathBuf]) -> Result<tar::Archive<Box<dyn Read>>> {
+    let first = parts.first().ok_or_else(|| anyhow!("Archive has no parts"))?;
+    let mut reader: Box<dyn Read> = Box::new(io::empty());
+    for part in parts {
+        let file = fs::File::open(part)
+            .context(format!("Failed to open part: {:?}", part))?;
+        reader = Box::new(reader.chain(file));
+    }
+
+    let mut reader = BufReader::new(reader);
+    let is_gzip = reader.fill_buf()
+        .context(format!("Failed to read archive: {:?}", first))?
+        .starts_with(&[0x1f, 0x8b]);
+    if !is_gzip && parts.len() > 1 {
+        return Err(anyhow!("Multi-volume archives can't be read directly, extract them with `tar -xM`: {:?}", first));
+    }
+    let reader: Box<dyn Read> = if is_gzip { Box::new(MultiGzDecoder::new(reader)) } else { Box::new(reader) };
+
+    let mut archive = tar::Archive::new(reader);
+    // Independent parts each end with an end-of-archive marker, so read past them
+    archive.set_ignore_zeros(true);
+    Ok(archive)
+}
+
+/// Write the parts of an archive to `writer` end to end (Giving the archive as a single stream)
+/// Returns the bytes written
+pub fn copy_parts(parts: &[PathBuf], writer: &mut impl Write) -> Result<u64> {
+    let mut total = 0;
+    for part in parts {
+        let mut file = fs::File::open(part)
+            .context(format!("Failed to open part: {:?}", part))?;
+        total += io::copy(&mut file, writer)
+            .context(format!("Failed to copy part: {:?}", part))?;
+    }
+    writer.flush().context("Failed to flush output")?;
+    Ok(total)
+}
+
+/// Number of a part from its file name (i.e. `docs.tar.gz.part012` is 12)
+pub fn part_number(name: &str) -> Option<u32> {
+    let (_, num) = name.rsplit_once(".part")?;
+    if num.is_empty() || !num.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    num.parse().ok()
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/archive_reader_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[test]
+    fn test_part_number() {
+        assert_eq!(part_number("docs.tar.gz.part001"), Some(1));
+        assert_eq!(part_number("docs.tar.gz.part1000"), Some(1000));
+        assert_eq!(part_number("docs.tar.gz.parts"), None);
+        assert_eq!(part_number("docs.tar.gz"), None);
+    }
+
+    #[test]
+    fn test_find_archive_parts() {
+        let test_name = "find_parts";
+        let test_dir = setup_test_dir(test_name);
+        fs::write(test_dir.join("single.tar.gz"), b"").unwrap();
+        for i in [2, 1, 10] {
+            fs::write(test_dir.join(format!("split.tar.gz.part{:03}", i)), b"").unwrap();
+        }
+        fs::write(test_dir.join("split.tar.gz.parts"), b"").unwrap();
+
+        assert_eq!(find_archive_parts(&test_dir.join("single")).unwrap(), [test_dir.join("single.tar.gz")]);
+        assert_eq!(find_archive_parts(&test_dir.join("single.tar.gz")).unwrap(), [test_dir.join("single.tar.gz")]);
+
+        let expected: Vec<PathBuf> = [1, 2, 10].iter().map(|i| test_dir.join(format!("split.tar.gz.part{:03}", i))).collect();
+        assert_eq!(find_archive_parts(&test_dir.join("split")).unwrap(), expected);
+        assert_eq!(find_archive_parts(&test_dir.join("split.tar.gz.part002")).unwrap(), expected);
+
+        assert!(find_archive_parts(&test_dir.join("missing")).is_err());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_copy_parts() {
+        let test_name = "copy_parts";
+        let test_dir = setup_test_dir(test_name);
+        for (i, data) in [b"abc", b"def", b"ghi"].iter().enumerate() {
+            fs::write(test_dir.join(format!("split.tar.gz.part{:03}", i + 1)), data).unwrap();
+        }
+
+        let mut output = Vec::new();
+        let parts = find_archive_parts(&test_dir.join("split")).unwrap();
+        assert_eq!(copy_parts(&parts, &mut output).unwrap(), 9);
+        assert_eq!(output, b"abcdefghi");
+
+        cleanup_test_dir(test_name);
+    }
+}
@@ -0,0 +1,392 @@
+use anyhow::{Context, Result, anyhow};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::info;
+use crate::rolling_writer::RollingWriter;
+use crate::archive_info::{ArchiveInfo, INFO_FILE, MANIFEST_ENTRY};
+use crate::multivolume::MultiVolumeWriter;
+use crate::checkpoint::{Checkpoint, write_checkpoint};
+use crate::hasher::{FileHash, StreamingSegmentHash};
+use crate::hash_cache::file_stamp;
+use crate::index::{EntryKind, IndexEntry};
+
+// File permission constants
+const FILE_MODE_READ: u32 = 0o644;  // Read-only file permissions (rw-r--r--)
+
+type TarBuilder = tar::Builder<CountingStream>;
+
+/// Output stream under the tar builder
+pub enum ArchiveStream {
+    Gzip(GzEncoder<RollingWriter>),
+    /// Uncompressed GNU tar multi-volume parts
+    MultiVolume(MultiVolumeWriter),
+}
+
+impl ArchiveStream {
+    /// Gzip-compress the tar stream into `writer`
+    pub fn gzip(writer: RollingWriter, compression: Compression) -> Self {
+        ArchiveStream::Gzip(GzEncoder::new(writer, compression))
+    }
+
+    /// Write the (uncompressed) tar stream as GNU tar multi-volume parts of `volume_size`
+    pub fn multi_volume(writer: RollingWriter, volume_size: usize) -> io::Result<Self> {
+        Ok(ArchiveStream::MultiVolume(MultiVolumeWriter::new(writer, volume_size)?))
+    }
+
+    /// Number of bytes written to the current part
+    fn current_size(&self) -> usize {
+        match self {
+            ArchiveStream::Gzip(enc) => enc.get_ref().current_size(),
+            ArchiveStream::MultiVolume(writer) => writer.current_size(),
+        }
+    }
+
+    fn finish(self) -> io::Result<RollingWriter> {
+        match self {
+            ArchiveStream::Gzip(enc) => enc.finish(),
+            ArchiveStream::MultiVolume(writer) => writer.finish(),
+        }
+    }
+}
+
+impl Write for ArchiveStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ArchiveStream::Gzip(enc) => enc.write(buf),
+            ArchiveStream::MultiVolume(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ArchiveStream::Gzip(enc) => enc.flush(),
+            ArchiveStream::MultiVolume(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Counts the (uncompressed) tar bytes written to an ArchiveStream, giving each entry's offset
+struct CountingStream {
+    inner: ArchiveStream,
+    written: u64,
+}
+
+impl CountingStream {
+    fn new(inner: ArchiveStream) -> Self {
+        Self { inner, written: 0 }
+    }
+}
+
+impl Write for CountingStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes_written = self.inner.write(buf)?;
+        self.written += bytes_written as u64;
+        Ok(bytes_written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Tar stream written to a RollingWriter (Via an ArchiveStream).
+///
+/// When `split_at` is set, the tar and gzip streams are finished and restarted (in a new part)
+/// at the first entry boundary after reaching that size, so every part is a standalone tar.gz
+/// (Gzip streams only).
+pub struct ArchiveWriter {
+    tar: Option<TarBuilder>,
+    compression: Compression,
+    info_json: String,
+    split_at: Option<usize>,
+    /// Number of entries (besides the info file) in the current part
+    part_entries: usize,
+    /// Number of entries (besides info files) appended or skipped so far
+    entries: usize,
+    /// Entries already archived by an interrupted run (These are skipped)
+    skip_entries: usize,
+    checkpoint: Option<CheckpointTarget>,
+    /// If set, the segment hash is computed from the data as it is archived
+    segment_hash: Option<StreamingSegmentHash>,
+    /// Number of the current part (Only counts standalone parts)
+    part: usize,
+    /// If set, the part and offset of each entry is recorded
+    index: Option<Vec<IndexEntry>>,
+}
+
+/// Feeds everything read from a file to its hash
+struct HashingReader<'a> {
+    inner: fs::File,
+    hash: &'a mut FileHash,
+}
+
+impl Read for HashingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.hash.update(&buf[..bytes_read]);
+        Ok(bytes_read)
+    }
+}
+
+/// Where (and for what) to record progress after each part
+struct CheckpointTarget {
+    path: PathBuf,
+    segment: String,
+    hash: String,
+}
+
+impl ArchiveWriter {
+    /// Start the archive, injecting the info file
+    /// 
+    /// NOTE: If using `split_at`, the RollingWriter must be set to manual rollover
+    pub fn new(stream: ArchiveStream, compression: Compression, info: &ArchiveInfo, split_at: Option<usize>) -> Result<Self> {
+        if split_at.is_some() && matches!(stream, ArchiveStream::MultiVolume(_)) {
+            return Err(anyhow!("Multi-volume archives cannot be split at entry boundaries"));
+        }
+
+        let mut archive = Self {
+            tar: Some(tar::Builder::new(CountingStream::new(stream))),
+            compression,
+            info_json: info.to_json()?,
+            split_at,
+            part_entries: 0,
+            entries: 0,
+            skip_entries: 0,
+            checkpoint: None,
+            segment_hash: None,
+            part: 1,
+            index: None,
+        };
+        archive.append_info()?;
+        if let Some(manifest_json) = info.manifest_json()? {
+            archive.append_metadata_entry(MANIFEST_ENTRY, manifest_json.as_bytes())?;
+        }
+        Ok(archive)
+    }
+
+    /// Write a checkpoint after each completed part, and skip the first `entries_done` entries
+    /// (Already archived by an interrupted run). Entries must be appended in the same order on every run.
+    pub fn set_checkpoint(&mut self, path: PathBuf, segment: &str, hash: &str, entries_done: usize) {
+        self.skip_entries = entries_done;
+        self.checkpoint = Some(CheckpointTarget {
+            path,
+            segment: segment.to_string(),
+            hash: hash.to_string(),
+        });
+    }
+
+    /// Hash every file as it is archived (So the segment doesn't need a separate hashing pass)
+    pub fn set_segment_hash(&mut self, segment_hash: StreamingSegmentHash) {
+        self.segment_hash = Some(segment_hash);
+    }
+
+    /// Remove the segment hash (Call once every file has been appended)
+    pub fn take_segment_hash(&mut self) -> Option<StreamingSegmentHash> {
+        self.segment_hash.take()
+    }
+
+    /// Record where each entry is written, for an index file
+    pub fn set_index(&mut self) {
+        self.index = Some(Vec::new());
+    }
+
+    /// Remove the recorded entries (Call once every entry has been appended)
+    pub fn take_index(&mut self) -> Option<Vec<IndexEntry>> {
+        self.index.take()
+    }
+
+    /// Append a file or symlink (including broken ones), returning the number of content bytes added
+    pub fn append_path(&mut self, path: &Path, relative_path: &Path) -> Result<u64> {
+        self.split_if_full()?;
+
+        // Check if this is a symlink
+        let (is_symlink, size) = match fs::symlink_metadata(path) {
+            Ok(m) => (m.file_type().is_symlink(), m.len()),
+            Err(_) => (false, 0),
+        };
+        if self.skip_entry() {
+            return Ok(if is_symlink { 0 } else { size });
+        }
+
+        let mut segment_hash = self.segment_hash.take();
+        let unhashed = segment_hash.as_ref().is_some_and(|segment_hash| segment_hash.is_ignored(path));
+        let hashing = segment_hash.as_mut().filter(|_| !unhashed);
+        let result = self.append_entry(path, relative_path, is_symlink, hashing);
+        if let Some(segment_hash) = segment_hash.as_mut() {
+            match result {
+                Err(_) => segment_hash.mark_failed(),
+                Ok(()) if unhashed => segment_hash.add_unhashed(relative_path),
+                Ok(()) => {}
+            }
+        }
+        self.segment_hash = segment_hash;
+        result?;
+
+        self.part_entries += 1;
+        Ok(if is_symlink { 0 } else { size })
+    }
+
+    /// Append an (empty) directory entry
+    pub fn append_dir(&mut self, path: &Path, relative_path: &Path) -> Result<()> {
+        self.split_if_full()?;
+        if self.skip_entry() {
+            return Ok(());
+        }
+        let tar = self.builder()?;
+        let offset = tar.get_ref().written;
+        tar.append_dir(relative_path, path)
+            .context(format!("Failed to add directory to archive: {:?}", path))?;
+        let mtime = fs::metadata(path).ok().as_ref().and_then(file_stamp).map_or(0, |(_, secs, _)| secs);
+        self.add_to_index(relative_path, EntryKind::Dir, 0, mtime, None, offset);
+        self.part_entries += 1;
+        Ok(())
+    }
+
+    /// Append a file holding `contents` (i.e. a command's output) modified now, returning the number of content bytes added
+    pub fn append_contents(&mut self, relative_path: &Path, contents: &[u8]) -> Result<u64> {
+        self.split_if_full()?;
+        let size = contents.len() as u64;
+        if self.skip_entry() {
+            return Ok(size);
+        }
+        let mtime = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(size);
+        header.set_mode(FILE_MODE_READ);
+        header.set_mtime(mtime);
+        let tar = self.builder()?;
+        let offset = tar.get_ref().written;
+        tar.append_data(&mut header, relative_path, contents)
+            .context(format!("Failed to add entry to archive: {:?}", relative_path))?;
+        self.add_to_index(relative_path, EntryKind::File, size, mtime, None, offset);
+        self.part_entries += 1;
+        Ok(size)
+    }
+
+    /// Finish the tar and gzip streams, returning the (not yet finalized) writer
+    pub fn finish(mut self) -> Result<RollingWriter> {
+        self.finish_stream()
+    }
+
+    // --- Private methods --- //
+
+    fn builder(&mut self) -> Result<&mut TarBuilder> {
+        self.tar.as_mut().ok_or_else(|| anyhow!("Archive stream is closed"))
+    }
+
+    /// Write a file or symlink entry, feeding it to `segment_hash` if set
+    fn append_entry(&mut self, path: &Path, relative_path: &Path, is_symlink: bool, segment_hash: Option<&mut StreamingSegmentHash>) -> Result<()> {
+        let tar = self.builder()?;
+        let offset = tar.get_ref().written;
+        if is_symlink {
+            // Handle symlinks (including broken ones)
+            let target = fs::read_link(path)
+                .context(format!("Failed to read symlink target: {:?}", path))?;
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_mode(FILE_MODE_READ);
+            header.set_size(0); // (Left blank, readers other than GNU tar reject the entry)
+            tar.append_link(&mut header, relative_path, &target)
+                .context(format!("Failed to add symlink to archive: {:?}", path))?;
+            if let Some(segment_hash) = segment_hash {
+                segment_hash.add_symlink(relative_path, &target);
+            }
+            let target = target.to_string_lossy().to_string();
+            self.add_to_index(relative_path, EntryKind::Link, 0, 0, Some(target), offset);
+            return Ok(());
+        }
+
+        // Regular file
+        let file = fs::File::open(path)
+            .context(format!("Failed to open file for archiving: {:?}", path))?;
+        let metadata = file.metadata()
+            .context(format!("Failed to read metadata for archiving: {:?}", path))?;
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(&metadata);
+        match segment_hash {
+            Some(segment_hash) => {
+                let mut file_hash = segment_hash.start_file(relative_path);
+                let reader = HashingReader { inner: file, hash: &mut file_hash };
+                tar.append_data(&mut header, relative_path, reader)
+                    .context(format!("Failed to add file to archive: {:?}", path))?;
+                segment_hash.add_file(relative_path, file_hash, &metadata);
+            }
+            None => {
+                tar.append_data(&mut header, relative_path, file)
+                    .context(format!("Failed to add file to archive: {:?}", path))?;
+            }
+        }
+        self.add_to_index(relative_path, EntryKind::File, metadata.len(), header.mtime().unwrap_or(0), None, offset);
+        Ok(())
+    }
+
+    fn add_to_index(&mut self, relative_path: &Path, kind: EntryKind, size: u64, mtime: u64, link_target: Option<String>, offset: u64) {
+        if let Some(index) = self.index.as_mut() {
+            let path = relative_path.to_string_lossy().to_string();
+            index.push(IndexEntry { path, kind, size, mtime, link_target, part: self.part, offset });
+        }
+    }
+
+    /// Count the next entry, returning true if it was archived by an interrupted run
+    fn skip_entry(&mut self) -> bool {
+        self.entries += 1;
+        self.entries <= self.skip_entries
+    }
+
+    fn append_info(&mut self) -> Result<()> {
+        let info_json = self.info_json.to_owned();
+        self.append_metadata_entry(INFO_FILE, info_json.as_bytes())
+    }
+
+    /// Append an entry written by this tool (Not a source file)
+    fn append_metadata_entry(&mut self, path: &str, contents: &[u8]) -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_path(path)?;
+        header.set_size(contents.len() as u64);
+        header.set_mode(FILE_MODE_READ);
+        header.set_cksum(); // Removing this line will cause the archive to be corrupted
+        self.builder()?.append(&header, contents)?;
+        Ok(())
+    }
+
+    /// Start a new standalone part if the current one has reached split_at
+    fn split_if_full(&mut self) -> Result<()> {
+        let Some(split_at) = self.split_at else {
+            return Ok(());
+        };
+        let current_size = self.builder()?.get_ref().inner.current_size();
+        if self.part_entries == 0 || current_size < split_at {
+            return Ok(());
+        }
+
+        let mut writer = self.finish_stream()?;
+        writer.start_new_part()?;
+        info!("Started new standalone part after {} entries", self.part_entries);
+
+        // Every entry so far is in a completed part
+        if let Some(target) = &self.checkpoint {
+            let checkpoint = Checkpoint {
+                segment: target.segment.to_owned(),
+                hash: target.hash.to_owned(),
+                entries_done: self.entries,
+                parts: writer.completed_parts().to_vec(),
+            };
+            write_checkpoint(&target.path, &checkpoint)?;
+        }
+
+        self.tar = Some(tar::Builder::new(CountingStream::new(ArchiveStream::gzip(writer, self.compression))));
+        self.part_entries = 0;
+        self.part += 1;
+        self.append_info()
+    }
+
+    fn finish_stream(&mut self) -> Result<RollingWriter> {
+        let tar = self.tar.take().ok_or_else(|| anyhow!("Archive stream is closed"))?;
+        let stream = tar.into_inner().context("Failed to finalize tar archive")?;
+        stream.inner.finish().context("Failed to finalize archive stream")
+    }
+}
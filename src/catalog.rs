@@ -0,0 +1,133 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::io::Write;
+use std::fs;
+use globset::Glob;
+use log::warn;
+use crate::manifest::ManifestFiles;
+use crate::rolling_writer::PartInfo;
+
+/// One line of the catalog: an archive that was written, and the files it holds
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct CatalogEntry {
+    pub segment: String,
+    /// Archive file name (Before any .part### suffix)
+    pub archive: String,
+    /// Time the archive was created (RFC 3339)
+    pub created: String,
+    /// Tagged segment hash (Missing if the segment could not be hashed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    pub parts: Vec<PartInfo>,
+    /// Hash of each file in the archive, keyed by relative path
+    /// (Only the changed files of an increment, empty if the segment's files weren't hashed)
+    pub files: ManifestFiles,
+}
+
+/// A file found in the catalog, and the archive holding it
+#[derive(Debug)]
+pub struct CatalogMatch<'a> {
+    pub path: &'a str,
+    pub hash: &'a str,
+    pub entry: &'a CatalogEntry,
+}
+
+impl CatalogMatch<'_> {
+    /// File path, then the archive holding it: segment, archive name, creation time, parts and the file's hash
+    pub fn line(&self) -> String {
+        format!("{}\t{} {} ({}, {} part(s), file hash {})", self.path, self.entry.segment, self.entry.archive, self.entry.created, self.entry.parts.len(), self.hash)
+    }
+}
+
+/// Append an archive to the catalog (As one JSON line, creating the file and its directory if needed)
+pub fn append_catalog(path: &Path, entry: &CatalogEntry) -> Result<()> {
+    if let Some(dir) = path.parent()
+        && !dir.as_os_str().is_empty() {
+        fs::create_dir_all(dir)
+            .context(format!("Failed to create directory for catalog: {:?}", dir))?;
+    }
+    let mut line = serde_json::to_string(entry)
+        .context("Failed to serialize catalog entry")?;
+    line.push('\n');
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .context(format!("Failed to write catalog: {:?}", path))
+}
+
+/// Read every archive in the catalog (Oldest first). Unreadable lines (i.e. from a run killed mid-write) are skipped.
+pub fn read_catalog(path: &Path) -> Result<Vec<CatalogEntry>> {
+    let contents = fs::read_to_string(path)
+        .context(format!("Failed to read catalog: {:?}", path))?;
+    Ok(contents.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(i, line)| serde_json::from_str(line)
+            .inspect_err(|e| warn!("Skipping unreadable line {} of catalog {:?}: {}", i + 1, path, e))
+            .ok())
+        .collect())
+}
+
+/// Every cataloged file whose path or file name matches a glob pattern (Oldest archive first)
+pub fn find_files<'a>(entries: &'a [CatalogEntry], pattern: &str) -> Result<Vec<CatalogMatch<'a>>> {
+    let matcher = Glob::new(pattern)
+        .context(format!("Invalid find pattern: {}", pattern))?
+        .compile_matcher();
+    Ok(entries.iter()
+        .flat_map(|entry| entry.files.iter().map(move |(path, file)| CatalogMatch { path, hash: &file.hash, entry }))
+        .filter(|found| matcher.is_match(found.path)
+            || Path::new(found.path).file_name().is_some_and(|name| matcher.is_match(name)))
+        .collect())
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::manifest::ManifestEntry;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/catalog_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn entry(archive: &str, files: &[&str]) -> CatalogEntry {
+        CatalogEntry {
+            segment: "docs".to_string(),
+            archive: archive.to_string(),
+            created: "2024-01-01T00:00:00+00:00".to_string(),
+            hash: Some("xxh3:0123456789abcdef".to_string()),
+            parts: vec![PartInfo { name: archive.to_string(), path: PathBuf::new(), size: 4, checksum: None }],
+            files: files.iter().map(|path| (path.to_string(), ManifestEntry { hash: format!("hash of {}", path), metadata: None })).collect(),
+        }
+    }
+
+    #[test]
+    fn test_catalog_find() {
+        let test_name = "find";
+        cleanup_test_dir(test_name);
+        let path = get_test_dir(test_name).join("catalog.jsonl");
+
+        append_catalog(&path, &entry("docs.tar.gz", &["reports/2024/q1.pdf", "notes.txt"])).unwrap();
+        append_catalog(&path, &entry("docs.inc001.tar.gz", &["reports/2024/q1.pdf"])).unwrap();
+        let entries = read_catalog(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        // File names match on their own, and paths match as a whole
+        let found = find_files(&entries, "q1.pdf").unwrap();
+        assert_eq!(found.iter().map(|found| found.entry.archive.as_str()).collect::<Vec<_>>(), ["docs.tar.gz", "docs.inc001.tar.gz"]);
+        assert_eq!(found[0].line(), "reports/2024/q1.pdf\tdocs docs.tar.gz (2024-01-01T00:00:00+00:00, 1 part(s), file hash hash of reports/2024/q1.pdf)");
+        assert_eq!(find_files(&entries, "reports/**").unwrap().len(), 2);
+        assert_eq!(find_files(&entries, "*.txt").unwrap().len(), 1);
+        assert!(find_files(&entries, "missing.txt").unwrap().is_empty());
+
+        cleanup_test_dir(test_name);
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::fs;
+
+/// Extension of each segment's chain file
+pub const CHAIN_EXT: &str = "chain.json";
+
+/// The archives needed to restore a segment: a full archive, then each increment on top of it (In order)
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Chain {
+    pub segment: String,
+    pub generations: Vec<Generation>,
+}
+
+/// One archive in a chain
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Generation {
+    /// 0 for the full archive, then 1, 2, ... for each increment
+    pub generation: u32,
+    /// Time the archive was created (RFC 3339)
+    pub created: String,
+    /// Archive file name (Before any .part### suffix)
+    pub archive: String,
+    /// Names of the files written for the archive
+    pub parts: Vec<String>,
+    /// Files deleted since the previous generation (To remove after extracting this one)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<String>,
+}
+
+impl Chain {
+    /// Generation of the next archive (0, a full archive, once the chain has `full_every` archives)
+    pub fn next_generation(&self, full_every: u32) -> u32 {
+        match self.generations.last() {
+            Some(last) if last.generation + 1 < full_every => last.generation + 1,
+            _ => 0,
+        }
+    }
+
+    /// Add an archive to the chain (A full archive starts a new chain)
+    pub fn push(&mut self, generation: Generation) {
+        if generation.generation == 0 {
+            self.generations.clear();
+        }
+        self.generations.push(generation);
+    }
+}
+
+/// Path of the chain file for a segment
+pub fn chain_path(manifest_dir: &Path, segment: &str) -> PathBuf {
+    manifest_dir.join(format!("{}.{}", segment, CHAIN_EXT))
+}
+
+/// File name of a segment's archive for a generation (Increments are numbered, so they don't replace the full archive)
+pub fn archive_name(segment: &str, generation: u32, extension: &str) -> String {
+    match generation {
+        0 => format!("{}.{}", segment, extension),
+        _ => format!("{}.inc{:03}.{}", segment, generation, extension),
+    }
+}
+
+/// Write a chain (Via a temporary file, so a crash never leaves it half-written)
+pub fn write_chain(path: &Path, chain: &Chain) -> Result<()> {
+    let contents = serde_json::to_string_pretty(chain)
+        .context("Failed to serialize chain")?;
+    let temp_path = PathBuf::from(format!("{}.tmp", path.display()));
+    fs::write(&temp_path, contents)
+        .context(format!("Failed to write chain file: {:?}", temp_path))?;
+    fs::rename(&temp_path, path)
+        .context(format!("Failed to replace chain file: {:?}", path))
+}
+
+/// Read a chain written by a previous run
+pub fn read_chain(path: &Path) -> Result<Chain> {
+    let contents = fs::read_to_string(path)
+        .context(format!("Failed to read chain file: {:?}", path))?;
+    serde_json::from_str(&contents)
+        .context(format!("Failed to parse chain file: {:?}", path))
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/chain_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    fn generation(generation: u32) -> Generation {
+        Generation {
+            generation,
+            created: String::new(),
+            archive: archive_name("docs", generation, "tar.gz"),
+            parts: vec![archive_name("docs", generation, "tar.gz")],
+            removed: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_archive_name() {
+        assert_eq!(archive_name("docs", 0, "tar.gz"), "docs.tar.gz");
+        assert_eq!(archive_name("docs", 2, "tar.gz"), "docs.inc002.tar.gz");
+    }
+
+    #[test]
+    fn test_chain_schedules_fulls() {
+        let mut chain = Chain::default();
+        let mut generations = Vec::new();
+        for _ in 0..7 {
+            let next = chain.next_generation(3);
+            generations.push(next);
+            chain.push(generation(next));
+        }
+        assert_eq!(generations, [0, 1, 2, 0, 1, 2, 0]);
+        assert_eq!(chain.generations.len(), 1, "A full archive should start a new chain");
+
+        // Without increments every archive is full
+        assert_eq!(chain.next_generation(1), 0);
+    }
+
+    #[test]
+    fn test_chain_round_trip() {
+        let test_name = "round_trip";
+        let test_dir = setup_test_dir(test_name);
+        let path = chain_path(&test_dir, "docs");
+
+        let mut chain = Chain { segment: "docs".to_string(), generations: Vec::new() };
+        chain.push(generation(0));
+        chain.push(Generation { removed: vec!["old.txt".to_string()], ..generation(1) });
+        write_chain(&path, &chain).unwrap();
+
+        let read = read_chain(&path).unwrap();
+        assert_eq!(read.generations.len(), 2);
+        assert_eq!(read.generations[1].archive, "docs.inc001.tar.gz");
+        assert_eq!(read.generations[1].removed, ["old.txt"]);
+
+        cleanup_test_dir(test_name);
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::fs;
+use crate::rolling_writer::{PartInfo, partial_path};
+
+/// Extension appended to the archive path for its checkpoint file
+pub const CHECKPOINT_EXT: &str = "checkpoint";
+
+/// Progress of an interrupted archive (Written after each completed part)
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Checkpoint {
+    pub segment: String,
+    /// Segment hash when archiving started (The source must be unchanged to resume)
+    pub hash: String,
+    /// Number of entries (Files, symlinks and empty directories) in the completed parts
+    pub entries_done: usize,
+    pub parts: Vec<PartInfo>,
+}
+
+impl Checkpoint {
+    /// True if every completed part is still on disk (Under its temporary name) at its recorded size
+    pub fn parts_exist(&self, dir: &Path) -> bool {
+        self.parts.iter().all(|part| {
+            fs::metadata(partial_path(&dir.join(&part.name))).is_ok_and(|m| m.len() == part.size)
+        })
+    }
+}
+
+/// Path of the checkpoint file for an archive
+pub fn checkpoint_path(archive_path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.{}", archive_path.display(), CHECKPOINT_EXT))
+}
+
+/// Write the checkpoint (Via a temporary file, so a crash never leaves it half-written)
+pub fn write_checkpoint(path: &Path, checkpoint: &Checkpoint) -> Result<()> {
+    let contents = toml::to_string(checkpoint)
+        .context("Failed to serialize checkpoint")?;
+    let temp_path = PathBuf::from(format!("{}.tmp", path.display()));
+    fs::write(&temp_path, contents)
+        .context(format!("Failed to write checkpoint file: {:?}", temp_path))?;
+    fs::rename(&temp_path, path)
+        .context(format!("Failed to replace checkpoint file: {:?}", path))
+}
+
+pub fn read_checkpoint(path: &Path) -> Result<Checkpoint> {
+    let contents = fs::read_to_string(path)
+        .context(format!("Failed to read checkpoint file: {:?}", path))?;
+    toml::from_str(&contents)
+        .context(format!("Failed to parse checkpoint file: {:?}", path))
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/checkpoint_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[test]
+    fn test_checkpoint_path() {
+        let path = checkpoint_path(Path::new("/tmp/archives/docs.tar.gz"));
+        assert_eq!(path, PathBuf::from("/tmp/archives/docs.tar.gz.checkpoint"));
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let test_name = "round_trip";
+        let test_dir = setup_test_dir(test_name);
+        fs::write(test_dir.join("docs.tar.gz.part001.partial"), [0u8; 10]).unwrap();
+
+        let checkpoint = Checkpoint {
+            segment: "docs".to_string(),
+            hash: "0123456789abcdef".to_string(),
+            entries_done: 4,
+            parts: vec![PartInfo {
+                name: "docs.tar.gz.part001".to_string(),
+                path: test_dir.join("docs.tar.gz.part001"),
+                size: 10,
+                checksum: None,
+            }],
+        };
+        let path = checkpoint_path(&test_dir.join("docs.tar.gz"));
+        write_checkpoint(&path, &checkpoint).unwrap();
+
+        let read = read_checkpoint(&path).unwrap();
+        assert_eq!(read.segment, "docs");
+        assert_eq!(read.entries_done, 4);
+        assert!(read.parts_exist(&test_dir));
+
+        // A truncated part cannot be resumed from
+        fs::write(test_dir.join("docs.tar.gz.part001.partial"), [0u8; 5]).unwrap();
+        assert!(!read.parts_exist(&test_dir));
+
+        cleanup_test_dir(test_name);
+    }
+}
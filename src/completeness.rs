@@ -0,0 +1,230 @@
+use anyhow::{Context, Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::fs;
+use crate::archive_info::read_archive_info;
+use crate::archive_reader::{archive_base, find_archive_parts, part_number};
+use crate::digest::DigestAlgorithm;
+use crate::index::{index_path, read_index};
+use crate::rolling_writer::{PART_LIST_EXT, PartInfo};
+use crate::sidecar::{read_sidecar, sidecar_path};
+
+/// Parts of an archive as recorded at backup time
+#[derive(Debug)]
+pub struct RecordedParts {
+    pub parts: Vec<PartInfo>,
+    /// Algorithm of the parts' checksums (Only recorded in the metadata file)
+    pub checksum_algorithm: Option<DigestAlgorithm>,
+    /// File they were recorded in
+    pub source: PathBuf,
+}
+
+/// Result of checking that an archive's parts are all present
+#[derive(Debug)]
+pub struct PartCheck {
+    /// Parts found, in order
+    pub parts: Vec<PathBuf>,
+    /// None if no record was found (So only the part numbering was checked)
+    pub recorded: Option<RecordedParts>,
+}
+
+/// Find the parts of an archive, checking none are missing, out of sequence or unexpected.
+/// They are checked against the parts recorded at backup time (By name and size), if found,
+/// otherwise their numbers must run from part001 without gaps (Which misses lost trailing parts).
+/// A merged archive is checked against the total size of its recorded parts.
+///
+/// # Errors
+/// Returns an error naming the first missing or unexpected part
+pub fn check_archive_parts(archive: &Path) -> Result<PartCheck> {
+    let parts = find_archive_parts(archive)?;
+    let recorded = recorded_parts(&parts);
+    match &recorded {
+        Some(recorded) if parts.len() == 1 && number(&parts[0]).is_none() && recorded.parts.len() > 1 => check_merged(&parts[0], &recorded.parts)?,
+        Some(recorded) => check_parts(&parts, &recorded.parts)?,
+        None => check_sequence(&parts)?,
+    }
+    Ok(PartCheck { parts, recorded })
+}
+
+/// Parts recorded for an archive, by its segment's metadata file if that describes this archive,
+/// otherwise by its `.parts` listing, otherwise by its index
+pub fn recorded_parts(parts: &[PathBuf]) -> Option<RecordedParts> {
+    let base = archive_base(parts.first()?);
+    let dir = base.parent().unwrap_or(Path::new("."));
+    // (Only the first part of a stream has the info entry, but any part of an independent archive does)
+    let sidecar_file = parts.iter()
+        .find_map(|part| read_archive_info(part).ok())
+        .map(|info| sidecar_path(dir, &info.segment));
+    if let Some(sidecar_file) = sidecar_file
+        && let Ok(sidecar) = read_sidecar(&sidecar_file)
+        && sidecar.parts.first().is_some_and(|part| archive_base(&dir.join(&part.name)) == base) {
+        return Some(RecordedParts { parts: sidecar.parts, checksum_algorithm: sidecar.checksum_algorithm, source: sidecar_file });
+    }
+
+    let part_list = PathBuf::from(format!("{}.{}", base.display(), PART_LIST_EXT));
+    if let Ok(contents) = fs::read_to_string(&part_list) {
+        let parts = contents.lines()
+            .filter_map(|line| {
+                let (size, name) = line.split_once(' ')?;
+                Some(PartInfo { name: name.to_string(), path: dir.join(name), size: size.parse().ok()?, checksum: None })
+            })
+            .collect();
+        return Some(RecordedParts { parts, checksum_algorithm: None, source: part_list });
+    }
+
+    let index_file = index_path(&base);
+    let index = read_index(&index_file).ok()?;
+    let parts = index.parts.into_iter()
+        .map(|part| PartInfo { path: dir.join(&part.name), name: part.name, size: part.size, checksum: None })
+        .collect();
+    Some(RecordedParts { parts, checksum_algorithm: None, source: index_file })
+}
+
+/// Check the parts found on disk are exactly the expected ones, in order and at their recorded sizes
+fn check_parts(parts: &[PathBuf], expected: &[PartInfo]) -> Result<()> {
+    for (i, part) in expected.iter().enumerate() {
+        let Some(path) = parts.get(i).filter(|path| path.file_name().is_some_and(|name| name.to_string_lossy() == part.name)) else {
+            return Err(anyhow!("Part is missing: {} (Found {} of {} part(s))", part.name, parts.len(), expected.len()));
+        };
+        let size = fs::metadata(path)
+            .context(format!("Failed to read part: {:?}", path))?
+            .len();
+        if size != part.size {
+            return Err(anyhow!("Part {} is {} bytes, expected {}", part.name, size, part.size));
+        }
+    }
+    if let Some(extra) = parts.get(expected.len()) {
+        return Err(anyhow!("Found more parts than recorded (i.e. {:?})", extra));
+    }
+    Ok(())
+}
+
+/// Check a merged archive is the size of the parts it was merged from
+fn check_merged(path: &Path, expected: &[PartInfo]) -> Result<()> {
+    let size = fs::metadata(path)
+        .context(format!("Failed to read archive: {:?}", path))?
+        .len();
+    let expected_size: u64 = expected.iter().map(|part| part.size).sum();
+    if size != expected_size {
+        return Err(anyhow!("Merged archive is {} bytes, but its {} recorded part(s) total {}", size, expected.len(), expected_size));
+    }
+    Ok(())
+}
+
+/// Check the parts found are numbered from 1 without gaps (i.e. part001, part002, part003)
+fn check_sequence(parts: &[PathBuf]) -> Result<()> {
+    if parts.len() == 1 && number(&parts[0]).is_none() {
+        return Ok(());
+    }
+    for (expected, path) in (1..).zip(parts) {
+        if number(path) != Some(expected) {
+            let base = archive_base(path);
+            return Err(anyhow!("Part is missing: {}.part{:03} (Found {:?} in its place)", base.display(), expected, path));
+        }
+    }
+    Ok(())
+}
+
+fn number(path: &Path) -> Option<u32> {
+    part_number(&path.file_name()?.to_string_lossy())
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive_info::ArchiveInfo;
+    use crate::helpers::{ArchiveOptions, SplitMode, create_archive};
+    use crate::sidecar::{SegmentMetadata, write_sidecar};
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/completeness_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[test]
+    fn test_check_sequence() {
+        let test_name = "sequence";
+        let test_dir = setup_test_dir(test_name);
+        for i in [1, 2, 3] {
+            fs::write(test_dir.join(format!("docs.tar.gz.part{:03}", i)), b"").unwrap();
+        }
+        let check = check_archive_parts(&test_dir.join("docs")).unwrap();
+        assert_eq!(check.parts.len(), 3);
+        assert!(check.recorded.is_none());
+
+        fs::remove_file(test_dir.join("docs.tar.gz.part002")).unwrap();
+        let error = check_archive_parts(&test_dir.join("docs")).unwrap_err().to_string();
+        assert!(error.contains("docs.tar.gz.part002"), "{}", error);
+
+        fs::remove_file(test_dir.join("docs.tar.gz.part001")).unwrap();
+        assert!(check_archive_parts(&test_dir.join("docs")).is_err(), "A missing first part should be found");
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_check_recorded_parts() {
+        let test_name = "recorded";
+        let test_dir = setup_test_dir(test_name);
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(&src_dir).unwrap();
+        for i in 0..6 {
+            fs::write(src_dir.join(format!("file{}.bin", i)), vec![i as u8; 20000]).unwrap();
+        }
+
+        let output_dir = test_dir.join("out");
+        fs::create_dir_all(&output_dir).unwrap();
+        let info = ArchiveInfo::new("docs", &src_dir, &None, None).unwrap();
+        let options = ArchiveOptions { compression_level: Some(0), max_size_bytes: Some(30000), split_mode: SplitMode::Independent, ..Default::default() };
+        let summary = create_archive(&src_dir, &fs::metadata(&src_dir).unwrap(), &output_dir.join("docs.tar.gz"),
+            &[], &info, &options, None, None).unwrap();
+        let sidecar = SegmentMetadata {
+            segment: "docs".to_string(),
+            source_path: src_dir,
+            hash: None,
+            created: info.created,
+            file_count: summary.file_count,
+            uncompressed_bytes: summary.uncompressed_bytes,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            checksum_algorithm: None,
+            parts: summary.parts.parts.clone(),
+        };
+        write_sidecar(&sidecar_path(&output_dir, "docs"), &sidecar).unwrap();
+        let parts = summary.parts.parts;
+        assert!(parts.len() > 1);
+
+        let check = check_archive_parts(&output_dir.join("docs")).unwrap();
+        assert_eq!(check.recorded.unwrap().source, sidecar_path(&output_dir, "docs"));
+
+        // A lost last part is found from the recorded part count
+        let last = parts.last().unwrap();
+        let data = fs::read(&last.path).unwrap();
+        fs::remove_file(&last.path).unwrap();
+        let error = check_archive_parts(&output_dir.join("docs")).unwrap_err().to_string();
+        assert!(error.contains(&last.name), "{}", error);
+
+        // A merged archive is checked by its total size
+        let mut merged: Vec<u8> = parts[..parts.len() - 1].iter().flat_map(|part| fs::read(&part.path).unwrap()).collect();
+        for part in &parts[..parts.len() - 1] {
+            fs::remove_file(&part.path).unwrap();
+        }
+        fs::write(output_dir.join("docs.tar.gz"), &merged).unwrap();
+        assert!(check_archive_parts(&output_dir.join("docs")).is_err());
+        merged.extend(data);
+        fs::write(output_dir.join("docs.tar.gz"), &merged).unwrap();
+        assert!(check_archive_parts(&output_dir.join("docs")).is_ok());
+
+        cleanup_test_dir(test_name);
+    }
+}
@@ -0,0 +1,716 @@
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use log::{info, warn};
+use crate::sink::{ArchiveSink, CommandSink, PART_NAME_PLACEHOLDER};
+use crate::retry::RetryPolicy;
+
+/// One destination, or a list of them that each part is delivered to (i.e. a NAS and S3)
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Destinations {
+    One(Destination),
+    Many(Vec<Destination>),
+}
+
+impl Destinations {
+    pub fn into_vec(self) -> Vec<Destination> {
+        match self {
+            Destinations::One(destination) => vec![destination],
+            Destinations::Many(destinations) => destinations,
+        }
+    }
+}
+
+/// Parts delivered to one destination
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UploadReport {
+    /// Where the parts were uploaded (See Destination::label)
+    pub destination: String,
+    pub uploaded: usize,
+    pub failed: usize,
+}
+
+/// Uploads to each destination, counted as parts are delivered (Shared by the upload threads)
+#[derive(Debug, Default)]
+pub struct UploadTally(Mutex<Vec<UploadReport>>);
+
+impl UploadTally {
+    fn record(&self, destination: &Destination, uploaded: bool) {
+        let label = destination.label();
+        let mut reports = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let index = match reports.iter().position(|report| report.destination == label) {
+            Some(index) => index,
+            None => {
+                reports.push(UploadReport { destination: label, uploaded: 0, failed: 0 });
+                reports.len() - 1
+            }
+        };
+        match uploaded {
+            true => reports[index].uploaded += 1,
+            false => reports[index].failed += 1,
+        }
+    }
+
+    /// Counts since the last call, per destination (Resetting them)
+    pub fn take(&self) -> Vec<UploadReport> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// Upload a finished file to every destination (Even if an earlier one fails), counting each upload
+///
+/// # Errors
+/// Returns the error of each destination that failed
+pub fn upload_all(destinations: &[Destination], path: &Path, tally: &UploadTally, retry: &RetryPolicy) -> io::Result<()> {
+    let mut errors = Vec::new();
+    for destination in destinations {
+        let result = destination.upload(path, retry);
+        tally.record(destination, result.is_ok());
+        if let Err(e) = result {
+            errors.push(format!("{}: {}", destination.label(), e));
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(io::Error::other(errors.join("; "))),
+    }
+}
+
+/// Where finished parts are uploaded to (After they are written to output_path, before the post-script)
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Destination {
+    S3(S3Destination),
+    Rclone(RcloneDestination),
+    Webdav(WebdavDestination),
+    Sftp(SftpDestination),
+}
+
+impl Destination {
+    /// Upload a finished file, retrying failed attempts (With the destination's `attempts`, if set)
+    ///
+    /// # Errors
+    /// Returns the last attempt's error once every attempt has failed
+    pub fn upload(&self, path: &Path, retry: &RetryPolicy) -> io::Result<()> {
+        retry.with_attempts(self.attempts()).run(&format!("Upload of {:?}", path), || match self {
+            Destination::S3(s3) => s3.upload(path),
+            Destination::Rclone(rclone) => rclone.upload(path),
+            Destination::Webdav(webdav) => webdav.upload(path),
+            Destination::Sftp(sftp) => sftp.upload(path),
+        })
+    }
+
+    /// Shell command to pipe each part into (As `part_command`, with `{name}` for the part's file name),
+    /// if parts are streamed to the destination instead of being written to output_path (None uploads them once written)
+    pub fn stream_command(&self, max_size: Option<usize>) -> Option<String> {
+        match self {
+            Destination::S3(s3) => s3.stream_command(max_size),
+            Destination::Rclone(rclone) => rclone.stream_command(),
+            Destination::Webdav(webdav) => webdav.stream_command(),
+            Destination::Sftp(sftp) => sftp.stream_command(),
+        }
+    }
+
+    /// Sink streaming each part to the destination (None if parts are uploaded once written to output_path)
+    pub fn sink(&self, max_size: Option<usize>) -> Option<Arc<dyn ArchiveSink>> {
+        self.stream_command(max_size)
+            .map(|command| Arc::new(CommandSink::new(command)) as Arc<dyn ArchiveSink>)
+    }
+
+    /// Where files are uploaded (For logs and reports)
+    pub fn label(&self) -> String {
+        match self {
+            Destination::S3(s3) => format!("s3://{}/{}", s3.bucket, s3.key("")),
+            Destination::Rclone(rclone) => rclone.remote.clone(),
+            Destination::Webdav(webdav) => webdav.url.clone(),
+            Destination::Sftp(sftp) => format!("{}:{}", sftp.host, sftp.path),
+        }
+    }
+
+    fn attempts(&self) -> Option<u32> {
+        match self {
+            Destination::S3(s3) => s3.attempts,
+            Destination::Rclone(rclone) => rclone.attempts,
+            Destination::Webdav(webdav) => webdav.attempts,
+            Destination::Sftp(sftp) => sftp.attempts,
+        }
+    }
+}
+
+/// An S3 bucket (Or S3 compatible service), uploaded to with the AWS CLI
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct S3Destination {
+    pub bucket: String,
+    /// Prepended to each file name to make its key (i.e. `backups/`)
+    pub prefix: Option<String>,
+    /// i.e. `DEEP_ARCHIVE`
+    pub storage_class: Option<String>,
+    /// For S3 compatible services
+    pub endpoint_url: Option<String>,
+    /// AWS CLI profile to use
+    pub profile: Option<String>,
+    /// Upload attempts per file (Overrides the retry policy's)
+    pub attempts: Option<u32>,
+    /// Path to the AWS CLI (Default: `aws`)
+    pub cli: Option<PathBuf>,
+    /// Stream each part into a multipart upload as it is compressed, instead of writing it to output_path first
+    pub stream: Option<bool>,
+}
+
+impl S3Destination {
+    /// Object key of a file
+    pub fn key(&self, name: &str) -> String {
+        format!("{}{}", self.prefix.as_deref().unwrap_or_default(), name)
+    }
+
+    /// Command that streams stdin to the part's key (None unless streaming)
+    fn stream_command(&self, max_size: Option<usize>) -> Option<String> {
+        if !self.stream.unwrap_or(false) {
+            return None;
+        }
+        let mut args = vec!["s3".to_string(), "cp".to_string(), "-".to_string(), format!("s3://{}/{}", self.bucket, self.key(PART_NAME_PLACEHOLDER)),
+            "--only-show-errors".to_string(), "--checksum-algorithm".to_string(), "SHA256".to_string()];
+        // (The CLI sizes the multipart chunks from this, otherwise large streams can run out of chunks)
+        if let Some(max_size) = max_size {
+            args.extend(["--expected-size".to_string(), max_size.to_string()]);
+        }
+        if let Some(storage_class) = &self.storage_class {
+            args.extend(["--storage-class".to_string(), storage_class.clone()]);
+        }
+        args.extend(self.connection_args());
+        Some(shell_command(&self.cli(), &args))
+    }
+
+    /// Upload a file with a SHA-256 checksum (Which S3 checks on receipt), then check the stored object's size
+    fn upload(&self, path: &Path) -> io::Result<()> {
+        let key = self.key(&file_name(path)?);
+        let size = fs::metadata(path)?.len();
+        info!("Uploading {:?} to s3://{}/{}", path, self.bucket, key);
+
+        let mut args = vec!["s3".to_string(), "cp".to_string(), path.display().to_string(), format!("s3://{}/{}", self.bucket, key),
+            "--only-show-errors".to_string(), "--checksum-algorithm".to_string(), "SHA256".to_string()];
+        if let Some(storage_class) = &self.storage_class {
+            args.extend(["--storage-class".to_string(), storage_class.clone()]);
+        }
+        args.extend(self.connection_args());
+        run_cli(&self.cli(), &args, None)?;
+
+        let mut args = vec!["s3api".to_string(), "head-object".to_string(), "--bucket".to_string(), self.bucket.clone(),
+            "--key".to_string(), key.clone(), "--query".to_string(), "ContentLength".to_string(), "--output".to_string(), "text".to_string()];
+        args.extend(self.connection_args());
+        let stored = run_cli(&self.cli(), &args, None)?;
+        check_size(stored.trim().parse().ok(), size, &format!("s3://{}/{}", self.bucket, key))
+    }
+
+    fn cli(&self) -> PathBuf {
+        self.cli.clone().unwrap_or_else(|| PathBuf::from("aws"))
+    }
+
+    /// Endpoint and profile arguments for the AWS CLI
+    fn connection_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(endpoint_url) = &self.endpoint_url {
+            args.extend(["--endpoint-url".to_string(), endpoint_url.clone()]);
+        }
+        if let Some(profile) = &self.profile {
+            args.extend(["--profile".to_string(), profile.clone()]);
+        }
+        args
+    }
+}
+
+/// Any remote supported by [rclone](https://rclone.org), uploaded to with the rclone CLI
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct RcloneDestination {
+    /// Configured remote and path to upload into (i.e. `b2:my-bucket/backups`)
+    pub remote: String,
+    /// Extra arguments passed to every rclone command (i.e. `["--config", "/etc/rclone.conf"]`)
+    #[serde(default)]
+    pub flags: Vec<String>,
+    /// Upload attempts per file (Overrides the retry policy's)
+    pub attempts: Option<u32>,
+    /// Path to rclone (Default: `rclone`)
+    pub cli: Option<PathBuf>,
+    /// Stream each part to the remote as it is compressed (With `rclone rcat`), instead of writing it to output_path first
+    pub stream: Option<bool>,
+}
+
+impl RcloneDestination {
+    /// Remote path of a file
+    pub fn remote_path(&self, name: &str) -> String {
+        match self.remote.ends_with(':') || self.remote.ends_with('/') {
+            true => format!("{}{}", self.remote, name),
+            false => format!("{}/{}", self.remote, name),
+        }
+    }
+
+    /// Command that streams stdin to the part's remote path (None unless streaming)
+    fn stream_command(&self) -> Option<String> {
+        if !self.stream.unwrap_or(false) {
+            return None;
+        }
+        let mut args = vec!["rcat".to_string(), self.remote_path(PART_NAME_PLACEHOLDER)];
+        args.extend(self.flags.iter().cloned());
+        Some(shell_command(&self.cli(), &args))
+    }
+
+    /// Upload a file (rclone checks its hash once uploaded, where the remote supports one), then check the stored file's size
+    fn upload(&self, path: &Path) -> io::Result<()> {
+        let remote_path = self.remote_path(&file_name(path)?);
+        let size = fs::metadata(path)?.len();
+        info!("Uploading {:?} to {}", path, remote_path);
+
+        let mut args = vec!["copyto".to_string(), path.display().to_string(), remote_path.clone()];
+        args.extend(self.flags.iter().cloned());
+        run_cli(&self.cli(), &args, None)?;
+
+        let mut args = vec!["size".to_string(), "--json".to_string(), remote_path.clone()];
+        args.extend(self.flags.iter().cloned());
+        let stored = run_cli(&self.cli(), &args, None)?;
+        let stored = serde_json::from_str::<serde_json::Value>(&stored).ok()
+            .and_then(|stored| stored.get("bytes")?.as_u64());
+        check_size(stored, size, &remote_path)
+    }
+
+    fn cli(&self) -> PathBuf {
+        self.cli.clone().unwrap_or_else(|| PathBuf::from("rclone"))
+    }
+}
+
+/// A WebDAV collection (i.e. a Nextcloud folder), uploaded to with curl
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct WebdavDestination {
+    /// URL of the collection to upload into, which must exist
+    /// (i.e. `https://cloud.example.com/remote.php/dav/files/user/Backups`)
+    pub url: String,
+    pub username: Option<String>,
+    /// Passed to curl on stdin, so it isn't visible in the process list (Can't be used when streaming)
+    pub password: Option<String>,
+    /// netrc file holding the credentials instead (Works when streaming)
+    pub netrc_file: Option<PathBuf>,
+    /// Upload attempts per file (Overrides the retry policy's)
+    pub attempts: Option<u32>,
+    /// Path to curl (Default: `curl`)
+    pub cli: Option<PathBuf>,
+    /// Stream each part with a chunked PUT as it is compressed, instead of writing it to output_path first
+    pub stream: Option<bool>,
+}
+
+impl WebdavDestination {
+    /// URL of a file
+    pub fn file_url(&self, name: &str) -> String {
+        format!("{}/{}", self.url.trim_end_matches('/'), name)
+    }
+
+    /// Command that PUTs stdin (Chunked) to the part's URL (None unless streaming)
+    fn stream_command(&self) -> Option<String> {
+        if !self.stream.unwrap_or(false) {
+            return None;
+        }
+        // (Only the netrc file keeps the password out of the logged command)
+        if self.password.is_some() {
+            warn!("WebDAV password can't be passed to streamed uploads, use netrc_file instead");
+        }
+        let mut args = self.common_args(false);
+        args.extend(["--upload-file".to_string(), "-".to_string(), self.file_url(PART_NAME_PLACEHOLDER)]);
+        Some(shell_command(&self.cli(), &args))
+    }
+
+    /// PUT a file, then check its stored size (From a HEAD request)
+    fn upload(&self, path: &Path) -> io::Result<()> {
+        let url = self.file_url(&file_name(path)?);
+        let size = fs::metadata(path)?.len();
+        info!("Uploading {:?} to {}", path, url);
+
+        let mut args = self.common_args(true);
+        args.extend(["--upload-file".to_string(), path.display().to_string(), url.clone()]);
+        run_cli(&self.cli(), &args, self.credentials().as_deref())?;
+
+        let mut args = self.common_args(true);
+        args.extend(["--head".to_string(), url.clone()]);
+        let headers = run_cli(&self.cli(), &args, self.credentials().as_deref())?;
+        let stored = headers.lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, value)| value.trim().parse().ok());
+        check_size(stored, size, &url)
+    }
+
+    /// Arguments for every request (Reading a curl config from stdin if `stdin_config` is set)
+    fn common_args(&self, stdin_config: bool) -> Vec<String> {
+        let mut args = vec!["--fail".to_string(), "--silent".to_string(), "--show-error".to_string()];
+        if let Some(netrc_file) = &self.netrc_file {
+            args.extend(["--netrc-file".to_string(), netrc_file.display().to_string()]);
+        }
+        if stdin_config && self.credentials().is_some() {
+            args.extend(["--config".to_string(), "-".to_string()]);
+        }
+        args
+    }
+
+    /// curl config setting the username and password (If both are set)
+    fn credentials(&self) -> Option<String> {
+        let (username, password) = self.username.as_ref().zip(self.password.as_ref())?;
+        let user = format!("{}:{}", username, password);
+        Some(format!("user = \"{}\"\n", user.replace('\\', "\\\\").replace('"', "\\\"")))
+    }
+
+    fn cli(&self) -> PathBuf {
+        self.cli.clone().unwrap_or_else(|| PathBuf::from("curl"))
+    }
+}
+
+/// A directory on an SFTP server, uploaded to with OpenSSH's sftp
+/// (Authenticating with a key or ssh-agent, since there is no password prompt)
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct SftpDestination {
+    /// Server to connect to (i.e. `backup@nas.local`, or a host from ~/.ssh/config)
+    pub host: String,
+    /// Remote directory to upload into, which must exist
+    pub path: String,
+    pub port: Option<u16>,
+    /// Private key to authenticate with
+    pub identity_file: Option<PathBuf>,
+    /// Upload attempts per file (Overrides the retry policy's)
+    pub attempts: Option<u32>,
+    /// Path to sftp (Default: `sftp`)
+    pub cli: Option<PathBuf>,
+    /// Stream each part to the server as it is compressed (With `ssh`, which must be able to run `cat` there),
+    /// instead of writing it to output_path first
+    pub stream: Option<bool>,
+}
+
+impl SftpDestination {
+    /// Remote path of a file
+    pub fn remote_path(&self, name: &str) -> String {
+        format!("{}/{}", self.path.trim_end_matches('/'), name)
+    }
+
+    /// Command that writes stdin to the part's remote path over ssh (None unless streaming)
+    fn stream_command(&self) -> Option<String> {
+        if !self.stream.unwrap_or(false) {
+            return None;
+        }
+        let mut args = self.connection_args("-p");
+        args.extend([self.host.clone(), format!("cat > {}", shell_quote(&self.remote_path(PART_NAME_PLACEHOLDER)))]);
+        Some(shell_command(Path::new("ssh"), &args))
+    }
+
+    /// Upload a file, then check its stored size (From a long listing, in the same batch)
+    fn upload(&self, path: &Path) -> io::Result<()> {
+        let remote_path = self.remote_path(&file_name(path)?);
+        let size = fs::metadata(path)?.len();
+        info!("Uploading {:?} to {}:{}", path, self.host, remote_path);
+
+        let mut args = vec!["-b".to_string(), "-".to_string()];
+        args.extend(self.connection_args("-P"));
+        args.push(self.host.clone());
+        let batch = format!("put {} {}\nls -ln {}\n", batch_quote(&path.display().to_string()), batch_quote(&remote_path), batch_quote(&remote_path));
+        let listing = run_cli(&self.cli(), &args, Some(&batch))?;
+        // (sftp echoes each batch command, then `ls -ln` prints: mode links uid gid size date name)
+        let stored = listing.lines()
+            .filter(|line| !line.starts_with("sftp>"))
+            .find_map(|line| line.split_whitespace().nth(4)?.parse().ok());
+        check_size(stored, size, &format!("{}:{}", self.host, remote_path))
+    }
+
+    /// Port (Passed with `port_flag`, which differs between ssh and sftp), key and non-interactive arguments
+    fn connection_args(&self, port_flag: &str) -> Vec<String> {
+        let mut args = vec!["-o".to_string(), "BatchMode=yes".to_string()];
+        if let Some(port) = self.port {
+            args.extend([port_flag.to_string(), port.to_string()]);
+        }
+        if let Some(identity_file) = &self.identity_file {
+            args.extend(["-i".to_string(), identity_file.display().to_string()]);
+        }
+        args
+    }
+
+    fn cli(&self) -> PathBuf {
+        self.cli.clone().unwrap_or_else(|| PathBuf::from("sftp"))
+    }
+}
+
+/// Quote a path for an sftp batch file
+fn batch_quote(path: &str) -> String {
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// File name of a path to upload
+fn file_name(path: &Path) -> io::Result<String> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| io::Error::other(format!("Not a file: {:?}", path)))
+}
+
+/// Check an uploaded file's stored size (None if it couldn't be read)
+fn check_size(stored: Option<u64>, size: u64, location: &str) -> io::Result<()> {
+    match stored {
+        Some(stored) if stored == size => {
+            info!("Uploaded {} ({} bytes)", location, size);
+            Ok(())
+        }
+        Some(stored) => Err(io::Error::other(format!("Uploaded file {} is {} bytes, expected {}", location, stored, size))),
+        None => Err(io::Error::other(format!("Failed to read the size of uploaded file {}", location))),
+    }
+}
+
+/// Run a CLI (Writing `input` to its stdin, if set), logging its stderr, and returning its stdout
+pub fn run_cli(cli: &Path, args: &[String], input: Option<&str>) -> io::Result<String> {
+    let mut child = Command::new(cli)
+        .args(args)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::other(format!("Failed to run {:?}: {}", cli, e)))?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    let label = cli.file_name().unwrap_or(cli.as_os_str()).to_string_lossy();
+    for line in String::from_utf8_lossy(&output.stderr).lines().filter(|line| !line.trim().is_empty()) {
+        warn!("{}> {}", label, line);
+    }
+    if !output.status.success() {
+        return Err(io::Error::other(format!("{:?} {} failed: {}", cli, args.first().map_or("", String::as_str), output.status)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// A command line for `sh` (As `part_command`)
+fn shell_command(cli: &Path, args: &[String]) -> String {
+    std::iter::once(cli.display().to_string())
+        .chain(args.iter().cloned())
+        .map(|arg| shell_quote(&arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quote an argument for `sh` (Leaving plain words, and the part name placeholder, as-is)
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=@{}".contains(c)) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/destination_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[cfg(unix)]
+    fn write_script(path: &PathBuf, contents: &str) {
+        use std::os::unix::fs::PermissionsExt;
+        fs::write(path, contents).unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_destination_config() {
+        let destination: Destination = toml::from_str("type = \"s3\"\nbucket = \"my-bucket\"\nprefix = \"backups/\"").unwrap();
+        let Destination::S3(s3) = &destination else {
+            panic!("Expected an S3 destination");
+        };
+        assert_eq!(s3.key("docs.tar.gz.part001"), "backups/docs.tar.gz.part001");
+        assert_eq!(destination.attempts(), None);
+        assert_eq!(destination.stream_command(None), None);
+    }
+
+    #[test]
+    fn test_s3_stream_command() {
+        let destination: Destination = toml::from_str("type = \"s3\"\nbucket = \"my-bucket\"\nprefix = \"my backups/\"\nstream = true\nprofile = \"backup\"").unwrap();
+        assert_eq!(destination.stream_command(Some(1024)).unwrap(),
+            "aws s3 cp - 's3://my-bucket/my backups/{name}' --only-show-errors --checksum-algorithm SHA256 --expected-size 1024 --profile backup");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_s3_upload() {
+        let test_name = "s3_upload";
+        let test_dir = setup_test_dir(test_name);
+        let part = test_dir.join("docs.tar.gz.part001");
+        fs::write(&part, b"data").unwrap();
+
+        // Stands in for the AWS CLI: records its arguments, and reports the object's size
+        let cli = test_dir.join("aws");
+        write_script(&cli, &format!("#!/bin/bash\necho \"$@\" >> \"{}/calls\"\nif [ \"$1\" = \"s3api\" ]; then cat \"{}/size\"; fi\n",
+            test_dir.display(), test_dir.display()));
+        fs::write(test_dir.join("size"), "4\n").unwrap();
+
+        let destination = Destination::S3(S3Destination {
+            bucket: "my-bucket".to_string(),
+            prefix: Some("backups/".to_string()),
+            storage_class: Some("DEEP_ARCHIVE".to_string()),
+            endpoint_url: None,
+            profile: Some("backup".to_string()),
+            attempts: Some(1),
+            cli: Some(cli),
+            stream: None,
+        });
+        destination.upload(&part, &RetryPolicy::default()).unwrap();
+        let calls = fs::read_to_string(test_dir.join("calls")).unwrap();
+        let calls: Vec<&str> = calls.lines().collect();
+        assert_eq!(calls[0], format!("s3 cp {} s3://my-bucket/backups/docs.tar.gz.part001 --only-show-errors --checksum-algorithm SHA256 --storage-class DEEP_ARCHIVE --profile backup", part.display()));
+        assert!(calls[1].starts_with("s3api head-object --bucket my-bucket --key backups/docs.tar.gz.part001 "));
+
+        // A stored object of the wrong size fails the upload
+        fs::write(test_dir.join("size"), "3\n").unwrap();
+        assert!(destination.upload(&part, &RetryPolicy::default()).unwrap_err().to_string().contains("is 3 bytes, expected 4"));
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rclone_destination() {
+        let destination: Destination = toml::from_str("type = \"rclone\"\nremote = \"b2:my-bucket/backups\"\nflags = [\"--fast-list\"]\nstream = true").unwrap();
+        let Destination::Rclone(rclone) = &destination else {
+            panic!("Expected an rclone destination");
+        };
+        assert_eq!(rclone.remote_path("docs.tar.gz"), "b2:my-bucket/backups/docs.tar.gz");
+        assert_eq!(RcloneDestination { remote: "b2:".to_string(), ..rclone.clone() }.remote_path("docs.tar.gz"), "b2:docs.tar.gz");
+        assert_eq!(destination.stream_command(None).unwrap(), "rclone rcat b2:my-bucket/backups/{name} --fast-list");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_rclone_upload() {
+        let test_name = "rclone_upload";
+        let test_dir = setup_test_dir(test_name);
+        let part = test_dir.join("docs.tar.gz.part001");
+        fs::write(&part, b"data").unwrap();
+
+        // Stands in for rclone: records its arguments, and reports the file's size
+        let cli = test_dir.join("rclone");
+        write_script(&cli, &format!("#!/bin/bash\necho \"$@\" >> \"{}/calls\"\nif [ \"$1\" = \"size\" ]; then echo '{{\"count\":1,\"bytes\":4}}'; fi\n",
+            test_dir.display()));
+        let destination = Destination::Rclone(RcloneDestination {
+            remote: "nas:backups".to_string(),
+            flags: vec!["--fast-list".to_string()],
+            attempts: Some(1),
+            cli: Some(cli),
+            stream: None,
+        });
+        destination.upload(&part, &RetryPolicy::default()).unwrap();
+        let calls = fs::read_to_string(test_dir.join("calls")).unwrap();
+        assert_eq!(calls.lines().collect::<Vec<_>>(), [
+            format!("copyto {} nas:backups/docs.tar.gz.part001 --fast-list", part.display()),
+            "size --json nas:backups/docs.tar.gz.part001 --fast-list".to_string(),
+        ]);
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_webdav_upload() {
+        let test_name = "webdav_upload";
+        let test_dir = setup_test_dir(test_name);
+        let part = test_dir.join("docs.tar.gz.part001");
+        fs::write(&part, b"data").unwrap();
+
+        // Stands in for curl: records its arguments and stdin, and answers HEAD requests
+        let cli = test_dir.join("curl");
+        write_script(&cli, &format!("#!/bin/bash\necho \"$@\" >> \"{0}/calls\"\ncat >> \"{0}/stdin\"\nif [ \"$6\" = \"--head\" ]; then printf 'HTTP/1.1 200 OK\\r\\nContent-Length: 4\\r\\n'; fi\n",
+            test_dir.display()));
+        let destination: Destination = toml::from_str(&format!("type = \"webdav\"\nurl = \"https://cloud.example.com/dav/Backups/\"\nusername = \"user\"\npassword = \"p\\\"w\"\nattempts = 1\ncli = {:?}", cli)).unwrap();
+        destination.upload(&part, &RetryPolicy::default()).unwrap();
+        let calls = fs::read_to_string(test_dir.join("calls")).unwrap();
+        assert_eq!(calls.lines().collect::<Vec<_>>(), [
+            format!("--fail --silent --show-error --config - --upload-file {} https://cloud.example.com/dav/Backups/docs.tar.gz.part001", part.display()),
+            "--fail --silent --show-error --config - --head https://cloud.example.com/dav/Backups/docs.tar.gz.part001".to_string(),
+        ]);
+        // (The password is only passed on stdin)
+        assert_eq!(fs::read_to_string(test_dir.join("stdin")).unwrap(), "user = \"user:p\\\"w\"\n".repeat(2));
+
+        let Destination::Webdav(webdav) = destination else {
+            panic!("Expected a WebDAV destination");
+        };
+        let streaming = WebdavDestination { password: None, netrc_file: Some(PathBuf::from("/etc/netrc")), stream: Some(true), cli: None, ..webdav };
+        assert_eq!(streaming.stream_command().unwrap(),
+            "curl --fail --silent --show-error --netrc-file /etc/netrc --upload-file - https://cloud.example.com/dav/Backups/{name}");
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_sftp_upload() {
+        let test_name = "sftp_upload";
+        let test_dir = setup_test_dir(test_name);
+        let part = test_dir.join("docs.tar.gz.part001");
+        fs::write(&part, b"data").unwrap();
+
+        // Stands in for sftp: records its arguments and batch, then echoes the batch and lists the file
+        let cli = test_dir.join("sftp");
+        write_script(&cli, &format!("#!/bin/bash\necho \"$@\" >> \"{0}/calls\"\ncat > \"{0}/batch\"\nsed 's/^/sftp> /' \"{0}/batch\"\necho '-rw-r--r--    1 1000     1000            4 Jan  1 00:00 /backups/docs.tar.gz.part001'\n",
+            test_dir.display()));
+        let destination: Destination = toml::from_str(&format!("type = \"sftp\"\nhost = \"backup@nas\"\npath = \"/backups/\"\nport = 2222\nattempts = 1\ncli = {:?}", cli)).unwrap();
+        destination.upload(&part, &RetryPolicy::default()).unwrap();
+        assert_eq!(fs::read_to_string(test_dir.join("calls")).unwrap(), "-b - -o BatchMode=yes -P 2222 backup@nas\n");
+        assert_eq!(fs::read_to_string(test_dir.join("batch")).unwrap(),
+            format!("put \"{}\" \"/backups/docs.tar.gz.part001\"\nls -ln \"/backups/docs.tar.gz.part001\"\n", part.display()));
+
+        let Destination::Sftp(sftp) = destination else {
+            panic!("Expected an SFTP destination");
+        };
+        let streaming = SftpDestination { path: "/my backups".to_string(), stream: Some(true), ..sftp };
+        assert_eq!(streaming.stream_command().unwrap(),
+            "ssh -o BatchMode=yes -p 2222 backup@nas 'cat > '\\''/my backups/{name}'\\'''");
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_upload_all() {
+        let test_name = "upload_all";
+        let test_dir = setup_test_dir(test_name);
+        let part = test_dir.join("docs.tar.gz.part001");
+        fs::write(&part, b"data").unwrap();
+
+        // Stands in for rclone: uploads to the "down:" remote fail
+        let cli = test_dir.join("rclone");
+        write_script(&cli, "#!/bin/bash\ncase \"$3\" in down:*) exit 1;; esac\nif [ \"$1\" = \"size\" ]; then echo '{\"bytes\":4}'; fi\n");
+        let config = format!("destination = [\n  {{ type = \"rclone\", remote = \"nas:backups\", attempts = 1, cli = {0:?} }},\n  {{ type = \"rclone\", remote = \"down:backups\", attempts = 1, cli = {0:?} }},\n]", cli);
+        let destinations = toml::from_str::<toml::Table>(&config).unwrap()["destination"].clone()
+            .try_into::<Destinations>().unwrap()
+            .into_vec();
+        assert_eq!(destinations.len(), 2);
+
+        // Every destination is attempted, and the failure is reported
+        let tally = UploadTally::default();
+        upload_all(&destinations[..1], &part, &tally, &RetryPolicy::default()).unwrap();
+        let error = upload_all(&destinations, &part, &tally, &RetryPolicy::default()).unwrap_err().to_string();
+        assert!(error.starts_with("down:backups: "), "Unexpected error: {}", error);
+        assert_eq!(tally.take(), [
+            UploadReport { destination: "nas:backups".to_string(), uploaded: 2, failed: 0 },
+            UploadReport { destination: "down:backups".to_string(), uploaded: 0, failed: 1 },
+        ]);
+        assert!(tally.take().is_empty());
+
+        cleanup_test_dir(test_name);
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::{Context, Result, anyhow};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::fs;
+use globset::GlobSet;
+use crate::archive_info::{INFO_FILE, LEGACY_PATH_FILE, MANIFEST_ENTRY};
+use crate::archive_reader::open_archive;
+use crate::hash_cache::file_stamp;
+use crate::helpers::collect_filtered_entries;
+use crate::manifest::ManifestChanges;
+
+/// Size and modification time (In seconds) of each file by relative path (None for symlinks, whose size isn't comparable)
+pub type FileStamps = BTreeMap<String, Option<(u64, u64)>>;
+
+/// Stamps of the files and symlinks in an archive (Without extracting it)
+pub fn archive_stamps(parts: &[PathBuf]) -> Result<FileStamps> {
+    let mut tar = open_archive(parts)?;
+    let mut stamps = FileStamps::new();
+    for entry in tar.entries().context("Failed to read archive")? {
+        let entry = entry.context("Failed to read archive entry")?;
+        let path = entry.path().context("Failed to read archive entry path")?.to_string_lossy().to_string();
+        let header = entry.header();
+        let entry_type = header.entry_type();
+        if path == INFO_FILE || path == LEGACY_PATH_FILE || path == MANIFEST_ENTRY {
+            continue;
+        }
+        if entry_type.is_file() {
+            stamps.insert(path, Some((header.size()?, header.mtime()?)));
+        } else if entry_type.is_symlink() {
+            stamps.insert(path, None);
+        }
+    }
+    Ok(stamps)
+}
+
+/// Stamps of the files and symlinks a segment would archive now
+pub fn source_stamps(
+    src_dir: &Path,
+    metadata: &fs::Metadata,
+    exclusions: &[&PathBuf],
+    ignore_patterns: Option<&GlobSet>,
+) -> Result<FileStamps> {
+    if metadata.is_file() {
+        let name = src_dir.file_name().ok_or_else(|| anyhow!("Failed to get filename from path: {:?}", src_dir))?;
+        return Ok(FileStamps::from([(name.to_string_lossy().to_string(), Some(stamp(metadata)))]));
+    }
+    let mut stamps = FileStamps::new();
+    for entry in collect_filtered_entries(src_dir, exclusions, ignore_patterns) {
+        let file_type = entry.file_type();
+        let Ok(relative_path) = entry.path().strip_prefix(src_dir) else {
+            continue;
+        };
+        let relative_path = relative_path.to_string_lossy().to_string();
+        if file_type.is_symlink() {
+            stamps.insert(relative_path, None);
+        } else if file_type.is_file() {
+            let metadata = entry.metadata()
+                .context(format!("Failed to read metadata: {:?}", entry.path()))?;
+            stamps.insert(relative_path, Some(stamp(&metadata)));
+        }
+    }
+    Ok(stamps)
+}
+
+/// Files added, removed, or with a different size or modification time in `current`
+pub fn diff_stamps(previous: &FileStamps, current: &FileStamps) -> ManifestChanges {
+    let mut changes = ManifestChanges::default();
+    for (path, stamp) in current {
+        match previous.get(path) {
+            None => changes.added.push(path.clone()),
+            Some(previous_stamp) if previous_stamp != stamp => changes.modified.push(path.clone()),
+            Some(_) => {}
+        }
+    }
+    changes.removed = previous.keys()
+        .filter(|path| !current.contains_key(*path))
+        .cloned()
+        .collect();
+    changes
+}
+
+fn stamp(metadata: &fs::Metadata) -> (u64, u64) {
+    file_stamp(metadata).map_or((metadata.len(), 0), |(size, secs, _)| (size, secs))
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive_info::ArchiveInfo;
+    use crate::helpers::{ArchiveOptions, create_archive};
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/diff_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[test]
+    fn test_diff_archive_against_source() {
+        let test_name = "source";
+        let test_dir = setup_test_dir(test_name);
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(src_dir.join("sub")).unwrap();
+        fs::write(src_dir.join("same.txt"), b"same").unwrap();
+        fs::write(src_dir.join("changed.txt"), b"before").unwrap();
+        fs::write(src_dir.join("sub/removed.txt"), b"removed").unwrap();
+
+        let archive_path = test_dir.join("docs.tar.gz");
+        let info = ArchiveInfo::new("docs", &src_dir, &None, None).unwrap();
+        let metadata = fs::metadata(&src_dir).unwrap();
+        create_archive(&src_dir, &metadata, &archive_path, &[], &info, &ArchiveOptions::default(), None, None).unwrap();
+        let archived = archive_stamps(&[archive_path]).unwrap();
+        assert_eq!(archived, source_stamps(&src_dir, &metadata, &[], None).unwrap());
+
+        fs::write(src_dir.join("changed.txt"), b"after!!").unwrap();
+        fs::remove_file(src_dir.join("sub/removed.txt")).unwrap();
+        fs::write(src_dir.join("added.txt"), b"added").unwrap();
+        let changes = diff_stamps(&archived, &source_stamps(&src_dir, &metadata, &[], None).unwrap());
+        assert_eq!(changes, ManifestChanges {
+            added: vec!["added.txt".to_string()],
+            removed: vec!["sub/removed.txt".to_string()],
+            modified: vec!["changed.txt".to_string()],
+        });
+
+        cleanup_test_dir(test_name);
+    }
+}
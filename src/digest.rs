@@ -0,0 +1,494 @@
+use xxhash_rust::xxh3::Xxh3;
+
+/// Algorithms available for checksumming archive output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestAlgorithm {
+    /// Fast, non-cryptographic (64-bit)
+    Xxh3,
+    /// Cryptographic (256-bit), slower
+    Sha256,
+}
+
+/// A running checksum over streamed data
+pub enum Digest {
+    Xxh3(Box<Xxh3>),
+    Sha256(Box<Sha256>),
+}
+
+impl Digest {
+    pub fn new(algorithm: DigestAlgorithm) -> Self {
+        match algorithm {
+            DigestAlgorithm::Xxh3 => Digest::Xxh3(Box::new(Xxh3::new())),
+            DigestAlgorithm::Sha256 => Digest::Sha256(Box::default()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Digest::Xxh3(hasher) => hasher.update(data),
+            Digest::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Hex digest of all data so far, resetting the checksum for reuse
+    pub fn finish_hex(&mut self) -> String {
+        match self {
+            Digest::Xxh3(hasher) => {
+                let hex = format!("{:016x}", hasher.digest());
+                hasher.reset();
+                hex
+            }
+            Digest::Sha256(hasher) => {
+                let hex = to_hex(&hasher.finish());
+                **hasher = Sha256::new();
+                hex
+            }
+        }
+    }
+}
+
+/// Lowercase hex encoding
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decode lowercase or uppercase hex (None if invalid)
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// --- SHA-256 (FIPS 180-4) --- //
+
+const SHA256_INIT: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Streaming SHA-256 hasher
+pub struct Sha256 {
+    state: [u32; 8],
+    buffer: [u8; 64],
+    buffer_len: usize,
+    total_len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self {
+            state: SHA256_INIT,
+            buffer: [0; 64],
+            buffer_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+
+        // Top up a partially filled block
+        if self.buffer_len > 0 {
+            let take = (64 - self.buffer_len).min(data.len());
+            self.buffer[self.buffer_len..self.buffer_len + take].copy_from_slice(&data[..take]);
+            self.buffer_len += take;
+            data = &data[take..];
+            if self.buffer_len < 64 {
+                return;
+            }
+            let block = self.buffer;
+            self.compress(&block);
+            self.buffer_len = 0;
+        }
+
+        // Process whole blocks directly from the input
+        let mut chunks = data.chunks_exact(64);
+        for block in &mut chunks {
+            self.compress(block.try_into().unwrap());
+        }
+        let rest = chunks.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffer_len = rest.len();
+    }
+
+    /// Pad the message and return the 32-byte digest
+    pub fn finish(&mut self) -> [u8; 32] {
+        let bit_len = self.total_len.wrapping_mul(8);
+
+        let mut padding = vec![0x80u8];
+        let padded_len = (self.buffer_len + 1) % 64;
+        let zeroes = if padded_len <= 56 { 56 - padded_len } else { 120 - padded_len };
+        padding.resize(1 + zeroes, 0);
+        padding.extend_from_slice(&bit_len.to_be_bytes());
+
+        // (total_len also counts the padding, but bit_len was captured beforehand)
+        self.update(&padding);
+
+        let mut digest = [0u8; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(chunk.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let temp1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+// --- BLAKE3 (Hash mode, 256-bit output) --- //
+
+const BLAKE3_IV: [u32; 8] = SHA256_INIT;
+const BLAKE3_MSG_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+const BLAKE3_BLOCK_LEN: usize = 64;
+const BLAKE3_CHUNK_LEN: usize = 1024;
+const CHUNK_START: u32 = 1 << 0;
+const CHUNK_END: u32 = 1 << 1;
+const PARENT: u32 = 1 << 2;
+const ROOT: u32 = 1 << 3;
+
+fn blake3_g(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, mx: u32, my: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(mx);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(my);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+fn blake3_round(state: &mut [u32; 16], m: &[u32; 16]) {
+    // Columns, then diagonals
+    blake3_g(state, 0, 4, 8, 12, m[0], m[1]);
+    blake3_g(state, 1, 5, 9, 13, m[2], m[3]);
+    blake3_g(state, 2, 6, 10, 14, m[4], m[5]);
+    blake3_g(state, 3, 7, 11, 15, m[6], m[7]);
+    blake3_g(state, 0, 5, 10, 15, m[8], m[9]);
+    blake3_g(state, 1, 6, 11, 12, m[10], m[11]);
+    blake3_g(state, 2, 7, 8, 13, m[12], m[13]);
+    blake3_g(state, 3, 4, 9, 14, m[14], m[15]);
+}
+
+/// Compress one block, returning the first 8 words of the output (All that hash mode needs)
+fn blake3_compress(chaining_value: &[u32; 8], block: &[u32; 16], counter: u64, block_len: u32, flags: u32) -> [u32; 8] {
+    let mut state = [
+        chaining_value[0], chaining_value[1], chaining_value[2], chaining_value[3],
+        chaining_value[4], chaining_value[5], chaining_value[6], chaining_value[7],
+        BLAKE3_IV[0], BLAKE3_IV[1], BLAKE3_IV[2], BLAKE3_IV[3],
+        counter as u32, (counter >> 32) as u32, block_len, flags,
+    ];
+    let mut m = *block;
+    for round in 0..7 {
+        blake3_round(&mut state, &m);
+        if round < 6 {
+            m = BLAKE3_MSG_PERMUTATION.map(|i| m[i]);
+        }
+    }
+    let mut out = [0u32; 8];
+    for (i, word) in out.iter_mut().enumerate() {
+        *word = state[i] ^ state[i + 8];
+    }
+    out
+}
+
+fn blake3_words(block: &[u8; BLAKE3_BLOCK_LEN]) -> [u32; 16] {
+    let mut words = [0u32; 16];
+    for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_le_bytes(bytes.try_into().unwrap());
+    }
+    words
+}
+
+/// Inputs to the final compression of a node (Deferred so the root can be flagged)
+struct Blake3Output {
+    chaining_value: [u32; 8],
+    block: [u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+}
+
+impl Blake3Output {
+    fn chaining_value(&self) -> [u32; 8] {
+        blake3_compress(&self.chaining_value, &self.block, self.counter, self.block_len, self.flags)
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        let words = blake3_compress(&self.chaining_value, &self.block, 0, self.block_len, self.flags | ROOT);
+        let mut hash = [0u8; 32];
+        for (bytes, word) in hash.chunks_exact_mut(4).zip(words) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        hash
+    }
+}
+
+fn blake3_parent_output(left: &[u32; 8], right: &[u32; 8]) -> Blake3Output {
+    let mut block = [0u32; 16];
+    block[..8].copy_from_slice(left);
+    block[8..].copy_from_slice(right);
+    Blake3Output { chaining_value: BLAKE3_IV, block, counter: 0, block_len: BLAKE3_BLOCK_LEN as u32, flags: PARENT }
+}
+
+/// State of the 1KB chunk currently being hashed
+struct Blake3Chunk {
+    chaining_value: [u32; 8],
+    counter: u64,
+    block: [u8; BLAKE3_BLOCK_LEN],
+    block_len: usize,
+    blocks_compressed: u32,
+}
+
+impl Blake3Chunk {
+    fn new(counter: u64) -> Self {
+        Self { chaining_value: BLAKE3_IV, counter, block: [0; BLAKE3_BLOCK_LEN], block_len: 0, blocks_compressed: 0 }
+    }
+
+    fn len(&self) -> usize {
+        BLAKE3_BLOCK_LEN * self.blocks_compressed as usize + self.block_len
+    }
+
+    fn start_flag(&self) -> u32 {
+        if self.blocks_compressed == 0 { CHUNK_START } else { 0 }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // Only compress a full block once more input arrives (The last block is compressed by output)
+            if self.block_len == BLAKE3_BLOCK_LEN {
+                self.chaining_value = blake3_compress(
+                    &self.chaining_value, &blake3_words(&self.block), self.counter,
+                    BLAKE3_BLOCK_LEN as u32, self.start_flag(),
+                );
+                self.blocks_compressed += 1;
+                self.block = [0; BLAKE3_BLOCK_LEN];
+                self.block_len = 0;
+            }
+            let take = (BLAKE3_BLOCK_LEN - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+        }
+    }
+
+    fn output(&self) -> Blake3Output {
+        Blake3Output {
+            chaining_value: self.chaining_value,
+            block: blake3_words(&self.block),
+            counter: self.counter,
+            block_len: self.block_len as u32,
+            flags: self.start_flag() | CHUNK_END,
+        }
+    }
+}
+
+/// Streaming BLAKE3 hasher
+///
+/// Chunks are compressed one at a time on the calling thread (No SIMD or parallel tree hashing),
+/// so a single large file hashes at serial speed. Segment hashing gets its parallelism from hashing files concurrently.
+pub struct Blake3 {
+    chunk: Blake3Chunk,
+    /// Chaining values of completed subtrees (At most one per level)
+    stack: Vec<[u32; 8]>,
+}
+
+impl Default for Blake3 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Blake3 {
+    pub fn new() -> Self {
+        Self { chunk: Blake3Chunk::new(0), stack: Vec::new() }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // Only finish a full chunk once more input arrives (The last chunk becomes the root)
+            if self.chunk.len() == BLAKE3_CHUNK_LEN {
+                let chunk_cv = self.chunk.output().chaining_value();
+                let total_chunks = self.chunk.counter + 1;
+                self.push_chunk(chunk_cv, total_chunks);
+                self.chunk = Blake3Chunk::new(total_chunks);
+            }
+            let take = (BLAKE3_CHUNK_LEN - self.chunk.len()).min(data.len());
+            self.chunk.update(&data[..take]);
+            data = &data[take..];
+        }
+    }
+
+    /// Return the 32-byte digest
+    pub fn finish(&self) -> [u8; 32] {
+        let mut output = self.chunk.output();
+        for left in self.stack.iter().rev() {
+            output = blake3_parent_output(left, &output.chaining_value());
+        }
+        output.root_hash()
+    }
+
+    /// Merge completed subtrees (One per trailing zero bit of the chunk count)
+    fn push_chunk(&mut self, mut chaining_value: [u32; 8], mut total_chunks: u64) {
+        while total_chunks & 1 == 0 {
+            let left = self.stack.pop().unwrap();
+            chaining_value = blake3_parent_output(&left, &chaining_value).chaining_value();
+            total_chunks >>= 1;
+        }
+        self.stack.push(chaining_value);
+    }
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blake3_hex(data: &[u8]) -> String {
+        let mut hasher = Blake3::new();
+        hasher.update(data);
+        to_hex(&hasher.finish())
+    }
+
+    fn sha256_hex(data: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        to_hex(&hasher.finish())
+    }
+
+    #[test]
+    fn test_sha256_known_vectors() {
+        assert_eq!(sha256_hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            sha256_hex(&vec![b'a'; 1_000_000]),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+        assert_eq!(
+            sha256_hex(b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu"),
+            "cf5b16a778af8380036ce59e7b0492370b249b11e8f07a51afac45037afee9d1"
+        );
+    }
+
+    #[test]
+    fn test_sha256_large_input() {
+        // 1600 blocks of varying data (Digest from a reference implementation)
+        let data: Vec<u8> = (0..102400u32).map(|i| (i % 251) as u8).collect();
+        assert_eq!(sha256_hex(&data), "74588b7f0bcc354ac14d9cf199fa3a20c05f0c7293b9075b2f2e146e718de800");
+    }
+
+    #[test]
+    fn test_sha256_streaming_matches_single_update() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let mut hasher = Sha256::new();
+        for chunk in data.chunks(37) {
+            hasher.update(chunk);
+        }
+        assert_eq!(to_hex(&hasher.finish()), sha256_hex(&data));
+    }
+
+    #[test]
+    fn test_blake3_known_vectors() {
+        // (Official test vectors use the repeating input 0, 1, ..., 250, 0, 1, ...)
+        let input = |len: usize| -> Vec<u8> { (0..len).map(|i| (i % 251) as u8).collect() };
+        assert_eq!(blake3_hex(b""), "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+        assert_eq!(blake3_hex(b"abc"), "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+        assert_eq!(blake3_hex(&input(1)), "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213");
+        assert_eq!(blake3_hex(&input(1024)), "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7");
+        assert_eq!(blake3_hex(&input(1025)), "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444");
+        // (Multi-chunk inputs: whole and partial trees, then 100 chunks)
+        assert_eq!(blake3_hex(&input(2048)), "e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a");
+        assert_eq!(blake3_hex(&input(3072)), "b98cb0ff3623be03326b373de6b9095218513e64f1ee2edd2525c7ad1e5cffd2");
+        assert_eq!(blake3_hex(&input(8193)), "bab6c09cb8ce8cf459261398d2e7aef35700bf488116ceb94a36d0f5f1b7bc3b");
+        assert_eq!(blake3_hex(&input(31744)), "62b6960e1a44bcc1eb1a611a8d6235b6b4b78f32e7abc4fb4c6cdcce94895c47");
+        assert_eq!(blake3_hex(&input(102400)), "bc3e3d41a1146b069abffad3c0d44860cf664390afce4d9661f7902e7943e085");
+    }
+
+    #[test]
+    fn test_blake3_streaming_matches_single_update() {
+        let data: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+        let mut hasher = Blake3::new();
+        for chunk in data.chunks(333) {
+            hasher.update(chunk);
+        }
+        assert_eq!(to_hex(&hasher.finish()), blake3_hex(&data));
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        let bytes = [0x00, 0x7f, 0xab, 0xff];
+        assert_eq!(to_hex(&bytes), "007fabff");
+        assert_eq!(from_hex("007fabff").unwrap(), bytes);
+        assert_eq!(from_hex("007FABFF").unwrap(), bytes);
+        assert!(from_hex("abc").is_none());
+        assert!(from_hex("zz").is_none());
+    }
+
+    #[test]
+    fn test_digest_finish_resets() {
+        for algorithm in [DigestAlgorithm::Xxh3, DigestAlgorithm::Sha256] {
+            let mut digest = Digest::new(algorithm);
+            digest.update(b"part one");
+            let first = digest.finish_hex();
+            digest.update(b"part one");
+            assert_eq!(digest.finish_hex(), first, "{:?} should reset after finish", algorithm);
+        }
+    }
+}
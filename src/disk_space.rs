@@ -0,0 +1,47 @@
+use std::io;
+use std::path::Path;
+
+/// Bytes available to unprivileged users on the filesystem containing `path`
+/// (None if this platform does not support the check)
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> io::Result<Option<u64>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Field types vary by platform
+    #[allow(clippy::unnecessary_cast)]
+    let available = stat.f_bavail as u64 * stat.f_frsize as u64;
+    Ok(Some(available))
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_path: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_available_space() {
+        let available = available_space(Path::new("/tmp")).unwrap();
+        assert!(available.is_some_and(|bytes| bytes > 0));
+    }
+
+    #[test]
+    fn test_available_space_missing_path() {
+        let result = available_space(Path::new("/tmp/disk_space_test_missing/nested"));
+        assert!(result.is_err() || result.unwrap().is_none());
+    }
+}
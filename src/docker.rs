@@ -0,0 +1,232 @@
+use std::io;
+use std::path::PathBuf;
+use log::{error, info, warn};
+
+/// Docker Engine API socket used when none is set
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+/// Containers to quiesce while a segment is archived, so their files aren't changing underneath it
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DockerQuiesce {
+    /// Containers to stop, then start again afterwards
+    #[serde(default)]
+    pub stop: Vec<String>,
+    /// Containers to pause, then unpause afterwards (Quicker, but writes the container hasn't flushed aren't archived)
+    #[serde(default)]
+    pub pause: Vec<String>,
+    /// Seconds a container has to stop before Docker kills it (Docker's default if unset)
+    pub stop_timeout_secs: Option<u32>,
+    /// Path to the Docker Engine API socket (Default: /var/run/docker.sock)
+    pub socket: Option<PathBuf>,
+}
+
+/// Containers that were quiesced, which are started or unpaused again when resumed or dropped
+/// (Only containers that were running beforehand are resumed)
+pub struct Quiesced {
+    socket: PathBuf,
+    stopped: Vec<String>,
+    paused: Vec<String>,
+}
+
+/// Pause, then stop, the configured containers
+///
+/// # Errors
+/// Fails if any container can't be quiesced, after resuming the ones that were
+pub fn quiesce(config: &DockerQuiesce) -> io::Result<Quiesced> {
+    let mut quiesced = Quiesced {
+        socket: config.socket.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET)),
+        stopped: Vec::new(),
+        paused: Vec::new(),
+    };
+    for name in &config.pause {
+        match quiesced.post(&format!("/containers/{}/pause", encode_name(name)))? {
+            (204, _) => {
+                info!("Paused Docker container: {}", name);
+                quiesced.paused.push(name.clone());
+            }
+            (409, message) => warn!("Docker container {} was not paused: {}", name, message),
+            (status, message) => return Err(api_error("pause", name, status, &message)),
+        }
+    }
+    for name in &config.stop {
+        let query = config.stop_timeout_secs.map(|secs| format!("?t={}", secs)).unwrap_or_default();
+        match quiesced.post(&format!("/containers/{}/stop{}", encode_name(name), query))? {
+            (204, _) => {
+                info!("Stopped Docker container: {}", name);
+                quiesced.stopped.push(name.clone());
+            }
+            (304, _) => info!("Docker container {} was already stopped", name),
+            (status, message) => return Err(api_error("stop", name, status, &message)),
+        }
+    }
+    Ok(quiesced)
+}
+
+impl Quiesced {
+    /// Start the stopped containers, then unpause the paused ones
+    ///
+    /// # Errors
+    /// Tries every container, then fails if any couldn't be resumed
+    pub fn resume(mut self) -> io::Result<()> {
+        self.resume_all()
+    }
+
+    fn resume_all(&mut self) -> io::Result<()> {
+        let mut errors = Vec::new();
+        let stopped: Vec<_> = self.stopped.drain(..).map(|name| ("start", name)).collect();
+        let paused: Vec<_> = self.paused.drain(..).map(|name| ("unpause", name)).collect();
+        for (action, name) in stopped.into_iter().chain(paused) {
+            match self.post(&format!("/containers/{}/{}", encode_name(&name), action)) {
+                Ok((204 | 304, _)) => info!("Resumed Docker container: {}", name),
+                Ok((status, message)) => errors.push(api_error(action, &name, status, &message).to_string()),
+                Err(e) => errors.push(format!("Failed to {} Docker container {}: {}", action, name, e)),
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(io::Error::other(errors.join("; "))),
+        }
+    }
+
+    /// POST to the Docker Engine API, returning the status code and error message (If any)
+    fn post(&self, endpoint: &str) -> io::Result<(u16, String)> {
+        let response = request(&self.socket, endpoint)?;
+        let status = response.split_whitespace().nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| io::Error::other(format!("Invalid response from Docker: {:?}", response.lines().next())))?;
+        Ok((status, error_message(&response)))
+    }
+}
+
+impl Drop for Quiesced {
+    fn drop(&mut self) {
+        if let Err(e) = self.resume_all() {
+            error!("Failed to resume Docker containers: {}", e);
+        }
+    }
+}
+
+/// Percent-encode a container name for a request path (Leaving only unreserved characters as they are)
+fn encode_name(name: &str) -> String {
+    name.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+        _ => format!("%{:02X}", byte),
+    }).collect()
+}
+
+fn api_error(action: &str, name: &str, status: u16, message: &str) -> io::Error {
+    io::Error::other(format!("Failed to {} Docker container {} (HTTP {}): {}", action, name, status, message))
+}
+
+/// The `message` of a Docker API error body, or the whole body if it isn't one
+fn error_message(response: &str) -> String {
+    let body = response.split_once("\r\n\r\n").map(|(_, body)| body).unwrap_or_default();
+    let json = match (body.find('{'), body.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &body[start..=end],
+        _ => body,
+    };
+    serde_json::from_str::<serde_json::Value>(json).ok()
+        .and_then(|value| value["message"].as_str().map(str::to_string))
+        .unwrap_or_else(|| body.trim().to_string())
+}
+
+/// Send a bodiless POST over the socket and read the whole response
+#[cfg(unix)]
+fn request(socket: &std::path::Path, endpoint: &str) -> io::Result<String> {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = UnixStream::connect(socket)
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to connect to Docker at {:?}: {}", socket, e)))?;
+    write!(stream, "POST {} HTTP/1.1\r\nHost: docker\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", endpoint)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response)
+}
+
+#[cfg(not(unix))]
+fn request(_socket: &std::path::Path, _endpoint: &str) -> io::Result<String> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Docker quiescing is only supported on Unix"))
+}
+
+// --- Tests --- //
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Fake Docker socket that answers every request with the status for its endpoint, and records the endpoints
+    fn fake_docker(socket: &str, statuses: &'static [(&'static str, u16)]) -> Arc<Mutex<Vec<String>>> {
+        let _ = std::fs::remove_file(socket);
+        let listener = UnixListener::bind(socket).unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut line = String::new();
+                BufReader::new(&stream).read_line(&mut line).unwrap();
+                let endpoint = line.split_whitespace().nth(1).unwrap_or_default().to_string();
+                let status = statuses.iter()
+                    .find(|(suffix, _)| endpoint.ends_with(suffix))
+                    .map_or(204, |(_, status)| *status);
+                let body = match status {
+                    204 | 304 => String::new(),
+                    _ => "{\"message\":\"No such container\"}".to_string(),
+                };
+                write!(stream, "HTTP/1.1 {} Status\r\nContent-Length: {}\r\n\r\n{}", status, body.len(), body).unwrap();
+                recorded.lock().unwrap().push(endpoint);
+            }
+        });
+        requests
+    }
+
+    #[test]
+    fn test_quiesce() {
+        let socket = "/tmp/docker_test_quiesce.sock";
+        let requests = fake_docker(socket, &[("/idle/stop?t=10", 304)]);
+        let config = DockerQuiesce {
+            stop: vec!["db".to_string(), "idle".to_string()],
+            pause: vec!["web".to_string()],
+            stop_timeout_secs: Some(10),
+            socket: Some(PathBuf::from(socket)),
+        };
+        quiesce(&config).unwrap().resume().unwrap();
+        assert_eq!(encode_name("db/../../images?all=1 #"), "db%2F..%2F..%2Fimages%3Fall%3D1%20%23");
+        assert_eq!(*requests.lock().unwrap(), [
+            "/containers/web/pause",
+            "/containers/db/stop?t=10",
+            "/containers/idle/stop?t=10",
+            "/containers/db/start",
+            "/containers/web/unpause",
+        ], "Containers that were already stopped aren't started");
+        let _ = std::fs::remove_file(socket);
+    }
+
+    #[test]
+    fn test_quiesce_failure() {
+        let socket = "/tmp/docker_test_quiesce_failure.sock";
+        let requests = fake_docker(socket, &[("/missing/stop", 404)]);
+        let config = DockerQuiesce {
+            stop: vec!["db".to_string(), "missing".to_string()],
+            socket: Some(PathBuf::from(socket)),
+            ..DockerQuiesce::default()
+        };
+        let error = quiesce(&config).err().unwrap().to_string();
+        assert_eq!(error, "Failed to stop Docker container missing (HTTP 404): No such container");
+        assert_eq!(*requests.lock().unwrap(), [
+            "/containers/db/stop",
+            "/containers/missing/stop",
+            "/containers/db/start",
+        ], "Containers stopped before the failure are started again");
+
+        let config = DockerQuiesce { socket: Some(PathBuf::from("/tmp/docker_test_missing.sock")), ..config };
+        assert!(quiesce(&config).err().unwrap().to_string().contains("Failed to connect to Docker"));
+        let _ = std::fs::remove_file(socket);
+    }
+}
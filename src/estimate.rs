@@ -0,0 +1,152 @@
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::fs;
+use globset::GlobSet;
+use crate::helpers::collect_filtered_entries;
+use crate::sidecar::SegmentMetadata;
+
+// Size of a tar header block (Added per entry when no previous ratio is known)
+const TAR_BLOCK_SIZE: u64 = 512;
+
+/// Forecast of an archive's size, made before archiving
+#[derive(Debug)]
+pub struct SizeEstimate {
+    pub file_count: usize,
+    pub uncompressed_bytes: u64,
+    pub compressed_bytes: u64,
+    pub parts: u64,
+    /// Compression ratio learned from the previous run (None assumes no compression)
+    pub ratio: Option<f64>,
+}
+
+/// Estimate the compressed size and part count of a segment from a walk of its files
+pub fn estimate_size(
+    src_dir: &Path,
+    metadata: &fs::Metadata,
+    exclusions: &[&PathBuf],
+    ignore_patterns: Option<&GlobSet>,
+    previous: Option<&SegmentMetadata>,
+    max_size: Option<usize>,
+) -> Result<SizeEstimate> {
+    let (file_count, uncompressed_bytes) = if metadata.is_file() {
+        (1, metadata.len())
+    } else if metadata.is_dir() {
+        collect_filtered_entries(src_dir, exclusions, ignore_patterns)
+            .into_iter()
+            .filter(|entry| entry.file_type().is_file())
+            .fold((0, 0), |(count, bytes), entry| {
+                (count + 1, bytes + entry.metadata().map(|m| m.len()).unwrap_or(0))
+            })
+    } else {
+        return Err(anyhow!("Path is neither a file nor a directory: {:?}", src_dir));
+    };
+
+    let ratio = previous.and_then(previous_ratio);
+    let compressed_bytes = match ratio {
+        Some(ratio) => (uncompressed_bytes as f64 * ratio).ceil() as u64,
+        // Worst case: incompressible data plus a tar header per entry (And the info file)
+        None => uncompressed_bytes + TAR_BLOCK_SIZE * (file_count as u64 + 1),
+    };
+    let parts = match max_size {
+        Some(max_size) => compressed_bytes.div_ceil(max_size as u64).max(1),
+        None => 1,
+    };
+
+    Ok(SizeEstimate { file_count, uncompressed_bytes, compressed_bytes, parts, ratio })
+}
+
+/// Compressed/uncompressed ratio of a previous archive
+fn previous_ratio(previous: &SegmentMetadata) -> Option<f64> {
+    let compressed: u64 = previous.parts.iter().map(|part| part.size).sum();
+    if previous.uncompressed_bytes == 0 || compressed == 0 {
+        return None;
+    }
+    Some(compressed as f64 / previous.uncompressed_bytes as f64)
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rolling_writer::PartInfo;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/estimate_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    fn previous_run(uncompressed_bytes: u64, part_sizes: &[u64]) -> SegmentMetadata {
+        SegmentMetadata {
+            segment: "test".to_string(),
+            source_path: PathBuf::from("/tmp"),
+            hash: None,
+            created: "2024-01-01T00:00:00+00:00".to_string(),
+            file_count: 1,
+            uncompressed_bytes,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            checksum_algorithm: None,
+            parts: part_sizes.iter().map(|&size| PartInfo {
+                name: "test.tar.gz".to_string(),
+                path: PathBuf::new(),
+                size,
+                checksum: None,
+            }).collect(),
+        }
+    }
+
+    #[test]
+    fn test_estimate_size_without_previous_run() {
+        let test_name = "no_previous";
+        let test_dir = setup_test_dir(test_name);
+        fs::write(test_dir.join("a.bin"), vec![0u8; 3000]).unwrap();
+        fs::create_dir(test_dir.join("sub")).unwrap();
+        fs::write(test_dir.join("sub/b.bin"), vec![0u8; 2000]).unwrap();
+
+        let metadata = fs::metadata(&test_dir).unwrap();
+        let estimate = estimate_size(&test_dir, &metadata, &[], None, None, Some(2000)).unwrap();
+
+        assert_eq!(estimate.file_count, 2);
+        assert_eq!(estimate.uncompressed_bytes, 5000);
+        assert_eq!(estimate.compressed_bytes, 5000 + 3 * TAR_BLOCK_SIZE);
+        assert_eq!(estimate.parts, 4);
+        assert!(estimate.ratio.is_none());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_estimate_size_with_previous_ratio() {
+        let test_name = "previous_ratio";
+        let test_dir = setup_test_dir(test_name);
+        let file_path = test_dir.join("a.bin");
+        fs::write(&file_path, vec![0u8; 10000]).unwrap();
+
+        // Previous run compressed 8000 bytes into 2000 (Ratio 0.25)
+        let previous = previous_run(8000, &[1500, 500]);
+        let metadata = fs::metadata(&file_path).unwrap();
+        let estimate = estimate_size(&file_path, &metadata, &[], None, Some(&previous), Some(1000)).unwrap();
+
+        assert_eq!(estimate.ratio, Some(0.25));
+        assert_eq!(estimate.compressed_bytes, 2500);
+        assert_eq!(estimate.parts, 3);
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_estimate_size_ignores_empty_previous_run() {
+        let previous = previous_run(0, &[]);
+        assert!(previous_ratio(&previous).is_none());
+    }
+}
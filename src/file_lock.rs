@@ -0,0 +1,96 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Exclusive advisory lock on a file, held until dropped
+/// (Only cooperating processes are kept out, and only on unix)
+pub struct FileLock {
+    _file: fs::File,
+}
+
+impl FileLock {
+    /// Block until the lock on `path` is acquired (Creating the lock file if needed)
+    pub fn acquire(path: &Path) -> io::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        lock_exclusive(&file)?;
+        Ok(Self { _file: file })
+    }
+}
+
+// The lock is released when the file is closed
+#[cfg(unix)]
+fn lock_exclusive(file: &fs::File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            return Ok(());
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn lock_exclusive(_file: &fs::File) -> io::Result<()> {
+    Ok(())
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/file_lock_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_file_lock_is_exclusive() {
+        let test_name = "exclusive";
+        let test_dir = setup_test_dir(test_name);
+        let lock_path = test_dir.join("test.lock");
+
+        // Only one thread may hold the lock at a time
+        let holders = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..4).map(|_| {
+            let lock_path = lock_path.clone();
+            let holders = holders.clone();
+            thread::spawn(move || {
+                let _lock = FileLock::acquire(&lock_path).unwrap();
+                assert_eq!(holders.fetch_add(1, Ordering::SeqCst), 0, "Lock should be exclusive");
+                thread::sleep(Duration::from_millis(20));
+                holders.fetch_sub(1, Ordering::SeqCst);
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        cleanup_test_dir(test_name);
+    }
+}
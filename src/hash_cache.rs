@@ -0,0 +1,122 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use std::fs;
+
+/// File hashes from previous runs, so unchanged files are not re-read
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct HashCache {
+    pub segments: HashMap<String, SegmentCache>,
+}
+
+/// Cached file hashes for one segment
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct SegmentCache {
+    /// Algorithm the hashes were made with (Cached hashes of another algorithm are never used)
+    pub algorithm: String,
+    /// Keyed by relative path
+    pub files: HashMap<String, CachedFile>,
+}
+
+/// A file's hash, along with the size and modification time it was computed at
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CachedFile {
+    pub size: u64,
+    pub modified_secs: u64,
+    pub modified_nanos: u32,
+    /// Hex encoded
+    pub hash: String,
+}
+
+impl CachedFile {
+    /// True if the file still has the size and modification time it was hashed at
+    pub fn matches(&self, metadata: &fs::Metadata) -> bool {
+        file_stamp(metadata) == Some((self.size, self.modified_secs, self.modified_nanos))
+    }
+}
+
+/// Size and modification time of a file (None if the platform has no modification time)
+pub fn file_stamp(metadata: &fs::Metadata) -> Option<(u64, u64, u32)> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), modified.as_secs(), modified.subsec_nanos()))
+}
+
+/// Read the hash cache (Empty if it doesn't exist yet)
+pub fn read_hash_cache(path: &Path) -> Result<HashCache> {
+    if !path.exists() {
+        return Ok(HashCache::default());
+    }
+    let contents = fs::read_to_string(path)
+        .context(format!("Failed to read hash cache: {:?}", path))?;
+    serde_json::from_str(&contents)
+        .context(format!("Failed to parse hash cache: {:?}", path))
+}
+
+/// Write the hash cache (Via a temporary file, so a crash never leaves it half-written)
+pub fn write_hash_cache(path: &Path, cache: &HashCache) -> Result<()> {
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty() && !parent.exists() {
+        fs::create_dir_all(parent)
+            .context(format!("Failed to create directory for hash cache: {:?}", parent))?;
+    }
+    let contents = serde_json::to_string(cache)
+        .context("Failed to serialize hash cache")?;
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, contents)
+        .context(format!("Failed to write hash cache: {:?}", temp_path))?;
+    fs::rename(&temp_path, path)
+        .context(format!("Failed to replace hash cache: {:?}", path))
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/hash_cache_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[test]
+    fn test_hash_cache_round_trip() {
+        let test_name = "round_trip";
+        let test_dir = setup_test_dir(test_name);
+        let cache_path = test_dir.join("cache.json");
+        assert!(read_hash_cache(&cache_path).unwrap().segments.is_empty());
+
+        let file_path = test_dir.join("file.txt");
+        fs::write(&file_path, b"content").unwrap();
+        let (size, modified_secs, modified_nanos) = file_stamp(&fs::metadata(&file_path).unwrap()).unwrap();
+        let cached = CachedFile { size, modified_secs, modified_nanos, hash: "0123456789abcdef".to_string() };
+
+        let mut cache = HashCache::default();
+        let segment = cache.segments.entry("docs".to_string()).or_default();
+        segment.algorithm = "xxh3".to_string();
+        segment.files.insert("file.txt".to_string(), cached.clone());
+        write_hash_cache(&cache_path, &cache).unwrap();
+
+        let read = read_hash_cache(&cache_path).unwrap();
+        assert_eq!(read.segments["docs"].files["file.txt"], cached);
+        assert!(cached.matches(&fs::metadata(&file_path).unwrap()));
+
+        // A rewritten file no longer matches
+        fs::write(&file_path, b"new content").unwrap();
+        assert!(!cached.matches(&fs::metadata(&file_path).unwrap()));
+
+        cleanup_test_dir(test_name);
+    }
+}
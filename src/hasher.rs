@@ -1,58 +1,510 @@
 use anyhow::{Context, Result, anyhow};
-use xxhash_rust::xxh3::Xxh3;
-use std::collections::HashMap;
+use xxhash_rust::xxh3::{Xxh3, xxh3_64};
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
-use std::io::{BufReader, BufRead, Write, Read};
+use std::io::{self, Write, Read, Seek, SeekFrom};
 use std::fs;
+use std::time::UNIX_EPOCH;
 use log::{warn};
 use globset::GlobSet;
 use rayon::prelude::*;
 use crate::helpers::collect_filtered_entries;
+use crate::digest::{Blake3, Sha256, from_hex, to_hex};
+use crate::hash_cache::{CachedFile, SegmentCache, file_stamp};
+use crate::rolling_writer::PartInfo;
+use crate::file_lock::FileLock;
+use crate::manifest::{ManifestEntry, ManifestFiles};
 
-// Buffer size for reading files during hashing (256KB)
-const HASHER_BUFFER_SIZE: usize = 262144;
+// Default buffer size for reading files during hashing (1MB)
+pub const HASHER_BUFFER_SIZE: usize = 1048576;
+// Default bytes hashed from each end of a partially hashed file (16MB)
+pub const PARTIAL_HASH_SAMPLE_BYTES: u64 = 16777216;
+
+/// Hashes contributed by one file (Its content, then optionally its metadata)
+type FileHashes = Vec<Vec<u8>>;
+
+/// Algorithms available for segment hashes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// Fast change detection (64-bit)
+    #[default]
+    Xxh3,
+    /// Fast change detection (128-bit, fewer collisions on huge segments)
+    Xxh128,
+    /// Cryptographic (256-bit)
+    Sha256,
+    /// Cryptographic (256-bit), so hashes can also be published as integrity proofs
+    Blake3,
+}
+
+/// What a segment hash is built from
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeDetection {
+    /// Path and full content of every file
+    #[default]
+    Content,
+    /// Path, size and modification time of every file (Much faster, but misses changes that keep both)
+    Metadata,
+}
+
+/// What happens when a file can't be read while hashing a segment
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashFailure {
+    /// Fail the whole segment hash (So the segment is backed up)
+    #[default]
+    Abort,
+    /// Hash a placeholder for the file and report it (Stays unchanged while the file is unreadable)
+    Skip,
+}
+
+/// Hash only the size, start and end of large files (Misses changes in the middle, but reads far less)
+#[derive(Debug, Clone, Copy)]
+pub struct PartialHash {
+    /// Files larger than this are partially hashed
+    pub min_bytes: u64,
+    /// Bytes hashed from each end of the file
+    pub sample_bytes: u64,
+}
+
+impl PartialHash {
+    /// True if a file of `size` bytes is partially hashed
+    fn applies(&self, size: u64) -> bool {
+        size > self.min_bytes
+    }
+}
+
+/// Segment hash settings shared across all segments
+#[derive(Debug)]
+pub struct HashOptions {
+    pub algorithm: HashAlgorithm,
+    pub change_detection: ChangeDetection,
+    /// Bytes read from each file at a time (Larger suits spinning disks and network mounts)
+    pub buffer_size: usize,
+    /// Also hash each file's mode, ownership and modification time (As stored in the archive)
+    pub include_metadata: bool,
+    /// Files left out of the hash (They are still archived)
+    pub hash_ignore: Option<GlobSet>,
+    /// Partially hash large files (None hashes all content)
+    pub partial_hash: Option<PartialHash>,
+    pub on_failure: HashFailure,
+}
+
+impl Default for HashOptions {
+    fn default() -> Self {
+        Self {
+            algorithm: HashAlgorithm::default(),
+            change_detection: ChangeDetection::default(),
+            buffer_size: HASHER_BUFFER_SIZE,
+            include_metadata: false,
+            hash_ignore: None,
+            partial_hash: None,
+            on_failure: HashFailure::default(),
+        }
+    }
+}
+
+impl HashAlgorithm {
+    /// Name recorded alongside each hash in the hash file
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Xxh3 => "xxh3",
+            HashAlgorithm::Xxh128 => "xxh128",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [HashAlgorithm::Xxh3, HashAlgorithm::Xxh128, HashAlgorithm::Sha256, HashAlgorithm::Blake3]
+            .into_iter()
+            .find(|algorithm| algorithm.name() == name)
+    }
+
+    fn hasher(&self) -> Box<dyn Hasher> {
+        match self {
+            HashAlgorithm::Xxh3 => Box::new(Xxh3::new()),
+            HashAlgorithm::Xxh128 => Box::new(Xxh128(Xxh3::new())),
+            HashAlgorithm::Sha256 => Box::new(Sha256::new()),
+            HashAlgorithm::Blake3 => Box::new(Blake3::new()),
+        }
+    }
+
+    /// Cryptographic file hashes are combined in path order (XOR would let changes cancel out)
+    fn is_cryptographic(&self) -> bool {
+        matches!(self, HashAlgorithm::Sha256 | HashAlgorithm::Blake3)
+    }
+}
+
+/// A streaming hash used for segment hashes
+trait Hasher {
+    fn update(&mut self, data: &[u8]);
+    fn finish(&mut self) -> Vec<u8>;
+}
+
+impl Hasher for Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        Xxh3::update(self, data)
+    }
+    fn finish(&mut self) -> Vec<u8> {
+        self.digest().to_be_bytes().to_vec()
+    }
+}
+
+/// 128-bit output of xxh3
+struct Xxh128(Xxh3);
+
+impl Hasher for Xxh128 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data)
+    }
+    fn finish(&mut self) -> Vec<u8> {
+        self.0.digest128().to_be_bytes().to_vec()
+    }
+}
+
+impl Hasher for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Sha256::update(self, data)
+    }
+    fn finish(&mut self) -> Vec<u8> {
+        Sha256::finish(self).to_vec()
+    }
+}
+
+impl Hasher for Blake3 {
+    fn update(&mut self, data: &[u8]) {
+        Blake3::update(self, data)
+    }
+    fn finish(&mut self) -> Vec<u8> {
+        Blake3::finish(self).to_vec()
+    }
+}
+
+/// A tagged segment hash, along with the hashes of the files it was made from
+#[derive(Debug)]
+pub struct SegmentHash {
+    pub hash: String,
+    pub files: ManifestFiles,
+    /// Relative paths of the files left out by hash_ignore
+    pub unhashed: Vec<String>,
+    /// Files that couldn't be read, with the reason (Only with HashFailure::Skip)
+    pub skipped: Vec<(String, String)>,
+}
+
+/// One file's contribution to a segment hash
+struct HashedFile {
+    hashes: FileHashes,
+    cached: Option<CachedFile>,
+    /// Why the file was skipped (If it couldn't be read)
+    error: Option<String>,
+}
 
 /// Computes a hash for a segment by hashing all files (excluding folders and exclusions)
-/// Uses xxHash (xxh3) for individual files, then XORs all hashes together
+/// File hashes are XORed together, or hashed in path order for cryptographic algorithms.
 /// Includes file paths in the hash to detect renames and moves
 /// Works with a src_dir that is a file or directory
-pub fn compute_segment_hash(src_dir: &Path, metadata: &fs::Metadata, exclusions: &[&PathBuf], ignore_patterns: Option<&GlobSet>) -> Result<String> {
-    let mut combined_hash: u64;
-    let file_count: usize;
-    
-    if metadata.is_file() {
+/// 
+/// The hash is `<algorithm>:<hex>` (i.e. `xxh3:0123456789abcdef`), so hashes from different algorithms never match
+/// 
+/// If `cache` is set, files with the same size and modification time as in the cache are not re-read,
+/// and the cache is replaced with this segment's file hashes.
+pub fn compute_segment_hash(
+    src_dir: &Path,
+    metadata: &fs::Metadata,
+    exclusions: &[&PathBuf],
+    ignore_patterns: Option<&GlobSet>,
+    options: &HashOptions,
+    mut cache: Option<&mut SegmentCache>,
+) -> Result<SegmentHash> {
+    let algorithm = options.algorithm;
+    let file_paths = if metadata.is_file() {
         // Use the filename only as the relative path
         let relative_path = src_dir.file_name().ok_or_else(|| anyhow!("Failed to get filename from path: {:?}", src_dir))?;
-        combined_hash = hash_file(src_dir, Path::new(relative_path))?;
-        file_count = 1;
+        vec![(src_dir.to_path_buf(), PathBuf::from(relative_path))]
     } else if metadata.is_dir() {
-        (combined_hash, file_count) = hash_dir_contents(src_dir, exclusions, ignore_patterns)?;
+        list_dir_files(src_dir, exclusions, ignore_patterns)
     } else {
         return Err(anyhow!("Path is neither a file nor a directory: {:?}", src_dir));
+    };
+    let (unhashed, file_paths): (Vec<_>, Vec<_>) = file_paths.into_iter()
+        .partition(|(file_path, _)| is_hash_ignored(options.hash_ignore.as_ref(), file_path));
+    let unhashed = unhashed.into_iter()
+        .map(|(_, relative_path)| relative_path.to_string_lossy().to_string())
+        .collect();
+
+    // Metadata hashes don't read file content, so there's nothing to cache
+    if options.change_detection == ChangeDetection::Metadata {
+        cache = None;
+    }
+    let mut previous = HashMap::new();
+    if let Some(cache) = cache.as_deref_mut()
+        && cache.algorithm == algorithm.name() {
+        previous = std::mem::take(&mut cache.files);
+    }
+    let previous = cache.is_some().then_some(&previous);
+
+    // Hash files in parallel (collect keeps the path order)
+    let results: Result<Vec<HashedFile>> = file_paths
+        .par_iter()
+        .map(|(file_path, relative_path)| {
+            let (hash, cached) = match hash_file_cached(file_path, relative_path, options, previous) {
+                Ok(result) => result,
+                Err(e) if options.on_failure == HashFailure::Skip => {
+                    let hashes = vec![unreadable_hash(algorithm, relative_path)];
+                    return Ok(HashedFile { hashes, cached: None, error: Some(format!("{:#}", e)) });
+                }
+                Err(e) => return Err(e),
+            };
+            let mut hashes = vec![hash];
+            // (Hashed separately, so cached content hashes stay valid after a chmod/chown)
+            if options.include_metadata
+                && let Ok(metadata) = fs::symlink_metadata(file_path)
+                && metadata.is_file() {
+                hashes.push(metadata_hash(algorithm, relative_path, &metadata));
+            }
+            Ok(HashedFile { hashes, cached, error: None })
+        })
+        .collect();
+    let results = results?;
+    let skipped = file_paths.iter()
+        .zip(&results)
+        .filter_map(|((_, relative_path), result)| Some((relative_path.to_string_lossy().to_string(), result.error.clone()?)))
+        .collect();
+    let (hashes, cached): (Vec<FileHashes>, Vec<Option<CachedFile>>) = results.into_iter()
+        .map(|result| (result.hashes, result.cached))
+        .unzip();
+    let files = file_paths.iter()
+        .zip(&hashes)
+        .map(|((_, relative_path), hashes)| (relative_path.to_string_lossy().to_string(), manifest_entry(hashes)))
+        .collect();
+    let hashes = hashes.into_iter().flatten().collect();
+
+    if let Some(cache) = cache {
+        cache.algorithm = algorithm.name().to_string();
+        cache.files = file_paths.iter()
+            .zip(cached)
+            .filter_map(|((_, relative_path), cached)| Some((relative_path.to_string_lossy().to_string(), cached?)))
+            .collect();
+    }
+
+    Ok(SegmentHash { hash: combine_hashes(algorithm, hashes), files, unhashed, skipped })
+}
+
+/// True if a file is left out of the segment hash
+fn is_hash_ignored(hash_ignore: Option<&GlobSet>, path: &Path) -> bool {
+    hash_ignore.is_some_and(|patterns| patterns.is_match(path))
+}
+
+/// Manifest entry for a file's hashes (Its content hash, then its metadata hash if included)
+fn manifest_entry(hashes: &[Vec<u8>]) -> ManifestEntry {
+    ManifestEntry {
+        hash: hashes.first().map(|hash| to_hex(hash)).unwrap_or_default(),
+        metadata: hashes.get(1).map(|hash| to_hex(hash)),
+    }
+}
+
+/// Combine file hashes (In path order) into a tagged segment hash
+fn combine_hashes(algorithm: HashAlgorithm, hashes: Vec<Vec<u8>>) -> String {
+    let combined_hash = if algorithm.is_cryptographic() {
+        let mut segment_hasher = algorithm.hasher();
+        for hash in &hashes {
+            segment_hasher.update(hash);
+        }
+        segment_hasher.finish()
+    } else if hashes.is_empty() {
+        // If no files were found, hash an empty string
+        algorithm.hasher().finish()
+    } else {
+        // (Order doesn't matter for XOR)
+        hashes.into_iter()
+            .reduce(|acc, hash| acc.iter().zip(hash).map(|(a, b)| a ^ b).collect())
+            .unwrap_or_default()
+    };
+
+    format!("{}:{}", algorithm.name(), to_hex(&combined_hash))
+}
+
+/// Start a file's hash with its relative path (Detects renames and moves)
+fn start_file_hash(algorithm: HashAlgorithm, relative_path: &Path) -> Box<dyn Hasher> {
+    // Convert path to string bytes for consistent hashing across platforms
+    let path_str = relative_path.to_string_lossy();
+    let mut hasher = algorithm.hasher();
+    if algorithm.is_cryptographic() {
+        // Length-prefix the path, so path and content can't be shifted into each other
+        hasher.update(&(path_str.len() as u64).to_le_bytes());
+    }
+    hasher.update(path_str.as_bytes());
+    hasher
+}
+
+/// Hex hash of a file's content (Or a symlink's target), as recorded in its manifest entry
+pub fn manifest_hash(algorithm: HashAlgorithm, relative_path: &Path, mut content: impl Read) -> io::Result<String> {
+    let mut hasher = start_file_hash(algorithm, relative_path);
+    let mut buffer = vec![0u8; HASHER_BUFFER_SIZE];
+    loop {
+        let bytes_read = content.read(&mut buffer)?;
+        if bytes_read == 0 {
+            return Ok(to_hex(&hasher.finish()));
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+}
+
+/// Placeholder hash for a file that couldn't be read (So the segment hash is stable while it stays unreadable)
+fn unreadable_hash(algorithm: HashAlgorithm, relative_path: &Path) -> Vec<u8> {
+    let mut hasher = start_file_hash(algorithm, relative_path);
+    // (Marked, so it can never equal a content hash)
+    hasher.update(b"\0unreadable\0");
+    hasher.finish()
+}
+
+/// Hash of a regular file's mode, ownership and modification time (The metadata stored in its tar header)
+fn metadata_hash(algorithm: HashAlgorithm, relative_path: &Path, metadata: &fs::Metadata) -> Vec<u8> {
+    let mut hasher = start_file_hash(algorithm, relative_path);
+    // (Marked, so it can never equal a content hash)
+    hasher.update(b"\0metadata\0");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        hasher.update(&metadata.mode().to_le_bytes());
+        hasher.update(&metadata.uid().to_le_bytes());
+        hasher.update(&metadata.gid().to_le_bytes());
+    }
+    #[cfg(not(unix))]
+    hasher.update(&[metadata.permissions().readonly() as u8]);
+    let modified = metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    hasher.update(&modified.as_secs().to_le_bytes());
+    hasher.finish()
+}
+
+/// Cache entry for a file hashed with `metadata` (None if the file has no modification time)
+fn cache_entry(metadata: &fs::Metadata, hash: &[u8]) -> Option<CachedFile> {
+    file_stamp(metadata).map(|(size, modified_secs, modified_nanos)| CachedFile {
+        size,
+        modified_secs,
+        modified_nanos,
+        hash: to_hex(hash),
+    })
+}
+
+/// Builds a segment hash from files read elsewhere (i.e. while archiving), matching compute_segment_hash
+/// 
+/// Files must be added in the same order compute_segment_hash hashes them (The order of collect_filtered_entries).
+#[derive(Debug)]
+pub struct StreamingSegmentHash {
+    algorithm: HashAlgorithm,
+    include_metadata: bool,
+    hash_ignore: Option<GlobSet>,
+    hashes: Vec<Vec<u8>>,
+    files: ManifestFiles,
+    unhashed: Vec<String>,
+    cached: Vec<(String, CachedFile)>,
+    /// Set if any file could not be read, so the hash would be wrong
+    failed: bool,
+}
+
+/// Hash of one file's content, fed as it is read
+pub struct FileHash(Box<dyn Hasher>);
+
+impl FileHash {
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+}
+
+impl StreamingSegmentHash {
+    pub fn new(options: &HashOptions) -> Self {
+        Self {
+            algorithm: options.algorithm,
+            include_metadata: options.include_metadata,
+            hash_ignore: options.hash_ignore.clone(),
+            hashes: Vec::new(),
+            files: ManifestFiles::new(),
+            unhashed: Vec::new(),
+            cached: Vec::new(),
+            failed: false,
+        }
+    }
+
+    /// Start hashing a regular file, whose content is then fed to the returned FileHash
+    pub fn start_file(&self, relative_path: &Path) -> FileHash {
+        FileHash(start_file_hash(self.algorithm, relative_path))
+    }
+
+    /// Add a fully read regular file (`metadata` was read before its content, for the hash cache)
+    pub fn add_file(&mut self, relative_path: &Path, mut file_hash: FileHash, metadata: &fs::Metadata) {
+        let key = relative_path.to_string_lossy().to_string();
+        let mut hashes = vec![file_hash.0.finish()];
+        if let Some(cached) = cache_entry(metadata, &hashes[0]) {
+            self.cached.push((key.clone(), cached));
+        }
+        if self.include_metadata {
+            hashes.push(metadata_hash(self.algorithm, relative_path, metadata));
+        }
+        self.files.insert(key, manifest_entry(&hashes));
+        self.hashes.extend(hashes);
+    }
+
+    /// Add a symlink (Its target path is hashed, not the target file)
+    pub fn add_symlink(&mut self, relative_path: &Path, target: &Path) {
+        let mut hasher = start_file_hash(self.algorithm, relative_path);
+        hasher.update(target.to_string_lossy().as_bytes());
+        let hash = hasher.finish();
+        self.files.insert(relative_path.to_string_lossy().to_string(), manifest_entry(std::slice::from_ref(&hash)));
+        self.hashes.push(hash);
+    }
+
+    /// True if a file is left out of the hash (It should be archived without hashing, then added with add_unhashed)
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        is_hash_ignored(self.hash_ignore.as_ref(), path)
+    }
+
+    /// Add a file that was archived but left out of the hash
+    pub fn add_unhashed(&mut self, relative_path: &Path) {
+        self.unhashed.push(relative_path.to_string_lossy().to_string());
     }
 
-    // If no files were found, hash an empty string
-    if file_count == 0 {
-        let mut hasher = Xxh3::new();
-        hasher.update(b"");
-        combined_hash = hasher.digest();
+    /// Record that a file could not be read
+    pub fn mark_failed(&mut self) {
+        self.failed = true;
     }
 
-    // Format as 16-character hex string
-    Ok(format!("{:016x}", combined_hash))
+    /// Segment hash (None if any file failed), replacing `cache` with the hashed files
+    pub fn finish(self, cache: Option<&mut SegmentCache>) -> Option<SegmentHash> {
+        if self.failed {
+            return None;
+        }
+        if let Some(cache) = cache {
+            cache.algorithm = self.algorithm.name().to_string();
+            cache.files = self.cached.into_iter().collect();
+        }
+        Some(SegmentHash { hash: combine_hashes(self.algorithm, self.hashes), files: self.files, unhashed: self.unhashed, skipped: Vec::new() })
+    }
 }
 
-/// Recursively hash files in a directory, applying the same exclusion logic as tar creation
-/// Returns (combined_hash, file_count)
-fn hash_dir_contents(
+/// True if a hash from the hash file matches a computed one
+/// (Hashes without an algorithm name were written by older versions, using xxh3)
+pub fn hashes_match(stored: &str, computed: &str) -> bool {
+    if stored.contains(':') {
+        stored == computed
+    } else {
+        computed.strip_prefix("xxh3:") == Some(stored)
+    }
+}
+
+/// List files in a directory, applying the same exclusion logic as tar creation
+/// Returns (path, relative_path) pairs in path order
+fn list_dir_files(
     base_dir: &Path,
     exclusions: &[&PathBuf],
     ignore_patterns: Option<&GlobSet>,
-) -> Result<(u64, usize)> {
-    let entries = collect_filtered_entries(base_dir, exclusions, ignore_patterns);
-    
-    // Filter to only files and symlinks, extract paths
-    let file_paths: Vec<(PathBuf, PathBuf)> = entries
+) -> Vec<(PathBuf, PathBuf)> {
+    collect_filtered_entries(base_dir, exclusions, ignore_patterns)
         .into_iter()
         .filter_map(|entry| {
             let path = entry.path().to_path_buf();
@@ -68,35 +520,54 @@ fn hash_dir_contents(
                 None
             }
         })
-        .collect();
+        .collect()
+}
 
-    let file_count = file_paths.len();
+/// Hash a single file, reusing its hash from `previous` if it is a regular file whose size and mtime are unchanged
+/// Returns the hash, plus the cache entry for it (If caching)
+fn hash_file_cached(
+    file_path: &Path,
+    relative_path: &Path,
+    options: &HashOptions,
+    previous: Option<&HashMap<String, CachedFile>>,
+) -> Result<(Vec<u8>, Option<CachedFile>)> {
+    // (Metadata read before the content, so a file modified while hashing is re-read next time)
+    // (Partially hashed files are cheap to re-hash, and their hashes must never mix with full ones)
+    let metadata = previous
+        .and_then(|_| fs::symlink_metadata(file_path).ok())
+        .filter(|metadata| metadata.is_file())
+        .filter(|metadata| !options.partial_hash.is_some_and(|partial| partial.applies(metadata.len())));
 
-    // Hash files in parallel
-    let hashes: Result<Vec<u64>> = file_paths
-        .par_iter()
-        .map(|(file_path, relative_path)| {
-            hash_file(file_path, relative_path)
-        })
-        .collect();
+    let key = relative_path.to_string_lossy();
+    if let (Some(previous), Some(metadata)) = (previous, &metadata)
+        && let Some(cached) = previous.get(key.as_ref())
+        && cached.matches(metadata)
+        && let Some(hash) = from_hex(&cached.hash) {
+        return Ok((hash, Some(cached.to_owned())));
+    }
 
-    // (Order doesn't matter for XOR)
-    let combined_hash = hashes?
-        .into_iter()
-        .fold(0u64, |acc, hash| acc ^ hash);
+    let mut hasher = start_file_hash(options.algorithm, relative_path);
+    hash_file(file_path, options, |data| hasher.update(data))?;
+    let hash = hasher.finish();
 
-    Ok((combined_hash, file_count))
+    let cached = metadata.as_ref().and_then(|metadata| cache_entry(metadata, &hash));
+    Ok((hash, cached))
 }
 
-/// Hash a single file + its path using xxHash
-fn hash_file(file_path: &Path, relative_path: &Path) -> Result<u64> {
-    let mut hasher = Xxh3::new();
-    
-    // Include the relative path in the hash (detects renames and moves)
-    // Convert path to string bytes for consistent hashing across platforms
-    let path_str = relative_path.to_string_lossy();
-    hasher.update(path_str.as_bytes());
-    
+/// Feed up to `limit` bytes of `file` to `update`, `buffer` at a time
+fn read_into_hash(file: &mut fs::File, limit: u64, buffer: &mut [u8], update: &mut impl FnMut(&[u8])) -> Result<()> {
+    let mut reader = file.take(limit);
+    loop {
+        let bytes_read = reader.read(buffer)?;
+        if bytes_read == 0 {
+            return Ok(());
+        }
+        update(&buffer[..bytes_read]);
+    }
+}
+
+/// Feed a single file's content (Or symlink target) to `update`
+fn hash_file(file_path: &Path, options: &HashOptions, mut update: impl FnMut(&[u8])) -> Result<()> {
     // Check if this is a symlink
     let is_symlink = match fs::symlink_metadata(file_path) {
         Ok(m) => m.file_type().is_symlink(),
@@ -108,40 +579,200 @@ fn hash_file(file_path: &Path, relative_path: &Path) -> Result<u64> {
         let target = fs::read_link(file_path)
             .context(format!("Failed to read symlink target: {:?}", file_path))?;
         let target_str = target.to_string_lossy();
-        hasher.update(target_str.as_bytes());
+        update(target_str.as_bytes());
+    } else if options.change_detection == ChangeDetection::Metadata {
+        // Hash the size and modification time instead of reading the file
+        let metadata = fs::metadata(file_path)
+            .context(format!("Failed to read metadata for hashing: {:?}", file_path))?;
+        let modified = metadata.modified()
+            .context(format!("Failed to read modification time: {:?}", file_path))?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        update(&metadata.len().to_le_bytes());
+        update(&modified.as_secs().to_le_bytes());
+        update(&modified.subsec_nanos().to_le_bytes());
     } else {
-        // For regular files, hash the file content
-        let file = fs::File::open(file_path)
+        // For regular files, hash the file content (Read directly, the buffer is already large)
+        let mut file = fs::File::open(file_path)
             .context(format!("Failed to open file for hashing: {:?}", file_path))?;
-        let mut reader = BufReader::new(file);
-        
-        let mut buffer = vec![0u8; HASHER_BUFFER_SIZE];
-        loop {
-            let bytes_read = reader.read(&mut buffer)?;
-            if bytes_read == 0 {
-                break;
-            }
-            hasher.update(&buffer[..bytes_read]);
+        let size = file.metadata()
+            .context(format!("Failed to read metadata for hashing: {:?}", file_path))?
+            .len();
+
+        let mut buffer = vec![0u8; options.buffer_size.max(1)];
+        if let Some(partial) = options.partial_hash
+            && partial.applies(size) {
+            // Hash the size, then the start and end of the file (Overlapping if the samples cover it all)
+            update(b"\0partial\0");
+            update(&size.to_le_bytes());
+            let sample_bytes = partial.sample_bytes.min(size);
+            read_into_hash(&mut file, sample_bytes, &mut buffer, &mut update)
+                .context(format!("Failed to read start of file for hashing: {:?}", file_path))?;
+            file.seek(SeekFrom::Start(size - sample_bytes))?;
+            read_into_hash(&mut file, sample_bytes, &mut buffer, &mut update)
+                .context(format!("Failed to read end of file for hashing: {:?}", file_path))?;
+        } else {
+            read_into_hash(&mut file, u64::MAX, &mut buffer, &mut update)
+                .context(format!("Failed to read file for hashing: {:?}", file_path))?;
         }
     }
     
-    Ok(hasher.digest())
+    Ok(())
 }
 
-/// Read the hash file into a HashMap
-pub fn read_hash_file(hash_file_path: &Path) -> Result<HashMap<String, String>> {
-    let mut hashes = HashMap::new();
-    
+/// Current version of the hash file format
+pub const HASH_FILE_VERSION: u32 = 2;
+
+// Extension appended to the hash file path for its lock file
+const HASH_FILE_LOCK_EXT: &str = "lock";
+
+// Prefix of the hash file's last line, which holds a checksum of everything before it
+const CHECKSUM_PREFIX: &str = "# checksum: ";
+
+/// Contents of the hash file (Segment records keyed by segment name)
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct HashFile {
+    pub version: u32,
+    #[serde(default)]
+    pub segments: BTreeMap<String, SegmentRecord>,
+}
+
+impl Default for HashFile {
+    fn default() -> Self {
+        Self { version: HASH_FILE_VERSION, segments: BTreeMap::new() }
+    }
+}
+
+impl HashFile {
+    /// Stored hash of a segment
+    pub fn hash(&self, segment: &str) -> Option<&str> {
+        self.segments.get(segment).map(|record| record.hash.as_str())
+    }
+}
+
+/// What is known about a segment's last backup
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SegmentRecord {
+    pub hash: String,
+    pub algorithm: String,
+    /// RFC 3339 timestamp of the last backup (None for hashes migrated from the legacy format)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_backup: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_count: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uncompressed_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<PartInfo>,
+}
+
+impl SegmentRecord {
+    /// Record with only a hash (The algorithm is read from its tag, untagged hashes are xxh3)
+    pub fn new(hash: String) -> Self {
+        let algorithm = match hash.split_once(':') {
+            Some((algorithm, _)) => algorithm.to_string(),
+            None => HashAlgorithm::Xxh3.name().to_string(),
+        };
+        Self { hash, algorithm, last_backup: None, file_count: None, uncompressed_bytes: None, parts: Vec::new() }
+    }
+}
+
+/// Read the hash file (Legacy key=hash files are migrated, and rewritten in the new format on the next write)
+pub fn read_hash_file(hash_file_path: &Path) -> Result<HashFile> {
+    let _lock = lock_hash_file(hash_file_path)?;
+    read_hash_file_unlocked(hash_file_path)
+}
+
+/// Store one segment's record in the hash file, or remove it if None
+/// (Re-read while locked, so runs sharing the hash file don't lose each other's updates)
+pub fn update_hash_file(hash_file_path: &Path, segment: &str, record: Option<&SegmentRecord>) -> Result<()> {
+    let _lock = lock_hash_file(hash_file_path)?;
+    let mut hashes = read_hash_file_unlocked(hash_file_path)?;
+    match record {
+        Some(record) => hashes.segments.insert(segment.to_string(), record.clone()),
+        None => hashes.segments.remove(segment),
+    };
+    write_hash_file(hash_file_path, &hashes)
+}
+
+fn read_hash_file_unlocked(hash_file_path: &Path) -> Result<HashFile> {
     if !hash_file_path.exists() {
-        return Ok(hashes);
+        return Ok(HashFile::default());
     }
 
-    let file = fs::File::open(hash_file_path)
-        .context(format!("Failed to open hash file: {:?}", hash_file_path))?;
-    let reader = BufReader::new(file);
+    let contents = fs::read_to_string(hash_file_path)
+        .context(format!("Failed to read hash file: {:?}", hash_file_path))?;
 
-    for (line_num, line) in reader.lines().enumerate() {
-        let line = line.context(format!("Failed to read line {} from hash file", line_num + 1))?;
+    // Structured files always start with their version
+    let is_structured = contents.lines().next()
+        .is_some_and(|line| line.parse::<toml::Table>().is_ok_and(|table| table.contains_key("version")));
+    if !is_structured {
+        return Ok(read_legacy_hashes(&contents));
+    }
+
+    // A truncated or hand-edited file can't be trusted, so every segment is backed up again
+    let checksum_matches = contents.rsplit_once(CHECKSUM_PREFIX)
+        .is_some_and(|(body, checksum)| checksum.trim() == hash_file_checksum(body));
+    if !checksum_matches {
+        warn!("Hash file {:?} is corrupted or was modified (Checksum does not match), all segments will be backed up", hash_file_path);
+        return Ok(HashFile::default());
+    }
+
+    let hashes: HashFile = toml::from_str(&contents)
+        .context(format!("Failed to parse hash file: {:?}", hash_file_path))?;
+    if hashes.version > HASH_FILE_VERSION {
+        return Err(anyhow!("Hash file {:?} is version {} (This version supports up to {})",
+            hash_file_path, hashes.version, HASH_FILE_VERSION));
+    }
+    Ok(hashes)
+}
+
+/// Write the hash file (Via a temporary file, so readers never see it half-written)
+fn write_hash_file(hash_file_path: &Path, hashes: &HashFile) -> Result<()> {
+    // Create parent directory if it doesn't exist
+    if let Some(parent) = hash_file_path.parent()
+        && !parent.exists() {
+        fs::create_dir_all(parent)
+            .context(format!("Failed to create directory for hash file: {:?}", parent))?;
+    }
+
+    let contents = toml::to_string(&HashFile { version: HASH_FILE_VERSION, segments: hashes.segments.clone() })
+        .context("Failed to serialize hash file")?;
+    let contents = format!("{}{}{}\n", contents, CHECKSUM_PREFIX, hash_file_checksum(&contents));
+
+    let temp_path = PathBuf::from(format!("{}.tmp", hash_file_path.display()));
+    let mut file = fs::File::create(&temp_path)
+        .context(format!("Failed to create hash file: {:?}", temp_path))?;
+    file.write_all(contents.as_bytes())
+        .context(format!("Failed to write to hash file: {:?}", temp_path))?;
+    file.sync_all()
+        .context(format!("Failed to sync hash file: {:?}", temp_path))?;
+    fs::rename(&temp_path, hash_file_path)
+        .context(format!("Failed to replace hash file: {:?}", hash_file_path))
+}
+
+/// Lock shared by every run using the hash file (A separate file, since the hash file is replaced on write)
+fn lock_hash_file(hash_file_path: &Path) -> Result<FileLock> {
+    if let Some(parent) = hash_file_path.parent()
+        && !parent.as_os_str().is_empty() && !parent.exists() {
+        fs::create_dir_all(parent)
+            .context(format!("Failed to create directory for hash file: {:?}", parent))?;
+    }
+    let lock_path = PathBuf::from(format!("{}.{}", hash_file_path.display(), HASH_FILE_LOCK_EXT));
+    FileLock::acquire(&lock_path)
+        .context(format!("Failed to lock hash file: {:?}", lock_path))
+}
+
+/// Checksum of the hash file's contents (xxh3 hex)
+fn hash_file_checksum(contents: &str) -> String {
+    format!("{:016x}", xxh3_64(contents.as_bytes()))
+}
+
+/// Parse the legacy key=hash format
+fn read_legacy_hashes(contents: &str) -> HashFile {
+    let mut hashes = HashFile::default();
+
+    for (line_num, line) in contents.lines().enumerate() {
         let line = line.trim();
         
         // Skip empty lines
@@ -153,49 +784,19 @@ pub fn read_hash_file(hash_file_path: &Path) -> Result<HashMap<String, String>>
         if let Some(equal_pos) = line.find('=') {
             let key = line[..equal_pos].trim().to_string();
             let hash = line[equal_pos + 1..].trim().to_string();
-            if hashes.contains_key(&key) {
+            if hashes.segments.contains_key(&key) {
                 warn!("Duplicate key in hash file (Last value is used): {}", key);
             }
-            hashes.insert(key, hash);
+            hashes.segments.insert(key, SegmentRecord::new(hash));
         } else {
             warn!("Invalid line in hash file (line {}): {}", line_num + 1, line);
         }
     }
 
-    Ok(hashes)
-}
-
-/// Write a HashMap to the hash file in key=hash format
-pub fn write_hash_file(hash_file_path: &Path, hashes: &HashMap<String, String>) -> Result<()> {
-    // Create parent directory if it doesn't exist
-    if let Some(parent) = hash_file_path.parent() {
-        if !parent.exists() {
-            fs::create_dir_all(parent)
-                .context(format!("Failed to create directory for hash file: {:?}", parent))?;
-        }
-    }
-
-    let mut file = fs::File::create(hash_file_path)
-        .context(format!("Failed to create hash file: {:?}", hash_file_path))?;
-
-    // Sort keys for consistent output
-    let mut sorted_keys: Vec<&String> = hashes.keys().collect();
-    sorted_keys.sort();
-
-    for key in sorted_keys {
-        if let Some(hash) = hashes.get(key) {
-            writeln!(file, "{}={}", key, hash)
-                .context(format!("Failed to write to hash file: {:?}", hash_file_path))?;
-        }
-    }
-
-    file.sync_all()
-        .context(format!("Failed to sync hash file: {:?}", hash_file_path))?;
-
-    Ok(())
+    hashes
 }
 
-/// --- Tests --- ///
+// --- Tests --- //
 
 #[cfg(test)]
 mod tests {
@@ -228,13 +829,13 @@ mod tests {
         let file1 = test_dir.join("original.txt");
         fs::write(&file1, b"same content").unwrap();
         let metadata1 = fs::metadata(&test_dir).unwrap();
-        let hash1 = compute_segment_hash(&test_dir, &metadata1, &[], None).unwrap();
+        let hash1 = compute_segment_hash(&test_dir, &metadata1, &[], None, &HashOptions::default(), None).unwrap().hash;
         
         // Rename file (same content, different path)
         let file2 = test_dir.join("renamed.txt");
         fs::rename(&file1, &file2).unwrap();
         let metadata2 = fs::metadata(&test_dir).unwrap();
-        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], None).unwrap();
+        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], None, &HashOptions::default(), None).unwrap().hash;
         
         // Hashes should be different (path is included)
         assert_ne!(hash1, hash2, "Hash should change when filename changes");
@@ -253,7 +854,7 @@ mod tests {
         let file1 = subdir1.join("file.txt");
         fs::write(&file1, b"same content").unwrap();
         let metadata1 = fs::metadata(&test_dir).unwrap();
-        let hash1 = compute_segment_hash(&test_dir, &metadata1, &[], None).unwrap();
+        let hash1 = compute_segment_hash(&test_dir, &metadata1, &[], None, &HashOptions::default(), None).unwrap().hash;
         
         // Move file to different subdirectory
         let subdir2 = test_dir.join("dir2");
@@ -261,7 +862,7 @@ mod tests {
         let file2 = subdir2.join("file.txt");
         fs::rename(&file1, &file2).unwrap();
         let metadata2 = fs::metadata(&test_dir).unwrap();
-        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], None).unwrap();
+        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], None, &HashOptions::default(), None).unwrap().hash;
         
         // Hashes should be different (path is included)
         assert_ne!(hash1, hash2, "Hash should change when file is moved");
@@ -278,12 +879,12 @@ mod tests {
         let file = test_dir.join("file.txt");
         fs::write(&file, b"original content").unwrap();
         let metadata1 = fs::metadata(&test_dir).unwrap();
-        let hash1 = compute_segment_hash(&test_dir, &metadata1, &[], None).unwrap();
+        let hash1 = compute_segment_hash(&test_dir, &metadata1, &[], None, &HashOptions::default(), None).unwrap().hash;
         
         // Change file content
         fs::write(&file, b"modified content").unwrap();
         let metadata2 = fs::metadata(&test_dir).unwrap();
-        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], None).unwrap();
+        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], None, &HashOptions::default(), None).unwrap().hash;
         
         // Hashes should be different
         assert_ne!(hash1, hash2, "Hash should change when content changes");
@@ -306,13 +907,13 @@ mod tests {
         fs::write(&file2, b"identical content").unwrap();
         
         let metadata = fs::metadata(&test_dir).unwrap();
-        let hash = compute_segment_hash(&test_dir, &metadata, &[], None).unwrap();
+        let hash = compute_segment_hash(&test_dir, &metadata, &[], None, &HashOptions::default(), None).unwrap().hash;
         
         // Edit both files identically
         fs::write(&file1, b"new identical content").unwrap();
         fs::write(&file2, b"new identical content").unwrap();
         let metadata_after = fs::metadata(&test_dir).unwrap();
-        let hash_after = compute_segment_hash(&test_dir, &metadata_after, &[], None).unwrap();
+        let hash_after = compute_segment_hash(&test_dir, &metadata_after, &[], None, &HashOptions::default(), None).unwrap().hash;
         
         // Hashes should be different (different paths = different hashes)
         assert_ne!(hash, hash_after, "Hash should change even if identical files are edited identically");
@@ -327,12 +928,12 @@ mod tests {
         
         // Empty directory should produce a hash (of empty string)
         let metadata = fs::metadata(&test_dir).unwrap();
-        let hash = compute_segment_hash(&test_dir, &metadata, &[], None).unwrap();
+        let hash = compute_segment_hash(&test_dir, &metadata, &[], None, &HashOptions::default(), None).unwrap().hash;
         assert!(!hash.is_empty(), "Empty segment should produce a hash");
         
         // Hash should be consistent
         let metadata2 = fs::metadata(&test_dir).unwrap();
-        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], None).unwrap();
+        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], None, &HashOptions::default(), None).unwrap().hash;
         assert_eq!(hash, hash2, "Empty segment hash should be consistent");
         
         cleanup_test_dir(test_name);
@@ -350,25 +951,25 @@ mod tests {
         
         // Should succeed with a single file
         let metadata1 = fs::metadata(&test_file).unwrap();
-        let hash1 = compute_segment_hash(&test_file, &metadata1, &[], None).unwrap();
+        let hash1 = compute_segment_hash(&test_file, &metadata1, &[], None, &HashOptions::default(), None).unwrap().hash;
         assert!(!hash1.is_empty(), "Single file should produce a hash");
         
         // Hash should be consistent
         let metadata2 = fs::metadata(&test_file).unwrap();
-        let hash2 = compute_segment_hash(&test_file, &metadata2, &[], None).unwrap();
+        let hash2 = compute_segment_hash(&test_file, &metadata2, &[], None, &HashOptions::default(), None).unwrap().hash;
         assert_eq!(hash1, hash2, "Single file hash should be consistent");
         
         // Hash should change when content changes
         fs::write(&test_file, b"different content").unwrap();
         let metadata3 = fs::metadata(&test_file).unwrap();
-        let hash3 = compute_segment_hash(&test_file, &metadata3, &[], None).unwrap();
+        let hash3 = compute_segment_hash(&test_file, &metadata3, &[], None, &HashOptions::default(), None).unwrap().hash;
         assert_ne!(hash1, hash3, "Hash should change when file content changes");
         
         // Hash should change when filename changes (even with same content)
         let test_file2 = test_dir.join("backup2.bak");
         fs::write(&test_file2, file_content).unwrap();
         let metadata4 = fs::metadata(&test_file2).unwrap();
-        let hash4 = compute_segment_hash(&test_file2, &metadata4, &[], None).unwrap();
+        let hash4 = compute_segment_hash(&test_file2, &metadata4, &[], None, &HashOptions::default(), None).unwrap().hash;
         assert_ne!(hash1, hash4, "Hash should change when filename changes");
         
         cleanup_test_dir(test_name);
@@ -391,18 +992,18 @@ mod tests {
         let ignore_matcher = Some(builder.build().unwrap());
         
         let metadata1 = fs::metadata(&test_dir).unwrap();
-        let hash1 = compute_segment_hash(&test_dir, &metadata1, &[], ignore_matcher.as_ref()).unwrap();
+        let hash1 = compute_segment_hash(&test_dir, &metadata1, &[], ignore_matcher.as_ref(), &HashOptions::default(), None).unwrap().hash;
         
         // Change ignored file (should not affect hash)
         fs::write(test_dir.join("file2.tmp"), b"different content").unwrap();
         let metadata2 = fs::metadata(&test_dir).unwrap();
-        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], ignore_matcher.as_ref()).unwrap();
+        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], ignore_matcher.as_ref(), &HashOptions::default(), None).unwrap().hash;
         assert_eq!(hash1, hash2, "Hash should not change when ignored file changes");
         
         // Change non-ignored file (should affect hash)
         fs::write(test_dir.join("file1.txt"), b"different content").unwrap();
         let metadata3 = fs::metadata(&test_dir).unwrap();
-        let hash3 = compute_segment_hash(&test_dir, &metadata3, &[], ignore_matcher.as_ref()).unwrap();
+        let hash3 = compute_segment_hash(&test_dir, &metadata3, &[], ignore_matcher.as_ref(), &HashOptions::default(), None).unwrap().hash;
         assert_ne!(hash1, hash3, "Hash should change when non-ignored file changes");
         
         cleanup_test_dir(test_name);
@@ -422,21 +1023,298 @@ mod tests {
         
         // Hash should be consistent across multiple calls
         let metadata1 = fs::metadata(&test_dir).unwrap();
-        let hash1 = compute_segment_hash(&test_dir, &metadata1, &[], None).unwrap();
+        let hash1 = compute_segment_hash(&test_dir, &metadata1, &[], None, &HashOptions::default(), None).unwrap().hash;
         let metadata2 = fs::metadata(&test_dir).unwrap();
-        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], None).unwrap();
+        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], None, &HashOptions::default(), None).unwrap().hash;
         assert_eq!(hash1, hash2, "Hash should be consistent for same directory");
         
         cleanup_test_dir(test_name);
     }
 
+    #[test]
+    fn test_hash_blake3() {
+        let test_name = "blake3";
+        let test_dir = setup_test_dir(test_name);
+        
+        fs::write(test_dir.join("file1.txt"), b"content1").unwrap();
+        fs::write(test_dir.join("file2.txt"), b"content2").unwrap();
+        
+        let metadata = fs::metadata(&test_dir).unwrap();
+        let blake3 = HashOptions { algorithm: HashAlgorithm::Blake3, ..Default::default() };
+        let hash1 = compute_segment_hash(&test_dir, &metadata, &[], None, &blake3, None).unwrap().hash;
+        assert_eq!(hash1.len(), "blake3:".len() + 64, "BLAKE3 hash should be 64 hex characters");
+        assert_eq!(hash1, compute_segment_hash(&test_dir, &metadata, &[], None, &blake3, None).unwrap().hash);
+        
+        // Moving bytes between the path and content should change the hash
+        fs::remove_file(test_dir.join("file2.txt")).unwrap();
+        fs::write(test_dir.join("file2.txtc"), b"ontent2").unwrap();
+        let hash2 = compute_segment_hash(&test_dir, &metadata, &[], None, &blake3, None).unwrap().hash;
+        assert_ne!(hash1, hash2, "Hash should detect path/content shifts");
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_hash_algorithms_are_tagged() {
+        let test_name = "algorithms";
+        let test_dir = setup_test_dir(test_name);
+        fs::write(test_dir.join("file1.txt"), b"content1").unwrap();
+        
+        let metadata = fs::metadata(&test_dir).unwrap();
+        let expected_lengths = [
+            (HashAlgorithm::Xxh3, 16),
+            (HashAlgorithm::Xxh128, 32),
+            (HashAlgorithm::Sha256, 64),
+            (HashAlgorithm::Blake3, 64),
+        ];
+        for (algorithm, hex_len) in expected_lengths {
+            let options = HashOptions { algorithm, ..Default::default() };
+            let hash = compute_segment_hash(&test_dir, &metadata, &[], None, &options, None).unwrap().hash;
+            let (name, hex) = hash.split_once(':').unwrap();
+            assert_eq!(name, algorithm.name());
+            assert_eq!(hex.len(), hex_len, "{:?} hash has the wrong length", algorithm);
+        }
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_hash_metadata_change_detection() {
+        let test_name = "metadata_detection";
+        let test_dir = setup_test_dir(test_name);
+        let file_path = test_dir.join("file1.txt");
+        fs::write(&file_path, b"content1").unwrap();
+        
+        let metadata = fs::metadata(&test_dir).unwrap();
+        let options = HashOptions { change_detection: ChangeDetection::Metadata, ..Default::default() };
+        let hash1 = compute_segment_hash(&test_dir, &metadata, &[], None, &options, None).unwrap().hash;
+        assert_ne!(hash1, compute_segment_hash(&test_dir, &metadata, &[], None, &HashOptions::default(), None).unwrap().hash);
+        
+        // Same-size content change with the original mtime goes unnoticed
+        let modified = fs::metadata(&file_path).unwrap().modified().unwrap();
+        fs::write(&file_path, b"content2").unwrap();
+        fs::File::options().write(true).open(&file_path).unwrap().set_modified(modified).unwrap();
+        assert_eq!(hash1, compute_segment_hash(&test_dir, &metadata, &[], None, &options, None).unwrap().hash);
+        
+        // Touching the file is detected
+        fs::File::options().write(true).open(&file_path).unwrap()
+            .set_modified(modified + std::time::Duration::from_secs(1)).unwrap();
+        assert_ne!(hash1, compute_segment_hash(&test_dir, &metadata, &[], None, &options, None).unwrap().hash);
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_hash_cache_skips_unchanged_files() {
+        let test_name = "cache";
+        let test_dir = setup_test_dir(test_name);
+        fs::write(test_dir.join("file1.txt"), b"content1").unwrap();
+        fs::write(test_dir.join("file2.txt"), b"content2").unwrap();
+        
+        let metadata = fs::metadata(&test_dir).unwrap();
+        let options = HashOptions::default();
+        let mut cache = SegmentCache::default();
+        let hash1 = compute_segment_hash(&test_dir, &metadata, &[], None, &options, Some(&mut cache)).unwrap().hash;
+        assert_eq!(hash1, compute_segment_hash(&test_dir, &metadata, &[], None, &options, None).unwrap().hash);
+        assert_eq!(cache.algorithm, "xxh3");
+        assert_eq!(cache.files.len(), 2);
+        
+        // Unchanged files use the cached hash instead of being re-read
+        cache.files.get_mut("file1.txt").unwrap().hash = "0000000000000000".to_string();
+        let hash2 = compute_segment_hash(&test_dir, &metadata, &[], None, &options, Some(&mut cache)).unwrap().hash;
+        assert_ne!(hash1, hash2, "Cached hash should be used");
+        
+        // Touched files are re-read
+        fs::write(test_dir.join("file1.txt"), b"content1").unwrap();
+        let mut touched = fs::metadata(test_dir.join("file1.txt")).unwrap().modified().unwrap();
+        touched += std::time::Duration::from_secs(1);
+        fs::File::options().write(true).open(test_dir.join("file1.txt")).unwrap().set_modified(touched).unwrap();
+        let hash3 = compute_segment_hash(&test_dir, &metadata, &[], None, &options, Some(&mut cache)).unwrap().hash;
+        assert_eq!(hash1, hash3, "Modified file should be re-read");
+        
+        // Hashes from another algorithm are never reused
+        let blake3 = HashOptions { algorithm: HashAlgorithm::Blake3, ..Default::default() };
+        compute_segment_hash(&test_dir, &metadata, &[], None, &blake3, Some(&mut cache)).unwrap();
+        assert_eq!(cache.algorithm, "blake3");
+        assert_eq!(cache.files["file1.txt"].hash.len(), 64);
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_hash_buffer_size_does_not_change_hash() {
+        let test_name = "buffer_size";
+        let test_dir = setup_test_dir(test_name);
+        let data: Vec<u8> = (0..100000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(test_dir.join("file1.bin"), &data).unwrap();
+        
+        let metadata = fs::metadata(&test_dir).unwrap();
+        let default_hash = compute_segment_hash(&test_dir, &metadata, &[], None, &HashOptions::default(), None).unwrap().hash;
+        for buffer_size in [1, 4096, 65537] {
+            let options = HashOptions { buffer_size, ..Default::default() };
+            let hash = compute_segment_hash(&test_dir, &metadata, &[], None, &options, None).unwrap().hash;
+            assert_eq!(hash, default_hash, "Buffer size {} should give the same hash", buffer_size);
+        }
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_hash_includes_metadata() {
+        use std::os::unix::fs::PermissionsExt;
+        let test_name = "include_metadata";
+        let test_dir = setup_test_dir(test_name);
+        let file_path = test_dir.join("file1.txt");
+        fs::write(&file_path, b"content1").unwrap();
+        fs::set_permissions(&file_path, fs::Permissions::from_mode(0o644)).unwrap();
+        
+        let metadata = fs::metadata(&test_dir).unwrap();
+        let options = HashOptions { include_metadata: true, ..Default::default() };
+        let content_hash = compute_segment_hash(&test_dir, &metadata, &[], None, &HashOptions::default(), None).unwrap().hash;
+        let hash1 = compute_segment_hash(&test_dir, &metadata, &[], None, &options, None).unwrap().hash;
+        
+        // A chmod is only detected when metadata is included
+        fs::set_permissions(&file_path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(content_hash, compute_segment_hash(&test_dir, &metadata, &[], None, &HashOptions::default(), None).unwrap().hash);
+        assert_ne!(hash1, compute_segment_hash(&test_dir, &metadata, &[], None, &options, None).unwrap().hash);
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_partial_hash() {
+        let test_name = "partial_hash";
+        let test_dir = setup_test_dir(test_name);
+        let file_path = test_dir.join("disk.img");
+        let mut content = vec![0u8; 4096];
+        fs::write(&file_path, &content).unwrap();
+
+        let metadata = fs::metadata(&file_path).unwrap();
+        let options = HashOptions {
+            partial_hash: Some(PartialHash { min_bytes: 1024, sample_bytes: 512 }),
+            ..Default::default()
+        };
+        let hash = |options: &HashOptions| compute_segment_hash(&file_path, &metadata, &[], None, options, None).unwrap().hash;
+        let partial = hash(&options);
+        assert_ne!(partial, hash(&HashOptions::default()), "Partial hashes should differ from full ones");
+
+        // A change in the middle is missed, but not one at either end
+        content[2048] = 1;
+        fs::write(&file_path, &content).unwrap();
+        assert_eq!(partial, hash(&options));
+        content[4000] = 1;
+        fs::write(&file_path, &content).unwrap();
+        assert_ne!(partial, hash(&options));
+
+        // Files under the threshold are fully hashed
+        let small = PartialHash { min_bytes: 4096, sample_bytes: 512 };
+        assert_eq!(hash(&HashOptions::default()), hash(&HashOptions { partial_hash: Some(small), ..Default::default() }));
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_hash_failure_skip() {
+        use std::os::unix::fs::PermissionsExt;
+        let test_name = "hash_failure_skip";
+        let test_dir = setup_test_dir(test_name);
+        fs::write(test_dir.join("file1.txt"), b"content1").unwrap();
+        let locked_path = test_dir.join("locked.txt");
+        fs::write(&locked_path, b"secret").unwrap();
+        fs::set_permissions(&locked_path, fs::Permissions::from_mode(0o000)).unwrap();
+        if fs::File::open(&locked_path).is_ok() {
+            // (Running as root, permissions can't make the file unreadable)
+            cleanup_test_dir(test_name);
+            return;
+        }
+
+        let metadata = fs::metadata(&test_dir).unwrap();
+        assert!(compute_segment_hash(&test_dir, &metadata, &[], None, &HashOptions::default(), None).is_err());
+
+        let options = HashOptions { on_failure: HashFailure::Skip, ..Default::default() };
+        let hash1 = compute_segment_hash(&test_dir, &metadata, &[], None, &options, None).unwrap();
+        assert_eq!(hash1.skipped.len(), 1);
+        assert_eq!(hash1.skipped[0].0, "locked.txt");
+
+        // Stable while the file stays unreadable, but still detects other changes
+        let hash2 = compute_segment_hash(&test_dir, &metadata, &[], None, &options, None).unwrap();
+        assert_eq!(hash1.hash, hash2.hash);
+        fs::write(test_dir.join("file1.txt"), b"changed").unwrap();
+        let hash3 = compute_segment_hash(&test_dir, &metadata, &[], None, &options, None).unwrap();
+        assert_ne!(hash1.hash, hash3.hash);
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_hash_ignore() {
+        let test_name = "hash_ignore";
+        let test_dir = setup_test_dir(test_name);
+        fs::write(test_dir.join("file1.txt"), b"content1").unwrap();
+        fs::write(test_dir.join("app.pid"), b"1234").unwrap();
+
+        let metadata = fs::metadata(&test_dir).unwrap();
+        let options = HashOptions {
+            hash_ignore: crate::helpers::build_ignore_matcher(&["*.pid".to_string()]).unwrap(),
+            ..Default::default()
+        };
+        let hash1 = compute_segment_hash(&test_dir, &metadata, &[], None, &options, None).unwrap();
+        assert_eq!(hash1.unhashed, ["app.pid"]);
+        assert!(!hash1.files.contains_key("app.pid"));
+
+        // Touching the ignored file doesn't change the hash
+        fs::write(test_dir.join("app.pid"), b"5678").unwrap();
+        assert_eq!(hash1.hash, compute_segment_hash(&test_dir, &metadata, &[], None, &options, None).unwrap().hash);
+        assert_ne!(hash1.hash, compute_segment_hash(&test_dir, &metadata, &[], None, &HashOptions::default(), None).unwrap().hash);
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_segment_hash_lists_files() {
+        let test_name = "lists_files";
+        let test_dir = setup_test_dir(test_name);
+        fs::write(test_dir.join("file1.txt"), b"content1").unwrap();
+        fs::create_dir(test_dir.join("sub")).unwrap();
+        fs::write(test_dir.join("sub/file2.txt"), b"content2").unwrap();
+
+        let metadata = fs::metadata(&test_dir).unwrap();
+        let hash1 = compute_segment_hash(&test_dir, &metadata, &[], None, &HashOptions::default(), None).unwrap();
+        assert_eq!(hash1.files.keys().collect::<Vec<_>>(), ["file1.txt", "sub/file2.txt"]);
+        assert!(hash1.files.values().all(|entry| entry.metadata.is_none()));
+
+        // Only the changed file's hash changes
+        fs::write(test_dir.join("sub/file2.txt"), b"changed").unwrap();
+        let hash2 = compute_segment_hash(&test_dir, &metadata, &[], None, &HashOptions::default(), None).unwrap();
+        assert_eq!(hash1.files["file1.txt"], hash2.files["file1.txt"]);
+        assert_ne!(hash1.files["sub/file2.txt"], hash2.files["sub/file2.txt"]);
+
+        let options = HashOptions { include_metadata: true, ..Default::default() };
+        let hash3 = compute_segment_hash(&test_dir, &metadata, &[], None, &options, None).unwrap();
+        assert_eq!(hash3.files["file1.txt"].hash, hash1.files["file1.txt"].hash);
+        assert!(hash3.files["file1.txt"].metadata.is_some());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_hashes_match() {
+        assert!(hashes_match("xxh3:0123456789abcdef", "xxh3:0123456789abcdef"));
+        // Untagged hashes from older versions are xxh3
+        assert!(hashes_match("0123456789abcdef", "xxh3:0123456789abcdef"));
+        assert!(!hashes_match("0123456789abcdef", "xxh128:0123456789abcdef"));
+        assert!(!hashes_match("xxh3:0123456789abcdef", "blake3:0123456789abcdef"));
+    }
+
     #[test]
     fn test_read_hash_file_missing() {
         let test_name = "read_missing";
         let missing_file = get_test_dir(test_name).join("nonexistent.hash");
         
         let hashes = read_hash_file(&missing_file).unwrap();
-        assert!(hashes.is_empty(), "Reading missing hash file should return empty HashMap");
+        assert!(hashes.segments.is_empty(), "Reading missing hash file should return no segments");
         
         cleanup_test_dir(test_name);
     }
@@ -448,16 +1326,16 @@ mod tests {
         let hash_file = test_dir.join("test.hash");
         
         // Write hash file
-        let mut hashes = HashMap::new();
-        hashes.insert("segment1".to_string(), "abc123".to_string());
-        hashes.insert("segment2".to_string(), "def456".to_string());
+        let mut hashes = HashFile::default();
+        hashes.segments.insert("segment1".to_string(), SegmentRecord::new("abc123".to_string()));
+        hashes.segments.insert("segment2".to_string(), SegmentRecord::new("def456".to_string()));
         write_hash_file(&hash_file, &hashes).unwrap();
         
         // Read it back
         let read_hashes = read_hash_file(&hash_file).unwrap();
-        assert_eq!(read_hashes.len(), 2);
-        assert_eq!(read_hashes.get("segment1"), Some(&"abc123".to_string()));
-        assert_eq!(read_hashes.get("segment2"), Some(&"def456".to_string()));
+        assert_eq!(read_hashes.segments.len(), 2);
+        assert_eq!(read_hashes.hash("segment1"), Some("abc123"));
+        assert_eq!(read_hashes.hash("segment2"), Some("def456"));
         
         cleanup_test_dir(test_name);
     }
@@ -471,7 +1349,7 @@ mod tests {
         // Write hash file with empty lines
         let mut file = fs::File::create(&hash_file).unwrap();
         writeln!(file, "segment1=abc123").unwrap();
-        writeln!(file, "").unwrap();
+        writeln!(file).unwrap();
         writeln!(file, "segment2=def456").unwrap();
         writeln!(file, "   ").unwrap();
         writeln!(file, "segment3=ghi789").unwrap();
@@ -479,10 +1357,10 @@ mod tests {
         
         // Read it back (empty lines should be skipped)
         let read_hashes = read_hash_file(&hash_file).unwrap();
-        assert_eq!(read_hashes.len(), 3);
-        assert_eq!(read_hashes.get("segment1"), Some(&"abc123".to_string()));
-        assert_eq!(read_hashes.get("segment2"), Some(&"def456".to_string()));
-        assert_eq!(read_hashes.get("segment3"), Some(&"ghi789".to_string()));
+        assert_eq!(read_hashes.segments.len(), 3);
+        assert_eq!(read_hashes.hash("segment1"), Some("abc123"));
+        assert_eq!(read_hashes.hash("segment2"), Some("def456"));
+        assert_eq!(read_hashes.hash("segment3"), Some("ghi789"));
         
         cleanup_test_dir(test_name);
     }
@@ -494,22 +1372,117 @@ mod tests {
         let hash_file = test_dir.join("test.hash");
         
         // Write hash file with unsorted keys
-        let mut hashes = HashMap::new();
-        hashes.insert("zebra".to_string(), "hash1".to_string());
-        hashes.insert("apple".to_string(), "hash2".to_string());
-        hashes.insert("banana".to_string(), "hash3".to_string());
+        let mut hashes = HashFile::default();
+        hashes.segments.insert("zebra".to_string(), SegmentRecord::new("hash1".to_string()));
+        hashes.segments.insert("apple".to_string(), SegmentRecord::new("hash2".to_string()));
+        hashes.segments.insert("banana".to_string(), SegmentRecord::new("hash3".to_string()));
         write_hash_file(&hash_file, &hashes).unwrap();
         
         // Read file content and verify it's sorted
         let content = fs::read_to_string(&hash_file).unwrap();
-        let lines: Vec<&str> = content.lines().collect();
-        assert_eq!(lines[0], "apple=hash2");
-        assert_eq!(lines[1], "banana=hash3");
-        assert_eq!(lines[2], "zebra=hash1");
+        let apple = content.find("[segments.apple]").unwrap();
+        let banana = content.find("[segments.banana]").unwrap();
+        let zebra = content.find("[segments.zebra]").unwrap();
+        assert!(apple < banana && banana < zebra, "Segments should be written in sorted order");
         
         cleanup_test_dir(test_name);
     }
 
+    #[test]
+    fn test_update_hash_file_keeps_other_segments() {
+        let test_name = "update_merge";
+        let test_dir = setup_test_dir(test_name);
+        let hash_file = test_dir.join("test.hash");
+
+        // Two runs sharing the hash file, each updating its own segments
+        let handles: Vec<_> = ["a", "b"].into_iter().map(|run| {
+            let hash_file = hash_file.clone();
+            std::thread::spawn(move || {
+                for i in 0..5 {
+                    let record = SegmentRecord::new(format!("xxh3:{}{}", run, i));
+                    update_hash_file(&hash_file, &format!("{}{}", run, i), Some(&record)).unwrap();
+                }
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let hashes = read_hash_file(&hash_file).unwrap();
+        assert_eq!(hashes.segments.len(), 10, "No update should be lost");
+        assert_eq!(hashes.hash("b4"), Some("xxh3:b4"));
+
+        update_hash_file(&hash_file, "a0", None).unwrap();
+        assert!(read_hash_file(&hash_file).unwrap().hash("a0").is_none());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_hash_file_detects_corruption() {
+        let test_name = "corruption";
+        let test_dir = setup_test_dir(test_name);
+        let hash_file = test_dir.join("test.hash");
+
+        let mut hashes = HashFile::default();
+        hashes.segments.insert("docs".to_string(), SegmentRecord::new("xxh3:0123456789abcdef".to_string()));
+        hashes.segments.insert("photos".to_string(), SegmentRecord::new("xxh3:fedcba9876543210".to_string()));
+        write_hash_file(&hash_file, &hashes).unwrap();
+        assert_eq!(read_hash_file(&hash_file).unwrap().segments.len(), 2);
+        let contents = fs::read_to_string(&hash_file).unwrap();
+
+        // Edited hash
+        fs::write(&hash_file, contents.replace("0123456789abcdef", "0000000000000000")).unwrap();
+        assert!(read_hash_file(&hash_file).unwrap().segments.is_empty(), "Modified file should not be trusted");
+
+        // Truncated file (Still valid TOML, but missing its checksum)
+        let truncated = &contents[..contents.find("[segments.photos]").unwrap()];
+        fs::write(&hash_file, truncated).unwrap();
+        assert!(read_hash_file(&hash_file).unwrap().segments.is_empty(), "Truncated file should not be trusted");
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_hash_file_migrates_legacy_format() {
+        let test_name = "migrate_legacy";
+        let test_dir = setup_test_dir(test_name);
+        let hash_file = test_dir.join("test.hash");
+        fs::write(&hash_file, "docs=0123456789abcdef\nphotos=blake3:abcd\n").unwrap();
+
+        let mut hashes = read_hash_file(&hash_file).unwrap();
+        assert_eq!(hashes.segments["docs"].algorithm, "xxh3");
+        assert_eq!(hashes.segments["photos"].algorithm, "blake3");
+        assert!(hashes.segments["docs"].last_backup.is_none());
+
+        // Rewritten in the structured format, with backup details
+        let record = hashes.segments.get_mut("docs").unwrap();
+        record.last_backup = Some("2024-01-01T00:00:00+00:00".to_string());
+        record.file_count = Some(3);
+        record.uncompressed_bytes = Some(1024);
+        record.parts.push(PartInfo {
+            name: "docs.tar.gz".to_string(),
+            path: PathBuf::new(),
+            size: 512,
+            checksum: None,
+        });
+        write_hash_file(&hash_file, &hashes).unwrap();
+        assert!(fs::read_to_string(&hash_file).unwrap().starts_with("version = 2"));
+
+        let read = read_hash_file(&hash_file).unwrap();
+        assert_eq!(read.version, HASH_FILE_VERSION);
+        assert_eq!(read.segments["docs"].file_count, Some(3));
+        assert_eq!(read.segments["docs"].parts[0].size, 512);
+        assert_eq!(read.hash("photos"), Some("blake3:abcd"));
+
+        // Files from a newer version are rejected rather than misread
+        let newer = "version = 99\n";
+        fs::write(&hash_file, format!("{}{}{}\n", newer, CHECKSUM_PREFIX, hash_file_checksum(newer))).unwrap();
+        assert!(read_hash_file(&hash_file).is_err());
+
+        cleanup_test_dir(test_name);
+    }
+
     #[test]
     fn test_hash_symlink_target() {
         let test_name = "symlink_target";
@@ -529,7 +1502,7 @@ mod tests {
         std::os::windows::fs::symlink_file(&target1, &symlink_path).unwrap();
         
         let metadata1 = fs::metadata(&test_dir).unwrap();
-        let hash1 = compute_segment_hash(&test_dir, &metadata1, &[], None).unwrap();
+        let hash1 = compute_segment_hash(&test_dir, &metadata1, &[], None, &HashOptions::default(), None).unwrap().hash;
         
         // Remove old symlink and create new one pointing to target2
         fs::remove_file(&symlink_path).unwrap();
@@ -539,7 +1512,7 @@ mod tests {
         std::os::windows::fs::symlink_file(&target2, &symlink_path).unwrap();
         
         let metadata2 = fs::metadata(&test_dir).unwrap();
-        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], None).unwrap();
+        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], None, &HashOptions::default(), None).unwrap().hash;
         
         // Hash should change when symlink target changes
         assert_ne!(hash1, hash2, "Hash should change when symlink target changes");
@@ -564,7 +1537,7 @@ mod tests {
         std::os::windows::fs::symlink_file(&target, &symlink1).unwrap();
         
         let metadata1 = fs::metadata(&test_dir).unwrap();
-        let hash1 = compute_segment_hash(&test_dir, &metadata1, &[], None).unwrap();
+        let hash1 = compute_segment_hash(&test_dir, &metadata1, &[], None, &HashOptions::default(), None).unwrap().hash;
         
         // Remove old symlink and create new one with different name (same target)
         fs::remove_file(&symlink1).unwrap();
@@ -575,7 +1548,7 @@ mod tests {
         std::os::windows::fs::symlink_file(&target, &symlink2).unwrap();
         
         let metadata2 = fs::metadata(&test_dir).unwrap();
-        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], None).unwrap();
+        let hash2 = compute_segment_hash(&test_dir, &metadata2, &[], None, &HashOptions::default(), None).unwrap().hash;
         
         // Hash should change when symlink path changes (even if target is same)
         assert_ne!(hash1, hash2, "Hash should change when symlink path changes");
@@ -592,7 +1565,7 @@ mod tests {
         let regular_file = test_dir.join("regular.txt");
         fs::write(&regular_file, b"content").unwrap();
         let metadata1 = fs::metadata(&test_dir).unwrap();
-        let hash_with_regular = compute_segment_hash(&test_dir, &metadata1, &[], None).unwrap();
+        let hash_with_regular = compute_segment_hash(&test_dir, &metadata1, &[], None, &HashOptions::default(), None).unwrap().hash;
         
         // Create a broken symlink (pointing to non-existent file)
         let broken_symlink = test_dir.join("broken_link.txt");
@@ -604,14 +1577,14 @@ mod tests {
         
         // Hash should succeed even with broken symlink (hashes the target path string)
         let metadata2 = fs::metadata(&test_dir).unwrap();
-        let hash_with_broken = compute_segment_hash(&test_dir, &metadata2, &[], None).unwrap();
+        let hash_with_broken = compute_segment_hash(&test_dir, &metadata2, &[], None, &HashOptions::default(), None).unwrap().hash;
         
         // Hash should be different (broken symlink adds a new path)
         assert_ne!(hash_with_regular, hash_with_broken, "Hash should change when broken symlink is added");
         
         // Hash should be consistent across multiple calls
         let metadata3 = fs::metadata(&test_dir).unwrap();
-        let hash_with_broken2 = compute_segment_hash(&test_dir, &metadata3, &[], None).unwrap();
+        let hash_with_broken2 = compute_segment_hash(&test_dir, &metadata3, &[], None, &HashOptions::default(), None).unwrap().hash;
         assert_eq!(hash_with_broken, hash_with_broken2, "Hash should be consistent for broken symlink");
         
         // Change the broken symlink target path (still broken, but different target)
@@ -623,7 +1596,7 @@ mod tests {
         std::os::windows::fs::symlink_file(&different_target, &broken_symlink).unwrap();
         
         let metadata4 = fs::metadata(&test_dir).unwrap();
-        let hash_with_different_broken = compute_segment_hash(&test_dir, &metadata4, &[], None).unwrap();
+        let hash_with_different_broken = compute_segment_hash(&test_dir, &metadata4, &[], None, &HashOptions::default(), None).unwrap().hash;
         
         // Hash should change when symlink target path changes (even if both are broken)
         assert_ne!(hash_with_broken, hash_with_different_broken, "Hash should change when broken symlink target path changes");
@@ -646,9 +1619,9 @@ mod tests {
         
         // Should read valid entries and warn about invalid line
         let hashes = read_hash_file(&hash_file).unwrap();
-        assert_eq!(hashes.len(), 2, "Should read 2 valid entries");
-        assert_eq!(hashes.get("segment1"), Some(&"abc123".to_string()));
-        assert_eq!(hashes.get("segment2"), Some(&"def456".to_string()));
+        assert_eq!(hashes.segments.len(), 2, "Should read 2 valid entries");
+        assert_eq!(hashes.hash("segment1"), Some("abc123"));
+        assert_eq!(hashes.hash("segment2"), Some("def456"));
         
         cleanup_test_dir(test_name);
     }
@@ -668,9 +1641,9 @@ mod tests {
         
         // Should read entries (last value for duplicate key wins)
         let hashes = read_hash_file(&hash_file).unwrap();
-        assert_eq!(hashes.len(), 2, "Should have 2 unique keys");
-        assert_eq!(hashes.get("segment1"), Some(&"def456".to_string()), "Last value should win");
-        assert_eq!(hashes.get("segment2"), Some(&"ghi789".to_string()));
+        assert_eq!(hashes.segments.len(), 2, "Should have 2 unique keys");
+        assert_eq!(hashes.hash("segment1"), Some("def456"), "Last value should win");
+        assert_eq!(hashes.hash("segment2"), Some("ghi789"));
         
         cleanup_test_dir(test_name);
     }
@@ -691,9 +1664,9 @@ mod tests {
         
         // Should handle long lines without issues
         let hashes = read_hash_file(&hash_file).unwrap();
-        assert_eq!(hashes.len(), 2, "Should read both entries");
-        assert_eq!(hashes.get(&long_key), Some(&long_value));
-        assert_eq!(hashes.get("segment2"), Some(&"normal".to_string()));
+        assert_eq!(hashes.segments.len(), 2, "Should read both entries");
+        assert_eq!(hashes.hash(&long_key), Some(long_value.as_str()));
+        assert_eq!(hashes.hash("segment2"), Some("normal"));
         
         cleanup_test_dir(test_name);
     }
@@ -712,9 +1685,9 @@ mod tests {
         
         // Should handle empty key (though unusual)
         let hashes = read_hash_file(&hash_file).unwrap();
-        assert_eq!(hashes.len(), 2, "Should read both entries");
-        assert_eq!(hashes.get(""), Some(&"abc123".to_string()));
-        assert_eq!(hashes.get("segment2"), Some(&"def456".to_string()));
+        assert_eq!(hashes.segments.len(), 2, "Should read both entries");
+        assert_eq!(hashes.hash(""), Some("abc123"));
+        assert_eq!(hashes.hash("segment2"), Some("def456"));
         
         cleanup_test_dir(test_name);
     }
@@ -733,9 +1706,9 @@ mod tests {
         
         // Should handle empty value
         let hashes = read_hash_file(&hash_file).unwrap();
-        assert_eq!(hashes.len(), 2, "Should read both entries");
-        assert_eq!(hashes.get("segment1"), Some(&"".to_string()));
-        assert_eq!(hashes.get("segment2"), Some(&"def456".to_string()));
+        assert_eq!(hashes.segments.len(), 2, "Should read both entries");
+        assert_eq!(hashes.hash("segment1"), Some(""));
+        assert_eq!(hashes.hash("segment2"), Some("def456"));
         
         cleanup_test_dir(test_name);
     }
@@ -754,10 +1727,10 @@ mod tests {
         
         // Should use first equals as delimiter
         let hashes = read_hash_file(&hash_file).unwrap();
-        assert_eq!(hashes.len(), 2, "Should read both entries");
-        assert_eq!(hashes.get("segment1"), Some(&"abc=123=xyz".to_string()), 
+        assert_eq!(hashes.segments.len(), 2, "Should read both entries");
+        assert_eq!(hashes.hash("segment1"), Some("abc=123=xyz"), 
             "Value should include all content after first equals");
-        assert_eq!(hashes.get("segment2"), Some(&"def456".to_string()));
+        assert_eq!(hashes.hash("segment2"), Some("def456"));
         
         cleanup_test_dir(test_name);
     }
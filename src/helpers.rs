@@ -1,26 +1,39 @@
 use anyhow::{Context, Result, anyhow};
-use flate2::write::GzEncoder;
 use flate2::Compression;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::io;
 use std::io::{BufRead, BufReader};
 use std::fs;
+use std::env;
 use std::collections::HashSet;
-use log::{info,warn,error};
+use std::sync::Arc;
+use log::{debug,info,warn,error,trace};
 use globset::{GlobSet, GlobSetBuilder};
 use walkdir::WalkDir;
-use crate::rolling_writer::RollingWriter;
-
-const PATH_FILE: &str = ".seg_arc.path";
-
-// File permission constants
-const FILE_MODE_READ: u32 = 0o644;  // Read-only file permissions (rw-r--r--)
+use crate::rolling_writer::{CreatedFiles, FinishedPart, PartInfo, PartsSummary, RollingWriter};
+use crate::archive_info::ArchiveInfo;
+use crate::archive_writer::{ArchiveStream, ArchiveWriter};
+use crate::digest::DigestAlgorithm;
+use crate::script_queue::{PartHandler, ScriptQueue};
+use crate::destination::{Destination, UploadTally, upload_all};
+use crate::sink::ArchiveSink;
+use crate::segment::CommandEntry;
+use crate::script_options::{ExitAction, Script, ScriptOptions};
+use crate::hooks::{HookEvent, HookKind, Hooks};
+use crate::retry::RetryPolicy;
+use crate::checkpoint::{Checkpoint, checkpoint_path, read_checkpoint};
+use crate::hasher::StreamingSegmentHash;
+use crate::index::{ArchiveIndex, index_path, write_index};
+use crate::progress::Progress;
 
 // Exit code threshold for detecting process panics/abnormal termination
 // Exit codes >= 128 typically indicate the process was killed by a signal
 const PROCESS_EXIT_CODE_THRESHOLD: i32 = 128;
 
+/// Scripts starting with this are shell command lines, run with `sh -c` (Their arguments are `$1`, etc.)
+pub const SHELL_SCRIPT_PREFIX: &str = "sh:";
+
 /// Builds a GlobSet from ignore patterns for efficient pattern matching
 pub fn build_ignore_matcher(patterns: &[String]) -> Result<Option<GlobSet>> {
     if patterns.is_empty() {
@@ -37,20 +50,186 @@ pub fn build_ignore_matcher(patterns: &[String]) -> Result<Option<GlobSet>> {
         .context("Failed to build GlobSet from ignore patterns")?))
 }
 
-/// Archives a file or directory, appending a path file and applying exclusions.
+/// How archives are split into parts once max_size_bytes is reached
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SplitMode {
+    /// Split the compressed stream at exactly max_size_bytes (Parts must be concatenated to extract)
+    #[default]
+    Stream,
+    /// Restart the tar/gzip stream at the first entry boundary after max_size_bytes,
+    /// so each part can be extracted on its own (Parts may exceed max_size_bytes)
+    Independent,
+    /// Never split: fail the segment if the archive exceeds max_size_bytes
+    Strict,
+    /// Uncompressed GNU tar multi-volume parts (Restorable with `tar -xM`)
+    MultiVolume,
+}
+
+impl SplitMode {
+    /// File extension of archives written in this mode
+    pub fn archive_extension(&self) -> &'static str {
+        match self {
+            SplitMode::MultiVolume => "tar",
+            _ => "tar.gz",
+        }
+    }
+}
+
+/// Archive settings shared across all segments (Besides `entries` and `progress`)
+#[derive(Default)]
+pub struct ArchiveOptions {
+    pub ignore_patterns: Option<GlobSet>,
+    pub compression_level: Option<u32>,
+    pub max_size_bytes: Option<usize>,
+    /// Abort a segment that would be split into more parts than this
+    pub max_parts: Option<u32>,
+    pub split_mode: SplitMode,
+    /// Run the part_finalized hook (post_script) on each finished part
+    pub hooks: Arc<Hooks>,
+    /// Upload each finished part to all of these (Before running the post-script)
+    pub destinations: Vec<Destination>,
+    /// Uploads to each destination (Taken after each archive)
+    pub uploads: Arc<UploadTally>,
+    /// Retries for failed uploads
+    pub retry: RetryPolicy,
+    /// Run the post-script (And uploads) in this many background threads (None runs it inline)
+    pub script_workers: Option<usize>,
+    /// Generate a parity file for multi-part archives
+    pub parity: bool,
+    /// Sync each part to disk before running the post-script
+    pub sync_parts: bool,
+    /// Write a listing of the parts of split archives
+    pub part_list: bool,
+    /// Checksum each part while it is written
+    pub part_checksum: Option<DigestAlgorithm>,
+    /// Write each part here instead of to output_path (i.e. piped to part_command, or streamed to a destination)
+    pub sink: Option<Arc<dyn ArchiveSink>>,
+    /// Free space required in output_path before opening each part
+    pub min_free_bytes: Option<u64>,
+    /// Maximum rate to write archive output
+    pub max_write_bytes_per_sec: Option<u64>,
+    /// Checkpoint independent parts so an interrupted archive can be resumed
+    pub resumable: bool,
+    /// Write an index of each archive's entries
+    pub index: bool,
+    /// Command output to add after the files of the current segment (Set for each segment)
+    pub entries: Vec<CommandEntry>,
+    /// Counters of the current segment's progress (Set for each segment, if it is shown)
+    pub progress: Option<Arc<Progress>>,
+}
+
+impl ArchiveOptions {
+    /// True if parts are written to files in output_path
+    pub fn stores_locally(&self) -> bool {
+        self.sink.as_ref().is_none_or(|sink| sink.is_local())
+    }
+}
+
+/// Details of a created archive
+#[derive(Debug, Default)]
+pub struct ArchiveSummary {
+    /// Number of files and symlinks added
+    pub file_count: usize,
+    /// Total size of the added files before compression
+    pub uncompressed_bytes: u64,
+    pub parts: PartsSummary,
+    /// Segment hash computed while archiving (If one was passed to create_archive)
+    pub segment_hash: Option<StreamingSegmentHash>,
+}
+
+impl ArchiveSummary {
+    /// Compressed/uncompressed ratio (None if no content was archived)
+    pub fn compression_ratio(&self) -> Option<f64> {
+        (self.uncompressed_bytes > 0).then(|| self.parts.total_size() as f64 / self.uncompressed_bytes as f64)
+    }
+}
+
+/// Archives a file or directory, appending an info file and applying exclusions.
+/// On failure, any files this run wrote are removed so output_path only contains complete archives
+/// (Archives left by previous runs are kept)
+/// (Unless the archive is resumable, in which case completed parts are kept for the next run).
+/// If `segment_hash` is set, every file is also hashed as it is archived.
+/// If `changed_files` is set, only those files (Relative paths) are archived, making an incremental archive.
+#[allow(clippy::too_many_arguments)]
 pub fn create_archive(
     src_dir: &Path,
     metadata: &fs::Metadata,
     output_path: &Path,
-    root_path: &Option<PathBuf>,
     exclusions: &[&PathBuf],
-    ignore_patterns: Option<&GlobSet>,
-    compression_level: Option<u32>,
-    max_size_bytes: Option<usize>,
-    script_path: Option<PathBuf>
-) -> Result<()> {
+    info: &ArchiveInfo,
+    options: &ArchiveOptions,
+    segment_hash: Option<StreamingSegmentHash>,
+    changed_files: Option<&HashSet<String>>,
+) -> Result<ArchiveSummary> {
+    let created = Arc::new(CreatedFiles::default());
+    let result = write_archive(src_dir, metadata, output_path, exclusions, info, options, segment_hash, changed_files, &created);
+    if result.is_err() {
+        if is_resumable(options) {
+            warn!("Keeping completed parts of {:?} to resume on the next run", output_path);
+        } else {
+            remove_partial_output(created.take());
+        }
+    }
+    result
+}
+
+/// Resuming is only possible for independent parts written to disk
+fn is_resumable(options: &ArchiveOptions) -> bool {
+    options.resumable
+        && options.split_mode == SplitMode::Independent
+        && options.max_size_bytes.is_some()
+        && options.stores_locally()
+}
+
+/// Read the checkpoint left by an interrupted run, if it can be resumed from
+fn resumable_checkpoint(path: &Path, output_path: &Path, info: &ArchiveInfo) -> Option<Checkpoint> {
+    if !path.exists() {
+        return None;
+    }
+    let checkpoint = match read_checkpoint(path) {
+        Ok(checkpoint) => checkpoint,
+        Err(e) => {
+            warn!("Ignoring unreadable checkpoint: {:#}", e);
+            return None;
+        }
+    };
+    let dir = output_path.parent().unwrap_or(Path::new("."));
+    if checkpoint.segment != info.segment
+        || info.hash.as_deref() != Some(checkpoint.hash.as_str())
+        || !checkpoint.parts_exist(dir) {
+        info!("Checkpoint for {:?} is out of date, starting over", output_path);
+        return None;
+    }
+    Some(checkpoint)
+}
+
+/// Delete the files written for a failed archive (Errors are logged, so the original error is returned)
+/// (Files already renamed or discarded by the writer are gone, so they are passed over)
+fn remove_partial_output(created: Vec<PathBuf>) {
+    for path in created {
+        match fs::remove_file(&path) {
+            Ok(_) => info!("Removed partial output: {:?}", path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => error!("Failed to remove partial output {:?}: {}", path, e),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_archive(
+    src_dir: &Path,
+    metadata: &fs::Metadata,
+    output_path: &Path,
+    exclusions: &[&PathBuf],
+    info: &ArchiveInfo,
+    options: &ArchiveOptions,
+    segment_hash: Option<StreamingSegmentHash>,
+    changed_files: Option<&HashSet<String>>,
+    created: &Arc<CreatedFiles>,
+) -> Result<ArchiveSummary> {
     // Configure tar compression
-    let comp = match compression_level {
+    let comp = match options.compression_level {
         Some(level) => {
             if level > 9 {
                 return Err(anyhow!("Compression level must be between 0 and 9: {}", level));
@@ -59,51 +238,172 @@ pub fn create_archive(
         },
         None => Compression::default()
     };
-    let mut file = RollingWriter::new(output_path.to_path_buf(), max_size_bytes)?;
-    if let Some(script) = script_path {
-        let callback = move |filename: &String| execute_script(script.to_owned(), filename.as_str());
-        file.set_listener(callback);
-    }
-    let enc = GzEncoder::new(file, comp);
-    let mut tar = tar::Builder::new(enc);
-
-    // Inject path file into archive
-    let path_str = strip_root(src_dir, root_path)?;
-    let mut header = tar::Header::new_gnu();
-    header.set_path(PATH_FILE)?;
-    header.set_size(path_str.len() as u64);
-    header.set_mode(FILE_MODE_READ);
-    header.set_cksum(); // Removing this line will cause the archive to be corrupted
-    tar.append(&header, path_str.as_bytes())?;
+    // In strict mode max_size_bytes is a hard limit on a single file
+    let (max_size, size_limit) = match options.split_mode {
+        SplitMode::Strict => (None, options.max_size_bytes),
+        _ => (options.max_size_bytes, None),
+    };
+    let checkpoint_file = is_resumable(options).then(|| checkpoint_path(output_path));
+    let checkpoint = checkpoint_file.as_deref()
+        .and_then(|path| resumable_checkpoint(path, output_path, info));
+    let entries_done = checkpoint.as_ref().map_or(0, |checkpoint| checkpoint.entries_done);
+    // (Checkpoints are only read for local parts)
+    let mut file = match (&options.sink, checkpoint) {
+        (_, Some(checkpoint)) => {
+            info!("Resuming {:?} after {} part(s) ({} entries)", output_path, checkpoint.parts.len(), checkpoint.entries_done);
+            RollingWriter::resume(output_path.to_path_buf(), max_size, checkpoint.parts)?
+        }
+        (Some(sink), None) => RollingWriter::with_sink(output_path.to_path_buf(), max_size, sink.clone())?,
+        (None, None) => RollingWriter::new(output_path.to_path_buf(), max_size)?,
+    };
+    file.set_created_files(created.clone());
+    if let Some(size_limit) = size_limit {
+        file.set_size_limit(size_limit);
+    }
+    if let Some(bytes_per_sec) = options.max_write_bytes_per_sec {
+        file.set_max_write_rate(bytes_per_sec);
+    }
+    if let Some(min_free_bytes) = options.min_free_bytes {
+        file.set_min_free_space(min_free_bytes)?;
+    }
+    let mut script_queue = None;
+    if let Some(handler) = part_handler(options, info, output_path) {
+        match options.script_workers {
+            Some(workers) if workers > 0 => {
+                let queue = ScriptQueue::new(handler, workers);
+                file.set_listener(queue.submitter());
+                script_queue = Some(queue);
+            }
+            _ => file.set_listener(move |part: &FinishedPart| handler(part)),
+        }
+    }
+    if options.sync_parts {
+        file.set_sync_parts();
+    }
+    if options.part_list {
+        file.set_part_list();
+    }
+    if let Some(algorithm) = options.part_checksum {
+        file.set_checksum(algorithm);
+    }
+    if let Some(max_parts) = options.max_parts {
+        file.set_max_parts(max_parts);
+    }
+    if let Some(progress) = &options.progress {
+        file.set_progress(progress.clone());
+    }
+    if options.parity {
+        if max_size.is_some() {
+            file.enable_parity().context("Failed to create parity file")?;
+        } else {
+            warn!("Parity is enabled but the archive is not split, skipping parity for {:?}", output_path);
+        }
+    }
+    let mut split_at = None;
+    let stream = match (options.split_mode, max_size) {
+        (SplitMode::MultiVolume, Some(volume_size)) => ArchiveStream::multi_volume(file, volume_size)?,
+        (SplitMode::MultiVolume, None) => return Err(anyhow!("Multi-volume split_mode requires max_size_bytes")),
+        (SplitMode::Independent, Some(size)) => {
+            file.set_manual_rollover();
+            split_at = Some(size);
+            ArchiveStream::gzip(file, comp)
+        }
+        _ => ArchiveStream::gzip(file, comp),
+    };
+    let mut archive = ArchiveWriter::new(stream, comp, info, split_at)?;
+    if let Some(path) = &checkpoint_file {
+        let hash = info.hash.as_deref().unwrap_or_default();
+        archive.set_checkpoint(path.to_owned(), &info.segment, hash, entries_done);
+    }
+    if let Some(segment_hash) = segment_hash {
+        archive.set_segment_hash(segment_hash);
+    }
+    // (Entries archived by an interrupted run aren't appended again, so can't be indexed)
+    if options.index {
+        if options.split_mode == SplitMode::MultiVolume || !options.stores_locally() || entries_done > 0 {
+            warn!("Not indexing multi-volume, piped or resumed archive: {:?}", output_path);
+        } else {
+            archive.set_index();
+        }
+    }
 
     // Check if src_dir is a file or directory
+    let mut summary = ArchiveSummary::default();
     if metadata.is_file() {
         // Use the file's parent directory as base_dir so the relative path is just the filename
         let base_dir = src_dir.parent()
             .ok_or_else(|| anyhow!("File has no parent directory: {:?}", src_dir))?;
-        append_file(&mut tar, src_dir, base_dir)?;
+        if is_changed(src_dir, base_dir, changed_files) {
+            summary.uncompressed_bytes = append_file(&mut archive, src_dir, base_dir)?;
+            summary.file_count = 1;
+            if let Some(progress) = &options.progress {
+                progress.add_file(summary.uncompressed_bytes);
+            }
+        }
     } else if metadata.is_dir() {
-        append_dir_contents(&mut tar, src_dir, src_dir, exclusions, ignore_patterns)?;
+        append_dir_contents(&mut archive, src_dir, src_dir, exclusions, options, changed_files, &mut summary)?;
     } else {
         return Err(anyhow!("Path is neither a file nor a directory: {:?}", src_dir));
     }
 
-    tar.finish().context("Failed to finalize tar archive")?;
-    let mut writer = tar.into_inner()?.finish().context("Failed to finalize Gzip encoding")?;
-    writer.finalize()?;
-    Ok(())
+    // (Appended after the files, so resumed archives skip the same entries)
+    for entry in &options.entries {
+        let contents = entry.capture().context(format!("Failed to capture entry {:?}", entry.name))?;
+        summary.uncompressed_bytes += archive.append_contents(Path::new(&entry.name), &contents)?;
+        summary.file_count += 1;
+    }
+
+    summary.segment_hash = archive.take_segment_hash();
+    let index_entries = archive.take_index();
+    let mut writer = archive.finish()?;
+    summary.parts = writer.finalize()?;
+    if let Some(entries) = index_entries {
+        let index = ArchiveIndex::new(split_at.is_some() || summary.parts.count() == 1, &summary.parts.parts, entries);
+        created.add(index_path(output_path));
+        write_index(&index_path(output_path), &index)?;
+    }
+    if let Some(queue) = script_queue {
+        queue.finish().context("Post-script failed in background")?;
+    }
+    if let Some(path) = checkpoint_file
+        && path.exists() {
+        fs::remove_file(&path)
+            .context(format!("Failed to remove checkpoint file: {:?}", path))?;
+    }
+    Ok(summary)
 }
 
+/// Upload each finished part to the destinations, then run the post-script on it (None if neither is set)
+fn part_handler(options: &ArchiveOptions, info: &ArchiveInfo, output_path: &Path) -> Option<PartHandler> {
+    if options.destinations.is_empty() && options.hooks.script(HookKind::PartFinalized, Some(&info.segment)).is_none() {
+        return None;
+    }
+    let destinations = options.destinations.clone();
+    let uploads = options.uploads.clone();
+    let retry = options.retry;
+    let hooks = options.hooks.clone();
+    let segment = info.segment.clone();
+    let hash = info.hash.clone();
+    let output_dir = output_path.parent().map(Path::to_path_buf);
+    Some(Arc::new(move |part: &FinishedPart| {
+        if !destinations.is_empty() {
+            upload_all(&destinations, Path::new(&part.path), &uploads, &retry)?;
+        }
+        hooks.dispatch(&HookEvent::PartFinalized { segment: &segment, hash: hash.as_deref(), output_dir: output_dir.as_deref(), part })
+    }))
+}
 
 /// Recursively filter out 'exclusions' while adding files to the archive
 fn append_dir_contents(
-    tar: &mut tar::Builder<GzEncoder<RollingWriter>>,
+    archive: &mut ArchiveWriter,
     base_dir: &Path,
     current_dir: &Path,
     exclusions: &[&PathBuf],
-    ignore_patterns: Option<&GlobSet>,
+    options: &ArchiveOptions,
+    changed_files: Option<&HashSet<String>>,
+    summary: &mut ArchiveSummary,
 ) -> Result<()> {
-    let entries = collect_filtered_entries(current_dir, exclusions, ignore_patterns);
+    let entries = collect_filtered_entries(current_dir, exclusions, options.ignore_patterns.as_ref());
     
     // Track for determining empty directories
     let mut all_dirs: HashSet<PathBuf> = HashSet::new();
@@ -119,21 +419,35 @@ fn append_dir_contents(
             let dir_path = path.to_path_buf();
             if dir_path != base_dir && dir_path.starts_with(base_dir) {
                 all_dirs.insert(dir_path.clone());
-                if let Some(parent) = path.parent() {
-                    if parent != base_dir && parent.starts_with(base_dir) {
-                        non_empty_dirs.insert(parent.to_path_buf());
-                    }
+                if let Some(parent) = path.parent()
+                    && parent != base_dir && parent.starts_with(base_dir) {
+                    non_empty_dirs.insert(parent.to_path_buf());
                 }
             }
         } else if file_type.is_file() || file_type.is_symlink() {
+            // Unchanged files are left out of incremental archives (Their directory still isn't empty)
+            if !is_changed(path, base_dir, changed_files) {
+                trace!("Unchanged, left out of the increment: {:?}", path);
+                if let Some(parent) = path.parent()
+                    && parent != base_dir && parent.starts_with(base_dir) {
+                    non_empty_dirs.insert(parent.to_path_buf());
+                }
+                continue;
+            }
+
             // Add file/symlink to archive
-            match append_file(tar, path, base_dir) {
-                Ok(_) => {
+            match append_file(archive, path, base_dir) {
+                Ok(size) => {
+                    summary.file_count += 1;
+                    summary.uncompressed_bytes += size;
+                    if let Some(progress) = &options.progress {
+                        progress.add_file(size);
+                    }
+
                     // Mark parent dir as not-empty
-                    if let Some(parent) = path.parent() {
-                        if parent != base_dir && parent.starts_with(base_dir) {
-                            non_empty_dirs.insert(parent.to_path_buf());
-                        }
+                    if let Some(parent) = path.parent()
+                        && parent != base_dir && parent.starts_with(base_dir) {
+                        non_empty_dirs.insert(parent.to_path_buf());
                     }
                 }
                 Err(e) => {
@@ -143,67 +457,194 @@ fn append_dir_contents(
         }
     }
     
-    // Add empty directories to the archive
-    let empty_dirs: Vec<PathBuf> = all_dirs
+    // Add empty directories to the archive (Sorted so entries keep the same order across runs)
+    let mut empty_dirs: Vec<PathBuf> = all_dirs
         .difference(&non_empty_dirs)
         .cloned()
         .collect();
+    empty_dirs.sort();
     for dir_path in empty_dirs {
         if let Ok(relative_path) = dir_path.strip_prefix(base_dir) {
-            tar.append_dir(relative_path, &dir_path)?;
+            archive.append_dir(&dir_path, relative_path)?;
         }
     }
     
     Ok(())
 }
 
-/// Append a file to the archive
-fn append_file(tar: &mut tar::Builder<GzEncoder<RollingWriter>>, path: &Path, base_dir: &Path) -> Result<()> {
+/// True if a file belongs in the archive (Every file does, unless only changed files are archived)
+fn is_changed(path: &Path, base_dir: &Path, changed_files: Option<&HashSet<String>>) -> bool {
+    let Some(changed_files) = changed_files else {
+        return true;
+    };
+    path.strip_prefix(base_dir)
+        .is_ok_and(|relative_path| changed_files.contains(relative_path.to_string_lossy().as_ref()))
+}
+
+/// Append a file to the archive, returning the number of content bytes added
+fn append_file(archive: &mut ArchiveWriter, path: &Path, base_dir: &Path) -> Result<u64> {
     // Correctly map path relative to the archive root
     let relative_path = path.strip_prefix(base_dir)
         .context(format!("Failed to get relative path for {:?}", path))?;
+    archive.append_path(path, relative_path)
+}
 
-    // Check if this is a symlink
-    let is_symlink = match fs::symlink_metadata(&path) {
-        Ok(m) => m.file_type().is_symlink(),
-        Err(_) => false,
-    };
 
-    if is_symlink {
-        // Handle symlinks (including broken ones)
-        let target = fs::read_link(&path)
-            .context(format!("Failed to read symlink target: {:?}", path))?;
-        let mut header = tar::Header::new_gnu();
-        header.set_entry_type(tar::EntryType::Symlink);
-        header.set_mode(FILE_MODE_READ);
-        tar.append_link(&mut header, relative_path, &target)
-            .context(format!("Failed to add symlink to archive: {:?}", path))
-    } else {
-        // Regular file
-        tar.append_path_with_name(&path, relative_path)
-            .context(format!("Failed to add file to archive: {:?}", path))
+/// Why a script is being run (Passed to it as `SEG_ARC_STATUS`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptStatus {
+    /// pre_script, before the segment is hashed
+    Starting,
+    /// post_script, on a part of an archive that isn't finished yet
+    Part,
+    /// post_script, on the last part of an archive (Or its parity file or part list)
+    Complete,
+    /// skip_script, on an unchanged segment
+    Skipped,
+    /// on_failure_script
+    Failed,
+    /// on_finish_script, once every segment is processed
+    Finished,
+    /// segment_done hook, once a segment is archived
+    Archived,
+}
+
+impl ScriptStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScriptStatus::Starting => "starting",
+            ScriptStatus::Part => "part",
+            ScriptStatus::Complete => "complete",
+            ScriptStatus::Skipped => "skipped",
+            ScriptStatus::Failed => "failed",
+            ScriptStatus::Finished => "finished",
+            ScriptStatus::Archived => "archived",
+        }
     }
 }
 
+/// What a script is run for, passed to it in `SEG_ARC_*` environment variables (Unknown values are left unset)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptContext {
+    pub segment: Option<String>,
+    pub status: ScriptStatus,
+    pub hash: Option<String>,
+    pub output_dir: Option<PathBuf>,
+    pub part_index: Option<u32>,
+    pub part_count: Option<u32>,
+    /// Time of the segment's last backup (RFC 3339)
+    pub last_backup: Option<String>,
+    /// The segment's latest archive, if it's in the output directory
+    pub archive: Option<PathBuf>,
+    /// Run during a dry run (Nothing is archived)
+    pub dry_run: bool,
+}
+
+impl ScriptContext {
+    pub fn new(segment: Option<&str>, status: ScriptStatus) -> Self {
+        Self { segment: segment.map(str::to_string), status, hash: None, output_dir: None, part_index: None, part_count: None, last_backup: None, archive: None, dry_run: false }
+    }
+
+    /// The same context for a finished part
+    pub fn with_part(&self, part: &FinishedPart) -> Self {
+        Self {
+            status: if part.count.is_some() { ScriptStatus::Complete } else { ScriptStatus::Part },
+            part_index: part.index,
+            part_count: part.count,
+            ..self.clone()
+        }
+    }
 
-/// Executes an external script, returning exit code.
-pub fn execute_script(script_path: PathBuf, arg: &str) -> io::Result<i32> {
-    info!("Executing script w/ argument: {:?} {:?}", script_path, arg);
+    /// Environment variables to set (Every `SEG_ARC_*` variable is removed first, so unknown values aren't inherited)
+    pub fn env(&self) -> Vec<(&'static str, Option<String>)> {
+        vec![
+            ("SEG_ARC_SEGMENT", self.segment.clone()),
+            ("SEG_ARC_STATUS", Some(self.status.as_str().to_string())),
+            ("SEG_ARC_HASH", self.hash.clone()),
+            ("SEG_ARC_OUTPUT_DIR", self.output_dir.as_ref().map(|dir| dir.display().to_string())),
+            ("SEG_ARC_PART_INDEX", self.part_index.map(|index| index.to_string())),
+            ("SEG_ARC_PART_COUNT", self.part_count.map(|count| count.to_string())),
+            ("SEG_ARC_LAST_BACKUP", self.last_backup.clone()),
+            ("SEG_ARC_ARCHIVE", self.archive.as_ref().map(|archive| archive.display().to_string())),
+            ("SEG_ARC_DRY_RUN", self.dry_run.then(|| "1".to_string())),
+        ]
+    }
+}
 
-    let output = match Command::new(&script_path).arg(arg).output() {
+/// Executes an external script, retrying on any nonzero exit code, and returning the last exit code
+/// (A script that exits with an error code on every attempt is only warned about, like a single run)
+pub fn execute_script_with_retry(retry: &RetryPolicy, action: &str, script: &Script, args: &[&str], context: Option<&ScriptContext>, options: Option<&ScriptOptions>) -> io::Result<i32> {
+    let mut last_code = 0;
+    let result = retry.run(action, || {
+        last_code = 0;
+        match execute_script(script, args, context, options)? {
+            0 => Ok(0),
+            code => {
+                last_code = code;
+                Err(io::Error::other(format!("Exited with code {}", code)))
+            }
+        }
+    });
+    match result {
+        Err(_) if last_code != 0 => Ok(last_code),
+        result => result,
+    }
+}
+
+/// Executes an external script (With its context in the environment), returning exit code.
+pub fn execute_script(script: &Script, args: &[&str], context: Option<&ScriptContext>, options: Option<&ScriptOptions>) -> io::Result<i32> {
+    info!("Executing script w/ arguments: {} {:?}", script, args);
+
+    let (script_path, args) = match script {
+        Script::Path(script_path) => (script_path.clone(), args.iter().map(|arg| arg.to_string()).collect()),
+        Script::Template(template) => {
+            let mut template = fill_template(template, args, context)?.into_iter();
+            let program = template.next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Command template is empty"))?;
+            (PathBuf::from(program), template.collect::<Vec<_>>())
+        }
+    };
+
+    // (Relative script paths stay relative to the program's working directory, not the script's)
+    let cwd = options.and_then(|options| options.cwd.as_ref());
+    let is_command_line = script_path.to_str().is_some_and(|script| script.starts_with(SHELL_SCRIPT_PREFIX));
+    let script_path = match cwd {
+        Some(_) if !is_command_line && script_path.is_relative() && script_path.parent().is_some_and(|dir| !dir.as_os_str().is_empty()) =>
+            env::current_dir()?.join(script_path),
+        _ => script_path,
+    };
+    let script_path = script_path.as_path();
+    let mut command = match script {
+        Script::Path(_) => script_command(script_path),
+        Script::Template(_) => Command::new(script_path),
+    };
+    command.args(args);
+    if let Some(cwd) = cwd {
+        command.current_dir(cwd);
+    }
+    command.envs(options.map(|options| &options.env).into_iter().flatten());
+    for (name, value) in context.map(ScriptContext::env).unwrap_or_default() {
+        match value {
+            Some(value) => command.env(name, value),
+            None => command.env_remove(name),
+        };
+    }
+    let output = match command.output() {
         Ok(output) => output,
         Err(e) => {
             if e.kind() == io::ErrorKind::PermissionDenied {
                 // Handle common errors
-                let can_read = fs::metadata(&script_path).is_ok();
-                let error_msg = if can_read {
-                    format!("{} is missing execute permission.", script_path.display())
-                } else {
+                let can_read = fs::metadata(script_path).is_ok();
+                let error_msg = if !can_read {
                     format!("{} cannot be accessed due to permission issues.", script_path.display())
+                } else if cfg!(windows) {
+                    format!("{} can't be run (Scripts must be executables, or .bat, .cmd or .ps1 files).", script_path.display())
+                } else {
+                    format!("{} is missing execute permission.", script_path.display())
                 };
-                return Err(io::Error::new(io::ErrorKind::Other, error_msg))
+                return Err(io::Error::other(error_msg))
             }
-            return Err(io::Error::new(io::ErrorKind::Other, e.to_string()))
+            return Err(io::Error::other(e.to_string()))
         }
     };
 
@@ -211,17 +652,15 @@ pub fn execute_script(script_path: PathBuf, arg: &str) -> io::Result<i32> {
     let stdout_reader = BufReader::new(output.stdout.as_slice());
     let stderr_reader = BufReader::new(output.stderr.as_slice());
     for line in stdout_reader.lines() {
-        if let Ok(line) = line {
-            if !line.trim().is_empty() {
-                info!("Script> {}", line);
-            }
+        if let Ok(line) = line
+            && !line.trim().is_empty() {
+            info!("Script> {}", line);
         }
     }
     for line in stderr_reader.lines() {
-        if let Ok(line) = line {
-            if !line.trim().is_empty() {
-                warn!("Script> {}", line);
-            }
+        if let Ok(line) = line
+            && !line.trim().is_empty() {
+            warn!("Script> {}", line);
         }
     }
 
@@ -239,19 +678,111 @@ pub fn execute_script(script_path: PathBuf, arg: &str) -> io::Result<i32> {
 
     if exit_code == 0 {
         info!("Script finished successfully.");
-        Ok(0)
-    } else if exit_code < PROCESS_EXIT_CODE_THRESHOLD && exit_code > 0 {
-        warn!("Script finished with error code: {}", exit_code);
-        Ok(exit_code)
-    } else {
-        Err(io::Error::new(io::ErrorKind::Other, format!("Script panicked: {:?}", output.status)))
+        return Ok(0);
+    }
+    let policy = options.and_then(|options| options.exit_codes.as_ref());
+    match policy.and_then(|policy| policy.action(exit_code)).unwrap_or_else(|| default_exit_action(exit_code)) {
+        ExitAction::Ok => {
+            info!("Script finished with exit code {}, which is set as success.", exit_code);
+            Ok(0)
+        }
+        ExitAction::Warn => {
+            warn!("Script finished with error code: {}", exit_code);
+            Ok(exit_code)
+        }
+        ExitAction::Fatal if policy.is_some() && !is_abnormal_exit(exit_code) => {
+            Err(io::Error::other(format!("Script failed with exit code {}, which is set as fatal", exit_code)))
+        }
+        ExitAction::Fatal => Err(io::Error::other(format!("Script panicked: {:?}", output.status))),
+    }
+}
+
+/// What an exit code means without an exit code policy (Errors are warnings, unless the script crashed or was killed)
+fn default_exit_action(exit_code: i32) -> ExitAction {
+    match exit_code {
+        0 => ExitAction::Ok,
+        code if is_abnormal_exit(code) => ExitAction::Fatal,
+        _ => ExitAction::Warn,
+    }
+}
+
+/// Command that runs a script: `sh:` command lines with sh, and PowerShell scripts with PowerShell
+/// (Batch files are run directly, since on Windows the standard library runs them with cmd.exe, escaping their arguments)
+fn script_command(script_path: &Path) -> Command {
+    if let Some(command_line) = script_path.to_str().and_then(|script| script.strip_prefix(SHELL_SCRIPT_PREFIX)) {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(command_line).arg("segmented_archive");
+        return command;
+    }
+    let is_powershell = script_path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("ps1"));
+    if is_powershell {
+        let mut command = Command::new(if cfg!(windows) { "powershell" } else { "pwsh" });
+        command.args(["-NoProfile", "-NonInteractive", "-ExecutionPolicy", "Bypass", "-File"]).arg(script_path);
+        return command;
+    }
+    Command::new(script_path)
+}
+
+/// Fill in the `{placeholders}` of a command template (`{{` and `}}` are literal braces)
+/// - `{1}`, `{2}`, ...: The script's arguments
+/// - `{path}` (Or `{part}`) and `{file_name}` (Or `{part_name}`): The first argument, and its file name
+/// - `{segment}`, `{status}`, `{hash}`, ...: The script's context (As its `SEG_ARC_*` variable, empty if unset)
+fn fill_template(template: &[String], args: &[&str], context: Option<&ScriptContext>) -> io::Result<Vec<String>> {
+    let env = context.map(ScriptContext::env).unwrap_or_default();
+    let value = |name: &str| -> io::Result<String> {
+        let first = args.first().copied().unwrap_or_default();
+        match name {
+            "path" | "part" => return Ok(first.to_string()),
+            "file_name" | "part_name" => return Ok(Path::new(first).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default()),
+            _ => {}
+        }
+        if let Ok(number) = name.parse::<usize>() {
+            return number.checked_sub(1).and_then(|index| args.get(index)).map(|arg| arg.to_string())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Command template has no argument {{{}}}", name)));
+        }
+        let variable = format!("SEG_ARC_{}", name.to_uppercase());
+        env.iter().find(|(env_name, _)| *env_name == variable)
+            .map(|(_, value)| value.clone().unwrap_or_default())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown placeholder in command template: {{{}}}", name)))
+    };
+
+    template.iter().map(|arg| {
+        let mut filled = String::new();
+        let mut chars = arg.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    filled.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    filled.push('}');
+                }
+                '{' => {
+                    let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    filled.push_str(&value(&name)?);
+                }
+                c => filled.push(c),
+            }
+        }
+        Ok(filled)
+    }).collect()
+}
+
+/// True if an exit code means the script crashed or was killed, rather than reporting an error
+/// (128+ is a signal on Unix, while on Windows crashes are negative NTSTATUS codes, and any positive code can be a script's own)
+fn is_abnormal_exit(exit_code: i32) -> bool {
+    match cfg!(windows) {
+        true => exit_code < 0,
+        false => !(0..PROCESS_EXIT_CODE_THRESHOLD).contains(&exit_code),
     }
 }
 
-/// --- Helper Helpers --- ///
+// --- Helper Helpers --- //
 
 /// Strip the root path from a given path -- extracted to simplify testing
-fn strip_root(path: &Path, root_path: &Option<PathBuf>) -> Result<String> {
+pub fn strip_root(path: &Path, root_path: &Option<PathBuf>) -> Result<String> {
     Ok(match root_path {
         None => path.to_str()
             .ok_or_else(|| anyhow!("Invalid path string"))?
@@ -270,6 +801,26 @@ pub fn is_excluded(path: &Path, exclusions: &[&PathBuf]) -> bool {
     exclusions.iter().any(|&exclude_path| path.starts_with(exclude_path))
 }
 
+/// Describe the first archive output that is missing from `output_dir`, or None if they are all present
+/// (Checks the recorded parts and their sizes, or just the archive's first file when none are recorded)
+pub fn missing_output(output_dir: &Path, archive_path: &Path, parts: &[PartInfo]) -> Option<String> {
+    if parts.is_empty() {
+        let first_part = PathBuf::from(format!("{}.part001", archive_path.display()));
+        return (!archive_path.exists() && !first_part.exists())
+            .then(|| format!("{:?} not found", archive_path));
+    }
+    parts.iter().find_map(|part| {
+        let path = output_dir.join(&part.name);
+        match fs::metadata(&path) {
+            Err(_) => Some(format!("{:?} not found", path)),
+            Ok(metadata) if metadata.len() != part.size => {
+                Some(format!("{:?} is {} bytes (Expected {})", path, metadata.len(), part.size))
+            }
+            Ok(_) => None,
+        }
+    })
+}
+
 /// Collect filtered directory entries, applying exclusions and ignore patterns
 /// Returns all entries (files, directories, symlinks) that should be processed
 pub fn collect_filtered_entries(
@@ -277,7 +828,7 @@ pub fn collect_filtered_entries(
     exclusions: &[&PathBuf],
     ignore_patterns: Option<&GlobSet>,
 ) -> Vec<walkdir::DirEntry> {
-    let base_iter = WalkDir::new(base_dir).follow_links(false).into_iter();
+    let base_iter = WalkDir::new(base_dir).follow_links(false).sort_by_file_name().into_iter();
     
     // Collect entries first to avoid lifetime issues with the iterator
     let entries: Vec<_> = if !exclusions.is_empty() || ignore_patterns.is_some() {
@@ -287,13 +838,14 @@ pub fn collect_filtered_entries(
                 let path = entry.path();
                 
                 if is_excluded(path, exclusions) {
+                    debug!("Excluded (Part of another segment): {:?}", path);
                     return false;
                 }
                 
-                if let Some(patterns) = ignore_patterns {
-                    if patterns.is_match(path) {
-                        return false;
-                    }
+                if let Some(patterns) = ignore_patterns
+                    && patterns.is_match(path) {
+                    debug!("Ignored: {:?}", path);
+                    return false;
                 }
                 
                 true
@@ -314,20 +866,22 @@ pub fn collect_filtered_entries(
                     if is_excluded(path, exclusions) {
                         return None;
                     }
-                    if let Some(patterns) = ignore_patterns {
-                        if patterns.is_match(path) {
-                            return None;
-                        }
+                    if let Some(patterns) = ignore_patterns
+                        && patterns.is_match(path) {
+                        return None;
                     }
                     Some(e)
                 }
-                Err(_) => None,
+                Err(e) => {
+                    debug!("Skipped unreadable entry: {}", e);
+                    None
+                }
             }
         })
         .collect()
 }
 
-/// --- Tests --- ///
+// --- Tests --- //
 
 #[cfg(test)]
 mod tests {
@@ -335,8 +889,34 @@ mod tests {
     use std::path::PathBuf;
     use std::fs;
     use std::io::Read;
+    use std::collections::HashMap;
     use flate2::read::GzDecoder;
     use tar::Archive;
+    use crate::archive_info::INFO_FILE;
+    use crate::rolling_writer::partial_path;
+    use crate::hasher::{HashAlgorithm, HashOptions, compute_segment_hash};
+
+    #[test]
+    fn test_missing_output() {
+        let test_name = "missing_output";
+        let test_dir = setup_test_dir(test_name);
+        let archive_path = test_dir.join("docs.tar.gz");
+        assert!(missing_output(&test_dir, &archive_path, &[]).is_some());
+
+        // Without recorded parts, either the archive or its first part is enough
+        fs::write(test_dir.join("docs.tar.gz.part001"), [0u8; 10]).unwrap();
+        assert!(missing_output(&test_dir, &archive_path, &[]).is_none());
+
+        let part = |name: &str, size| PartInfo { name: name.to_string(), path: PathBuf::new(), size, checksum: None };
+        let parts = [part("docs.tar.gz.part001", 10), part("docs.tar.gz.part002", 5)];
+        assert!(missing_output(&test_dir, &archive_path, &parts).unwrap().contains("part002"));
+        fs::write(test_dir.join("docs.tar.gz.part002"), [0u8; 3]).unwrap();
+        assert!(missing_output(&test_dir, &archive_path, &parts).unwrap().contains("Expected 5"));
+        fs::write(test_dir.join("docs.tar.gz.part002"), [0u8; 5]).unwrap();
+        assert!(missing_output(&test_dir, &archive_path, &parts).is_none());
+
+        cleanup_test_dir(test_name);
+    }
 
     #[test]
     fn test_is_excluded() {
@@ -619,10 +1199,10 @@ mod tests {
         
         let globset = result.unwrap();
         // Test with full paths
-        assert!(globset.is_match(&PathBuf::from("/tmp/test_dir/file.tmp")));
-        assert!(globset.is_match(&PathBuf::from("/tmp/test_dir/.DS_Store")));
-        assert!(globset.is_match(&PathBuf::from("/tmp/test_dir/node_modules")));
-        assert!(!globset.is_match(&PathBuf::from("/tmp/test_dir/file.txt")));
+        assert!(globset.is_match(PathBuf::from("/tmp/test_dir/file.tmp")));
+        assert!(globset.is_match(PathBuf::from("/tmp/test_dir/.DS_Store")));
+        assert!(globset.is_match(PathBuf::from("/tmp/test_dir/node_modules")));
+        assert!(!globset.is_match(PathBuf::from("/tmp/test_dir/file.txt")));
     }
 
     #[test]
@@ -640,9 +1220,9 @@ mod tests {
         
         let globset = result.unwrap();
         // Test with full paths
-        assert!(globset.is_match(&PathBuf::from("/tmp/test_dir/node_modules")));
-        assert!(globset.is_match(&PathBuf::from("/tmp/test_dir/subdir/node_modules")));
-        assert!(globset.is_match(&PathBuf::from("/tmp/test_dir/deep/nested/node_modules")));
+        assert!(globset.is_match(PathBuf::from("/tmp/test_dir/node_modules")));
+        assert!(globset.is_match(PathBuf::from("/tmp/test_dir/subdir/node_modules")));
+        assert!(globset.is_match(PathBuf::from("/tmp/test_dir/deep/nested/node_modules")));
     }
 
     #[test]
@@ -653,9 +1233,9 @@ mod tests {
         
         let globset = result.unwrap();
         // Test with full paths - should match anything under /tmp
-        assert!(globset.is_match(&PathBuf::from("/tmp/test_file.txt")));
-        assert!(globset.is_match(&PathBuf::from("/tmp/subdir/file.txt")));
-        assert!(!globset.is_match(&PathBuf::from("/var/test_file.txt")));
+        assert!(globset.is_match(PathBuf::from("/tmp/test_file.txt")));
+        assert!(globset.is_match(PathBuf::from("/tmp/subdir/file.txt")));
+        assert!(!globset.is_match(PathBuf::from("/var/test_file.txt")));
     }
 
     #[test]
@@ -691,7 +1271,7 @@ mod tests {
         let root_path = Some(PathBuf::from("/tmp/files"));
         
         let path_str = strip_root(&src_dir, &root_path).unwrap();
-        assert!(path_str == "");
+        assert!(path_str.is_empty());
     }
 
     fn get_test_dir(test_name: &str) -> PathBuf {
@@ -709,6 +1289,13 @@ mod tests {
         test_dir
     }
 
+    /// Name of the script written by the execute_script tests (Batch files on Windows)
+    const TEST_SCRIPT_NAME: &str = if cfg!(windows) { "test_script.bat" } else { "test_script.sh" };
+
+    fn test_info(src_dir: &Path) -> ArchiveInfo {
+        ArchiveInfo::new("test", src_dir, &None, None).unwrap()
+    }
+
     fn extract_archive_contents(archive_path: &Path) -> Vec<String> {
         let file = fs::File::open(archive_path).unwrap();
         let decoder = GzDecoder::new(file);
@@ -747,10 +1334,13 @@ mod tests {
             &test_dir,
             &metadata,
             &archive_path,
-            &None,
             &exclusions,
-            ignore_matcher.as_ref(),
-            Some(6),
+            &test_info(&test_dir),
+            &ArchiveOptions {
+                ignore_patterns: ignore_matcher,
+                compression_level: Some(6),
+                ..Default::default()
+            },
             None,
             None,
         ).unwrap();
@@ -772,7 +1362,7 @@ mod tests {
         let test_dir = setup_test_dir(test_name);
         
         // Create a simple script that exits with 0
-        let script_path = test_dir.join("test_script.sh");
+        let script_path = test_dir.join(TEST_SCRIPT_NAME);
         #[cfg(unix)]
         {
             fs::write(&script_path, "#!/bin/bash\nexit 0\n").unwrap();
@@ -785,7 +1375,7 @@ mod tests {
             fs::write(&script_path, "@echo off\nexit /b 0\n").unwrap();
         }
         
-        let result = execute_script(script_path, "test_arg");
+        let result = execute_script(&Script::Path(script_path.clone()), &["test_arg"], None, None);
         assert!(result.is_ok(), "Script should execute successfully");
         assert_eq!(result.unwrap(), 0, "Script should return exit code 0");
         
@@ -798,7 +1388,7 @@ mod tests {
         let test_dir = setup_test_dir(test_name);
         
         // Create a script that exits with non-zero code
-        let script_path = test_dir.join("test_script.sh");
+        let script_path = test_dir.join(TEST_SCRIPT_NAME);
         #[cfg(unix)]
         {
             fs::write(&script_path, "#!/bin/bash\nexit 42\n").unwrap();
@@ -810,7 +1400,7 @@ mod tests {
             fs::write(&script_path, "@echo off\nexit /b 42\n").unwrap();
         }
         
-        let result = execute_script(script_path, "test_arg");
+        let result = execute_script(&Script::Path(script_path.clone()), &["test_arg"], None, None);
         assert!(result.is_ok(), "Script execution should not panic");
         assert_eq!(result.unwrap(), 42, "Script should return exit code 42");
         
@@ -825,7 +1415,7 @@ mod tests {
         // Try to execute a non-existent script
         let script_path = test_dir.join("nonexistent_script.sh");
         
-        let result = execute_script(script_path, "test_arg");
+        let result = execute_script(&Script::Path(script_path.clone()), &["test_arg"], None, None);
         assert!(result.is_err(), "Should return error for non-existent script");
         
         cleanup_test_dir(test_name);
@@ -846,7 +1436,7 @@ mod tests {
             // Remove execute permission
             fs::set_permissions(&script_path, fs::Permissions::from_mode(0o644)).unwrap();
             
-            let result = execute_script(script_path.clone(), "test_arg");
+            let result = execute_script(&Script::Path(script_path.clone()), &["test_arg"], None, None);
             assert!(result.is_err(), "Should return error for script without execute permission");
             
             // Verify the error message mentions permission
@@ -870,7 +1460,7 @@ mod tests {
         let test_dir = setup_test_dir(test_name);
         
         // Create a script that exits with code > 128 (simulating panic/abnormal termination)
-        let script_path = test_dir.join("test_script.sh");
+        let script_path = test_dir.join(TEST_SCRIPT_NAME);
         #[cfg(unix)]
         {
             fs::write(&script_path, "#!/bin/bash\nexit 255\n").unwrap();
@@ -879,12 +1469,11 @@ mod tests {
         }
         #[cfg(windows)]
         {
-            // Windows batch files can't easily exit with > 128, so we'll skip this test
-            // or use a different approach
-            fs::write(&script_path, "@echo off\nexit /b 255\n").unwrap();
+            // Any positive code can be a script's own on Windows, so crash with an NTSTATUS code instead (Access violation)
+            fs::write(&script_path, "@echo off\nexit /b -1073741819\n").unwrap();
         }
         
-        let result = execute_script(script_path, "test_arg");
+        let result = execute_script(&Script::Path(script_path.clone()), &["test_arg"], None, None);
         // The function should return an error for exit codes >= 128
         assert!(result.is_err(), "Should return error for exit code >= 128");
         
@@ -895,13 +1484,152 @@ mod tests {
         cleanup_test_dir(test_name);
     }
 
+    #[test]
+    fn test_script_command() {
+        let command = script_command(Path::new("upload.ps1"));
+        assert_eq!(command.get_program(), if cfg!(windows) { "powershell" } else { "pwsh" });
+        assert_eq!(command.get_args().last(), Some(std::ffi::OsStr::new("upload.ps1")));
+        assert_eq!(script_command(Path::new("upload.bat")).get_program(), "upload.bat");
+        assert_eq!(script_command(Path::new("sh:exit 0")).get_program(), "sh");
+
+        assert!(!is_abnormal_exit(1));
+        assert!(is_abnormal_exit(-1073741819), "Access violation on Windows");
+        assert_eq!(is_abnormal_exit(200), !cfg!(windows));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_execute_shell_script() {
+        let test_name = "shell_script";
+        let test_dir = setup_test_dir(test_name);
+        let output_file = test_dir.join("output.txt");
+
+        let script = Script::Path(PathBuf::from(format!("{}echo \"$1 $SEG_ARC_STATUS$SEG_ARC_DRY_RUN\" > {:?}", SHELL_SCRIPT_PREFIX, output_file)));
+        let context = ScriptContext::new(Some("docs"), ScriptStatus::Skipped);
+        assert_eq!(execute_script(&script, &["docs.tar.gz"], Some(&context), None).unwrap(), 0);
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), "docs.tar.gz skipped\n");
+        let context = ScriptContext { dry_run: true, ..context };
+        assert_eq!(execute_script(&script, &["docs.tar.gz"], Some(&context), None).unwrap(), 0);
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), "docs.tar.gz skipped1\n");
+
+        let script = Script::Path(PathBuf::from(format!("{}exit 3", SHELL_SCRIPT_PREFIX)));
+        assert_eq!(execute_script(&script, &["docs.tar.gz"], None, None).unwrap(), 3);
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_script_context_env() {
+        let context = ScriptContext {
+            hash: Some("xxh3:1234".to_string()),
+            last_backup: Some("2024-01-01T00:00:00+00:00".to_string()),
+            archive: Some(PathBuf::from("/backups/docs.tar.gz")),
+            ..ScriptContext::new(Some("docs"), ScriptStatus::Skipped)
+        };
+        let env: HashMap<_, _> = context.env().into_iter().collect();
+        assert_eq!(env["SEG_ARC_LAST_BACKUP"].as_deref(), Some("2024-01-01T00:00:00+00:00"));
+        assert_eq!(env["SEG_ARC_ARCHIVE"].as_deref(), Some("/backups/docs.tar.gz"));
+        assert_eq!(env["SEG_ARC_PART_INDEX"], None, "Unknown values are unset");
+        assert_eq!(env["SEG_ARC_DRY_RUN"], None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_execute_script_exit_codes() {
+        let options: ScriptOptions = toml::from_str("exit_codes = { ok = [1], fatal = [\"2-3\"], warn = [255] }").unwrap();
+        let run = |code: i32| execute_script(&Script::Path(PathBuf::from(format!("{}exit {}", SHELL_SCRIPT_PREFIX, code))), &[], None, Some(&options));
+
+        assert_eq!(run(1).unwrap(), 0, "Exit code 1 is set as success");
+        assert!(run(3).unwrap_err().to_string().contains("set as fatal"));
+        assert_eq!(run(255).unwrap(), 255, "Exit code 255 is set as a warning instead of a crash");
+        assert_eq!(run(4).unwrap(), 4, "Unlisted codes follow the default rules");
+        assert!(run(200).is_err());
+    }
+
+    #[test]
+    fn test_fill_template() {
+        let context = ScriptContext { part_index: Some(2), ..ScriptContext::new(Some("docs"), ScriptStatus::Part) };
+        let template = ["rclone", "copyto", "{part}", "remote:backups/{segment}/{part_name}", "--header={{\"index\": {part_index}}}", "{hash}"].map(String::from);
+        assert_eq!(fill_template(&template, &["/backups/docs.tar.gz.part002"], Some(&context)).unwrap(),
+            ["rclone", "copyto", "/backups/docs.tar.gz.part002", "remote:backups/docs/docs.tar.gz.part002", "--header={\"index\": 2}", ""]);
+        assert_eq!(fill_template(&["{2}".to_string(), "{1}".to_string()], &["docs", "Path not found"], None).unwrap(), ["Path not found", "docs"]);
+
+        let error = fill_template(&["{segmnet}".to_string()], &[], Some(&context)).unwrap_err();
+        assert_eq!(error.to_string(), "Unknown placeholder in command template: {segmnet}");
+        assert!(fill_template(&["{3}".to_string()], &["docs"], Some(&context)).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_execute_script_template() {
+        let test_name = "script_template";
+        let test_dir = setup_test_dir(test_name);
+        let part = test_dir.join("docs.tar.gz");
+        fs::write(&part, "archive").unwrap();
+
+        let script: Script = toml::from_str::<HashMap<String, Script>>("script = [\"cp\", \"{part}\", \"{part}.{status} copy\"]").unwrap().remove("script").unwrap();
+        let context = ScriptContext::new(Some("docs"), ScriptStatus::Complete);
+        assert_eq!(execute_script(&script, &[&part.display().to_string()], Some(&context), None).unwrap(), 0);
+        assert_eq!(fs::read_to_string(test_dir.join("docs.tar.gz.complete copy")).unwrap(), "archive", "Arguments aren't split by a shell");
+
+        let script = Script::Template(vec!["false".to_string()]);
+        assert_eq!(execute_script(&script, &[], Some(&context), None).unwrap(), 1);
+        assert!(execute_script(&Script::Template(Vec::new()), &[], Some(&context), None).is_err());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_execute_script_cwd_and_env() {
+        let test_name = "script_cwd_env";
+        let test_dir = setup_test_dir(test_name);
+        let options = ScriptOptions {
+            cwd: Some(test_dir.clone()),
+            env: HashMap::from([("UPLOAD_TOKEN".to_string(), "secret".to_string()), ("SEG_ARC_STATUS".to_string(), "ignored".to_string())]),
+            ..ScriptOptions::default()
+        };
+        let script = Script::Path(PathBuf::from(format!("{}echo \"$UPLOAD_TOKEN $SEG_ARC_STATUS\" > output.txt", SHELL_SCRIPT_PREFIX)));
+        let context = ScriptContext::new(Some("docs"), ScriptStatus::Starting);
+        assert_eq!(execute_script(&script, &[], Some(&context), Some(&options)).unwrap(), 0);
+        assert_eq!(fs::read_to_string(test_dir.join("output.txt")).unwrap(), "secret starting\n", "SEG_ARC_* variables can't be overridden");
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_execute_script_with_retry() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let test_name = "script_retry";
+        let test_dir = setup_test_dir(test_name);
+
+        // Fails with exit code 1 until it has run twice
+        let script_path = test_dir.join("flaky.sh");
+        let runs = test_dir.join("runs");
+        fs::write(&script_path, format!("#!/bin/bash\necho run >> {0:?}\n[ $(wc -l < {0:?}) -ge $1 ]\n", runs)).unwrap();
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755)).unwrap();
+        let retry = RetryPolicy { attempts: 3, delay_secs: 0.0, ..RetryPolicy::default() };
+
+        assert_eq!(execute_script_with_retry(&retry, "Test", &Script::Path(script_path.clone()), &["2"], None, None).unwrap(), 0);
+        assert_eq!(fs::read_to_string(&runs).unwrap().lines().count(), 2);
+
+        // Still exiting with an error code after every attempt returns the code
+        fs::remove_file(&runs).unwrap();
+        assert_eq!(execute_script_with_retry(&retry, "Test", &Script::Path(script_path.clone()), &["10"], None, None).unwrap(), 1);
+        assert_eq!(fs::read_to_string(&runs).unwrap().lines().count(), 3);
+
+        cleanup_test_dir(test_name);
+    }
+
     #[test]
     fn test_execute_script_with_argument() {
         let test_name = "post_script_arg";
         let test_dir = setup_test_dir(test_name);
         
         // Create a script that writes the argument to a file
-        let script_path = test_dir.join("test_script.sh");
+        let script_path = test_dir.join(TEST_SCRIPT_NAME);
         let output_file = test_dir.join("output.txt");
         
         #[cfg(unix)]
@@ -918,7 +1646,7 @@ mod tests {
         }
         
         let test_arg = "test_argument_value";
-        let result = execute_script(script_path, test_arg);
+        let result = execute_script(&Script::Path(script_path.clone()), &[test_arg], None, None);
         assert!(result.is_ok(), "Script should execute successfully");
         
         // Verify the argument was passed correctly
@@ -930,6 +1658,41 @@ mod tests {
         cleanup_test_dir(test_name);
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn test_create_archive_script_context() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let test_name = "script_context";
+        let test_dir = setup_test_dir(test_name);
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(&src_dir).unwrap();
+        fs::write(src_dir.join("data.bin"), (0..4096u32).map(|i| (i * 7919 % 251) as u8).collect::<Vec<u8>>()).unwrap();
+
+        // Records the context of each part (The count is only known on the last)
+        let script_path = test_dir.join("post.sh");
+        let calls = test_dir.join("calls");
+        fs::write(&script_path, format!("#!/bin/bash\necho \"$SEG_ARC_SEGMENT $SEG_ARC_STATUS $SEG_ARC_PART_INDEX/$SEG_ARC_PART_COUNT $SEG_ARC_HASH $SEG_ARC_OUTPUT_DIR\" >> {:?}\n", calls)).unwrap();
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let archive_path = test_dir.join("out").join("test.tar.gz");
+        fs::create_dir_all(archive_path.parent().unwrap()).unwrap();
+        let hooks = Hooks { scripts: HashMap::from([(HookKind::PartFinalized, Script::Path(script_path))]), ..Hooks::default() };
+        let options = ArchiveOptions { max_size_bytes: Some(1024), compression_level: Some(0), hooks: Arc::new(hooks), ..ArchiveOptions::default() };
+        let info = ArchiveInfo { hash: Some("abc123".to_string()), ..test_info(&src_dir) };
+        let summary = create_archive(&src_dir, &fs::metadata(&src_dir).unwrap(), &archive_path, &[], &info, &options, None, None).unwrap();
+
+        let count = summary.parts.count();
+        assert!(count > 1);
+        let calls = fs::read_to_string(&calls).unwrap();
+        let calls: Vec<&str> = calls.lines().collect();
+        let output_dir = test_dir.join("out");
+        assert_eq!(calls[0], format!("{} part 1/ abc123 {}", info.segment, output_dir.display()));
+        assert_eq!(calls[count - 1], format!("{} complete {}/{} abc123 {}", info.segment, count, count, output_dir.display()));
+
+        cleanup_test_dir(test_name);
+    }
+
     #[test]
     fn test_create_archive_empty_base_directory() {
         let test_name = "empty_base_dir";
@@ -947,10 +1710,12 @@ mod tests {
             &empty_dir,
             &metadata,
             &archive_path,
-            &None,
             &[],
-            None,
-            Some(6),
+            &test_info(&empty_dir),
+            &ArchiveOptions {
+                compression_level: Some(6),
+                ..Default::default()
+            },
             None,
             None,
         ).unwrap();
@@ -961,9 +1726,9 @@ mod tests {
         // Extract and verify contents
         let entries = extract_archive_contents(&archive_path);
         
-        // Should contain at least the path file (.seg_arc.path)
-        assert!(entries.iter().any(|e| e.contains(".seg_arc.path")), 
-            "Archive should contain path file");
+        // Should contain at least the info file (.seg_arc.json)
+        assert!(entries.iter().any(|e| e.contains(".seg_arc.json")), 
+            "Archive should contain info file");
         
         cleanup_test_dir(test_name);
     }
@@ -982,27 +1747,35 @@ mod tests {
         let metadata = fs::metadata(&test_file).unwrap();
         
         // Should succeed with a single file
-        create_archive(
+        let summary = create_archive(
             &test_file,
             &metadata,
             &archive_path,
-            &None,
             &[],
-            None,
-            Some(6),
+            &test_info(&test_file),
+            &ArchiveOptions {
+                compression_level: Some(6),
+                ..Default::default()
+            },
             None,
             None,
         ).unwrap();
         
         // Archive should exist and be valid
         assert!(archive_path.exists(), "Archive should be created for single file");
+        assert_eq!(summary.file_count, 1, "Summary should count the single file");
+        assert_eq!(summary.uncompressed_bytes, file_content.len() as u64);
+        assert_eq!(summary.parts.count(), 1);
+        assert_eq!(summary.parts.parts[0].name, "backup.tar.gz");
+        assert_eq!(summary.compression_ratio(), Some(summary.parts.total_size() as f64 / file_content.len() as f64));
+        assert_eq!(ArchiveSummary::default().compression_ratio(), None);
         
         // Extract and verify contents
         let entries = extract_archive_contents(&archive_path);
         
-        // Should contain the path file (.seg_arc.path)
-        assert!(entries.iter().any(|e| e.contains(".seg_arc.path")), 
-            "Archive should contain path file");
+        // Should contain the info file (.seg_arc.json)
+        assert!(entries.iter().any(|e| e.contains(".seg_arc.json")), 
+            "Archive should contain info file");
         
         // Should contain the file itself (just the filename, not full path)
         assert!(entries.iter().any(|e| e == "backup.bak"), 
@@ -1030,6 +1803,432 @@ mod tests {
         cleanup_test_dir(test_name);
     }
 
+    #[test]
+    fn test_create_archive_single_pass_hash() {
+        let test_name = "single_pass_hash";
+        let test_dir = setup_test_dir(test_name);
+        
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(src_dir.join("sub")).unwrap();
+        fs::write(src_dir.join("a.txt"), b"content a").unwrap();
+        fs::write(src_dir.join("a.txt.bak"), b"backup").unwrap();
+        fs::write(src_dir.join("sub").join("b.txt"), b"content b").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("a.txt", src_dir.join("link")).unwrap();
+        
+        let metadata = fs::metadata(&src_dir).unwrap();
+        let bak_files = build_ignore_matcher(&["*.bak".to_string()]).unwrap();
+        for (algorithm, include_metadata, hash_ignore) in [
+            (HashAlgorithm::Xxh3, false, None),
+            (HashAlgorithm::Blake3, true, None),
+            (HashAlgorithm::Xxh3, false, bak_files),
+        ] {
+            let options = HashOptions { algorithm, include_metadata, hash_ignore, ..Default::default() };
+            let expected = compute_segment_hash(&src_dir, &metadata, &[], None, &options, None).unwrap();
+            
+            let mut summary = create_archive(
+                &src_dir,
+                &metadata,
+                &test_dir.join("test.tar.gz"),
+                &[],
+                &test_info(&src_dir),
+                &ArchiveOptions::default(),
+                Some(StreamingSegmentHash::new(&options)),
+                None,
+            ).unwrap();
+            let hash = summary.segment_hash.take().unwrap().finish(None).unwrap();
+            assert_eq!(hash.hash, expected.hash, "{:?} hash while archiving should match a separate pass", algorithm);
+            assert_eq!(hash.files, expected.files, "{:?} file hashes while archiving should match a separate pass", algorithm);
+            assert_eq!(hash.unhashed, expected.unhashed);
+        }
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_create_archive_only_changed_files() {
+        let test_name = "only_changed";
+        let test_dir = setup_test_dir(test_name);
+
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(src_dir.join("sub")).unwrap();
+        fs::create_dir_all(src_dir.join("empty")).unwrap();
+        fs::write(src_dir.join("a.txt"), b"unchanged").unwrap();
+        fs::write(src_dir.join("sub/b.txt"), b"changed").unwrap();
+        fs::write(src_dir.join("sub/c.txt"), b"unchanged").unwrap();
+
+        let archive_path = test_dir.join("test.inc001.tar.gz");
+        let metadata = fs::metadata(&src_dir).unwrap();
+        let changed_files = HashSet::from(["sub/b.txt".to_string()]);
+        let summary = create_archive(&src_dir, &metadata, &archive_path, &[], &test_info(&src_dir), &ArchiveOptions::default(), None, Some(&changed_files)).unwrap();
+
+        assert_eq!(summary.file_count, 1);
+        assert_eq!(extract_archive_contents(&archive_path), [INFO_FILE, "empty", "sub/b.txt"]);
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_create_archive_command_entries() {
+        let test_name = "command_entries";
+        let test_dir = setup_test_dir(test_name);
+
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(&src_dir).unwrap();
+        fs::write(src_dir.join("a.txt"), b"file").unwrap();
+
+        let archive_path = test_dir.join("test.tar.gz");
+        let metadata = fs::metadata(&src_dir).unwrap();
+        let options = ArchiveOptions {
+            entries: vec![CommandEntry { name: "metadata/packages.txt".to_string(), command: "echo bash".to_string() }],
+            ..ArchiveOptions::default()
+        };
+        let summary = create_archive(&src_dir, &metadata, &archive_path, &[], &test_info(&src_dir), &options, None, None).unwrap();
+        assert_eq!(summary.file_count, 2);
+        assert_eq!(extract_archive_contents(&archive_path), [INFO_FILE, "a.txt", "metadata/packages.txt"]);
+
+        // A failed command fails the archive
+        let options = ArchiveOptions {
+            entries: vec![CommandEntry { name: "failed.txt".to_string(), command: "exit 1".to_string() }],
+            ..ArchiveOptions::default()
+        };
+        let error = create_archive(&src_dir, &metadata, &archive_path, &[], &test_info(&src_dir), &options, None, None).unwrap_err();
+        assert!(format!("{:#}", error).contains("Failed to capture entry \"failed.txt\""));
+        assert_eq!(extract_archive_contents(&archive_path), [INFO_FILE, "a.txt", "metadata/packages.txt"], "The previous archive should be kept");
+        assert!(!partial_path(&archive_path).exists());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_create_archive_info_entry() {
+        let test_name = "info_entry";
+        let test_dir = setup_test_dir(test_name);
+        
+        let src_dir = test_dir.join("files").join("docs");
+        fs::create_dir_all(&src_dir).unwrap();
+        fs::write(src_dir.join("file.txt"), b"content").unwrap();
+        
+        let archive_path = test_dir.join("docs.tar.gz");
+        let metadata = fs::metadata(&src_dir).unwrap();
+        let root_path = Some(test_dir.join("files"));
+        let info = ArchiveInfo::new("docs", &src_dir, &root_path, Some("0123456789abcdef".to_string())).unwrap();
+        create_archive(&src_dir, &metadata, &archive_path, &[], &info, &ArchiveOptions::default(), None, None).unwrap();
+        
+        // Info entry should be the first entry and contain the segment details
+        let file = fs::File::open(&archive_path).unwrap();
+        let mut archive = Archive::new(GzDecoder::new(file));
+        let mut entry = archive.entries().unwrap().next().unwrap().unwrap();
+        assert_eq!(entry.path().unwrap().to_string_lossy(), INFO_FILE);
+        let mut content = String::new();
+        entry.read_to_string(&mut content).unwrap();
+        
+        let read: ArchiveInfo = serde_json::from_str(&content).unwrap();
+        assert_eq!(read.segment, "docs");
+        assert_eq!(read.path, "docs");
+        assert_eq!(read.source_path, src_dir);
+        assert_eq!(read.root_path, root_path);
+        assert_eq!(read.hash, Some("0123456789abcdef".to_string()));
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_create_archive_independent_parts() {
+        let test_name = "independent_parts";
+        let test_dir = setup_test_dir(test_name);
+        
+        // Create several incompressible files, each smaller than a part
+        // (Sized well above the Gzip encoder's internal buffer, which delays the size check)
+        let src_dir = test_dir.join("src");
+        fs::create_dir(&src_dir).unwrap();
+        for i in 0..5u32 {
+            let data: Vec<u8> = (0..50000u32).map(|j| ((j + i * 7919).wrapping_mul(2654435761) >> 13) as u8).collect();
+            fs::write(src_dir.join(format!("file{}.bin", i)), &data).unwrap();
+        }
+        
+        let archive_path = test_dir.join("test.tar.gz");
+        let metadata = fs::metadata(&src_dir).unwrap();
+        let summary = create_archive(
+            &src_dir,
+            &metadata,
+            &archive_path,
+            &[],
+            &test_info(&src_dir),
+            &ArchiveOptions {
+                compression_level: Some(0),
+                max_size_bytes: Some(60000),
+                split_mode: SplitMode::Independent,
+                ..Default::default()
+            },
+            None,
+            None,
+        ).unwrap();
+        assert!(summary.parts.count() > 1, "Archive should be split");
+        
+        // Every part should be a standalone archive containing the info file
+        let mut all_files = Vec::new();
+        for part in &summary.parts.parts {
+            let entries = extract_archive_contents(&part.path);
+            assert!(entries.iter().any(|e| e == INFO_FILE), "Part {} should contain the info file", part.name);
+            all_files.extend(entries.into_iter().filter(|e| e != INFO_FILE));
+        }
+        all_files.sort();
+        assert_eq!(all_files, vec!["file0.bin", "file1.bin", "file2.bin", "file3.bin", "file4.bin"]);
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_create_archive_resumes_from_checkpoint() {
+        let test_name = "resume";
+        let test_dir = setup_test_dir(test_name);
+        
+        let src_dir = test_dir.join("src");
+        fs::create_dir(&src_dir).unwrap();
+        for i in 0..5u32 {
+            let data: Vec<u8> = (0..50000u32).map(|j| ((j + i * 7919).wrapping_mul(2654435761) >> 13) as u8).collect();
+            fs::write(src_dir.join(format!("file{}.bin", i)), &data).unwrap();
+        }
+        let archive_path = test_dir.join("test.tar.gz");
+        let metadata = fs::metadata(&src_dir).unwrap();
+        let info = ArchiveInfo::new("test", &src_dir, &None, Some("0123456789abcdef".to_string())).unwrap();
+        let mut options = ArchiveOptions {
+            compression_level: Some(0),
+            max_size_bytes: Some(60000),
+            split_mode: SplitMode::Independent,
+            resumable: true,
+            // Interrupt the first run when it opens a third part
+            max_parts: Some(2),
+            ..Default::default()
+        };
+        
+        let result = create_archive(&src_dir, &metadata, &archive_path, &[], &info, &options, None, None);
+        assert!(result.is_err(), "First run should be interrupted");
+        let checkpoint = read_checkpoint(&checkpoint_path(&archive_path)).unwrap();
+        assert_eq!(checkpoint.parts.len(), 1);
+        let first_part = test_dir.join(&checkpoint.parts[0].name);
+        assert!(!first_part.exists(), "Parts should keep their temporary names until the archive is finished");
+        let first_part_data = fs::read(partial_path(&first_part)).unwrap();
+        
+        options.max_parts = None;
+        let summary = create_archive(&src_dir, &metadata, &archive_path, &[], &info, &options, None, None).unwrap();
+        assert_eq!(fs::read(&first_part).unwrap(), first_part_data, "Completed part should not be rewritten");
+        assert!(!checkpoint_path(&archive_path).exists(), "Checkpoint should be removed on success");
+        assert_eq!(summary.file_count, 5);
+        
+        // Every file is archived exactly once across the old and new parts
+        let mut all_files = Vec::new();
+        for part in &summary.parts.parts {
+            all_files.extend(extract_archive_contents(&part.path).into_iter().filter(|e| e != INFO_FILE));
+        }
+        all_files.sort();
+        assert_eq!(all_files, vec!["file0.bin", "file1.bin", "file2.bin", "file3.bin", "file4.bin"]);
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_create_archive_failure_removes_parts() {
+        let test_name = "failure_cleanup";
+        let test_dir = setup_test_dir(test_name);
+        
+        let src_dir = test_dir.join("src");
+        fs::create_dir(&src_dir).unwrap();
+        let data: Vec<u8> = (0..20000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        fs::write(src_dir.join("data.bin"), &data).unwrap();
+        
+        // Exceeding max_parts fails after some parts have been written
+        let out_dir = test_dir.join("out");
+        fs::create_dir(&out_dir).unwrap();
+        let metadata = fs::metadata(&src_dir).unwrap();
+        let result = create_archive(
+            &src_dir,
+            &metadata,
+            &out_dir.join("test.tar.gz"),
+            &[],
+            &test_info(&src_dir),
+            &ArchiveOptions {
+                compression_level: Some(0),
+                max_size_bytes: Some(5000),
+                max_parts: Some(2),
+                parity: true,
+                ..Default::default()
+            },
+            None,
+            None,
+        );
+        assert!(result.is_err(), "Archive should fail after max_parts");
+        
+        let remaining: Vec<_> = fs::read_dir(&out_dir).unwrap().collect();
+        assert!(remaining.is_empty(), "Partial parts should be removed: {:?}", remaining);
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_create_archive_failure_keeps_previous_archive() {
+        let test_name = "failure_keeps_previous";
+        let test_dir = setup_test_dir(test_name);
+        
+        let src_dir = test_dir.join("src");
+        fs::create_dir(&src_dir).unwrap();
+        let data: Vec<u8> = (0..20000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        fs::write(src_dir.join("data.bin"), &data).unwrap();
+        let metadata = fs::metadata(&src_dir).unwrap();
+        
+        // A complete archive from a previous run
+        let out_dir = test_dir.join("out");
+        fs::create_dir(&out_dir).unwrap();
+        let archive_path = out_dir.join("test.tar.gz");
+        create_archive(&src_dir, &metadata, &archive_path, &[], &test_info(&src_dir), &ArchiveOptions::default(), None, None).unwrap();
+        let previous = fs::read(&archive_path).unwrap();
+        
+        // Fails before anything is written
+        let options = ArchiveOptions { compression_level: Some(10), ..Default::default() };
+        assert!(create_archive(&src_dir, &metadata, &archive_path, &[], &test_info(&src_dir), &options, None, None).is_err());
+        assert_eq!(fs::read(&archive_path).unwrap(), previous, "Previous archive should be kept");
+        
+        // Fails after some parts have been written (Only those parts are removed)
+        let options = ArchiveOptions {
+            compression_level: Some(0),
+            max_size_bytes: Some(5000),
+            max_parts: Some(2),
+            parity: true,
+            ..Default::default()
+        };
+        assert!(create_archive(&src_dir, &metadata, &archive_path, &[], &test_info(&src_dir), &options, None, None).is_err());
+        let remaining: Vec<_> = fs::read_dir(&out_dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(remaining, ["test.tar.gz"], "Only this run's parts should be removed");
+        assert_eq!(fs::read(&archive_path).unwrap(), previous);
+        
+        // Fails after the file of the same name has been written to
+        let options = ArchiveOptions {
+            entries: vec![CommandEntry { name: "failed.txt".to_string(), command: "exit 1".to_string() }],
+            ..Default::default()
+        };
+        assert!(create_archive(&src_dir, &metadata, &archive_path, &[], &test_info(&src_dir), &options, None, None).is_err());
+        let remaining: Vec<_> = fs::read_dir(&out_dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(remaining, ["test.tar.gz"], "Only the temporary file should be removed");
+        assert_eq!(fs::read(&archive_path).unwrap(), previous);
+        
+        // A split archive fails after rewriting parts of the same names
+        let split_options = ArchiveOptions { compression_level: Some(0), max_size_bytes: Some(5000), ..Default::default() };
+        let summary = create_archive(&src_dir, &metadata, &archive_path, &[], &test_info(&src_dir), &split_options, None, None).unwrap();
+        assert!(summary.parts.count() > 2);
+        let previous_parts: Vec<_> = summary.parts.parts.iter().map(|part| fs::read(&part.path).unwrap()).collect();
+        let options = ArchiveOptions { max_parts: Some(2), ..split_options };
+        assert!(create_archive(&src_dir, &metadata, &archive_path, &[], &test_info(&src_dir), &options, None, None).is_err());
+        let remaining = fs::read_dir(&out_dir).unwrap().count();
+        assert_eq!(remaining, summary.parts.count(), "Only the temporary files should be removed");
+        for (part, previous) in summary.parts.parts.iter().zip(previous_parts) {
+            assert_eq!(fs::read(&part.path).unwrap(), previous, "Previous part {} should be kept", part.name);
+        }
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_create_archive_strict_size_limit() {
+        let test_name = "strict_limit";
+        let test_dir = setup_test_dir(test_name);
+        
+        let src_dir = test_dir.join("src");
+        fs::create_dir(&src_dir).unwrap();
+        let data: Vec<u8> = (0..20000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        fs::write(src_dir.join("data.bin"), &data).unwrap();
+        
+        let out_dir = test_dir.join("out");
+        fs::create_dir(&out_dir).unwrap();
+        let archive_path = out_dir.join("test.tar.gz");
+        let metadata = fs::metadata(&src_dir).unwrap();
+        let strict = |max_size_bytes| ArchiveOptions {
+            compression_level: Some(0),
+            max_size_bytes: Some(max_size_bytes),
+            split_mode: SplitMode::Strict,
+            ..Default::default()
+        };
+        
+        // Too large: fails without leaving any parts behind
+        let result = create_archive(&src_dir, &metadata, &archive_path, &[], &test_info(&src_dir), &strict(5000), None, None);
+        assert!(result.is_err(), "Archive over the size limit should fail");
+        assert_eq!(fs::read_dir(&out_dir).unwrap().count(), 0);
+        
+        // Within the limit: a single unnumbered file
+        let summary = create_archive(&src_dir, &metadata, &archive_path, &[], &test_info(&src_dir), &strict(100000), None, None).unwrap();
+        assert_eq!(summary.parts.count(), 1);
+        assert!(archive_path.exists());
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_create_archive_multi_volume() {
+        let test_name = "multi_volume";
+        let test_dir = setup_test_dir(test_name);
+        
+        let src_dir = test_dir.join("src");
+        fs::create_dir(&src_dir).unwrap();
+        let data: Vec<u8> = (0..20000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        fs::write(src_dir.join("data.bin"), &data).unwrap();
+        
+        let archive_path = test_dir.join("test.tar");
+        let metadata = fs::metadata(&src_dir).unwrap();
+        let options = ArchiveOptions {
+            max_size_bytes: Some(8192),
+            split_mode: SplitMode::MultiVolume,
+            ..Default::default()
+        };
+        let summary = create_archive(&src_dir, &metadata, &archive_path, &[], &test_info(&src_dir), &options, None, None).unwrap();
+        
+        // Uncompressed data is split across several volumes, each within max_size_bytes
+        assert!(summary.parts.count() >= 3, "Archive should be split into volumes");
+        assert!(summary.parts.parts.iter().all(|part| part.size <= 8192));
+        assert_eq!(options.split_mode.archive_extension(), "tar");
+        
+        // The first volume starts with a plain tar header
+        let first = fs::read(&summary.parts.parts[0].path).unwrap();
+        assert_eq!(&first[257..263], b"ustar ");
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_create_archive_with_parity() {
+        let test_name = "parity";
+        let test_dir = setup_test_dir(test_name);
+        
+        // Create incompressible content so the archive is split
+        let src_dir = test_dir.join("src");
+        fs::create_dir(&src_dir).unwrap();
+        let data: Vec<u8> = (0..20000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        fs::write(src_dir.join("data.bin"), &data).unwrap();
+        
+        let archive_path = test_dir.join("test.tar.gz");
+        let metadata = fs::metadata(&src_dir).unwrap();
+        create_archive(
+            &src_dir,
+            &metadata,
+            &archive_path,
+            &[],
+            &test_info(&src_dir),
+            &ArchiveOptions {
+                compression_level: Some(0),
+                max_size_bytes: Some(8192),
+                parity: true,
+                ..Default::default()
+            },
+            None,
+            None,
+        ).unwrap();
+        
+        // Parity file should be created next to the parts
+        assert!(test_dir.join("test.tar.gz.part002").exists(), "Archive should be split");
+        assert!(test_dir.join("test.tar.gz.parity").exists(), "Parity file should be created");
+        
+        cleanup_test_dir(test_name);
+    }
+
     #[test]
     fn test_create_archive_compression_level_validation() {
         let test_name = "compression_validation";
@@ -1047,10 +2246,12 @@ mod tests {
                 &test_dir,
                 &metadata,
                 &archive_path,
-                &None,
                 &[],
-                None,
-                Some(level),
+                &test_info(&test_dir),
+                &ArchiveOptions {
+                    compression_level: Some(level),
+                    ..Default::default()
+                },
                 None,
                 None,
             );
@@ -1062,10 +2263,12 @@ mod tests {
             &test_dir,
             &metadata,
             &archive_path,
-            &None,
             &[],
-            None,
-            Some(10),
+            &test_info(&test_dir),
+            &ArchiveOptions {
+                compression_level: Some(10),
+                ..Default::default()
+            },
             None,
             None,
         );
@@ -1079,10 +2282,12 @@ mod tests {
             &test_dir,
             &metadata,
             &archive_path,
-            &None,
             &[],
-            None,
-            Some(100),
+            &test_info(&test_dir),
+            &ArchiveOptions {
+                compression_level: Some(100),
+                ..Default::default()
+            },
             None,
             None,
         );
@@ -1126,10 +2331,12 @@ mod tests {
             &test_dir,
             &metadata,
             &archive_path,
-            &None,
             &[],
-            None,
-            Some(6),
+            &test_info(&test_dir),
+            &ArchiveOptions {
+                compression_level: Some(6),
+                ..Default::default()
+            },
             None,
             None,
         );
@@ -1193,10 +2400,12 @@ mod tests {
             &base_dir,
             &metadata,
             &archive_path,
-            &root_path,
             &[],
-            None,
-            Some(6),
+            &ArchiveInfo::new("test", &base_dir, &root_path, None).unwrap(),
+            &ArchiveOptions {
+                compression_level: Some(6),
+                ..Default::default()
+            },
             None,
             None,
         );
@@ -1215,9 +2424,9 @@ mod tests {
         assert!(entries.iter().any(|e| e.contains("LastFolder.Component/file.txt")), 
             "Archive should contain the file");
         
-        // Verify the path file exists (the exact content depends on root_path logic)
-        assert!(entries.iter().any(|e| e.contains(".seg_arc.path")), 
-            "Archive should contain path file");
+        // Verify the info file exists (the exact content depends on root_path logic)
+        assert!(entries.iter().any(|e| e.contains(".seg_arc.json")), 
+            "Archive should contain info file");
         
         cleanup_test_dir(test_name);
     }
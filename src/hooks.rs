@@ -0,0 +1,248 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::helpers::{ScriptContext, ScriptStatus, execute_script, execute_script_with_retry};
+use crate::retry::RetryPolicy;
+use crate::rolling_writer::FinishedPart;
+use crate::script_options::{Script, ScriptsConfig};
+
+/// Points in a run where a script can be run (The keys of the `hooks` table)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookKind {
+    SegmentStart,
+    PartFinalized,
+    SegmentDone,
+    SegmentSkipped,
+    RunDone,
+    Failure,
+}
+
+impl HookKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookKind::SegmentStart => "segment_start",
+            HookKind::PartFinalized => "part_finalized",
+            HookKind::SegmentDone => "segment_done",
+            HookKind::SegmentSkipped => "segment_skipped",
+            HookKind::RunDone => "run_done",
+            HookKind::Failure => "failure",
+        }
+    }
+
+    /// The config key that set this hook's script before the `hooks` table (If any)
+    pub fn script_key(&self) -> Option<&'static str> {
+        match self {
+            HookKind::SegmentStart => Some("pre_script"),
+            HookKind::PartFinalized => Some("post_script"),
+            HookKind::SegmentDone => None,
+            HookKind::SegmentSkipped => Some("skip_script"),
+            HookKind::RunDone => Some("on_finish_script"),
+            HookKind::Failure => Some("on_failure_script"),
+        }
+    }
+
+    /// Name of the script in the log
+    fn label(&self) -> &'static str {
+        match self {
+            HookKind::SegmentStart => "Pre-script",
+            HookKind::PartFinalized => "Post-script",
+            HookKind::SegmentDone => "Segment-done script",
+            HookKind::SegmentSkipped => "Skip-script",
+            HookKind::RunDone => "On-finish script",
+            HookKind::Failure => "On-failure script",
+        }
+    }
+}
+
+/// Something that happened during a run, passed to its hook's script as arguments and `SEG_ARC_*` variables
+pub enum HookEvent<'a> {
+    /// A segment is about to be hashed (Its script's error code vetoes the segment)
+    SegmentStart { segment: &'a str, path: &'a Path, output_dir: &'a Path },
+    /// A part of a segment's archive is finished (Or its parity file or part list)
+    PartFinalized { segment: &'a str, hash: Option<&'a str>, output_dir: Option<&'a Path>, part: &'a FinishedPart },
+    /// A segment's archive is finished, and its parts handled
+    SegmentDone { segment: &'a str, hash: Option<&'a str>, output_dir: &'a Path, archive: &'a Path, parts: usize },
+    /// A segment is unchanged since its last backup (`archive` is where it would be archived, `latest_archive` where it was)
+    SegmentSkipped { segment: &'a str, hash: Option<&'a str>, output_dir: &'a Path, archive: &'a Path, last_backup: Option<&'a str>, latest_archive: Option<PathBuf> },
+    /// Every segment was processed (With the path to the run summary)
+    RunDone { summary_file: &'a Path },
+    /// A segment failed (Or the run itself, without a segment)
+    Failure { segment: Option<&'a str>, error: &'a str },
+}
+
+impl HookEvent<'_> {
+    pub fn kind(&self) -> HookKind {
+        match self {
+            HookEvent::SegmentStart { .. } => HookKind::SegmentStart,
+            HookEvent::PartFinalized { .. } => HookKind::PartFinalized,
+            HookEvent::SegmentDone { .. } => HookKind::SegmentDone,
+            HookEvent::SegmentSkipped { .. } => HookKind::SegmentSkipped,
+            HookEvent::RunDone { .. } => HookKind::RunDone,
+            HookEvent::Failure { .. } => HookKind::Failure,
+        }
+    }
+
+    pub fn segment(&self) -> Option<&str> {
+        match self {
+            HookEvent::SegmentStart { segment, .. }
+            | HookEvent::PartFinalized { segment, .. }
+            | HookEvent::SegmentDone { segment, .. }
+            | HookEvent::SegmentSkipped { segment, .. } => Some(segment),
+            HookEvent::RunDone { .. } => None,
+            HookEvent::Failure { segment, .. } => *segment,
+        }
+    }
+
+    /// The script's arguments
+    fn args(&self) -> Vec<String> {
+        match self {
+            HookEvent::SegmentStart { path, .. } => vec![path.display().to_string()],
+            HookEvent::PartFinalized { part, .. } => vec![part.path.clone()],
+            HookEvent::SegmentDone { archive, .. } | HookEvent::SegmentSkipped { archive, .. } => vec![archive.display().to_string()],
+            HookEvent::RunDone { summary_file } => vec![summary_file.display().to_string()],
+            HookEvent::Failure { segment, error } => vec![segment.unwrap_or_default().to_string(), error.to_string()],
+        }
+    }
+
+    /// The script's context
+    fn context(&self) -> ScriptContext {
+        match self {
+            HookEvent::SegmentStart { segment, output_dir, .. } => ScriptContext {
+                output_dir: Some(output_dir.to_path_buf()),
+                ..ScriptContext::new(Some(segment), ScriptStatus::Starting)
+            },
+            HookEvent::PartFinalized { segment, hash, output_dir, part } => ScriptContext {
+                hash: hash.map(str::to_string),
+                output_dir: output_dir.map(Path::to_path_buf),
+                ..ScriptContext::new(Some(segment), ScriptStatus::Part)
+            }.with_part(part),
+            HookEvent::SegmentDone { segment, hash, output_dir, archive, parts } => ScriptContext {
+                hash: hash.map(str::to_string),
+                output_dir: Some(output_dir.to_path_buf()),
+                part_count: u32::try_from(*parts).ok(),
+                archive: Some(archive.to_path_buf()),
+                ..ScriptContext::new(Some(segment), ScriptStatus::Archived)
+            },
+            HookEvent::SegmentSkipped { segment, hash, output_dir, last_backup, latest_archive, .. } => ScriptContext {
+                hash: hash.map(str::to_string),
+                output_dir: Some(output_dir.to_path_buf()),
+                last_backup: last_backup.map(str::to_string),
+                archive: latest_archive.clone(),
+                ..ScriptContext::new(Some(segment), ScriptStatus::Skipped)
+            },
+            HookEvent::RunDone { .. } => ScriptContext::new(None, ScriptStatus::Finished),
+            HookEvent::Failure { segment, .. } => ScriptContext::new(segment.filter(|segment| !segment.is_empty()), ScriptStatus::Failed),
+        }
+    }
+}
+
+/// The scripts run on each kind of event, and how they are run
+#[derive(Debug, Default)]
+pub struct Hooks {
+    pub scripts: HashMap<HookKind, Script>,
+    /// Scripts set on segments, run instead of the global ones
+    pub segment_scripts: HashMap<String, HashMap<HookKind, Script>>,
+    pub options: ScriptsConfig,
+    /// Retries post-scripts that fail to run, or are killed
+    pub retry: RetryPolicy,
+    /// If set, post and skip scripts are retried with this on any nonzero exit code (Instead of with `retry`)
+    pub script_retry: Option<RetryPolicy>,
+    /// Scripts are run during a dry run (With `SEG_ARC_DRY_RUN` set)
+    pub dry_run: bool,
+}
+
+impl Hooks {
+    /// The script run on an event of a segment (Its own, or the global one)
+    pub fn script(&self, kind: HookKind, segment: Option<&str>) -> Option<&Script> {
+        segment.and_then(|segment| self.segment_scripts.get(segment))
+            .and_then(|scripts| scripts.get(&kind))
+            .or_else(|| self.scripts.get(&kind))
+    }
+
+    /// Run the script for an event, returning its exit code (0 if there is no script)
+    pub fn dispatch(&self, event: &HookEvent) -> io::Result<i32> {
+        let kind = event.kind();
+        let Some(script) = self.script(kind, event.segment()) else {
+            return Ok(0);
+        };
+        let args = event.args();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let context = ScriptContext { dry_run: self.dry_run, ..event.context() };
+        let options = self.options.get(kind);
+        let action = match event {
+            HookEvent::PartFinalized { part, .. } => format!("{} for {:?}", kind.label(), part.path),
+            _ => kind.label().to_string(),
+        };
+        match (kind, self.script_retry) {
+            (HookKind::PartFinalized | HookKind::SegmentSkipped, Some(script_retry)) =>
+                execute_script_with_retry(&script_retry, &action, script, &args, Some(&context), Some(options)),
+            (HookKind::PartFinalized, None) => self.retry.run(&action, || execute_script(script, &args, Some(&context), Some(options))),
+            _ => execute_script(script, &args, Some(&context), Some(options)),
+        }
+    }
+}
+
+/// Scripts for each kind of event, from a `hooks` table and the older script keys (i.e. `pre_script`)
+///
+/// # Errors
+/// Fails if a script is set by both
+pub fn collect_scripts(mut hooks: HashMap<HookKind, Script>, scripts: impl IntoIterator<Item = (HookKind, Option<Script>)>) -> Result<HashMap<HookKind, Script>> {
+    for (kind, script) in scripts {
+        if let Some(script) = script
+            && hooks.insert(kind, script).is_some() {
+            return Err(anyhow!("{} and hooks.{} can't both be set", kind.script_key().unwrap_or_default(), kind.name()));
+        }
+    }
+    Ok(hooks)
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_collect_scripts() {
+        let hooks: HashMap<HookKind, Script> = toml::from_str("segment_done = \"./done.sh\"\npart_finalized = [\"rclone\", \"copy\", \"{part}\", \"remote:\"]").unwrap();
+        let scripts = collect_scripts(hooks.clone(), [(HookKind::SegmentStart, Some(Script::Path(PathBuf::from("./pre.sh")))), (HookKind::Failure, None)]).unwrap();
+        assert_eq!(scripts.len(), 3);
+        assert_eq!(scripts[&HookKind::SegmentDone], Script::Path(PathBuf::from("./done.sh")));
+
+        let error = collect_scripts(hooks, [(HookKind::PartFinalized, Some(Script::Path(PathBuf::from("./post.sh"))))]).unwrap_err();
+        assert_eq!(error.to_string(), "post_script and hooks.part_finalized can't both be set");
+        assert!(toml::from_str::<HashMap<HookKind, Script>>("segment_finished = \"./done.sh\"").is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_dispatch() {
+        let test_dir = PathBuf::from("/tmp/hooks_test_dispatch");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+        let calls = test_dir.join("calls");
+        let record = |label: &str| Script::Path(PathBuf::from(format!("sh:echo \"{} $1 $SEG_ARC_STATUS $SEG_ARC_PART_COUNT\" >> {:?}", label, calls)));
+        let hooks = Hooks {
+            scripts: HashMap::from([(HookKind::SegmentDone, record("global")), (HookKind::Failure, record("failure"))]),
+            segment_scripts: HashMap::from([("photos".to_string(), HashMap::from([(HookKind::SegmentDone, record("photos"))]))]),
+            ..Hooks::default()
+        };
+
+        for segment in ["docs", "photos"] {
+            let event = HookEvent::SegmentDone { segment, hash: None, output_dir: &test_dir, archive: Path::new("/backups/archive.tar.gz"), parts: 3 };
+            assert_eq!(hooks.dispatch(&event).unwrap(), 0);
+        }
+        assert_eq!(hooks.dispatch(&HookEvent::Failure { segment: None, error: "Failed to prune" }).unwrap(), 0);
+        assert_eq!(hooks.dispatch(&HookEvent::RunDone { summary_file: Path::new("/tmp/summary.json") }).unwrap(), 0, "No script is set");
+        assert_eq!(fs::read_to_string(&calls).unwrap().lines().collect::<Vec<_>>(), [
+            "global /backups/archive.tar.gz archived 3",
+            "photos /backups/archive.tar.gz archived 3",
+            "failure  failed ",
+        ]);
+
+        let _ = fs::remove_dir_all(&test_dir);
+    }
+}
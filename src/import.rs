@@ -0,0 +1,100 @@
+use std::path::{Path, PathBuf};
+use crate::archive_info::read_archive_info;
+use crate::hasher::SegmentRecord;
+use crate::sidecar::{read_sidecar, sidecar_path};
+
+/// Hash record for a segment's existing archive, from its metadata file or else the archive's info entry
+/// (None if neither has a hash)
+pub fn import_record(output_dir: &Path, segment: &str, archive_path: &Path) -> Option<SegmentRecord> {
+    if let Ok(sidecar) = read_sidecar(&sidecar_path(output_dir, segment))
+        && let Some(hash) = sidecar.hash {
+        return Some(SegmentRecord {
+            last_backup: Some(sidecar.created),
+            file_count: Some(sidecar.file_count),
+            uncompressed_bytes: Some(sidecar.uncompressed_bytes),
+            parts: sidecar.parts,
+            ..SegmentRecord::new(hash)
+        });
+    }
+
+    let first_part = PathBuf::from(format!("{}.part001", archive_path.display()));
+    let archive_file = if archive_path.exists() { archive_path } else { first_part.as_path() };
+    let info = read_archive_info(archive_file).ok()?;
+    Some(SegmentRecord {
+        last_backup: Some(info.created),
+        ..SegmentRecord::new(info.hash?)
+    })
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::archive_info::ArchiveInfo;
+    use crate::helpers::{ArchiveOptions, create_archive};
+    use crate::sidecar::{SegmentMetadata, write_sidecar};
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/import_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[test]
+    fn test_import_record_from_archive() {
+        let test_name = "from_archive";
+        let test_dir = setup_test_dir(test_name);
+        let src_dir = test_dir.join("docs");
+        fs::create_dir_all(&src_dir).unwrap();
+        fs::write(src_dir.join("file.txt"), b"content").unwrap();
+
+        let archive_path = test_dir.join("docs.tar.gz");
+        assert!(import_record(&test_dir, "docs", &archive_path).is_none(), "No archive to import");
+
+        let info = ArchiveInfo::new("docs", &src_dir, &None, Some("xxh3:0123456789abcdef".to_string())).unwrap();
+        let metadata = fs::metadata(&src_dir).unwrap();
+        create_archive(&src_dir, &metadata, &archive_path, &[], &info, &ArchiveOptions::default(), None, None).unwrap();
+
+        let record = import_record(&test_dir, "docs", &archive_path).unwrap();
+        assert_eq!(record.hash, "xxh3:0123456789abcdef");
+        assert_eq!(record.last_backup, Some(info.created));
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_import_record_prefers_sidecar() {
+        let test_name = "prefers_sidecar";
+        let test_dir = setup_test_dir(test_name);
+        let sidecar = SegmentMetadata {
+            segment: "docs".to_string(),
+            source_path: PathBuf::from("/tmp"),
+            hash: Some("blake3:abcd".to_string()),
+            created: "2024-01-01T00:00:00+00:00".to_string(),
+            file_count: 3,
+            uncompressed_bytes: 1024,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            checksum_algorithm: None,
+            parts: Vec::new(),
+        };
+        write_sidecar(&sidecar_path(&test_dir, "docs"), &sidecar).unwrap();
+
+        let record = import_record(&test_dir, "docs", &test_dir.join("docs.tar.gz")).unwrap();
+        assert_eq!(record.hash, "blake3:abcd");
+        assert_eq!(record.algorithm, "blake3");
+        assert_eq!(record.file_count, Some(3));
+
+        cleanup_test_dir(test_name);
+    }
+}
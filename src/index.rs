@@ -0,0 +1,178 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::fs;
+use crate::archive_reader::archive_base;
+use crate::rolling_writer::PartInfo;
+
+/// Extension of the index file written next to each archive
+pub const INDEX_EXT: &str = "index.json";
+
+/// Type of an indexed entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Dir,
+    Link,
+}
+
+impl EntryKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryKind::File => "file",
+            EntryKind::Dir => "dir",
+            EntryKind::Link => "link",
+        }
+    }
+}
+
+/// Where an entry is in the archive
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IndexEntry {
+    pub path: String,
+    pub kind: EntryKind,
+    pub size: u64,
+    /// Modification time (Seconds since the Unix epoch)
+    pub mtime: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<String>,
+    /// Part (Numbered from 1) whose tar stream holds the entry
+    pub part: usize,
+    /// Offset of the entry's header in that tar stream, once decompressed
+    pub offset: u64,
+}
+
+/// Part of an indexed archive, to check the index still describes it
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IndexedPart {
+    pub name: String,
+    pub size: u64,
+}
+
+/// Every entry of an archive (Besides its info entry), by part and offset
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ArchiveIndex {
+    /// True if each part is a standalone tar.gz (Otherwise every part is one stream, starting in part 1)
+    pub standalone: bool,
+    pub parts: Vec<IndexedPart>,
+    pub entries: Vec<IndexEntry>,
+}
+
+impl ArchiveIndex {
+    pub fn new(standalone: bool, parts: &[PartInfo], entries: Vec<IndexEntry>) -> Self {
+        let parts = parts.iter()
+            .map(|part| IndexedPart { name: part.name.clone(), size: part.size })
+            .collect();
+        Self { standalone, parts, entries }
+    }
+
+    /// True if `parts` are the files this index was written for (By name and size)
+    fn describes(&self, parts: &[PathBuf]) -> bool {
+        self.parts.len() == parts.len() && self.parts.iter().zip(parts).all(|(indexed, path)| {
+            path.file_name().is_some_and(|name| name.to_string_lossy() == indexed.name)
+                && fs::metadata(path).is_ok_and(|metadata| metadata.len() == indexed.size)
+        })
+    }
+}
+
+/// Path of the index file for an archive
+pub fn index_path(archive_path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.{}", archive_path.display(), INDEX_EXT))
+}
+
+pub fn write_index(path: &Path, index: &ArchiveIndex) -> Result<()> {
+    let contents = serde_json::to_string(index)
+        .context("Failed to serialize archive index")?;
+    fs::write(path, contents)
+        .context(format!("Failed to write archive index: {:?}", path))
+}
+
+pub fn read_index(path: &Path) -> Result<ArchiveIndex> {
+    let contents = fs::read_to_string(path)
+        .context(format!("Failed to read archive index: {:?}", path))?;
+    serde_json::from_str(&contents)
+        .context(format!("Failed to parse archive index: {:?}", path))
+}
+
+/// Index of an archive's parts, if one was written for exactly these parts
+/// (An index left by an older archive of the same name is ignored)
+pub fn find_index(parts: &[PathBuf]) -> Option<ArchiveIndex> {
+    let path = index_path(&archive_base(parts.first()?));
+    if !path.exists() {
+        return None;
+    }
+    read_index(&path).ok().filter(|index| index.describes(parts))
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+    use crate::archive_info::ArchiveInfo;
+    use crate::helpers::{ArchiveOptions, SplitMode, create_archive};
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/index_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[test]
+    fn test_archive_index() {
+        let test_name = "index";
+        let test_dir = setup_test_dir(test_name);
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(src_dir.join("empty")).unwrap();
+        for i in 0..6 {
+            fs::write(src_dir.join(format!("file{}.bin", i)), vec![i as u8; 20000]).unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("file0.bin", src_dir.join("link")).unwrap();
+
+        let output_dir = test_dir.join("out");
+        fs::create_dir_all(&output_dir).unwrap();
+        let archive_path = output_dir.join("docs.tar.gz");
+        let info = ArchiveInfo::new("docs", &src_dir, &None, None).unwrap();
+        let options = ArchiveOptions {
+            compression_level: Some(0),
+            max_size_bytes: Some(30000),
+            split_mode: SplitMode::Independent,
+            index: true,
+            ..Default::default()
+        };
+        let summary = create_archive(&src_dir, &fs::metadata(&src_dir).unwrap(), &archive_path, &[], &info, &options, None, None).unwrap();
+        assert!(summary.parts.count() > 1);
+
+        let parts: Vec<PathBuf> = summary.parts.parts.iter().map(|part| part.path.clone()).collect();
+        let index = find_index(&parts).unwrap();
+        assert!(index.standalone);
+        assert_eq!(index.entries.len(), if cfg!(unix) { 8 } else { 7 });
+        assert!(index.entries.iter().any(|entry| entry.path == "empty" && entry.kind == EntryKind::Dir));
+
+        // Each entry's header is at its offset in its own part
+        for entry in &index.entries {
+            let mut tar = Vec::new();
+            GzDecoder::new(fs::File::open(&parts[entry.part - 1]).unwrap()).read_to_end(&mut tar).unwrap();
+            let header = tar::Header::from_byte_slice(&tar[entry.offset as usize..entry.offset as usize + 512]);
+            assert_eq!(header.path().unwrap().to_string_lossy().trim_end_matches('/'), entry.path);
+        }
+
+        // An index of other parts is ignored
+        fs::write(&parts[0], b"changed").unwrap();
+        assert!(find_index(&parts).is_none());
+
+        cleanup_test_dir(test_name);
+    }
+}
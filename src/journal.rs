@@ -0,0 +1,266 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::fs;
+use std::time::Instant;
+use chrono::{DateTime, Local};
+use log::warn;
+use crate::destination::UploadReport;
+
+/// Name of the run journal in the state directory
+pub const JOURNAL_FILE: &str = "journal.jsonl";
+
+/// What happened to a segment during a run
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Archived,
+    /// Unchanged since its last backup
+    Skipped,
+    Failed,
+}
+
+/// Result of one segment in a run
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SegmentOutcome {
+    pub segment: String,
+    pub outcome: Outcome,
+    /// Total size of the parts written
+    #[serde(default)]
+    pub bytes_written: u64,
+    #[serde(default)]
+    pub parts: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Parts delivered to (Or failed to upload to) each destination
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub uploads: Vec<UploadReport>,
+    /// Time spent on the segment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<f64>,
+}
+
+/// One line of the journal: a backup run and the outcome of each segment it processed
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RunRecord {
+    /// Time the run started (RFC 3339)
+    pub started: String,
+    /// Time the run finished (RFC 3339, empty until it is finished)
+    pub finished: String,
+    pub segments: Vec<SegmentOutcome>,
+    /// Errors that failed no particular segment (i.e. pruning)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+    /// Only reported which segments would be archived (Not stored, since dry runs aren't journaled)
+    #[serde(skip)]
+    pub dry_run: bool,
+    /// When processing of the current segment began (Not stored)
+    #[serde(skip)]
+    segment_started: Option<Instant>,
+}
+
+impl RunRecord {
+    /// Start recording a run
+    pub fn start() -> Self {
+        Self { started: Local::now().to_rfc3339(), finished: String::new(), segments: Vec::new(), errors: Vec::new(), dry_run: false, segment_started: None }
+    }
+
+    /// Start timing the next segment
+    pub fn begin_segment(&mut self) {
+        self.segment_started = Some(Instant::now());
+    }
+
+    /// Record a segment that was archived
+    pub fn archived(&mut self, segment: &str, bytes_written: u64, parts: usize) {
+        self.record(segment, Outcome::Archived, bytes_written, parts, None);
+    }
+
+    /// Record a segment that was skipped
+    pub fn skipped(&mut self, segment: &str) {
+        self.record(segment, Outcome::Skipped, 0, 0, None);
+    }
+
+    /// Record a segment that failed, and why
+    pub fn failed(&mut self, segment: &str, error: impl ToString) {
+        self.record(segment, Outcome::Failed, 0, 0, Some(error.to_string()));
+    }
+
+    fn record(&mut self, segment: &str, outcome: Outcome, bytes_written: u64, parts: usize, error: Option<String>) {
+        let duration_secs = self.segment_started.take().map(|started| started.elapsed().as_secs_f64());
+        self.segments.push(SegmentOutcome { segment: segment.to_string(), outcome, bytes_written, parts, error, uploads: Vec::new(), duration_secs });
+    }
+
+    /// Total size of the parts written by every segment
+    pub fn bytes_written(&self) -> u64 {
+        self.segments.iter().map(|segment| segment.bytes_written).sum()
+    }
+
+    /// True if any segment failed, or the run had other errors
+    pub fn has_failures(&self) -> bool {
+        !self.errors.is_empty() || self.segments.iter().any(|segment| segment.outcome == Outcome::Failed)
+    }
+
+    /// Seconds from start to finish (None until the run is finished)
+    pub fn duration_secs(&self) -> Option<f64> {
+        let started = DateTime::parse_from_rfc3339(&self.started).ok()?;
+        let finished = DateTime::parse_from_rfc3339(&self.finished).ok()?;
+        Some((finished - started).num_milliseconds() as f64 / 1000.0)
+    }
+
+    /// Plain text summary: one line per segment, then any other errors
+    pub fn summary(&self) -> String {
+        let mut lines = vec![format!("{} started {}{}", if self.dry_run { "Dry run" } else { "Backup run" }, self.started,
+            if self.finished.is_empty() { String::new() } else { format!(", finished {}", self.finished) })];
+        for segment in &self.segments {
+            lines.push(match segment.outcome {
+                Outcome::Archived if self.dry_run => format!("{}: would be archived", segment.segment),
+                Outcome::Archived => format!("{}: archived ({} part(s), {} bytes)", segment.segment, segment.parts, segment.bytes_written),
+                Outcome::Skipped => format!("{}: skipped", segment.segment),
+                Outcome::Failed => format!("{}: failed ({})", segment.segment, segment.error.as_deref().unwrap_or("Unknown error")),
+            });
+        }
+        lines.extend(self.errors.iter().map(|error| format!("Error: {}", error)));
+        lines.join("\n")
+    }
+
+    /// Record the uploads of the latest segment recorded
+    pub fn uploaded(&mut self, uploads: Vec<UploadReport>) {
+        if let Some(segment) = self.segments.last_mut() {
+            segment.uploads = uploads;
+        }
+    }
+}
+
+/// A segment's outcomes over the runs in a journal (Oldest first)
+#[derive(Debug, Default, PartialEq)]
+pub struct SegmentHistory {
+    pub archived: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Time of the latest run that failed the segment, and its error
+    pub last_failure: Option<(String, String)>,
+    /// Bytes written by each run that archived the segment
+    pub bytes_written: Vec<u64>,
+}
+
+impl SegmentHistory {
+    /// Collect a segment's outcomes from journal records
+    pub fn of(segment: &str, runs: &[RunRecord]) -> Self {
+        let mut history = Self::default();
+        for run in runs {
+            for outcome in run.segments.iter().filter(|outcome| outcome.segment == segment) {
+                match outcome.outcome {
+                    Outcome::Archived => {
+                        history.archived += 1;
+                        history.bytes_written.push(outcome.bytes_written);
+                    }
+                    Outcome::Skipped => history.skipped += 1,
+                    Outcome::Failed => {
+                        history.failed += 1;
+                        history.last_failure = Some((run.started.clone(), outcome.error.clone().unwrap_or_default()));
+                    }
+                }
+            }
+        }
+        history
+    }
+
+    /// Number of runs that processed the segment
+    pub fn runs(&self) -> usize {
+        self.archived + self.skipped + self.failed
+    }
+}
+
+/// Path of the journal in a state directory
+pub fn journal_path(state_dir: &Path) -> PathBuf {
+    state_dir.join(JOURNAL_FILE)
+}
+
+/// Append a finished run to the journal (As one JSON line, creating the file and its directory if needed)
+pub fn append_run(path: &Path, run: &RunRecord) -> Result<()> {
+    if let Some(dir) = path.parent()
+        && !dir.as_os_str().is_empty() {
+        fs::create_dir_all(dir)
+            .context(format!("Failed to create state directory: {:?}", dir))?;
+    }
+    let mut line = serde_json::to_string(run)
+        .context("Failed to serialize run record")?;
+    line.push('\n');
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .context(format!("Failed to write journal: {:?}", path))
+}
+
+/// Read every run in the journal (Oldest first). Unreadable lines (i.e. from a run killed mid-write) are skipped.
+pub fn read_journal(path: &Path) -> Result<Vec<RunRecord>> {
+    let contents = fs::read_to_string(path)
+        .context(format!("Failed to read journal: {:?}", path))?;
+    Ok(contents.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(i, line)| serde_json::from_str(line)
+            .inspect_err(|e| warn!("Skipping unreadable line {} of journal {:?}: {}", i + 1, path, e))
+            .ok())
+        .collect())
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/journal_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    #[test]
+    fn test_journal_round_trip() {
+        let test_name = "round_trip";
+        cleanup_test_dir(test_name);
+        let path = journal_path(&get_test_dir(test_name).join("state"));
+
+        let mut first = RunRecord::start();
+        first.archived("docs", 150, 2);
+        first.skipped("photos");
+        first.finished = Local::now().to_rfc3339();
+        append_run(&path, &first).unwrap();
+
+        let mut second = RunRecord::start();
+        second.failed("docs", "Failed to read file");
+        second.errors.push("Failed to prune superseded archives".to_string());
+        second.finished = Local::now().to_rfc3339();
+        append_run(&path, &second).unwrap();
+
+        // A truncated line is skipped
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"started\":").unwrap();
+
+        let runs = read_journal(&path).unwrap();
+        assert_eq!(runs, [first, second]);
+
+        let history = SegmentHistory::of("docs", &runs);
+        assert_eq!((history.archived, history.skipped, history.failed, history.runs()), (1, 0, 1, 2));
+        assert_eq!(history.bytes_written, [150]);
+        assert_eq!(history.last_failure.map(|(_, error)| error).as_deref(), Some("Failed to read file"));
+        assert_eq!(SegmentHistory::of("photos", &runs).skipped, 1);
+
+        assert!(!runs[0].has_failures() && runs[1].has_failures());
+        assert_eq!(runs[0].summary().lines().skip(1).collect::<Vec<_>>(), ["docs: archived (2 part(s), 150 bytes)", "photos: skipped"]);
+        assert_eq!(runs[1].summary().lines().skip(1).collect::<Vec<_>>(), ["docs: failed (Failed to read file)", "Error: Failed to prune superseded archives"]);
+
+        let mut dry_run = RunRecord::start();
+        dry_run.dry_run = true;
+        dry_run.archived("docs", 0, 0);
+        assert!(dry_run.summary().starts_with("Dry run started"));
+        assert_eq!(dry_run.summary().lines().nth(1), Some("docs: would be archived"));
+
+        cleanup_test_dir(test_name);
+    }
+}
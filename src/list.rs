@@ -0,0 +1,180 @@
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::io::Read;
+use chrono::{DateTime, Local};
+use crate::archive_info::{ArchiveInfo, INFO_FILE, LEGACY_PATH_FILE, MANIFEST_ENTRY};
+use crate::archive_reader::open_archive;
+use crate::index::find_index;
+
+/// One entry of an archive, as listed by the list command
+#[derive(Debug, PartialEq)]
+pub struct ListedEntry {
+    pub path: String,
+    pub size: u64,
+    /// `file`, `dir`, `link` or `other`
+    pub kind: &'static str,
+    /// Modification time (Seconds since the Unix epoch)
+    pub mtime: u64,
+    /// Target of a symlink
+    pub link_target: Option<String>,
+}
+
+impl ListedEntry {
+    /// One line per entry: type, size, modification time, then path
+    pub fn line(&self) -> String {
+        let mtime = DateTime::from_timestamp(self.mtime as i64, 0)
+            .map(|mtime| mtime.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        let target = self.link_target.as_ref().map(|target| format!(" -> {}", target)).unwrap_or_default();
+        format!("{:<5} {:>12} {} {}{}", self.kind, self.size, mtime, self.path, target)
+    }
+}
+
+/// What the info entry of a listed archive held
+#[derive(Debug, Default)]
+pub struct ListSummary {
+    pub entries: usize,
+    pub info: Option<ArchiveInfo>,
+    /// Restore path from the path entry of older archives
+    pub legacy_path: Option<String>,
+}
+
+/// Stream through an archive's parts, passing each entry (Including the info entry, but not the manifest) to `on_entry`
+/// (If the archive has an index, only its info entry is read, and the rest are listed from the index)
+pub fn list_archive(parts: &[PathBuf], mut on_entry: impl FnMut(&ListedEntry)) -> Result<ListSummary> {
+    let Some(index) = find_index(parts) else {
+        return read_entries(parts, usize::MAX, on_entry);
+    };
+    // (The info entry is always first)
+    let mut summary = read_entries(&parts[..1], 1, &mut on_entry)?;
+    for entry in index.entries {
+        on_entry(&ListedEntry {
+            path: entry.path,
+            size: entry.size,
+            kind: entry.kind.name(),
+            mtime: entry.mtime,
+            link_target: entry.link_target,
+        });
+        summary.entries += 1;
+    }
+    Ok(summary)
+}
+
+/// Read up to `max_entries` entries of an archive
+fn read_entries(parts: &[PathBuf], max_entries: usize, mut on_entry: impl FnMut(&ListedEntry)) -> Result<ListSummary> {
+    let mut tar = open_archive(parts)?;
+    let mut summary = ListSummary::default();
+    for entry in tar.entries().context("Failed to read archive")?.take(max_entries) {
+        let mut entry = entry.context("Failed to read archive entry")?;
+        let path = entry.path().context("Failed to read archive entry path")?.to_string_lossy().to_string();
+        // (The manifest only holds file hashes, for restore and verify)
+        if path == MANIFEST_ENTRY {
+            continue;
+        }
+        let header = entry.header();
+        let entry_type = header.entry_type();
+        let listed = ListedEntry {
+            kind: if entry_type.is_file() { "file" } else if entry_type.is_dir() { "dir" } else if entry_type.is_symlink() { "link" } else { "other" },
+            size: header.size().unwrap_or(0),
+            mtime: header.mtime().unwrap_or(0),
+            link_target: entry.link_name().ok().flatten().map(|target| target.to_string_lossy().to_string()),
+            path,
+        };
+
+        if listed.path == INFO_FILE || listed.path == LEGACY_PATH_FILE {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)
+                .context("Failed to read info entry")?;
+            if listed.path == INFO_FILE {
+                summary.info = Some(serde_json::from_str(&contents).context("Failed to parse info entry")?);
+            } else {
+                summary.legacy_path = Some(contents.trim_end().to_string());
+            }
+        }
+        on_entry(&listed);
+        summary.entries += 1;
+    }
+    Ok(summary)
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::helpers::{ArchiveOptions, create_archive};
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/list_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[test]
+    fn test_list_archive() {
+        let test_name = "list";
+        let test_dir = setup_test_dir(test_name);
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(src_dir.join("empty")).unwrap();
+        fs::write(src_dir.join("a.txt"), b"hello").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("a.txt", src_dir.join("link")).unwrap();
+
+        let archive_path = test_dir.join("docs.tar.gz");
+        let info = ArchiveInfo::new("docs", &src_dir, &None, None).unwrap();
+        create_archive(&src_dir, &fs::metadata(&src_dir).unwrap(), &archive_path, &[], &info, &ArchiveOptions::default(), None, None).unwrap();
+
+        let mut entries = Vec::new();
+        let summary = list_archive(&[archive_path], |entry| entries.push(entry.path.clone() + " " + entry.kind)).unwrap();
+        assert_eq!(summary.info.unwrap().segment, "docs");
+        assert_eq!(summary.entries, entries.len());
+        assert!(entries.contains(&format!("{} file", INFO_FILE)));
+        assert!(entries.contains(&"a.txt file".to_string()));
+        assert!(entries.iter().any(|entry| entry.starts_with("empty") && entry.ends_with(" dir")));
+        #[cfg(unix)]
+        assert!(entries.contains(&"link link".to_string()));
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_list_skips_manifest() {
+        use crate::hasher::{HashOptions, compute_segment_hash};
+
+        let test_name = "manifest";
+        let test_dir = setup_test_dir(test_name);
+        let source = test_dir.join("notes.txt");
+        fs::write(&source, b"notes").unwrap();
+        let hashed = compute_segment_hash(&source, &fs::metadata(&source).unwrap(), &[], None, &HashOptions::default(), None).unwrap();
+
+        let archive_path = test_dir.join("notes.tar.gz");
+        let mut info = ArchiveInfo::new("notes", &source, &None, Some(hashed.hash)).unwrap();
+        info.manifest = Some(hashed.files);
+        create_archive(&source, &fs::metadata(&source).unwrap(), &archive_path, &[], &info, &ArchiveOptions::default(), None, None).unwrap();
+
+        let mut entries = Vec::new();
+        let summary = list_archive(&[archive_path], |entry| entries.push(entry.path.clone())).unwrap();
+        assert_eq!(entries, [INFO_FILE, "notes.txt"], "The manifest entry isn't listed as a file");
+        assert_eq!(summary.entries, 2);
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_listed_entry_line() {
+        let entry = ListedEntry { path: "link".to_string(), size: 0, kind: "link", mtime: 0, link_target: Some("a.txt".to_string()) };
+        let line = entry.line();
+        assert!(line.starts_with("link "));
+        assert!(line.ends_with(" link -> a.txt"));
+    }
+}
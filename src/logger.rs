@@ -1,57 +1,193 @@
 use anyhow::{Context, Result};
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
 use std::fs::OpenOptions;
 use std::io::Write;
 use chrono::Local;
 use log::{info, LevelFilter};
 use log4rs::Handle;
-use log4rs::append::console::ConsoleAppender;
+use log4rs::append::console::{ConsoleAppender, Target};
+use log4rs::append::Append;
 use log4rs::append::file::FileAppender;
+use log4rs::append::rolling_file::RollingFileAppender;
+use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
+use log4rs::append::rolling_file::policy::compound::roll::Roll;
+use log4rs::append::rolling_file::policy::compound::roll::delete::DeleteRoller;
+use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
+use log4rs::append::rolling_file::policy::compound::trigger::size::SizeTrigger;
 use log4rs::config::{Appender, Config as LogConfig, Root};
 use log4rs::encode::pattern::PatternEncoder;
+use log4rs::filter::threshold::ThresholdFilter;
+use crate::progress;
+use crate::system_log::{SystemLogAppender, SystemLogConfig};
+
+/// Format of console log lines
+const CONSOLE_PATTERN: &str = "{h({l})} - {m}\n";
+/// Format of log file lines
+const FILE_PATTERN: &str = "{d} - {l} - {m}\n";
+
+/// When the log file is rolled over, and how many old ones are kept
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogRotation {
+    /// Roll the log file over once it reaches this size
+    pub max_size_bytes: u64,
+    /// Old log files to keep (As `<log_file>.1` (Newest) to `<log_file>.<keep>`, 0 deletes them)
+    pub keep: u32,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self { max_size_bytes: 10 * 1024 * 1024, keep: 5 }
+    }
+}
+
+/// Console appender that clears the progress bar (If one is drawn) while each message is written
+#[derive(Debug)]
+struct ConsoleOutput(ConsoleAppender);
+
+impl Append for ConsoleOutput {
+    fn append(&self, record: &log::Record) -> anyhow::Result<()> {
+        progress::suspend(|| self.0.append(record))
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+fn console_appender(target: Target) -> ConsoleOutput {
+    ConsoleOutput(ConsoleAppender::builder().target(target).encoder(Box::new(PatternEncoder::new(CONSOLE_PATTERN))).build())
+}
 
 /// Setup logging
-pub fn init_logger() -> Result<Handle> {
+pub fn init_logger(console_level: LevelFilter) -> Result<Handle> {
     // Setup console logging
-    let stdout = ConsoleAppender::builder().encoder(Box::new(PatternEncoder::new("{h({l})} - {m}\n"))).build();
     let base_config = LogConfig::builder()
-        .appender(Appender::builder().build("stdout", Box::new(stdout)))
-        .build(Root::builder().appender("stdout").build(LevelFilter::Info))
+        .appender(Appender::builder().build("stdout", Box::new(console_appender(Target::Stdout))))
+        .build(Root::builder().appender("stdout").build(console_level))
         .context("Failed to configure base logger")?;
     
     let handle = log4rs::init_config(base_config).context("Failed to start logger")?;
     Ok(handle)
 }
 
-/// Reconfigure logger if a log file is specified in config
-pub fn set_log_path(log_handle: &Handle, log_path: &PathBuf, log_level: LevelFilter) -> Result<()> {
-    let log_path = &replace_placeholders(log_path);
-    info!("Saving log to file: {:?}", log_path);
+/// Send console logging to stderr (For commands that write data to stdout)
+pub fn log_to_stderr(log_handle: &Handle, console_level: LevelFilter) -> Result<()> {
+    let stderr_config = LogConfig::builder()
+        .appender(Appender::builder().build("stderr", Box::new(console_appender(Target::Stderr))))
+        .build(Root::builder().appender("stderr").build(console_level))
+        .context("Failed to configure stderr logger")?;
+    log_handle.set_config(stderr_config);
+    Ok(())
+}
 
-    let file_appender = FileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new("{d} - {l} - {m}\n")))
-        .build(log_path)
-        .context("Failed to build file appender")?;
+/// Remove the verbosity flags from the command line, returning the console level they set (The last one wins)
+/// (`-q` only logs errors, `-v` debug messages and `-vv` everything)
+pub fn take_verbosity_flags(args: &mut Vec<String>) -> Option<LevelFilter> {
+    let mut console_level = None;
+    args.retain(|arg| {
+        let level = match arg.as_str() {
+            "-q" | "--quiet" => LevelFilter::Error,
+            "-v" | "--verbose" => match console_level {
+                Some(LevelFilter::Debug | LevelFilter::Trace) => LevelFilter::Trace,
+                _ => LevelFilter::Debug,
+            },
+            "-vv" => LevelFilter::Trace,
+            _ => return true,
+        };
+        console_level = Some(level);
+        false
+    });
+    console_level
+}
 
-    let file_config = LogConfig::builder()
-        .appender(Appender::builder().build("file_log", Box::new(file_appender)))
-        .build(Root::builder().appender("file_log").build(log_level))
-        .context("Failed to configure file logger")?;
+/// Where logs are sent besides the console, and the least severe messages sent to each
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    pub console_level: LevelFilter,
+    /// Log to stderr instead of stdout (When stdout is used for data, i.e. `--progress json`)
+    pub console_to_stderr: bool,
+    /// Log file path (`%D` is replaced with the date)
+    pub log_file: Option<PathBuf>,
+    pub file_level: LevelFilter,
+    pub rotation: Option<LogRotation>,
+    pub system_log: Option<SystemLogConfig>,
+}
 
-    // Re-initialize logger with the new file configuration
-    log_handle.set_config(file_config);
+/// Reconfigure logger from config (Logging to the console, and the log file and system log if set)
+pub fn configure_logger(log_handle: &Handle, options: &LogOptions) -> Result<()> {
+    let log_path = options.log_file.as_deref().map(replace_placeholders);
+
+    // Re-initialize logger with the new configuration
+    log_handle.set_config(log_config(log_path.as_deref(), options)?);
+    if let Some(system_log) = &options.system_log {
+        info!("Sending log to {}", system_log.name());
+    }
     
     // Write separator line and backup start message to the log file
-    if let Ok(mut file) = OpenOptions::new().append(true).open(log_path) {
-        let _ = writeln!(file, "--------------------------------");
+    if let Some(log_path) = &log_path {
+        if let Ok(mut file) = OpenOptions::new().append(true).open(log_path) {
+            let _ = writeln!(file, "--------------------------------");
+        }
+        info!("Saving log to file: {:?}", log_path);
+        info!("Backup process started.");
     }
-    info!("Backup process started.");
     
     Ok(())
 }
 
+/// Logger config for the console, log file and system log, each filtered to its own level
+fn log_config(log_path: Option<&Path>, options: &LogOptions) -> Result<LogConfig> {
+    let (console, target) = match options.console_to_stderr {
+        true => ("stderr", Target::Stderr),
+        false => ("stdout", Target::Stdout),
+    };
+    let mut builder = LogConfig::builder()
+        .appender(Appender::builder().filter(Box::new(ThresholdFilter::new(options.console_level))).build(console, Box::new(console_appender(target))));
+    let mut root = Root::builder().appender(console);
+    let mut level = options.console_level;
+
+    if let Some(log_path) = log_path {
+        let file_appender: Box<dyn Append> = match &options.rotation {
+            Some(rotation) => Box::new(rolling_file_appender(log_path, rotation)?),
+            None => Box::new(FileAppender::builder()
+                .encoder(Box::new(PatternEncoder::new(FILE_PATTERN)))
+                .build(log_path)
+                .context("Failed to build file appender")?),
+        };
+        builder = builder.appender(Appender::builder().filter(Box::new(ThresholdFilter::new(options.file_level))).build("file_log", file_appender));
+        root = root.appender("file_log");
+        level = level.max(options.file_level);
+    }
+    if let Some(system_log) = &options.system_log {
+        let system_level = system_log.level.unwrap_or(LevelFilter::Info);
+        let appender = SystemLogAppender::new(system_log).context(format!("Failed to connect to {}", system_log.name()))?;
+        builder = builder.appender(Appender::builder().filter(Box::new(ThresholdFilter::new(system_level))).build("system_log", Box::new(appender)));
+        root = root.appender("system_log");
+        level = level.max(system_level);
+    }
+
+    builder.build(root.build(level)).context("Failed to configure logger")
+}
+
+/// Log file appender that rolls the file over to `<log_file>.1`, `<log_file>.2`... once it is too large
+fn rolling_file_appender(log_path: &Path, rotation: &LogRotation) -> Result<RollingFileAppender> {
+    let roller: Box<dyn Roll> = match rotation.keep {
+        0 => Box::new(DeleteRoller::new()),
+        keep => {
+            let pattern = format!("{}.{{}}", log_path.display());
+            Box::new(FixedWindowRoller::builder().base(1).build(&pattern, keep).context("Failed to build log roller")?)
+        }
+    };
+    let policy = CompoundPolicy::new(Box::new(SizeTrigger::new(rotation.max_size_bytes)), roller);
+    RollingFileAppender::builder()
+        .encoder(Box::new(PatternEncoder::new(FILE_PATTERN)))
+        .build(log_path, Box::new(policy))
+        .context("Failed to build rolling file appender")
+}
+
 /// Helper function to replace placeholders in a path
-pub(crate) fn replace_placeholders(path: &PathBuf) -> PathBuf {
+pub(crate) fn replace_placeholders(path: &Path) -> PathBuf {
     let now = Local::now();
     let path_str = path.display().to_string()
 
@@ -61,14 +197,90 @@ pub(crate) fn replace_placeholders(path: &PathBuf) -> PathBuf {
     PathBuf::from(path_str)
 }
 
-/// --- Tests --- ///
+// --- Tests --- //
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
     use std::path::PathBuf;
     use chrono::Local;
 
+    #[test]
+    fn test_log_config() {
+        let test_dir = PathBuf::from("/tmp/logger_test_log_config");
+        let _ = std::fs::remove_dir_all(&test_dir);
+        let options = LogOptions {
+            console_level: LevelFilter::Warn,
+            console_to_stderr: false,
+            log_file: Some(test_dir.join("backup.log")),
+            file_level: LevelFilter::Debug,
+            rotation: None,
+            system_log: None,
+        };
+
+        let config = log_config(Some(&test_dir.join("backup.log")), &options).unwrap();
+        let appenders: Vec<&str> = config.appenders().iter().map(|appender| appender.name()).collect();
+        assert_eq!(appenders, ["stdout", "file_log"], "Logs to both the console and the file");
+        assert_eq!(config.root().appenders(), ["stdout", "file_log"]);
+        assert_eq!(config.root().level(), LevelFilter::Debug, "Passes records on to the most verbose appender");
+        assert!(test_dir.join("backup.log").exists());
+
+        let config = log_config(None, &options).unwrap();
+        assert_eq!(config.root().appenders(), ["stdout"]);
+        assert_eq!(config.root().level(), LevelFilter::Warn);
+        let config = log_config(None, &LogOptions { console_to_stderr: true, ..options.clone() }).unwrap();
+        assert_eq!(config.root().appenders(), ["stderr"]);
+
+        let levels: HashMap<String, LevelFilter> = toml::from_str("console_log_level = \"warn\"\nfile_log_level = \"DEBUG\"").unwrap();
+        assert_eq!(levels["console_log_level"], LevelFilter::Warn);
+        assert_eq!(levels["file_log_level"], LevelFilter::Debug);
+        assert!(toml::from_str::<HashMap<String, LevelFilter>>("console_log_level = \"verbose\"").is_err());
+
+        let _ = std::fs::remove_dir_all(&test_dir);
+    }
+
+    #[test]
+    fn test_take_verbosity_flags() {
+        let mut args: Vec<String> = ["segmented_archive", "-v", "--dry-run", "config.toml"].map(String::from).to_vec();
+        assert_eq!(take_verbosity_flags(&mut args), Some(LevelFilter::Debug));
+        assert_eq!(args, ["segmented_archive", "--dry-run", "config.toml"]);
+
+        let mut args: Vec<String> = ["segmented_archive", "config.toml", "-vv"].map(String::from).to_vec();
+        assert_eq!(take_verbosity_flags(&mut args), Some(LevelFilter::Trace));
+        let mut args: Vec<String> = ["segmented_archive", "-v", "-v", "config.toml"].map(String::from).to_vec();
+        assert_eq!(take_verbosity_flags(&mut args), Some(LevelFilter::Trace));
+        let mut args: Vec<String> = ["segmented_archive", "-v", "-q", "config.toml"].map(String::from).to_vec();
+        assert_eq!(take_verbosity_flags(&mut args), Some(LevelFilter::Error), "The last flag wins");
+        let mut args: Vec<String> = ["segmented_archive", "config.toml"].map(String::from).to_vec();
+        assert_eq!(take_verbosity_flags(&mut args), None);
+        assert_eq!(args.len(), 2);
+    }
+
+    #[test]
+    fn test_rolling_file_appender() {
+        let test_dir = PathBuf::from("/tmp/logger_test_rolling_file_appender");
+        let _ = std::fs::remove_dir_all(&test_dir);
+        let log_path = test_dir.join("backup.log");
+
+        let rotation: LogRotation = toml::from_str("max_size_bytes = 100\nkeep = 2").unwrap();
+        let appender = rolling_file_appender(&log_path, &rotation).unwrap();
+        for i in 0..20 {
+            appender.append(&log::Record::builder().args(format_args!("Line {} of the backup log", i)).level(log::Level::Info).build()).unwrap();
+        }
+        appender.flush();
+        for old_log in ["backup.log.1", "backup.log.2"] {
+            let size = std::fs::metadata(test_dir.join(old_log)).unwrap().len();
+            assert!(size > 100 && size < 200, "{} is rolled over once it reaches max_size_bytes", old_log);
+        }
+        assert!(!test_dir.join("backup.log.3").exists(), "Only keeps 2 old logs");
+
+        assert_eq!(toml::from_str::<LogRotation>("keep = 0").unwrap(), LogRotation { keep: 0, ..LogRotation::default() });
+        assert!(toml::from_str::<LogRotation>("max_bytes = 100").is_err());
+
+        let _ = std::fs::remove_dir_all(&test_dir);
+    }
+
     #[test]
     fn test_replace_placeholders_date() {
         let path = PathBuf::from("/tmp/log_%D.log");
@@ -2,45 +2,242 @@ pub(crate) mod rolling_writer;
 pub(crate) mod logger;
 pub(crate) mod hasher;
 pub(crate) mod helpers;
+pub(crate) mod parity;
+pub(crate) mod sidecar;
+pub(crate) mod archive_info;
+pub(crate) mod archive_writer;
+pub(crate) mod digest;
+pub(crate) mod script_queue;
+pub(crate) mod estimate;
+pub(crate) mod disk_space;
+pub(crate) mod throttle;
+pub(crate) mod multivolume;
+pub(crate) mod checkpoint;
+pub(crate) mod hash_cache;
+pub(crate) mod file_lock;
+pub(crate) mod verify;
+pub(crate) mod manifest;
+pub(crate) mod chain;
+pub(crate) mod import;
+pub(crate) mod archive_reader;
+pub(crate) mod restore;
+pub(crate) mod list;
+pub(crate) mod merge;
+pub(crate) mod diff;
+pub(crate) mod index;
+pub(crate) mod completeness;
+pub(crate) mod status;
+pub(crate) mod prune;
+pub(crate) mod journal;
+pub(crate) mod catalog;
+pub(crate) mod repair;
+pub(crate) mod destination;
+pub(crate) mod sink;
+pub(crate) mod retry;
+pub(crate) mod notify;
+pub(crate) mod systemd;
+pub(crate) mod segment;
+pub(crate) mod docker;
+pub(crate) mod script_options;
+pub(crate) mod hooks;
+pub(crate) mod system_log;
+pub(crate) mod progress;
 
 use anyhow::{Context, Result, anyhow};
 use std::collections::{HashMap, HashSet};
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::io::{IsTerminal, Write};
 use std::env;
-use log::{info, error, LevelFilter};
-use crate::logger::{init_logger, set_log_path};
-use crate::hasher::{compute_segment_hash, read_hash_file, write_hash_file};
-use crate::helpers::{create_archive, build_ignore_matcher, execute_script};
+use std::time::SystemTime;
+use std::sync::Arc;
+use chrono::{DateTime, FixedOffset, Local};
+use log::{info, warn, error, LevelFilter};
+use crate::logger::{LogOptions, LogRotation, configure_logger, init_logger, log_to_stderr, take_verbosity_flags};
+use crate::hash_cache::{HashCache, read_hash_cache, write_hash_cache};
+use crate::hasher::{ChangeDetection, HASHER_BUFFER_SIZE, HashAlgorithm, HashFailure, HashOptions, PARTIAL_HASH_SAMPLE_BYTES, PartialHash, SegmentHash, StreamingSegmentHash, compute_segment_hash, hashes_match, HashFile, SegmentRecord, read_hash_file, update_hash_file};
+use crate::helpers::{ArchiveOptions, SplitMode, create_archive, build_ignore_matcher, missing_output};
+use crate::archive_info::{ArchiveInfo, INFO_FILE, LEGACY_PATH_FILE};
+use crate::digest::DigestAlgorithm;
+use crate::destination::Destinations;
+use crate::retry::RetryPolicy;
+use crate::systemd::Watchdog;
+use crate::segment::{PreScriptFailure, SegmentConfig, run_dump, split_segments};
+use crate::docker::{Quiesced, quiesce};
+use crate::script_options::{DryRunScripts, Script, ScriptsConfig};
+use crate::hooks::{HookEvent, HookKind, Hooks, collect_scripts};
+use crate::system_log::SystemLogConfig;
+use crate::progress::{ProgressMode, ProgressReporter, ProgressSnapshot, print_event, take_progress_flag};
+use crate::notify::{ChatConfig, EmailConfig, MqttConfig, Notifier, NotifyOptions};
+use crate::sink::{ArchiveSink, CommandSink};
+use crate::sidecar::{SegmentMetadata, read_sidecar, sidecar_path, write_sidecar};
+use crate::verify::{read_archive_contents, verify_parts, verify_segment};
+use crate::manifest::{Manifest, ManifestChanges, ManifestFiles, diff_manifests, manifest_path, read_manifest, write_manifest};
+use crate::import::import_record;
+use crate::restore::{ConflictPolicy, RestoreArgs, restore_archive};
+use crate::archive_reader::{archive_base, copy_parts, find_archive_parts, latest_archive_parts};
+use crate::diff::{FileStamps, archive_stamps, diff_stamps, source_stamps};
+use crate::list::list_archive;
+use crate::merge::merge_parts;
+use crate::completeness::check_archive_parts;
+use crate::status::{SegmentStatus, SourceState};
+use crate::prune::{Retention, prune_segment};
+use crate::journal::{RunRecord, SegmentHistory, append_run, journal_path, read_journal};
+use crate::repair::repair_archive;
+use crate::catalog::{CatalogEntry, append_catalog, find_files, read_catalog};
+use crate::chain::{Chain, Generation, archive_name, chain_path, read_chain, write_chain};
 
 // --- Structs ---
 
 const CONFIG_PATH: &str = "config.toml"; // Default
 const LOG_LEVEL: LevelFilter = LevelFilter::Info;
 const CRASH_ON_HASH_FAILURE: bool = false;
+const DEFAULT_SCRIPT_WORKERS: usize = 1; // Handle parts in the background, unless resumable
 
 #[derive(Debug, serde::Deserialize)]
 struct Config {
     output_path: Option<PathBuf>,
     root_path: Option<PathBuf>,
-    post_script: Option<PathBuf>,
-    skip_script: Option<PathBuf>,
+    pre_script: Option<Script>,
+    pre_script_failure: Option<PreScriptFailure>,
+    post_script: Option<Script>,
+    #[serde(alias = "script_jobs")]
+    post_script_workers: Option<usize>,
+    destination: Option<Destinations>,
+    retry: Option<RetryPolicy>,
+    script_retry: Option<RetryPolicy>,
+    skip_script: Option<Script>,
+    on_failure_script: Option<Script>,
+    on_finish_script: Option<Script>,
+    hooks: Option<HashMap<HookKind, Script>>,
+    script_options: Option<ScriptsConfig>,
+    dry_run_scripts: Option<DryRunScripts>,
+    skip_requires_output: Option<bool>,
+    verify_before_skip: Option<bool>,
+    max_age_days: Option<u64>,
     hash_file: Option<PathBuf>,
+    hash_algorithm: Option<HashAlgorithm>,
+    hash_cache_file: Option<PathBuf>,
+    change_detection: Option<ChangeDetection>,
+    hash_buffer_bytes: Option<usize>,
+    hash_metadata: Option<bool>,
+    partial_hash_min_bytes: Option<u64>,
+    partial_hash_sample_bytes: Option<u64>,
+    hash_failure: Option<HashFailure>,
+    skipped_report_file: Option<PathBuf>,
+    manifest_dir: Option<PathBuf>,
+    change_report_file: Option<PathBuf>,
+    full_every: Option<u32>,
+    keep_last: Option<usize>,
+    keep_days: Option<u64>,
+    state_dir: Option<PathBuf>,
+    catalog_file: Option<PathBuf>,
+    healthcheck_url: Option<String>,
+    webhook_url: Option<String>,
+    webhook_per_segment: Option<bool>,
+    email: Option<EmailConfig>,
+    chat: Option<ChatConfig>,
+    mqtt: Option<MqttConfig>,
     log_file: Option<PathBuf>,
+    console_log_level: Option<LevelFilter>,
+    file_log_level: Option<LevelFilter>,
+    log_rotation: Option<LogRotation>,
+    system_log: Option<SystemLogConfig>,
     compression_level: Option<u32>,
     max_size_bytes: Option<usize>,
-    segments: HashMap<String, PathBuf>,
+    max_parts: Option<u32>,
+    split_mode: Option<SplitMode>,
+    parity: Option<bool>,
+    sync_parts: Option<bool>,
+    part_list: Option<bool>,
+    archive_index: Option<bool>,
+    embed_manifest: Option<bool>,
+    resumable: Option<bool>,
+    part_checksum: Option<DigestAlgorithm>,
+    part_command: Option<String>,
+    max_write_bytes_per_sec: Option<u64>,
+    check_free_space: Option<bool>,
+    min_free_bytes: Option<u64>,
+    estimate_size: Option<bool>,
+    estimate_warn_parts: Option<u64>,
+    segments: HashMap<String, SegmentConfig>,
     ignore: Option<Vec<String>>,
+    hash_ignore: Option<Vec<String>>,
+}
+
+/// Subcommands (Running with only a config path backs up)
+#[derive(PartialEq)]
+enum Command {
+    Backup,
+    /// Hash each segment and report which would be archived, without writing anything
+    DryRun,
+    /// Seed the hash file from existing archives
+    Import,
+    /// Recompute every segment's hash and manifest without archiving
+    Rehash,
+    /// Read a segment's latest archive end to end (None checks every segment)
+    Verify(Option<String>),
+    /// List the files changed in a segment since its last backup
+    Diff(String),
+    /// Print each segment's last backup, and whether its source has changed since
+    Status,
+    /// Delete superseded archives (Besides those kept by keep_last and keep_days)
+    Prune,
+    /// List the cataloged archives holding files that match a pattern
+    Find(String),
 }
 
 // --- Main Logic ---
 
 fn main() -> Result<()> {
-    let logger = init_logger()?;
+    // Verbosity flags (-q, -v, -vv) override console_log_level
+    let mut args: Vec<String> = env::args().collect();
+    let verbosity = take_verbosity_flags(&mut args);
+    let logger = init_logger(verbosity.unwrap_or(LOG_LEVEL))?;
+    // (JSON progress events take stdout, so logs go to stderr)
+    let progress_flag = take_progress_flag(&mut args)?;
+    if progress_flag == Some(ProgressMode::Json) {
+        log_to_stderr(&logger, verbosity.unwrap_or(LOG_LEVEL))?;
+    }
+
+    // Archive commands work on archives directly, without a config
+    match args.get(1).map(String::as_str) {
+        Some("restore") => return run_restore(&args[2..]),
+        Some("list") => return run_list(&args[2..]),
+        Some("merge") => return run_merge(&args[2..]),
+        Some("compare") => return run_compare(&args[2..]),
+        Some("check") => return run_check(&args[2..]),
+        Some("repair") => return run_repair(&args[2..]),
+        Some("cat") => {
+            log_to_stderr(&logger, verbosity.unwrap_or(LOG_LEVEL))?;
+            return run_cat(&args[2..]);
+        }
+        _ => {}
+    }
 
-    // Set config_path to 1st arg (If present)
-    let args: Vec<String> = env::args().collect();
-    let config_path = match args.get(1) {
+    // Set config_path to 1st arg (If present), after the command (If any)
+    let (command, config_arg) = match args.get(1).map(String::as_str) {
+        Some("--dry-run") => (Command::DryRun, args.get(2)),
+        Some("import") => (Command::Import, args.get(2)),
+        Some("rehash") => (Command::Rehash, args.get(2)),
+        Some("verify") => match args.get(2).map(String::as_str) {
+            Some("--all") => (Command::Verify(None), args.get(3)),
+            Some(segment) => (Command::Verify(Some(segment.to_string())), args.get(3)),
+            None => return Err(anyhow!("Usage: verify <segment|--all> [config]")),
+        },
+        Some("diff") => match args.get(2) {
+            Some(segment) => (Command::Diff(segment.clone()), args.get(3)),
+            None => return Err(anyhow!("Usage: diff <segment> [config]")),
+        },
+        Some("status") => (Command::Status, args.get(2)),
+        Some("prune") => (Command::Prune, args.get(2)),
+        Some("find") => match args.get(2) {
+            Some(pattern) => (Command::Find(pattern.clone()), args.get(3)),
+            None => return Err(anyhow!("Usage: find <pattern> [config]")),
+        },
+        _ => (Command::Backup, args.get(1)),
+    };
+    let config_path = match config_arg {
         Some(path_str) => PathBuf::from(path_str),
         None => PathBuf::from(CONFIG_PATH),
     };
@@ -51,19 +248,81 @@ fn main() -> Result<()> {
     let Config {
         output_path,
         root_path,
+        pre_script,
+        pre_script_failure,
         post_script,
+        post_script_workers,
+        destination,
+        retry,
+        script_retry,
         skip_script,
+        on_failure_script,
+        on_finish_script,
+        hooks,
+        script_options,
+        dry_run_scripts,
+        skip_requires_output,
+        verify_before_skip,
+        max_age_days,
         hash_file,
+        hash_algorithm,
+        hash_cache_file,
+        change_detection,
+        hash_buffer_bytes,
+        hash_metadata,
+        partial_hash_min_bytes,
+        partial_hash_sample_bytes,
+        hash_failure,
+        skipped_report_file,
+        manifest_dir,
+        change_report_file,
+        full_every,
+        keep_last,
+        keep_days,
+        state_dir,
+        catalog_file,
+        healthcheck_url,
+        webhook_url,
+        webhook_per_segment,
+        email,
+        chat,
+        mqtt,
         log_file,
+        console_log_level,
+        file_log_level,
+        log_rotation,
+        system_log,
         compression_level,
         max_size_bytes,
+        max_parts,
+        split_mode,
+        parity,
+        sync_parts,
+        part_list,
+        archive_index,
+        embed_manifest,
+        resumable,
+        part_checksum,
+        part_command,
+        max_write_bytes_per_sec,
+        check_free_space,
+        min_free_bytes,
+        estimate_size,
+        estimate_warn_parts,
         segments,
         ignore,
+        hash_ignore,
     } = toml::from_str(&config_str).context("Failed to parse config TOML")?;
 
-    if let Some(log_file) = log_file {
-        set_log_path(&logger, &log_file, LOG_LEVEL)?;
-    }
+    let console_level = verbosity.or(console_log_level).unwrap_or(LOG_LEVEL);
+    configure_logger(&logger, &LogOptions {
+        console_level,
+        console_to_stderr: progress_flag == Some(ProgressMode::Json),
+        log_file,
+        file_level: file_log_level.unwrap_or(LOG_LEVEL),
+        rotation: log_rotation,
+        system_log,
+    })?;
 
     let output_path = match output_path {
         Some(dir) => dir,
@@ -74,39 +333,419 @@ fn main() -> Result<()> {
     if output_path.exists() && !output_path.is_dir() {
         return Err(anyhow!("Output path exists but is not a directory: {:?}", output_path));
     }
-    if let Some(dir) = output_path.parent() {
-        if !dir.exists() {
-            return Err(anyhow!("Output directory not found: {:?}", dir));
-        }
+    if let Some(dir) = output_path.parent()
+        && !dir.exists() {
+        return Err(anyhow!("Output directory not found: {:?}", dir));
     }
     if !output_path.exists() {
         fs::create_dir(&output_path).context("Failed to create output directory")?;
     }
 
+    let (segments, segment_options) = split_segments(segments);
     let all_paths: HashSet<&PathBuf> = segments.values().collect();
 
+    // Scripts for each hook (Scripts set on a segment are run instead of the global ones)
+    // (A dry run hashes each segment, but doesn't dump, quiesce, archive or record anything)
+    let dry_run = command == Command::DryRun;
+    let run_scripts = !dry_run || dry_run_scripts.unwrap_or_default() == DryRunScripts::Run;
+    let retry = retry.unwrap_or_default();
+    let mut scripts = collect_scripts(hooks.unwrap_or_default(), [
+        (HookKind::SegmentStart, pre_script),
+        (HookKind::PartFinalized, post_script),
+        (HookKind::SegmentSkipped, skip_script),
+        (HookKind::RunDone, on_finish_script),
+        (HookKind::Failure, on_failure_script),
+    ])?;
+    let mut segment_scripts = HashMap::new();
+    for (name, options) in &segment_options {
+        let scripts = collect_scripts(options.hooks.clone(), [
+            (HookKind::SegmentStart, options.pre_script.clone()),
+            (HookKind::PartFinalized, options.post_script.clone()),
+            (HookKind::SegmentSkipped, options.skip_script.clone()),
+        ]).context(format!("Invalid scripts for segment '{}'", name))?;
+        segment_scripts.insert(name.clone(), scripts);
+    }
+    if !run_scripts {
+        scripts.clear();
+        segment_scripts.clear();
+    }
+    let hooks = Arc::new(Hooks {
+        scripts,
+        segment_scripts,
+        options: script_options.unwrap_or_default(),
+        retry,
+        script_retry,
+        dry_run,
+    });
+
     // Build ignore pattern matcher if patterns are provided
     let ignore_matcher = ignore.as_ref()
         .map_or_else(|| Ok(None), |patterns| build_ignore_matcher(patterns))
         .context("Failed to build ignore pattern matcher")?;
+    let hash_ignore_matcher = hash_ignore.as_ref()
+        .map_or_else(|| Ok(None), |patterns| build_ignore_matcher(patterns))
+        .context("Failed to build hash_ignore pattern matcher")?;
+
+    // Free space check defaults to room for a full part
+    let min_free_bytes = match min_free_bytes {
+        Some(bytes) => Some(bytes),
+        None if check_free_space.unwrap_or(false) => max_size_bytes.map(|size| size as u64),
+        None => None,
+    };
+
+    // Parts are written to output_path, unless they are piped to part_command or streamed to the destination
+    // (A streamed part can't also be uploaded elsewhere)
+    let destinations = destination.map(Destinations::into_vec).unwrap_or_default();
+    let stream_sink = match &destinations[..] {
+        [destination] => destination.sink(max_size_bytes),
+        _ if destinations.iter().any(|destination| destination.sink(max_size_bytes).is_some()) =>
+            return Err(anyhow!("stream can only be set when there is a single destination")),
+        _ => None,
+    };
+    let sink = match (part_command, stream_sink) {
+        (Some(_), Some(_)) => return Err(anyhow!("part_command can't be set with a streaming destination")),
+        (Some(command), None) => Some(Arc::new(CommandSink::new(command)) as Arc<dyn ArchiveSink>),
+        (None, sink) => sink,
+    };
+
+    let mut archive_options = ArchiveOptions {
+        ignore_patterns: ignore_matcher,
+        compression_level,
+        max_size_bytes,
+        max_parts,
+        split_mode: split_mode.unwrap_or_default(),
+        hooks: hooks.clone(),
+        destinations,
+        uploads: Default::default(),
+        retry,
+        // (Checkpoints of resumable archives must only record handled parts, so they are handled inline unless set)
+        script_workers: script_workers(post_script_workers, resumable.unwrap_or(false)),
+        parity: parity.unwrap_or(false),
+        sync_parts: sync_parts.unwrap_or(false),
+        part_list: part_list.unwrap_or(false),
+        part_checksum,
+        sink,
+        min_free_bytes,
+        max_write_bytes_per_sec,
+        resumable: resumable.unwrap_or(false),
+        index: archive_index.unwrap_or(false),
+        entries: Vec::new(),
+        progress: None,
+    };
+
+    if full_every.is_some() && manifest_dir.is_none() {
+        warn!("full_every requires manifest_dir (To find changed files), every archive will be full");
+    }
+
+    // Parts sent to a command never exist in the output directory
+    let skip_requires_output = skip_requires_output.unwrap_or(true) && archive_options.stores_locally();
+    let verify_before_skip = verify_before_skip.unwrap_or(false) && archive_options.stores_locally();
+
+    let hash_options = HashOptions {
+        algorithm: hash_algorithm.unwrap_or_default(),
+        change_detection: change_detection.unwrap_or_default(),
+        buffer_size: hash_buffer_bytes.unwrap_or(HASHER_BUFFER_SIZE),
+        include_metadata: hash_metadata.unwrap_or(false),
+        hash_ignore: hash_ignore_matcher,
+        partial_hash: partial_hash_min_bytes.map(|min_bytes| PartialHash {
+            min_bytes,
+            sample_bytes: partial_hash_sample_bytes.unwrap_or(PARTIAL_HASH_SAMPLE_BYTES),
+        }),
+        on_failure: hash_failure.unwrap_or_default(),
+    };
+
+    // Only full content hashes can be checked against extracted files
+    let mut embed_manifest = embed_manifest.unwrap_or(false);
+    if embed_manifest && (hash_options.change_detection != ChangeDetection::Content || hash_options.partial_hash.is_some()) {
+        warn!("embed_manifest requires change_detection = \"content\" without partial hashing, no manifest will be embedded");
+        embed_manifest = false;
+    }
+
+    // Load file hashes from previous runs (A damaged cache only costs re-reading files)
+    let mut hash_cache = hash_cache_file.as_ref().map(|cache_file| {
+        read_hash_cache(cache_file).unwrap_or_else(|e| {
+            warn!("Ignoring unreadable hash cache: {:#}", e);
+            HashCache::default()
+        })
+    });
 
     // Load existing hash file
     let mut segment_hashes = if let Some(hash_file) = &hash_file {
         read_hash_file(hash_file).context("Failed to read hash file")?
     } else {
-        HashMap::<String, String>::new()
+        HashFile::default()
     };
 
+    if let Command::Verify(target) = &command {
+        let mut names: Vec<&String> = match target {
+            Some(name) if segments.contains_key(name) => vec![name],
+            Some(name) => return Err(anyhow!("Unknown segment: '{}'", name)),
+            None => segments.keys().collect(),
+        };
+        names.sort();
+        let mut failed = 0;
+        for name in &names {
+            match verify_segment(&output_path, manifest_dir.as_deref(), name) {
+                Ok(check) => info!("Segment '{}': OK ({} file(s) in {} part(s){}{})", name, check.files, check.parts,
+                    if check.manifest_checked { ", matches manifest" } else { "" },
+                    if check.hashes_checked > 0 { format!(", {} hash(es) checked", check.hashes_checked) } else { String::new() }),
+                Err(e) => {
+                    error!("Segment '{}': FAILED - {:#}", name, e);
+                    failed += 1;
+                }
+            }
+        }
+        if failed > 0 {
+            return Err(anyhow!("{} of {} segment(s) failed verification", failed, names.len()));
+        }
+        info!("Verify finished.");
+        return Ok(());
+    }
+
+    if let Command::Find(pattern) = &command {
+        let Some(catalog_file) = &catalog_file else {
+            return Err(anyhow!("The find command requires catalog_file to be set"));
+        };
+        let entries = read_catalog(catalog_file)?;
+        let found = find_files(&entries, pattern)?;
+        for found in &found {
+            println!("{}", found.line());
+        }
+        info!("Found {} file(s) matching '{}' in {} cataloged archive(s)", found.len(), pattern, entries.len());
+        return Ok(());
+    }
+
+    let retention = Retention { keep_last, keep_days };
+    if command == Command::Prune {
+        prune_segments(&segments, &output_path, manifest_dir.as_deref(), retention)?;
+        info!("Prune finished.");
+        return Ok(());
+    }
+
+    if command == Command::Status {
+        // (A missing journal just means no runs were recorded yet)
+        let journal = state_dir.as_ref()
+            .map(|dir| journal_path(dir))
+            .filter(|journal_file| journal_file.exists())
+            .and_then(|journal_file| read_journal(&journal_file)
+                .inspect_err(|e| warn!("Failed to read journal: {:#}", e))
+                .ok());
+        let mut names: Vec<&String> = segments.keys().collect();
+        names.sort();
+        for name in names {
+            let path = &segments[name];
+            let mut status = SegmentStatus::last_run(name, segment_hashes.segments.get(name), &output_path);
+            status.history = journal.as_ref().map(|runs| SegmentHistory::of(name, runs));
+            if let Some(stored) = &status.hash {
+                let exclusions = get_exclusions(&all_paths, path);
+                let segment_cache = hash_cache.as_mut().map(|cache| cache.segments.entry(name.clone()).or_default());
+                let hash_result = fs::metadata(path)
+                    .context(format!("Failed to read metadata for segment root: {:?}", path))
+                    .and_then(|metadata| compute_segment_hash(path, &metadata, &exclusions, archive_options.ignore_patterns.as_ref(), &hash_options, segment_cache));
+                write_cache(&hash_cache_file, &hash_cache, false);
+                status.source = match hash_result {
+                    Ok(segment_hash) if hashes_match(stored, &segment_hash.hash) => SourceState::Unchanged,
+                    Ok(_) => SourceState::Changed,
+                    Err(e) => SourceState::Unreadable(format!("{:#}", e)),
+                };
+            }
+            println!("{}", status.line());
+        }
+        return Ok(());
+    }
+
+    if let Command::Diff(name) = &command {
+        let Some(path) = segments.get(name) else {
+            return Err(anyhow!("Unknown segment: '{}'", name));
+        };
+        let exclusions = get_exclusions(&all_paths, path);
+        let metadata = fs::metadata(path)
+            .context(format!("Failed to read metadata for segment root: {:?}", path))?;
+        let manifest_file = manifest_dir.as_ref()
+            .map(|dir| manifest_path(dir, name))
+            .filter(|manifest_file| manifest_file.exists());
+        // (The manifest compares hashes, otherwise the archive's sizes and modification times are compared)
+        let changes = if let Some(manifest_file) = manifest_file {
+            let previous = read_manifest(&manifest_file)
+                .context(format!("Failed to read manifest for segment '{}'", name))?;
+            let segment_cache = hash_cache.as_mut().map(|cache| cache.segments.entry(name.clone()).or_default());
+            let hash_result = compute_segment_hash(path, &metadata, &exclusions, archive_options.ignore_patterns.as_ref(), &hash_options, segment_cache);
+            write_cache(&hash_cache_file, &hash_cache, false);
+            let SegmentHash { hash, files, .. } = hash_result
+                .context(format!("Failed to compute hash for segment '{}'", name))?;
+            diff_manifests(&previous, &hash, &files)
+                .ok_or_else(|| anyhow!("Manifest of segment '{}' was made with another hash_algorithm, run rehash to compare", name))?
+        } else {
+            info!("No manifest for segment '{}', comparing with its latest archive", name);
+            let parts = latest_archive_parts(&output_path, name)?;
+            let previous = archive_stamps(&parts)?;
+            diff_stamps(&previous, &source_stamps(path, &metadata, &exclusions, archive_options.ignore_patterns.as_ref())?)
+        };
+        for line in changes.lines() {
+            println!("{}", line);
+        }
+        info!("Segment '{}': {} added, {} removed, {} modified", name, changes.added.len(), changes.removed.len(), changes.modified.len());
+        return Ok(());
+    }
+
+    if command == Command::Import {
+        let Some(hash_file) = &hash_file else {
+            return Err(anyhow!("The import command requires hash_file to be set"));
+        };
+        for (name, path) in &segments {
+            if segment_hashes.hash(name).is_some() {
+                info!("Segment '{}' already has a hash, skipping", name);
+                continue;
+            }
+            let archive_path = output_path.join(format!("{}.{}", name, archive_options.split_mode.archive_extension()));
+            let record = match import_record(&output_path, name, &archive_path) {
+                Some(record) => {
+                    info!("Imported hash for segment '{}' from its existing archive", name);
+                    record
+                }
+                None if missing_output(&output_path, &archive_path, &[]).is_none() => {
+                    // (i.e. archived by an older version)
+                    warn!("Archive of segment '{}' has no hash, hashing its current files (Assumes the archive is up to date)", name);
+                    let exclusions = get_exclusions(&all_paths, path);
+                    let hash_result = fs::metadata(path)
+                        .context(format!("Failed to read metadata for segment root: {:?}", path))
+                        .and_then(|metadata| compute_segment_hash(path, &metadata, &exclusions, archive_options.ignore_patterns.as_ref(), &hash_options, None));
+                    match hash_result {
+                        Ok(segment_hash) => SegmentRecord::new(segment_hash.hash),
+                        Err(e) => {
+                            error!("Failed to compute hash for segment '{}': {}", name, e);
+                            continue;
+                        }
+                    }
+                }
+                None => {
+                    info!("No existing archive for segment '{}', skipping", name);
+                    continue;
+                }
+            };
+            update_hash_file(hash_file, name, Some(&record))
+                .context(format!("Failed to write hash for segment '{}'", name))?;
+        }
+        info!("Import finished.");
+        return Ok(());
+    }
+
+    if command == Command::Rehash {
+        if hash_file.is_none() && manifest_dir.is_none() {
+            return Err(anyhow!("The rehash command requires hash_file or manifest_dir to be set"));
+        }
+        for (name, path) in &segments {
+            let exclusions = get_exclusions(&all_paths, path);
+            let segment_cache = hash_cache.as_mut().map(|cache| cache.segments.entry(name.clone()).or_default());
+            let hash_result = fs::metadata(path)
+                .context(format!("Failed to read metadata for segment root: {:?}", path))
+                .and_then(|metadata| compute_segment_hash(path, &metadata, &exclusions, archive_options.ignore_patterns.as_ref(), &hash_options, segment_cache));
+            write_cache(&hash_cache_file, &hash_cache, false);
+            let SegmentHash { hash, files, skipped, .. } = match hash_result {
+                Ok(segment_hash) => segment_hash,
+                Err(e) => {
+                    error!("Failed to compute hash for segment '{}': {}", name, e);
+                    continue;
+                }
+            };
+            info!("Computed new hash for segment '{}'", name);
+            report_skipped(name, &skipped, &skipped_report_file, false);
+
+            if let Some(hash_file) = &hash_file {
+                // (Keeps the details of the last backup)
+                let rehashed = SegmentRecord::new(hash.clone());
+                let record = match segment_hashes.segments.get(name) {
+                    Some(record) => SegmentRecord { hash: rehashed.hash, algorithm: rehashed.algorithm, ..record.clone() },
+                    None => rehashed,
+                };
+                update_hash_file(hash_file, name, Some(&record))
+                    .context(format!("Failed to write hash for segment '{}'", name))?;
+            }
+            if let Some(manifest_dir) = &manifest_dir {
+                let manifest = Manifest { segment: name.clone(), hash, created: Local::now().to_rfc3339(), files };
+                write_manifest(&manifest_path(manifest_dir, name), &manifest)
+                    .context(format!("Failed to write manifest for segment '{}'", name))?;
+            }
+        }
+        info!("Rehash finished.");
+        return Ok(());
+    }
+
     // ---- Process each section ---- //
-    for (name, path) in &segments {
+    // (Unless set by --progress, bars are only drawn on a terminal, and not with -q)
+    let progress_mode = progress_flag.unwrap_or(match std::io::stderr().is_terminal() && console_level >= LevelFilter::Info {
+        true => ProgressMode::Bar,
+        false => ProgressMode::Off,
+    });
+    let show_progress = progress_mode != ProgressMode::Off;
+    let json_progress = progress_mode == ProgressMode::Json;
+    let notifier = Notifier::new(NotifyOptions {
+        healthcheck_url,
+        webhook_url,
+        webhook_per_segment: webhook_per_segment.unwrap_or(false),
+        email,
+        chat,
+        mqtt,
+        hooks: hooks.clone(),
+        dry_run: dry_run.then(|| dry_run_scripts.unwrap_or_default()),
+    });
+    let mut run = RunRecord::start();
+    run.dry_run = dry_run;
+    notifier.run_started(&run);
+    let _watchdog = Watchdog::start();
+    for (number, (name, path)) in segments.iter().enumerate() {
+        notifier.segment_started(name, number + 1, segments.len());
+        run.begin_segment();
         info!("--- Processing Section: {} at {:?} ---", name, path);
+
+        // Run the pre-script, which can veto the segment
+        match hooks.dispatch(&HookEvent::SegmentStart { segment: name, path, output_dir: &output_path }) {
+            Ok(0) => {}
+            Ok(code) if pre_script_failure == Some(PreScriptFailure::Skip) => {
+                warn!("Pre-script exited with code {}, skipping segment '{}'", code, name);
+                run.skipped(name);
+                notifier.segments_finished(&run);
+                continue;
+            }
+            Ok(code) => {
+                error!("Pre-script exited with code {}, failing segment '{}'", code, name);
+                run.failed(name, format!("Pre-script exited with code {}", code));
+                notifier.segments_finished(&run);
+                continue;
+            }
+            Err(e) => {
+                error!("Pre-script failed, failing segment '{}': {}", name, e);
+                run.failed(name, format!("Pre-script failed: {}", e));
+                notifier.segments_finished(&run);
+                continue;
+            }
+        }
+        if let Some(command) = segment_options.get(name).and_then(|options| options.dump.as_ref()) {
+            if dry_run {
+                info!("Dry run: segment '{}' would be dumped and archived", name);
+                run.archived(name, 0, 0);
+                notifier.segments_finished(&run);
+                continue;
+            }
+            match run_dump(command, path) {
+                Ok(size) => info!("Dumped segment '{}' ({} bytes)", name, size),
+                Err(e) => {
+                    error!("Failed to dump segment '{}': {}", name, e);
+                    run.failed(name, format!("Failed to dump: {}", e));
+                    notifier.segments_finished(&run);
+                    continue;
+                }
+            }
+        }
         if !path.exists() {
             error!("Path not found, skipping: {:?}", path);
+            run.failed(name, format!("Path not found: {:?}", path));
+            notifier.segments_finished(&run);
             continue;
         }
 
         // Generate archive path
-        let archive_path = output_path.join(format!("{}.tar.gz", name));
+        let archive_path = output_path.join(format!("{}.{}", name, archive_options.split_mode.archive_extension()));
 
         // List paths to exclude from the current segment
         let exclusions = get_exclusions(&all_paths, path);
@@ -116,69 +755,678 @@ fn main() -> Result<()> {
             Ok(m) => m,
             Err(e) => {
                 error!("Failed to read metadata for segment root, skipping segment '{}': {:?} - {}", name, path, e);
+                run.failed(name, format!("Failed to read metadata for segment root: {}", e));
+                notifier.segments_finished(&run);
                 continue;
             }
         };
 
+        // A segment with no stored hash is archived regardless, so hash it while archiving
+        // (Unless resuming or embedding the manifest, which need the hash up front, or partially hashing, since archiving reads everything)
+        let single_pass = hash_options.change_detection == ChangeDetection::Content
+            && hash_options.partial_hash.is_none()
+            && !archive_options.resumable
+            && !embed_manifest
+            && segment_hashes.hash(name).is_none();
+
         // Compute and store segment hash
-        match compute_segment_hash(path, &metadata, &exclusions, ignore_matcher.as_ref()) {
-            Ok(hash) => {
-                if segment_hashes.get(name) == Some(&hash) {
-                    info!("Segment '{}' has not changed, skipping", name);
-                    if let Some(ref script) = skip_script {
-                        // Execute skip_script if provided
-                        execute_script(script.clone(), &archive_path.display().to_string())?;
+        let mut segment_files = None;
+        let mut unhashed_files = Vec::new();
+        let mut skipped_files = Vec::new();
+        let segment_hash = if single_pass {
+            info!("No previous hash for segment '{}', hashing while archiving", name);
+            None
+        } else {
+            if json_progress {
+                print_event(name, "hashing", None, &ProgressSnapshot::default());
+            }
+            let segment_cache = hash_cache.as_mut().map(|cache| cache.segments.entry(name.clone()).or_default());
+            let hash_result = compute_segment_hash(path, &metadata, &exclusions, archive_options.ignore_patterns.as_ref(), &hash_options, segment_cache);
+            write_cache(&hash_cache_file, &hash_cache, dry_run);
+            match hash_result {
+                Ok(SegmentHash { hash, files, unhashed, skipped }) => {
+                    report_skipped(name, &skipped, &skipped_report_file, dry_run);
+                    if segment_hashes.hash(name).is_some_and(|stored| hashes_match(stored, &hash)) {
+                        // Not skipped if its previous archive is too old, gone or damaged (Which would leave no good backup)
+                        let untrusted = max_age_days
+                            .and_then(|max_age_days| backup_too_old(segment_hashes.segments.get(name), max_age_days, Local::now().fixed_offset()))
+                            .or_else(|| untrusted_output(&segment_hashes, name, &output_path, &archive_path,
+                                skip_requires_output, verify_before_skip, archive_options.split_mode != SplitMode::MultiVolume));
+                        if let Some(reason) = untrusted {
+                            warn!("Segment '{}' has not changed, but its archive {}, archiving again", name, reason);
+                        } else {
+                            info!("Segment '{}' has not changed, skipping", name);
+                            if json_progress {
+                                print_event(name, "skipped", None, &ProgressSnapshot::default());
+                            }
+                            if hooks.script(HookKind::SegmentSkipped, Some(name)).is_some() {
+                                // Execute skip_script if provided
+                                let event = HookEvent::SegmentSkipped {
+                                    segment: name,
+                                    hash: segment_hashes.hash(name),
+                                    output_dir: &output_path,
+                                    archive: &archive_path,
+                                    last_backup: segment_hashes.segments.get(name).and_then(|record| record.last_backup.as_deref()),
+                                    latest_archive: latest_archive_parts(&output_path, name).ok()
+                                        .and_then(|parts| parts.first().map(|part| archive_base(part))),
+                                };
+                                if let Err(e) = hooks.dispatch(&event) {
+                                    run.failed(name, &e);
+                                    finish_run(&state_dir, &notifier, run);
+                                    return Err(e.into());
+                                }
+                            }
+                            run.skipped(name);
+                            notifier.segments_finished(&run);
+                            continue;
+                        }
+                    } else {
+                        info!("Computed new hash for segment '{}'", name);
+                    }
+                    segment_files = Some(files);
+                    unhashed_files = unhashed;
+                    skipped_files = skipped;
+                    Some(hash)
+                }
+                Err(e) => {
+                    error!("Failed to compute hash for segment '{}': {}", name, e);
+                    if CRASH_ON_HASH_FAILURE {
+                        run.failed(name, format!("Failed to compute hash: {}", e));
+                        finish_run(&state_dir, &notifier, run);
+                        return Err(anyhow!("Failed to compute hash for segment '{}'", name))
+                    } else {
+                        info!("Forcing backup of segment '{}' due to hash failure.", name);
+                        segment_hashes.segments.remove(name);
+                        // Remove this segment from the hash file so it will be backed up
+                        // on the next run (even if unchanged) because it can't be hashed.
+                        None
                     }
-                    continue;
-                } else {
-                    info!("Computed new hash for segment '{}'", name);
                 }
-                segment_hashes.insert(name.clone(), hash.clone());
             }
-            Err(e) => {
-                error!("Failed to compute hash for segment '{}': {}", name, e);
-                if CRASH_ON_HASH_FAILURE {
-                    return Err(anyhow!("Failed to compute hash for segment '{}'", name))
-                } else {
-                    info!("Forcing backup of segment '{}' due to hash failure.", name);
-                    segment_hashes.remove(name);
-                    // Remove this segment from the hash file so it will be backed up
-                    // on the next run (even if unchanged) because it can't be hashed.
+        };
+
+        // Forecast the archive size (Learning the compression ratio from the previous run)
+        // (Also the total size of the progress bar)
+        let log_estimate = estimate_size.unwrap_or(false) || estimate_warn_parts.is_some();
+        let mut progress_total = None;
+        if log_estimate || (show_progress && !dry_run) {
+            let previous = read_sidecar(&sidecar_path(&output_path, name)).ok();
+            match estimate::estimate_size(path, &metadata, &exclusions, archive_options.ignore_patterns.as_ref(), previous.as_ref(), max_size_bytes) {
+                Ok(estimate) if !log_estimate => progress_total = Some(estimate.uncompressed_bytes),
+                Ok(estimate) => {
+                    progress_total = Some(estimate.uncompressed_bytes);
+                    let ratio_source = match estimate.ratio {
+                        Some(ratio) => format!("ratio {:.2} from previous run", ratio),
+                        None => "assuming no compression".to_string(),
+                    };
+                    info!("Estimated size for segment '{}': {} file(s), {} bytes -> ~{} bytes in {} part(s) ({})",
+                        name, estimate.file_count, estimate.uncompressed_bytes, estimate.compressed_bytes, estimate.parts, ratio_source);
+                    if let Some(limit) = estimate_warn_parts
+                        && estimate.parts > limit {
+                        warn!("Segment '{}' is expected to need {} parts (More than estimate_warn_parts: {}), consider adjusting max_size_bytes", name, estimate.parts, limit);
+                    }
+                    if let Some(limit) = max_parts
+                        && estimate.parts > limit as u64 {
+                        warn!("Segment '{}' is expected to need {} parts, which will exceed max_parts: {}", name, estimate.parts, limit);
+                    }
                 }
+                Err(e) => warn!("Failed to estimate size of segment '{}': {}", name, e),
             }
         }
+        if dry_run {
+            info!("Dry run: segment '{}' would be archived", name);
+            run.archived(name, 0, 0);
+            notifier.segments_finished(&run);
+            continue;
+        }
+
+        // Archive only the changed files if the segment's chain is due an increment
+        let previous_manifest = manifest_dir.as_ref()
+            .map(|dir| manifest_path(dir, name))
+            .filter(|manifest_file| manifest_file.exists())
+            .and_then(|manifest_file| read_manifest(&manifest_file)
+                .inspect_err(|e| warn!("Failed to read previous manifest for segment '{}': {}", name, e))
+                .ok());
+        let mut chain = match (&manifest_dir, full_every) {
+            (Some(dir), Some(_)) => Some(read_chain(&chain_path(dir, name)).unwrap_or_else(|_| Chain {
+                segment: name.clone(),
+                generations: Vec::new(),
+            })),
+            _ => None,
+        };
+        let increment = chain.as_ref().zip(full_every).and_then(|(chain, full_every)| {
+            plan_increment(chain, full_every, previous_manifest.as_ref(), segment_hash.as_deref(), segment_files.as_ref())
+        });
+        let generation = increment.as_ref().map_or(0, |(generation, _)| *generation);
+        let archive_path = output_path.join(archive_name(name, generation, archive_options.split_mode.archive_extension()));
+        // (Files left out of the hash can't be compared, so they are in every increment)
+        let changed_files: Option<HashSet<String>> = increment.as_ref().map(|(_, changes)| {
+            changes.added.iter().chain(&changes.modified).chain(&unhashed_files).cloned().collect()
+        });
+        if let Some((generation, changes)) = &increment {
+            info!("Archiving increment {} of segment '{}' ({} changed file(s), {} removed)",
+                generation, name, changes.added.len() + changes.modified.len(), changes.removed.len());
+        }
 
         // Create the archive
-        if let Err(e) = create_archive(
-            path,
-            &metadata,
-            &archive_path,
-            &root_path,
-            &exclusions,
-            ignore_matcher.as_ref(),
-            compression_level,
-            max_size_bytes,
-            post_script.to_owned(),
-        ) {
-            error!("Failed on segment '{}': {}", name, e);
-            return Err(anyhow!("Failed on segment '{}'", name));
-        }
-        info!("Successfully created archive: {:?}", archive_path);
+        let mut info = match ArchiveInfo::new(name, path, &root_path, segment_hash) {
+            Ok(info) => info,
+            Err(e) => {
+                error!("Failed on segment '{}': {}", name, e);
+                run.failed(name, format!("{:#}", e));
+                finish_run(&state_dir, &notifier, run);
+                return Err(anyhow!("Failed on segment '{}'", name));
+            }
+        };
+        // (Unreadable files only have a placeholder hash)
+        if embed_manifest {
+            info.manifest = segment_files.clone().map(|mut files| {
+                for (skipped, _) in &skipped_files {
+                    files.remove(skipped);
+                }
+                files
+            });
+        }
+        // Quiesce the segment's containers while it's archived (Only once it's known to have changed)
+        let quiesced = match segment_options.get(name).and_then(|options| options.docker.as_ref()) {
+            Some(docker) => match quiesce(docker) {
+                Ok(quiesced) => Some(quiesced),
+                Err(e) => {
+                    error!("Failed to quiesce Docker containers, skipping segment '{}': {}", name, e);
+                    run.failed(name, format!("Failed to quiesce Docker containers: {}", e));
+                    notifier.segments_finished(&run);
+                    continue;
+                }
+            },
+            None => None,
+        };
+        let streaming_hash = single_pass.then(|| StreamingSegmentHash::new(&hash_options));
+        archive_options.entries = segment_options.get(name).map(|options| options.entries.clone()).unwrap_or_default();
+        // (Increments only read their changed files, so their share of the total isn't known)
+        let progress_total = progress_total.filter(|_| changed_files.is_none());
+        archive_options.progress = show_progress.then(Arc::default);
+        let progress_reporter = archive_options.progress.clone()
+            .and_then(|progress| ProgressReporter::start(progress_mode, name, progress_total, progress));
+        let result = create_archive(path, &metadata, &archive_path, &exclusions, &info, &archive_options, streaming_hash, changed_files.as_ref());
+        if let Some(progress_reporter) = progress_reporter {
+            progress_reporter.finish();
+        }
+        if json_progress && let Some(progress) = &archive_options.progress {
+            print_event(name, if result.is_ok() { "archived" } else { "failed" }, progress_total, &progress.snapshot());
+        }
+        resume_containers(name, quiesced, &mut run);
+        let uploads = archive_options.uploads.take();
+        for upload in &uploads {
+            info!("Uploaded {} file(s) of segment '{}' to {} ({} failed)", upload.uploaded, name, upload.destination, upload.failed);
+        }
+        let mut summary = match result {
+            Ok(summary) => summary,
+            Err(e) => {
+                error!("Failed on segment '{}': {}", name, e);
+                run.failed(name, format!("{:#}", e));
+                run.uploaded(uploads);
+                finish_run(&state_dir, &notifier, run);
+                return Err(anyhow!("Failed on segment '{}'", name));
+            }
+        };
+        info!("Successfully created archive: {:?} ({} part(s), {} bytes)", archive_path, summary.parts.count(), summary.parts.total_size());
+        run.archived(name, summary.parts.total_size(), summary.parts.count());
+        run.uploaded(uploads);
+        for part in &summary.parts.parts {
+            info!("  Part: {:?} ({} bytes)", part.path, part.size);
+        }
+        let ratio = summary.compression_ratio().map_or_else(|| "no content".to_string(), |ratio| format!("ratio {:.2}", ratio));
+        let duration = run.segments.last().and_then(|segment| segment.duration_secs).unwrap_or_default();
+        info!("Statistics for segment '{}': {} file(s), {} bytes -> {} bytes ({}) in {} part(s), took {:.1}s",
+            name, summary.file_count, summary.uncompressed_bytes, summary.parts.total_size(), ratio, summary.parts.count(), duration);
+
+        // Store the hash computed while archiving
+        let mut segment_hash = info.hash;
+        if let Some(streaming_hash) = summary.segment_hash.take() {
+            let segment_cache = hash_cache.as_mut().map(|cache| cache.segments.entry(name.clone()).or_default());
+            match streaming_hash.finish(segment_cache) {
+                Some(SegmentHash { hash, files, .. }) => {
+                    info!("Computed new hash for segment '{}'", name);
+                    segment_hash = Some(hash);
+                    segment_files = Some(files);
+                }
+                None => warn!("Some files in segment '{}' could not be read, it will be backed up again next run", name),
+            }
+            write_cache(&hash_cache_file, &hash_cache, dry_run);
+        }
+
+        // Write the metadata sidecar
+        let sidecar_file = sidecar_path(&output_path, name);
+        let sidecar = SegmentMetadata {
+            segment: name.clone(),
+            source_path: path.clone(),
+            hash: segment_hash,
+            created: info.created,
+            file_count: summary.file_count,
+            uncompressed_bytes: summary.uncompressed_bytes,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            checksum_algorithm: archive_options.part_checksum,
+            parts: summary.parts.parts,
+        };
+        if let Some(hash) = &sidecar.hash {
+            segment_hashes.segments.insert(name.clone(), SegmentRecord {
+                last_backup: Some(sidecar.created.clone()),
+                file_count: Some(sidecar.file_count),
+                uncompressed_bytes: Some(sidecar.uncompressed_bytes),
+                parts: sidecar.parts.clone(),
+                ..SegmentRecord::new(hash.clone())
+            });
+        }
+        if let Some(catalog_file) = &catalog_file {
+            // (An increment only holds the changed files)
+            let files = segment_files.as_ref().map(|files| files.iter()
+                .filter(|(path, _)| changed_files.as_ref().is_none_or(|changed| changed.contains(*path)))
+                .map(|(path, file)| (path.clone(), file.clone()))
+                .collect())
+                .unwrap_or_default();
+            if segment_files.is_none() {
+                warn!("Segment '{}' was not hashed, so its files are left out of the catalog", name);
+            }
+            let entry = CatalogEntry {
+                segment: name.clone(),
+                archive: archive_name(name, generation, archive_options.split_mode.archive_extension()),
+                created: sidecar.created.clone(),
+                hash: sidecar.hash.clone(),
+                parts: sidecar.parts.clone(),
+                files,
+            };
+            if let Err(e) = append_catalog(catalog_file, &entry) {
+                error!("Failed to add segment '{}' to the catalog: {:#}", name, e);
+            }
+        }
+        if let (Some(manifest_dir), Some(hash), Some(files)) = (&manifest_dir, &sidecar.hash, segment_files) {
+            let manifest_file = manifest_path(manifest_dir, name);
+            let manifest = Manifest { segment: name.clone(), hash: hash.clone(), created: sidecar.created.clone(), files };
+            if let Some(previous) = &previous_manifest {
+                report_changes(previous, &manifest, &change_report_file);
+            }
+            if let Err(e) = write_manifest(&manifest_file, &manifest) {
+                error!("Failed to write manifest for segment '{}': {}", name, e);
+            } else {
+                info!("Wrote manifest: {:?}", manifest_file);
+            }
+        }
+        if let (Some(manifest_dir), Some(chain)) = (&manifest_dir, chain.as_mut()) {
+            chain.push(Generation {
+                generation,
+                created: sidecar.created.clone(),
+                archive: archive_name(name, generation, archive_options.split_mode.archive_extension()),
+                parts: sidecar.parts.iter().map(|part| part.name.clone()).collect(),
+                removed: increment.map(|(_, changes)| changes.removed).unwrap_or_default(),
+            });
+            if let Err(e) = write_chain(&chain_path(manifest_dir, name), chain) {
+                error!("Failed to write chain file for segment '{}': {}", name, e);
+            }
+        }
+        if let Err(e) = write_sidecar(&sidecar_file, &sidecar) {
+            error!("Failed to write metadata file for segment '{}': {}", name, e);
+        } else {
+            info!("Wrote metadata file: {:?}", sidecar_file);
+        }
         
         if let Some(hash_file) = &hash_file {
-            if let Err(e) = write_hash_file(hash_file, &segment_hashes) {
-                info!("New hashes (You can manually update the hash file if you need to): {:?}", segment_hashes);
+            if let Err(e) = update_hash_file(hash_file, name, segment_hashes.segments.get(name)) {
+                info!("New hash (You can manually update the hash file if you need to): {:?}", segment_hashes.segments.get(name));
                 error!("Failed to write new hashes to '{}': {}", hash_file.display(), e);
             } else {
                 info!("Updated hash file: {:?}", hash_file);
             }
         }
+
+        let event = HookEvent::SegmentDone { segment: name, hash: sidecar.hash.as_deref(), output_dir: &output_path, archive: &archive_path, parts: sidecar.parts.len() };
+        match hooks.dispatch(&event) {
+            Ok(0) => {}
+            Ok(code) => warn!("Segment-done script exited with code {}", code),
+            Err(e) => {
+                error!("Segment-done script failed for segment '{}': {}", name, e);
+                run.errors.push(format!("Segment-done script failed for segment '{}': {}", name, e));
+            }
+        }
+        notifier.segments_finished(&run);
+    }
+
+    // (Only once retention is configured, since superseded archives are otherwise kept)
+    if (keep_last.is_some() || keep_days.is_some())
+        && !dry_run
+        && let Err(e) = prune_segments(&segments, &output_path, manifest_dir.as_deref(), retention) {
+        error!("Failed to prune superseded archives: {:#}", e);
+        run.errors.push(format!("Failed to prune superseded archives: {:#}", e));
+    }
+
+    finish_run(&state_dir, &notifier, run);
+
+    info!("{} finished.", if dry_run { "Dry run" } else { "Backup process" });
+    Ok(())
+}
+
+/// Resume a segment's quiesced containers, recording it in the run if any can't be
+fn resume_containers(name: &str, quiesced: Option<Quiesced>, run: &mut RunRecord) {
+    if let Some(quiesced) = quiesced
+        && let Err(e) = quiesced.resume() {
+        error!("Failed to resume Docker containers of segment '{}': {}", name, e);
+        run.errors.push(format!("Failed to resume Docker containers of segment '{}': {}", name, e));
+    }
+}
+
+/// Record the end of a run in the journal, if enabled, and send notifications (A failure only loses the record)
+fn finish_run(state_dir: &Option<PathBuf>, notifier: &Notifier, mut run: RunRecord) {
+    run.finished = Local::now().to_rfc3339();
+    notifier.run_finished(&run);
+    let Some(state_dir) = state_dir.as_ref().filter(|_| !run.dry_run) else {
+        return;
+    };
+    let journal_file = journal_path(state_dir);
+    if let Err(e) = append_run(&journal_file, &run) {
+        warn!("Failed to record run in journal: {:#}", e);
+    }
+}
+
+/// Delete each segment's superseded archives, logging the space freed
+fn prune_segments(segments: &HashMap<String, PathBuf>, output_path: &Path, manifest_dir: Option<&Path>, retention: Retention) -> Result<()> {
+    let mut names: Vec<&String> = segments.keys().collect();
+    names.sort();
+    let (mut removed, mut bytes, mut kept) = (0, 0, 0);
+    for name in names {
+        let summary = prune_segment(output_path, manifest_dir, name, retention, SystemTime::now())
+            .context(format!("Failed to prune segment '{}'", name))?;
+        removed += summary.removed.len();
+        bytes += summary.removed.iter().map(|archive| archive.bytes).sum::<u64>();
+        kept += summary.kept;
+    }
+    info!("Removed {} superseded archive(s) ({} bytes), kept {}", removed, bytes, kept);
+    Ok(())
+}
+
+/// Restore command: extract an archive
+fn run_restore(args: &[String]) -> Result<()> {
+    let restore_args = RestoreArgs::parse(args)?;
+    let options = restore_args.options()?;
+    let summary = restore_archive(&restore_args.archive, &options)?;
+    let segment = summary.info.map(|info| info.segment).unwrap_or_else(|| "(Unknown)".to_string());
+    match (summary.dest, options.dry_run) {
+        (Some(dest), true) => info!("Dry run: would restore {} entries ({} bytes) of segment '{}' to {:?}",
+            summary.entries, summary.bytes, segment, dest),
+        (Some(dest), false) => info!("Restored {} entries ({} bytes) of segment '{}' to {:?}", summary.entries, summary.bytes, segment, dest),
+        (None, _) => info!("Nothing to restore from segment '{}'", segment),
+    }
+    if summary.conflicts > 0 {
+        let action = match options.on_conflict {
+            ConflictPolicy::Refuse => "refused (Use --overwrite, --skip-existing or --keep-both)",
+            ConflictPolicy::Overwrite => "overwritten",
+            ConflictPolicy::SkipExisting => "skipped",
+            ConflictPolicy::KeepBoth => "kept, with the archived files extracted next to them",
+        };
+        warn!("{} existing file(s) {}{}", summary.conflicts, if options.dry_run { "would be " } else { "" }, action);
+    }
+    if summary.skipped > 0 {
+        info!("Skipped {} entries not matching --include", summary.skipped);
+    }
+    if let Some(first) = summary.mismatched.first() {
+        return Err(anyhow!("{} of {} restored file(s) don't match the archive's manifest (i.e. {:?})",
+            summary.mismatched.len(), summary.verified, first));
+    }
+    if summary.verified > 0 {
+        info!("Checked {} restored file(s) against the archive's manifest", summary.verified);
+    }
+    Ok(())
+}
+
+/// List command: print an archive's entries (Then its info entry) to stdout
+fn run_list(args: &[String]) -> Result<()> {
+    let [archive] = args else {
+        return Err(anyhow!("Usage: list <archive>"));
+    };
+    let parts = find_archive_parts(Path::new(archive))?;
+    let summary = list_archive(&parts, |entry| println!("{}", entry.line()))?;
+    if let Some(info) = &summary.info {
+        println!("\n{}:\n{}", INFO_FILE, info.to_json()?);
+    } else if let Some(path) = &summary.legacy_path {
+        println!("\n{}: {}", LEGACY_PATH_FILE, path);
+    }
+    info!("Listed {} entries in {} part(s)", summary.entries, parts.len());
+    Ok(())
+}
+
+/// Cat command: write an archive's parts to stdout in order (Logging to stderr)
+fn run_cat(args: &[String]) -> Result<()> {
+    let [archive] = args else {
+        return Err(anyhow!("Usage: cat <archive>"));
+    };
+    let parts = find_archive_parts(Path::new(archive))?;
+    let bytes = copy_parts(&parts, &mut std::io::stdout().lock())?;
+    info!("Wrote {} part(s) ({} bytes) to stdout", parts.len(), bytes);
+    Ok(())
+}
+
+/// Merge command: recombine an archive's parts into a single file
+fn run_merge(args: &[String]) -> Result<()> {
+    let (archive, remove_parts) = match args {
+        [archive] => (archive, false),
+        [archive, flag] if flag == "--remove-parts" => (archive, true),
+        _ => return Err(anyhow!("Usage: merge <archive> [--remove-parts]")),
+    };
+    let summary = merge_parts(Path::new(archive), remove_parts)?;
+    if !summary.checksums_verified {
+        warn!("Parts have no recorded checksums, only their sizes were checked");
+    }
+    info!("Merged {} part(s) into {:?} ({} bytes)", summary.parts, summary.output, summary.bytes);
+    Ok(())
+}
+
+/// Check command: find any missing or out-of-sequence parts of an archive
+fn run_check(args: &[String]) -> Result<()> {
+    let [archive] = args else {
+        return Err(anyhow!("Usage: check <archive>"));
+    };
+    let check = check_archive_parts(Path::new(archive))?;
+    match &check.recorded {
+        Some(recorded) => info!("All {} part(s) of {:?} are present (As recorded in {:?})", recorded.parts.len(), archive, recorded.source),
+        None => warn!("No part count recorded for {:?}, only checked that its {} part(s) are numbered in sequence", archive, check.parts.len()),
     }
+    Ok(())
+}
 
-    info!("Backup process finished.");
+/// Repair command: rebuild a missing or damaged part from the parity file, then read the archive end to end
+fn run_repair(args: &[String]) -> Result<()> {
+    let [archive] = args else {
+        return Err(anyhow!("Usage: repair <archive>"));
+    };
+    let summary = repair_archive(Path::new(archive))?;
+    match &summary.rebuilt {
+        Some(part) => info!("Rebuilt part {} of {:?} from {:?}", part, archive, summary.parity_file),
+        None => info!("All {} part(s) of {:?} match {:?}, nothing to repair", summary.parts, archive, summary.parity_file),
+    }
+    let check = check_archive_parts(Path::new(archive))?;
+    let contents = read_archive_contents(&check.parts)
+        .context(format!("Archive {:?} is still unreadable", archive))?;
+    if let Some(first) = contents.mismatched.first() {
+        return Err(anyhow!("{} file(s) don't match the archive's manifest entry (i.e. {:?})", contents.mismatched.len(), first));
+    }
+    info!("Verified {:?}: {} file(s) in {} part(s)", archive, contents.files.len(), check.parts.len());
     Ok(())
 }
 
+fn run_compare(args: &[String]) -> Result<()> {
+    let [old, new] = args else {
+        return Err(anyhow!("Usage: compare <old archive|manifest> <new archive|manifest>"));
+    };
+    let (old, new) = (Path::new(old), Path::new(new));
+    // (Manifests are compared by hash, archives by each file's size and modification time)
+    let changes = match (read_manifest(old).ok(), read_manifest(new).ok()) {
+        (Some(old_manifest), Some(new_manifest)) => diff_manifests(&old_manifest, &new_manifest.hash, &new_manifest.files)
+            .ok_or_else(|| anyhow!("Manifests were made with different hash algorithms, so can't be compared"))?,
+        (None, None) => {
+            let old_files = archive_stamps(&find_archive_parts(old)?)?;
+            let new_files = archive_stamps(&find_archive_parts(new)?)?;
+            let total_size = |files: &FileStamps| files.values().flatten().map(|(size, _)| size).sum::<u64>();
+            info!("Old archive: {} file(s), {} bytes, new archive: {} file(s), {} bytes",
+                old_files.len(), total_size(&old_files), new_files.len(), total_size(&new_files));
+            diff_stamps(&old_files, &new_files)
+        }
+        _ => return Err(anyhow!("Can't compare an archive with a manifest")),
+    };
+    for line in changes.lines() {
+        println!("{}", line);
+    }
+    info!("{} added, {} removed, {} modified", changes.added.len(), changes.removed.len(), changes.modified.len());
+    Ok(())
+}
+
+/// Why a segment's last backup is older than `max_age_days` (None if it is recent enough)
+fn backup_too_old(record: Option<&SegmentRecord>, max_age_days: u64, now: DateTime<FixedOffset>) -> Option<String> {
+    let Some(last_backup) = record.and_then(|record| record.last_backup.as_deref()) else {
+        return Some("has no recorded backup time".to_string());
+    };
+    let Ok(last_backup) = DateTime::parse_from_rfc3339(last_backup) else {
+        return Some(format!("has an unreadable backup time ({})", last_backup));
+    };
+    let age_days = (now - last_backup).num_days();
+    (age_days >= max_age_days as i64).then(|| format!("is {} day(s) old (max_age_days = {})", age_days, max_age_days))
+}
+
+/// Why an unchanged segment's previous archive can't be trusted (None if the segment can be skipped)
+fn untrusted_output(
+    segment_hashes: &HashFile,
+    name: &str,
+    output_path: &Path,
+    archive_path: &Path,
+    require_output: bool,
+    verify: bool,
+    compressed: bool,
+) -> Option<String> {
+    // Parts are recorded in the hash file, or only in the metadata file for older hash files
+    let sidecar = read_sidecar(&sidecar_path(output_path, name)).ok();
+    let parts = match segment_hashes.segments.get(name) {
+        Some(record) if !record.parts.is_empty() => record.parts.clone(),
+        _ => sidecar.as_ref().map(|sidecar| sidecar.parts.clone()).unwrap_or_default(),
+    };
+
+    if require_output
+        && let Some(missing) = missing_output(output_path, archive_path, &parts) {
+        return Some(format!("is missing ({})", missing));
+    }
+    if !verify {
+        return None;
+    }
+    if parts.is_empty() {
+        warn!("Segment '{}' has no recorded parts, skipping verification", name);
+        return None;
+    }
+    let checksum_algorithm = sidecar.and_then(|sidecar| sidecar.checksum_algorithm);
+    match verify_parts(output_path, &parts, checksum_algorithm, compressed) {
+        Ok(true) => {
+            info!("Verified existing archive for segment '{}'", name);
+            None
+        }
+        Ok(false) => {
+            warn!("Existing archive for segment '{}' can't be verified (Enable part_checksum to verify uncompressed archives)", name);
+            None
+        }
+        Err(e) => Some(format!("failed verification ({:#})", e)),
+    }
+}
+
+/// Generation of a segment's next archive and the files changed since the previous one, if an increment is due
+/// (None for a full archive, which is also made when the changes can't be found from the previous manifest)
+fn plan_increment(
+    chain: &Chain,
+    full_every: u32,
+    previous: Option<&Manifest>,
+    hash: Option<&str>,
+    files: Option<&ManifestFiles>,
+) -> Option<(u32, ManifestChanges)> {
+    let generation = chain.next_generation(full_every);
+    if generation == 0 {
+        return None;
+    }
+    let (Some(previous), Some(hash), Some(files)) = (previous, hash, files) else {
+        warn!("No file hashes to compare for segment '{}', making a full archive", chain.segment);
+        return None;
+    };
+    // The manifest must be from the chain's last archive, or files changed since then would be missed
+    if chain.generations.last().is_none_or(|last| last.created != previous.created) {
+        warn!("Manifest of segment '{}' doesn't match its chain, making a full archive", chain.segment);
+        return None;
+    }
+    diff_manifests(previous, hash, files).map(|changes| (generation, changes))
+}
+
+/// Log which files changed since the previous manifest, and append them to the report file (If set)
+fn report_changes(previous: &Manifest, manifest: &Manifest, report_file: &Option<PathBuf>) {
+    let Some(changes) = diff_manifests(previous, &manifest.hash, &manifest.files) else {
+        info!("Hash algorithm changed for segment '{}', can't list changed files", manifest.segment);
+        return;
+    };
+    if changes.is_empty() {
+        // (i.e. only directories were added or removed)
+        info!("No files changed in segment '{}' since {}", manifest.segment, previous.created);
+        return;
+    }
+    let summary = format!("Segment '{}' changed since {}: {} added, {} removed, {} modified",
+        manifest.segment, previous.created, changes.added.len(), changes.removed.len(), changes.modified.len());
+    info!("{}", summary);
+    let lines = changes.lines();
+    for line in &lines {
+        info!("  {}", line);
+    }
+
+    if let Some(report_file) = report_file {
+        let report = format!("[{}] {}\n{}", manifest.created, summary,
+            lines.iter().map(|line| format!("  {}\n", line)).collect::<String>());
+        let result = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(report_file)
+            .and_then(|mut file| file.write_all(report.as_bytes()));
+        if let Err(e) = result {
+            warn!("Failed to write change report to {:?}: {}", report_file, e);
+        }
+    }
+}
+
+/// Log files that couldn't be hashed, and append them to `report_file` (If set, and not a dry run)
+fn report_skipped(segment: &str, skipped: &[(String, String)], report_file: &Option<PathBuf>, dry_run: bool) {
+    if skipped.is_empty() {
+        return;
+    }
+    let summary = format!("Segment '{}': {} file(s) could not be read and were left out of the hash", segment, skipped.len());
+    warn!("{}", summary);
+    for (path, reason) in skipped {
+        warn!("  {}: {}", path, reason);
+    }
+
+    if let Some(report_file) = report_file.as_ref().filter(|_| !dry_run) {
+        let report = format!("[{}] {}\n{}", Local::now().to_rfc3339(), summary,
+            skipped.iter().map(|(path, reason)| format!("  {}: {}\n", path, reason)).collect::<String>());
+        let result = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(report_file)
+            .and_then(|mut file| file.write_all(report.as_bytes()));
+        if let Err(e) = result {
+            warn!("Failed to write skipped files report to {:?}: {}", report_file, e);
+        }
+    }
+}
+
+/// Save the hash cache, if enabled and not a dry run (A failure only costs re-reading files next run)
+fn write_cache(cache_file: &Option<PathBuf>, cache: &Option<HashCache>, dry_run: bool) {
+    if let (Some(cache_file), Some(cache)) = (cache_file.as_ref().filter(|_| !dry_run), cache)
+        && let Err(e) = write_hash_cache(cache_file, cache) {
+        warn!("Failed to update hash cache (Files will be re-read next run): {:#}", e);
+    }
+}
+
+/// Workers handling finished parts (None to handle each inline, the default for resumable archives)
+fn script_workers(post_script_workers: Option<usize>, resumable: bool) -> Option<usize> {
+    post_script_workers.or((!resumable).then_some(DEFAULT_SCRIPT_WORKERS))
+}
+
 /// Calculate paths to exclude -- extracted to simplify testing
 fn get_exclusions<'a>(all_paths: &'a HashSet<&PathBuf>, path: &PathBuf) -> Vec<&'a PathBuf> {
     all_paths.iter()
@@ -187,13 +1435,68 @@ fn get_exclusions<'a>(all_paths: &'a HashSet<&PathBuf>, path: &PathBuf) -> Vec<&
         .collect()
 }
 
-/// --- Tests --- ///
+// --- Tests --- //
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::collections::HashSet;
 
+    #[test]
+    fn test_backup_too_old() {
+        let now = DateTime::parse_from_rfc3339("2024-02-01T12:00:00+00:00").unwrap();
+        let mut record = SegmentRecord::new("xxh3:0123456789abcdef".to_string());
+        assert!(backup_too_old(Some(&record), 30, now).is_some(), "Segments with no backup time should be archived");
+        assert!(backup_too_old(None, 30, now).is_some());
+
+        record.last_backup = Some("2024-01-10T12:00:00+00:00".to_string());
+        assert!(backup_too_old(Some(&record), 30, now).is_none());
+        assert!(backup_too_old(Some(&record), 22, now).is_some_and(|reason| reason.contains("22 day(s)")));
+
+        record.last_backup = Some("yesterday".to_string());
+        assert!(backup_too_old(Some(&record), 30, now).is_some());
+    }
+
+    #[test]
+    fn test_dry_run_records_nothing() {
+        let test_dir = PathBuf::from("/tmp/main_test_dry_run_records_nothing");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+        let cache_file = Some(test_dir.join("hash.cache"));
+        let report_file = Some(test_dir.join("skipped.log"));
+        let skipped = [("locked.db".to_string(), "Permission denied".to_string())];
+
+        write_cache(&cache_file, &Some(HashCache::default()), true);
+        report_skipped("docs", &skipped, &report_file, true);
+        assert!(!test_dir.join("hash.cache").exists(), "A dry run doesn't update the hash cache");
+        assert!(!test_dir.join("skipped.log").exists(), "A dry run doesn't write the skipped files report");
+
+        write_cache(&cache_file, &Some(HashCache::default()), false);
+        report_skipped("docs", &skipped, &report_file, false);
+        assert!(test_dir.join("hash.cache").exists());
+        assert!(fs::read_to_string(test_dir.join("skipped.log")).unwrap().contains("locked.db: Permission denied"));
+
+        let _ = fs::remove_dir_all(&test_dir);
+    }
+
+    #[test]
+    fn test_script_workers() {
+        assert_eq!(script_workers(None, false), Some(DEFAULT_SCRIPT_WORKERS), "Parts should be handled in the background by default");
+        assert_eq!(script_workers(None, true), None, "Resumable archives should handle parts inline by default");
+        assert_eq!(script_workers(Some(4), true), Some(4));
+        assert_eq!(script_workers(Some(0), false), Some(0));
+    }
+
+    #[test]
+    fn test_config_script_jobs_alias() {
+        let parse = |keys: &str| toml::from_str::<Config>(&format!("{}\n[segments]\ndocs = \"/docs\"", keys));
+        assert_eq!(parse("script_jobs = 3").unwrap().post_script_workers, Some(3));
+        assert_eq!(parse("post_script_workers = 2").unwrap().post_script_workers, Some(2));
+
+        let both = parse("post_script_workers = 2\nscript_jobs = 3");
+        assert!(both.is_err_and(|e| e.to_string().contains("duplicate field")), "Setting both names should be rejected");
+    }
+
     #[test]
     fn test_exclusion_logic_no_exclusions() {
         let path1 = PathBuf::from("/tmp/test1");
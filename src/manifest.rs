@@ -0,0 +1,191 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::io::Read;
+use std::fs;
+use crate::hasher::{HashAlgorithm, manifest_hash};
+
+/// Extension of each segment's manifest file
+pub const MANIFEST_EXT: &str = "manifest.json";
+
+/// Hashes of every file in a segment, keyed by relative path
+pub type ManifestFiles = BTreeMap<String, ManifestEntry>;
+
+/// The file hashes behind a segment hash, as of its last backup
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    pub segment: String,
+    /// Tagged segment hash (i.e. `xxh3:0123456789abcdef`)
+    pub hash: String,
+    /// Time the segment was archived (RFC 3339)
+    pub created: String,
+    pub files: ManifestFiles,
+}
+
+impl Manifest {
+    /// Check a file's content (Or a symlink's target) against its hash in the manifest
+    /// (None if the file isn't in the manifest, or its algorithm is unknown)
+    pub fn check_file(&self, relative_path: &str, content: impl Read) -> Result<Option<bool>> {
+        let (Some(entry), Some(algorithm)) = (self.files.get(relative_path), HashAlgorithm::from_name(algorithm(&self.hash))) else {
+            return Ok(None);
+        };
+        let hash = manifest_hash(algorithm, Path::new(relative_path), content)
+            .context(format!("Failed to read {:?} for hashing", relative_path))?;
+        Ok(Some(hash == entry.hash))
+    }
+}
+
+/// One file's hashes (Hex encoded, made with the algorithm of the segment hash)
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ManifestEntry {
+    /// Hash of the content (Or symlink target)
+    pub hash: String,
+    /// Hash of the mode, ownership and modification time (Only with hash_metadata)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<String>,
+}
+
+/// Path of the manifest file for a segment
+pub fn manifest_path(manifest_dir: &Path, segment: &str) -> PathBuf {
+    manifest_dir.join(format!("{}.{}", segment, MANIFEST_EXT))
+}
+
+/// Write a manifest (Via a temporary file, so a crash never leaves it half-written)
+pub fn write_manifest(path: &Path, manifest: &Manifest) -> Result<()> {
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty() && !parent.exists() {
+        fs::create_dir_all(parent)
+            .context(format!("Failed to create directory for manifest: {:?}", parent))?;
+    }
+    let contents = serde_json::to_string_pretty(manifest)
+        .context("Failed to serialize manifest")?;
+    let temp_path = PathBuf::from(format!("{}.tmp", path.display()));
+    fs::write(&temp_path, contents)
+        .context(format!("Failed to write manifest: {:?}", temp_path))?;
+    fs::rename(&temp_path, path)
+        .context(format!("Failed to replace manifest: {:?}", path))
+}
+
+/// Read a manifest written by a previous run
+pub fn read_manifest(path: &Path) -> Result<Manifest> {
+    let contents = fs::read_to_string(path)
+        .context(format!("Failed to read manifest: {:?}", path))?;
+    serde_json::from_str(&contents)
+        .context(format!("Failed to parse manifest: {:?}", path))
+}
+
+/// Files that differ between two manifests of a segment (Each list in path order)
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ManifestChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+impl ManifestChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    /// One line per file: `+` added, `-` removed, `~` modified
+    pub fn lines(&self) -> Vec<String> {
+        let added = self.added.iter().map(|path| format!("+ {}", path));
+        let removed = self.removed.iter().map(|path| format!("- {}", path));
+        let modified = self.modified.iter().map(|path| format!("~ {}", path));
+        added.chain(removed).chain(modified).collect()
+    }
+}
+
+/// Compare a segment's files (And the segment hash they make) to a previous manifest of it
+/// (None if the hashes were made with different algorithms, so they can't be compared)
+pub fn diff_manifests(previous: &Manifest, hash: &str, files: &ManifestFiles) -> Option<ManifestChanges> {
+    if algorithm(&previous.hash) != algorithm(hash) {
+        return None;
+    }
+    let mut changes = ManifestChanges::default();
+    for (path, entry) in files {
+        match previous.files.get(path) {
+            None => changes.added.push(path.clone()),
+            Some(previous_entry) if previous_entry != entry => changes.modified.push(path.clone()),
+            Some(_) => {}
+        }
+    }
+    changes.removed = previous.files.keys()
+        .filter(|path| !files.contains_key(*path))
+        .cloned()
+        .collect();
+    Some(changes)
+}
+
+/// Algorithm name of a tagged hash
+fn algorithm(hash: &str) -> &str {
+    hash.split_once(':').map_or("", |(algorithm, _)| algorithm)
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/manifest_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let test_name = "round_trip";
+        cleanup_test_dir(test_name);
+        let path = manifest_path(&get_test_dir(test_name), "docs");
+        assert!(path.ends_with("docs.manifest.json"));
+
+        let mut manifest = Manifest {
+            segment: "docs".to_string(),
+            hash: "xxh3:0123456789abcdef".to_string(),
+            created: "2024-01-01T00:00:00+00:00".to_string(),
+            files: ManifestFiles::new(),
+        };
+        manifest.files.insert("a.txt".to_string(), ManifestEntry { hash: "00ff".to_string(), metadata: None });
+        manifest.files.insert("sub/b.txt".to_string(), ManifestEntry { hash: "ff00".to_string(), metadata: Some("0f0f".to_string()) });
+        write_manifest(&path, &manifest).unwrap();
+
+        let read = read_manifest(&path).unwrap();
+        assert_eq!(read.hash, manifest.hash);
+        assert_eq!(read.files, manifest.files);
+
+        cleanup_test_dir(test_name);
+    }
+
+    fn manifest(hash: &str, files: &[(&str, &str)]) -> Manifest {
+        Manifest {
+            segment: "docs".to_string(),
+            hash: hash.to_string(),
+            created: String::new(),
+            files: files.iter()
+                .map(|(path, hash)| (path.to_string(), ManifestEntry { hash: hash.to_string(), metadata: None }))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_diff_manifests() {
+        let previous = manifest("xxh3:01", &[("a.txt", "01"), ("b.txt", "02"), ("c.txt", "03")]);
+        let current = manifest("xxh3:02", &[("a.txt", "01"), ("c.txt", "33"), ("d.txt", "04")]);
+
+        let changes = diff_manifests(&previous, &current.hash, &current.files).unwrap();
+        assert_eq!(changes.added, ["d.txt"]);
+        assert_eq!(changes.removed, ["b.txt"]);
+        assert_eq!(changes.modified, ["c.txt"]);
+        assert_eq!(changes.lines(), ["+ d.txt", "- b.txt", "~ c.txt"]);
+
+        assert!(diff_manifests(&current, &current.hash, &current.files).unwrap().is_empty());
+
+        // Hashes from different algorithms can't be compared
+        let other = manifest("blake3:02", &[("a.txt", "01")]);
+        assert!(diff_manifests(&previous, &other.hash, &other.files).is_none());
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::{Context, Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::io::{Read, Write};
+use std::fs;
+use log::{info, warn};
+use crate::completeness::{PartCheck, RecordedParts, check_archive_parts};
+use crate::digest::{Digest, DigestAlgorithm};
+use crate::rolling_writer::PART_LIST_EXT;
+
+// Buffer size for copying parts into the merged archive
+const MERGE_BUFFER_SIZE: usize = 1048576;
+
+/// Result of merging an archive's parts
+#[derive(Debug)]
+pub struct MergeSummary {
+    pub output: PathBuf,
+    pub parts: usize,
+    pub bytes: u64,
+    /// True if each part's checksum was checked against its metadata file
+    pub checksums_verified: bool,
+}
+
+/// Merge the parts of a split archive into a single file (`docs.tar.gz.part001`, ... into `docs.tar.gz`).
+/// The parts are first checked against the segment's metadata file (Or the archive's `.parts` listing or index),
+/// which must list exactly the parts found, at the same sizes (And checksums, if recorded).
+///
+/// # Errors
+/// Fails without writing anything if a part is missing, unexpected, the wrong size, or fails its checksum
+pub fn merge_parts(archive: &Path, remove_parts: bool) -> Result<MergeSummary> {
+    let PartCheck { parts, recorded } = check_archive_parts(archive)?;
+    let Some(base) = parts.first().and_then(|first| first.to_string_lossy().rsplit_once(".part").map(|(base, _)| PathBuf::from(base))) else {
+        return Err(anyhow!("Archive is not split into parts: {:?}", archive));
+    };
+    if base.extension().is_some_and(|ext| ext == "tar") {
+        return Err(anyhow!("Multi-volume parts can't be merged into one archive: {:?}", base));
+    }
+
+    if recorded.is_none() {
+        warn!("No metadata file or part listing found for {:?}, can't check for missing trailing parts", base);
+    }
+    let checksums = match &recorded {
+        Some(RecordedParts { parts: expected, checksum_algorithm: Some(algorithm), .. }) if expected.iter().all(|part| part.checksum.is_some()) => {
+            Some((*algorithm, expected.iter().map(|part| part.checksum.clone().unwrap_or_default()).collect::<Vec<_>>()))
+        }
+        _ => None,
+    };
+
+    // (Written to a temporary file, so a failed merge never leaves a partial archive)
+    let temp_path = PathBuf::from(format!("{}.tmp", base.display()));
+    let result = write_merged(&parts, &temp_path, checksums.as_ref());
+    let bytes = match result {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+    };
+    fs::rename(&temp_path, &base)
+        .context(format!("Failed to replace archive: {:?}", base))?;
+
+    if remove_parts {
+        let part_list = PathBuf::from(format!("{}.{}", base.display(), PART_LIST_EXT));
+        for path in parts.iter().chain(part_list.exists().then_some(&part_list)) {
+            fs::remove_file(path)
+                .context(format!("Failed to remove part: {:?}", path))?;
+        }
+        info!("Removed {} merged part(s)", parts.len());
+    }
+
+    Ok(MergeSummary { output: base, parts: parts.len(), bytes, checksums_verified: checksums.is_some() })
+}
+
+/// Copy the parts end to end into `path`, checking each against its checksum (If set)
+fn write_merged(parts: &[PathBuf], path: &Path, checksums: Option<&(DigestAlgorithm, Vec<String>)>) -> Result<u64> {
+    let mut output = fs::File::create(path)
+        .context(format!("Failed to create merged archive: {:?}", path))?;
+    let mut buffer = vec![0u8; MERGE_BUFFER_SIZE];
+    let mut total = 0;
+    for (i, part) in parts.iter().enumerate() {
+        let mut file = fs::File::open(part)
+            .context(format!("Failed to open part: {:?}", part))?;
+        let mut digest = checksums.map(|(algorithm, _)| Digest::new(*algorithm));
+        loop {
+            let bytes_read = file.read(&mut buffer)
+                .context(format!("Failed to read part: {:?}", part))?;
+            if bytes_read == 0 {
+                break;
+            }
+            if let Some(digest) = digest.as_mut() {
+                digest.update(&buffer[..bytes_read]);
+            }
+            output.write_all(&buffer[..bytes_read])
+                .context(format!("Failed to write merged archive: {:?}", path))?;
+            total += bytes_read as u64;
+        }
+        if let (Some(mut digest), Some((_, expected))) = (digest, checksums)
+            && expected.get(i) != Some(&digest.finish_hex()) {
+            return Err(anyhow!("Checksum mismatch for part {:?}", part));
+        }
+    }
+    output.sync_all()
+        .context(format!("Failed to write merged archive: {:?}", path))?;
+    Ok(total)
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive_info::{ArchiveInfo, read_archive_info};
+    use crate::helpers::{ArchiveOptions, create_archive};
+    use crate::rolling_writer::PartInfo;
+    use crate::sidecar::{SegmentMetadata, sidecar_path, write_sidecar};
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/merge_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    /// Archive a directory into parts, recording them in a metadata file
+    fn write_split_archive(test_dir: &Path, part_checksum: Option<DigestAlgorithm>) -> Vec<PartInfo> {
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(&src_dir).unwrap();
+        fs::write(src_dir.join("a.bin"), (0..50000u32).map(|i| (i % 251) as u8).collect::<Vec<u8>>()).unwrap();
+
+        let output_dir = test_dir.join("out");
+        fs::create_dir_all(&output_dir).unwrap();
+        let info = ArchiveInfo::new("docs", &src_dir, &None, None).unwrap();
+        let options = ArchiveOptions { compression_level: Some(0), max_size_bytes: Some(10000), part_checksum, ..Default::default() };
+        let summary = create_archive(&src_dir, &fs::metadata(&src_dir).unwrap(), &output_dir.join("docs.tar.gz"),
+            &[], &info, &options, None, None).unwrap();
+        let sidecar = SegmentMetadata {
+            segment: "docs".to_string(),
+            source_path: src_dir,
+            hash: None,
+            created: info.created,
+            file_count: summary.file_count,
+            uncompressed_bytes: summary.uncompressed_bytes,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            checksum_algorithm: part_checksum,
+            parts: summary.parts.parts.clone(),
+        };
+        write_sidecar(&sidecar_path(&output_dir, "docs"), &sidecar).unwrap();
+        summary.parts.parts
+    }
+
+    #[test]
+    fn test_merge_parts() {
+        let test_name = "merge";
+        let test_dir = setup_test_dir(test_name);
+        let parts = write_split_archive(&test_dir, Some(DigestAlgorithm::Sha256));
+        let expected: Vec<u8> = parts.iter().flat_map(|part| fs::read(&part.path).unwrap()).collect();
+
+        let summary = merge_parts(&test_dir.join("out/docs"), true).unwrap();
+        assert_eq!(summary.output, test_dir.join("out/docs.tar.gz"));
+        assert_eq!(summary.parts, parts.len());
+        assert!(summary.checksums_verified);
+        assert_eq!(fs::read(&summary.output).unwrap(), expected);
+        assert!(parts.iter().all(|part| !part.path.exists()), "Merged parts should be removed");
+        assert!(read_archive_info(&summary.output).is_ok());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_merge_parts_checks_parts() {
+        let test_name = "check";
+        let test_dir = setup_test_dir(test_name);
+        let parts = write_split_archive(&test_dir, Some(DigestAlgorithm::Sha256));
+        assert!(parts.len() > 2);
+        let archive = test_dir.join("out/docs");
+
+        // Corrupted part
+        let mut data = fs::read(&parts[1].path).unwrap();
+        data[0] ^= 0xff;
+        fs::write(&parts[1].path, &data).unwrap();
+        assert!(merge_parts(&archive, false).unwrap_err().to_string().contains("Checksum mismatch"));
+        assert!(!test_dir.join("out/docs.tar.gz").exists(), "A failed merge should leave no archive");
+
+        // Missing part
+        fs::remove_file(&parts[1].path).unwrap();
+        assert!(merge_parts(&archive, false).unwrap_err().to_string().contains("missing"));
+
+        cleanup_test_dir(test_name);
+    }
+}
@@ -0,0 +1,390 @@
+use std::io::{self, BufWriter, Write};
+use crate::rolling_writer::RollingWriter;
+
+const BLOCK_SIZE: usize = 512;
+/// Smallest usable volume (A continuation header plus data, or a short long-name sequence)
+pub const MIN_VOLUME_SIZE: usize = 4 * BLOCK_SIZE;
+
+// Tar header fields used to follow (and continue) entries across volumes
+const NAME_FIELD: std::ops::Range<usize> = 0..100;
+const SIZE_FIELD: std::ops::Range<usize> = 124..136;
+const CHECKSUM_FIELD: std::ops::Range<usize> = 148..156;
+const TYPEFLAG_OFFSET: usize = 156;
+const MAGIC_FIELD: std::ops::Range<usize> = 257..265;
+const OFFSET_FIELD: std::ops::Range<usize> = 369..381;
+
+const GNU_MAGIC: &[u8; 8] = b"ustar  \0";
+const TYPE_MULTIVOLUME: u8 = b'M';
+const TYPE_LONG_NAME: u8 = b'L';
+/// Entries that describe the following header instead of holding file data
+const METADATA_TYPES: [u8; 4] = [TYPE_LONG_NAME, b'K', b'x', b'g'];
+
+/// File whose data is being written, and may need to be continued on the next volume
+struct ContinuedFile {
+    name: Vec<u8>,
+    size: u64,
+    written: u64,
+    blocks_left: u64,
+}
+
+/// Splits an uncompressed tar stream into GNU tar multi-volume parts, so they can be restored with
+/// stock GNU tar: `tar -xM -f archive.tar.part001 -f archive.tar.part002 ...`
+///
+/// Volumes end at the last whole block that fits in `volume_size`. A file split between volumes is
+/// continued with a GNU multi-volume ('M') header. Header sequences (i.e. long names) are never split.
+pub struct MultiVolumeWriter {
+    inner: BufWriter<RollingWriter>,
+    volume_size: u64,
+    volume_used: u64,
+    /// Incomplete block from the last write
+    pending: Vec<u8>,
+    /// Metadata blocks held until their entry's header arrives (To keep the sequence in one volume)
+    metadata: Vec<u8>,
+    metadata_blocks_left: u64,
+    /// Long name read from the held metadata (Used to continue the file)
+    long_name: Option<Vec<u8>>,
+    reading_long_name: bool,
+    current: Option<ContinuedFile>,
+}
+
+impl MultiVolumeWriter {
+    /// Write volumes of at most `volume_size` bytes (Rounded down to whole blocks) to `writer`
+    ///
+    /// # Errors
+    /// Returns an error if `volume_size` is less than MIN_VOLUME_SIZE
+    pub fn new(mut writer: RollingWriter, volume_size: usize) -> io::Result<Self> {
+        if volume_size < MIN_VOLUME_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Multi-volume parts must be at least {} bytes: {}", MIN_VOLUME_SIZE, volume_size)
+            ));
+        }
+        writer.set_manual_rollover();
+
+        Ok(Self {
+            inner: BufWriter::new(writer),
+            volume_size: (volume_size - volume_size % BLOCK_SIZE) as u64,
+            volume_used: 0,
+            pending: Vec::with_capacity(BLOCK_SIZE),
+            metadata: Vec::new(),
+            metadata_blocks_left: 0,
+            long_name: None,
+            reading_long_name: false,
+            current: None,
+        })
+    }
+
+    /// Number of bytes written to the current volume
+    pub fn current_size(&self) -> usize {
+        self.volume_used as usize
+    }
+
+    /// Flush the last volume, returning the (not yet finalized) writer
+    ///
+    /// # Errors
+    /// Returns an error if the tar stream ended part-way through an entry
+    pub fn finish(self) -> io::Result<RollingWriter> {
+        if !self.pending.is_empty() || !self.metadata.is_empty() || self.current.is_some() {
+            return Err(io::Error::other("Tar stream ended part-way through an entry"));
+        }
+        self.inner.into_inner().map_err(|e| e.into_error())
+    }
+
+    // --- Private methods --- //
+
+    fn process_block(&mut self, block: &[u8]) -> io::Result<()> {
+        // File data (Continued on a new volume if the current one is full)
+        if self.current.is_some() {
+            if self.volume_used + BLOCK_SIZE as u64 > self.volume_size {
+                let header = self.continuation_header()?;
+                self.new_volume()?;
+                self.emit(&header)?;
+            }
+            self.emit(block)?;
+            if let Some(file) = self.current.as_mut() {
+                file.blocks_left -= 1;
+                file.written = (file.written + BLOCK_SIZE as u64).min(file.size);
+                if file.blocks_left == 0 {
+                    self.current = None;
+                }
+            }
+            return Ok(());
+        }
+
+        // Data of a held metadata entry
+        if self.metadata_blocks_left > 0 {
+            self.metadata.extend_from_slice(block);
+            self.metadata_blocks_left -= 1;
+            if self.reading_long_name {
+                self.long_name.get_or_insert_with(Vec::new).extend_from_slice(block);
+            }
+            return Ok(());
+        }
+
+        // End-of-archive blocks
+        if self.metadata.is_empty() && block.iter().all(|&byte| byte == 0) {
+            if self.volume_used + BLOCK_SIZE as u64 > self.volume_size {
+                self.new_volume()?;
+            }
+            return self.emit(block);
+        }
+
+        // Header
+        let typeflag = block[TYPEFLAG_OFFSET];
+        let size = parse_numeric(&block[SIZE_FIELD])?;
+        let data_blocks = size.div_ceil(BLOCK_SIZE as u64);
+        self.metadata.extend_from_slice(block);
+        if METADATA_TYPES.contains(&typeflag) {
+            self.metadata_blocks_left = data_blocks;
+            self.reading_long_name = typeflag == TYPE_LONG_NAME;
+            if self.reading_long_name {
+                self.long_name = None;
+            }
+            return Ok(());
+        }
+        self.reading_long_name = false;
+
+        // Entry header: write the whole header sequence to one volume
+        let sequence = std::mem::take(&mut self.metadata);
+        if sequence.len() as u64 > self.volume_size {
+            return Err(io::Error::other(format!(
+                "Tar header sequence ({} bytes) is larger than a volume ({} bytes)", sequence.len(), self.volume_size
+            )));
+        }
+        if self.volume_used + sequence.len() as u64 > self.volume_size {
+            self.new_volume()?;
+        }
+        self.emit(&sequence)?;
+
+        let long_name = self.long_name.take();
+        if data_blocks > 0 {
+            let name = match long_name {
+                Some(name) => name.into_iter().take_while(|&byte| byte != 0).collect(),
+                None => block[NAME_FIELD].iter().copied().take_while(|&byte| byte != 0).collect(),
+            };
+            self.current = Some(ContinuedFile { name, size, written: 0, blocks_left: data_blocks });
+        }
+        Ok(())
+    }
+
+    /// GNU multi-volume header for the rest of the current file (Names are truncated to 100 bytes, as GNU tar does)
+    fn continuation_header(&self) -> io::Result<[u8; BLOCK_SIZE]> {
+        let file = self.current.as_ref()
+            .ok_or_else(|| io::Error::other("No file to continue"))?;
+        let offset = file.written;
+
+        let mut header = [0u8; BLOCK_SIZE];
+        let name_len = file.name.len().min(NAME_FIELD.len());
+        header[..name_len].copy_from_slice(&file.name[..name_len]);
+        header[100..108].copy_from_slice(b"0000000\0"); // Mode
+        header[108..116].copy_from_slice(b"0000000\0"); // UID
+        header[116..124].copy_from_slice(b"0000000\0"); // GID
+        write_numeric(&mut header[SIZE_FIELD], file.size - offset);
+        header[136..148].copy_from_slice(b"00000000000\0"); // Modified time
+        header[TYPEFLAG_OFFSET] = TYPE_MULTIVOLUME;
+        header[MAGIC_FIELD].copy_from_slice(GNU_MAGIC);
+        write_numeric(&mut header[OFFSET_FIELD], offset);
+
+        header[CHECKSUM_FIELD].fill(b' ');
+        let checksum: u32 = header.iter().map(|&byte| byte as u32).sum();
+        header[CHECKSUM_FIELD].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        Ok(header)
+    }
+
+    fn new_volume(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        self.inner.get_mut().start_new_part()?;
+        self.volume_used = 0;
+        Ok(())
+    }
+
+    fn emit(&mut self, blocks: &[u8]) -> io::Result<()> {
+        self.inner.write_all(blocks)?;
+        self.volume_used += blocks.len() as u64;
+        Ok(())
+    }
+}
+
+impl Write for MultiVolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut input = buf;
+
+        // Complete a block left over from the last write
+        if !self.pending.is_empty() {
+            let take = (BLOCK_SIZE - self.pending.len()).min(input.len());
+            self.pending.extend_from_slice(&input[..take]);
+            input = &input[take..];
+            if self.pending.len() < BLOCK_SIZE {
+                return Ok(buf.len());
+            }
+            let block = std::mem::take(&mut self.pending);
+            self.process_block(&block)?;
+        }
+
+        let mut blocks = input.chunks_exact(BLOCK_SIZE);
+        for block in &mut blocks {
+            self.process_block(block)?;
+        }
+        self.pending.extend_from_slice(blocks.remainder());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Parse a tar numeric field (Octal, or GNU base-256 for large values)
+fn parse_numeric(field: &[u8]) -> io::Result<u64> {
+    if field[0] & 0x80 != 0 {
+        return Ok(field[1..].iter().fold((field[0] & 0x7f) as u64, |acc, &byte| (acc << 8) | byte as u64));
+    }
+    let digits: String = field.iter()
+        .map(|&byte| byte as char)
+        .filter(|c| !c.is_whitespace() && *c != '\0')
+        .collect();
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(&digits, 8)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid tar header number {:?}: {}", digits, e)))
+}
+
+/// Write a tar numeric field (Octal, or GNU base-256 if it does not fit)
+fn write_numeric(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let octal = format!("{:0width$o}", value, width = digits);
+    if octal.len() <= digits {
+        field[..digits].copy_from_slice(octal.as_bytes());
+        field[digits] = 0;
+    } else {
+        field.fill(0);
+        let bytes = value.to_be_bytes();
+        let len = field.len();
+        field[len - bytes.len()..].copy_from_slice(&bytes);
+        field[0] |= 0x80;
+    }
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/multivolume_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    fn has_gnu_tar() -> bool {
+        Command::new("tar").arg("--version").output()
+            .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains("GNU tar"))
+    }
+
+    fn random_bytes(len: u32, seed: u32) -> Vec<u8> {
+        (0..len).map(|i| ((i + seed).wrapping_mul(2654435761) >> 13) as u8).collect()
+    }
+
+    /// Archive `files` into multi-volume parts, returning the part paths
+    fn write_volumes(test_dir: &Path, files: &[(&str, Vec<u8>)], volume_size: usize) -> Vec<PathBuf> {
+        let rolling = RollingWriter::new(test_dir.join("test.tar"), Some(volume_size)).unwrap();
+        let mut tar = tar::Builder::new(MultiVolumeWriter::new(rolling, volume_size).unwrap());
+        for (name, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, name, data.as_slice()).unwrap();
+        }
+        let mut rolling = tar.into_inner().unwrap().finish().unwrap();
+        rolling.finalize().unwrap().parts.into_iter().map(|part| part.path).collect()
+    }
+
+    #[test]
+    fn test_numeric_fields() {
+        let mut field = [0u8; 12];
+        write_numeric(&mut field, 19968);
+        assert_eq!(&field, b"00000047000\0");
+        assert_eq!(parse_numeric(&field).unwrap(), 19968);
+
+        // Too large for 11 octal digits
+        let large = 1u64 << 40;
+        write_numeric(&mut field, large);
+        assert_eq!(field[0] & 0x80, 0x80);
+        assert_eq!(parse_numeric(&field).unwrap(), large);
+    }
+
+    #[test]
+    fn test_multivolume_part_sizes() {
+        let test_name = "part_sizes";
+        let test_dir = setup_test_dir(test_name);
+
+        let parts = write_volumes(&test_dir, &[("a.bin", random_bytes(30000, 1)), ("b.bin", random_bytes(5000, 2))], 10240);
+        assert!(parts.len() >= 3, "Archive should span several volumes");
+        for part in &parts {
+            let size = fs::metadata(part).unwrap().len();
+            assert!(size <= 10240, "Volume {:?} is too large: {}", part, size);
+            assert_eq!(size % BLOCK_SIZE as u64, 0, "Volume {:?} should end on a block", part);
+        }
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_multivolume_volume_size_too_small() {
+        let test_name = "too_small";
+        let test_dir = setup_test_dir(test_name);
+
+        let rolling = RollingWriter::new(test_dir.join("test.tar"), Some(1024)).unwrap();
+        assert!(MultiVolumeWriter::new(rolling, 1024).is_err());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_multivolume_extracts_with_gnu_tar() {
+        if !has_gnu_tar() {
+            return; // GNU tar is required to check compatibility
+        }
+        let test_name = "gnu_tar";
+        let test_dir = setup_test_dir(test_name);
+
+        let long_name = format!("{}/long.bin", "nested_directory_name".repeat(6));
+        let files = vec![
+            ("a.bin", random_bytes(30000, 1)),
+            ("b.bin", random_bytes(5000, 2)),
+            ("empty.bin", Vec::new()),
+            (long_name.as_str(), random_bytes(12000, 3)),
+        ];
+        let parts = write_volumes(&test_dir, &files, 4096);
+
+        let out_dir = test_dir.join("out");
+        fs::create_dir(&out_dir).unwrap();
+        let mut command = Command::new("tar");
+        command.arg("-xM").current_dir(&out_dir);
+        for part in &parts {
+            command.arg("-f").arg(part);
+        }
+        let output = command.output().unwrap();
+        assert!(output.status.success(), "GNU tar failed: {}", String::from_utf8_lossy(&output.stderr));
+
+        for (name, data) in &files {
+            assert_eq!(&fs::read(out_dir.join(name)).unwrap(), data, "Content mismatch for {}", name);
+        }
+
+        cleanup_test_dir(test_name);
+    }
+}
@@ -0,0 +1,793 @@
+use std::cell::Cell;
+use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::{fs, process};
+use chrono::Local;
+use log::{debug, info, warn};
+use serde_json::json;
+use crate::destination::run_cli;
+use crate::hooks::{HookEvent, HookKind, Hooks};
+use crate::journal::{Outcome, RunRecord, SegmentOutcome};
+use crate::script_options::DryRunScripts;
+use crate::systemd;
+
+/// Longest wait for a notification to be sent
+const NOTIFY_TIMEOUT_SECS: u32 = 30;
+
+/// Email the run summary through an SMTP server (Sent with curl)
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(try_from = "RawEmailConfig")]
+pub struct EmailConfig {
+    /// i.e. `smtps://smtp.example.com` (Implicit TLS) or `smtp://smtp.example.com:587` (STARTTLS is required)
+    pub smtp_url: String,
+    pub username: Option<String>,
+    /// Passed to curl on stdin, so it isn't visible in the process list
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    /// Only send an email when a run fails
+    #[serde(default)]
+    pub only_on_failure: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct RawEmailConfig {
+    smtp_url: String,
+    username: Option<String>,
+    password: Option<String>,
+    from: String,
+    to: Vec<String>,
+    #[serde(default)]
+    only_on_failure: bool,
+}
+
+impl TryFrom<RawEmailConfig> for EmailConfig {
+    type Error = String;
+
+    /// Reject line breaks in the addresses, since they go straight into the message headers
+    fn try_from(raw: RawEmailConfig) -> Result<Self, Self::Error> {
+        if let Some(address) = std::iter::once(&raw.from).chain(&raw.to).find(|address| address.contains(['\r', '\n'])) {
+            return Err(format!("Invalid email address {:?} (Line breaks aren't allowed)", address));
+        }
+        Ok(Self {
+            smtp_url: raw.smtp_url,
+            username: raw.username,
+            password: raw.password,
+            from: raw.from,
+            to: raw.to,
+            only_on_failure: raw.only_on_failure,
+        })
+    }
+}
+
+/// Chat service to post a short message about each run to
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ChatService {
+    /// Incoming webhook URL (i.e. `https://hooks.slack.com/services/...`)
+    Slack { webhook_url: String },
+    /// Channel webhook URL (i.e. `https://discord.com/api/webhooks/...`)
+    Discord { webhook_url: String },
+    /// Bot token and the chat to message
+    Telegram { bot_token: String, chat_id: String },
+}
+
+/// Chat notification settings
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct ChatConfig {
+    #[serde(flatten)]
+    pub service: ChatService,
+    /// Only post when a run fails
+    #[serde(default)]
+    pub only_on_failure: bool,
+}
+
+impl ChatService {
+    fn name(&self) -> &'static str {
+        match self {
+            ChatService::Slack { .. } => "Slack",
+            ChatService::Discord { .. } => "Discord",
+            ChatService::Telegram { .. } => "Telegram",
+        }
+    }
+
+    /// URL to post to (Which contains a secret) and the JSON body for a message
+    fn request(&self, message: &str) -> (String, serde_json::Value) {
+        match self {
+            ChatService::Slack { webhook_url } => (webhook_url.clone(), json!({ "text": message })),
+            ChatService::Discord { webhook_url } => (webhook_url.clone(), json!({ "content": message })),
+            ChatService::Telegram { bot_token, chat_id } => (
+                format!("https://api.telegram.org/bot{}/sendMessage", bot_token),
+                json!({ "chat_id": chat_id, "text": message }),
+            ),
+        }
+    }
+}
+
+/// MQTT broker to publish run and segment status to (With mosquitto_pub)
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    /// Run status is published to `<topic>/run`, and each segment's to `<topic>/segment/<name>`
+    #[serde(default = "default_mqtt_topic")]
+    pub topic: String,
+    pub username: Option<String>,
+    /// (Passed as an argument, so it is visible in the process list. Prefer mosquitto_pub's options file.)
+    pub password: Option<String>,
+    /// Have the broker keep the latest message of each topic (So dashboards show it after restarting)
+    #[serde(default = "default_true")]
+    pub retain: bool,
+    /// Path to mosquitto_pub (Default: `mosquitto_pub`)
+    pub cli: Option<PathBuf>,
+}
+
+fn default_mqtt_topic() -> String {
+    "segmented_archive".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Where run notifications are sent
+#[derive(Debug, Default)]
+pub struct NotifyOptions {
+    /// healthchecks.io style check: pinged at `/start`, then at the URL itself or `/fail` (With the run summary)
+    pub healthcheck_url: Option<String>,
+    /// Sent a JSON summary when a run finishes
+    pub webhook_url: Option<String>,
+    /// Also send the webhook each segment's outcome
+    pub webhook_per_segment: bool,
+    /// Emailed the summary table when a run finishes
+    pub email: Option<EmailConfig>,
+    /// Posted a short message when a run finishes
+    pub chat: Option<ChatConfig>,
+    /// Published the status of the run and each segment
+    pub mqtt: Option<MqttConfig>,
+    /// Failure hook: run with each failed segment, then with each run error
+    /// Run-done hook: run with a JSON summary of each finished run (The webhook payload)
+    pub hooks: Arc<Hooks>,
+    /// Set during a dry run: `Skip` sends nothing, while `Run` sends notifications marked as a dry run
+    /// (The healthcheck is never pinged, and MQTT messages aren't retained)
+    pub dry_run: Option<DryRunScripts>,
+}
+
+/// Reports the start and outcome of each backup run (Failures to notify are only logged)
+pub struct Notifier {
+    options: NotifyOptions,
+    /// Segments of the run whose outcome has been sent
+    segments_sent: Cell<usize>,
+    /// Path to curl
+    curl: PathBuf,
+}
+
+impl Notifier {
+    pub fn new(options: NotifyOptions) -> Self {
+        Self { options, segments_sent: Cell::new(0), curl: PathBuf::from("curl") }
+    }
+
+    /// Report that a run has started
+    pub fn run_started(&self, run: &RunRecord) {
+        systemd::notify("READY=1\nSTATUS=Backup started");
+        if !self.sends() {
+            return;
+        }
+        if let Some(url) = self.healthcheck_url() {
+            self.request("healthcheck", &format!("{}/start", url.trim_end_matches('/')), None, None);
+        }
+        if let Some(mqtt) = &self.options.mqtt {
+            self.publish(mqtt, "run", &json!({ "event": "run", "status": "running", "started": run.started, "dry_run": run.dry_run }));
+        }
+    }
+
+    /// Report the segment being processed (Numbered from 1, of `total`)
+    pub fn segment_started(&self, segment: &str, number: usize, total: usize) {
+        systemd::notify(&format!("STATUS=Processing segment {} ({}/{})", segment, number, total));
+    }
+
+    /// Report the outcome of each segment recorded since the last call (If per-segment notifications are enabled)
+    pub fn segments_finished(&self, run: &RunRecord) {
+        let new_segments = run.segments.get(self.segments_sent.get()..).unwrap_or_default();
+        self.segments_sent.set(run.segments.len());
+        if let Some(url) = self.options.webhook_url.as_ref().filter(|_| self.options.webhook_per_segment && self.sends()) {
+            for segment in new_segments {
+                self.post_json(url, &segment_payload(segment, run.dry_run));
+            }
+        }
+        if let Some(mqtt) = self.options.mqtt.as_ref().filter(|_| self.sends()) {
+            for segment in new_segments {
+                self.publish(mqtt, &format!("segment/{}", segment.segment), &segment_payload(segment, run.dry_run));
+            }
+        }
+        for segment in new_segments.iter().filter(|segment| segment.outcome == Outcome::Failed) {
+            self.on_failure(&segment.segment, segment.error.as_deref().unwrap_or("Unknown error"));
+        }
+    }
+
+    /// Report a finished run
+    pub fn run_finished(&self, run: &RunRecord) {
+        self.segments_finished(run);
+        for error in &run.errors {
+            self.on_failure("", error);
+        }
+        self.on_finish(run);
+        systemd::notify(&format!("STOPPING=1\nSTATUS={}", run.summary().lines().next().unwrap_or_default()));
+        if !self.sends() {
+            return;
+        }
+        if let Some(url) = self.healthcheck_url() {
+            match run.has_failures() {
+                true => self.request("healthcheck", &format!("{}/fail", url.trim_end_matches('/')), Some(&run.summary()), None),
+                false => self.request("healthcheck", url, None, None),
+            }
+        }
+        if let Some(url) = &self.options.webhook_url {
+            self.post_json(url, &run_payload(run));
+        }
+        if let Some(mqtt) = &self.options.mqtt {
+            self.publish(mqtt, "run", &run_payload(run));
+        }
+        if let Some(email) = self.options.email.as_ref().filter(|email| run.has_failures() || !email.only_on_failure) {
+            self.send_email(email, run);
+        }
+        if let Some(chat) = self.options.chat.as_ref().filter(|chat| run.has_failures() || !chat.only_on_failure) {
+            self.post_chat(&chat.service, &chat_message(run));
+        }
+    }
+
+    /// False if notifications are skipped during this dry run
+    fn sends(&self) -> bool {
+        self.options.dry_run != Some(DryRunScripts::Skip)
+    }
+
+    /// Healthcheck to ping (Never during a dry run, which would pass for a real backup)
+    fn healthcheck_url(&self) -> Option<&String> {
+        self.options.healthcheck_url.as_ref().filter(|_| self.options.dry_run.is_none())
+    }
+
+    /// Run the failure hook with a segment name (Empty for run errors) and error message
+    fn on_failure(&self, segment: &str, error: &str) {
+        let event = HookEvent::Failure { segment: Some(segment).filter(|segment| !segment.is_empty()), error };
+        match self.options.hooks.dispatch(&event) {
+            Ok(0) => {}
+            Ok(code) => warn!("On-failure script exited with code {}", code),
+            Err(e) => warn!("Failed to run on-failure script: {}", e),
+        }
+    }
+
+    /// Run the run-done hook with the path to the run summary (Written to a temporary file, which is removed afterwards)
+    fn on_finish(&self, run: &RunRecord) {
+        if self.options.hooks.script(HookKind::RunDone, None).is_none() {
+            return;
+        }
+        let summary_file = std::env::temp_dir().join(format!("segmented_archive_run_{}.json", process::id()));
+        let summary = serde_json::to_string_pretty(&run_payload(run)).unwrap_or_default();
+        if let Err(e) = fs::write(&summary_file, summary) {
+            warn!("Failed to write run summary {:?}: {}", summary_file, e);
+            return;
+        }
+        match self.options.hooks.dispatch(&HookEvent::RunDone { summary_file: &summary_file }) {
+            Ok(0) => {}
+            Ok(code) => warn!("On-finish script exited with code {}", code),
+            Err(e) => warn!("Failed to run on-finish script: {}", e),
+        }
+        let _ = fs::remove_file(&summary_file);
+    }
+
+    /// Publish a payload to a subtopic of the configured topic (The payload is sent on stdin)
+    fn publish(&self, mqtt: &MqttConfig, subtopic: &str, payload: &serde_json::Value) {
+        let topic = format!("{}/{}", mqtt.topic.trim_end_matches('/'), subtopic);
+        let mut args = vec!["-h".to_string(), mqtt.host.clone(), "-t".to_string(), topic.clone(), "-q".to_string(), "1".to_string()];
+        if let Some(port) = mqtt.port {
+            args.extend(["-p".to_string(), port.to_string()]);
+        }
+        if let Some(username) = &mqtt.username {
+            args.extend(["-u".to_string(), username.clone()]);
+        }
+        if let Some(password) = &mqtt.password {
+            args.extend(["-P".to_string(), password.clone()]);
+        }
+        if mqtt.retain && self.options.dry_run.is_none() {
+            args.push("-r".to_string());
+        }
+        args.push("-s".to_string());
+        let cli = mqtt.cli.clone().unwrap_or_else(|| PathBuf::from("mosquitto_pub"));
+        match run_cli(&cli, &args, Some(&payload.to_string())) {
+            Ok(_) => debug!("Published to MQTT topic {}", topic),
+            Err(e) => warn!("Failed to publish to MQTT topic {}: {}", topic, e),
+        }
+    }
+
+    /// Post a message (The URL is passed to curl on stdin, since it holds the webhook secret or bot token)
+    fn post_chat(&self, service: &ChatService, message: &str) {
+        let (url, body) = service.request(message);
+        let args = ["--fail", "--silent", "--show-error", "--max-time", &NOTIFY_TIMEOUT_SECS.to_string(), "--retry", "3",
+            "--header", "Content-Type: application/json", "--data-raw", &body.to_string(), "--config", "-"]
+            .map(String::from);
+        match run_cli(Path::new(&self.curl), &args, Some(&curl_config("url", &url))) {
+            Ok(_) => info!("Posted run summary to {}", service.name()),
+            Err(e) => warn!("Failed to post run summary to {}: {}", service.name(), e),
+        }
+    }
+
+    /// Send the summary table to each recipient (The message is staged in a temporary file, since stdin carries the password)
+    fn send_email(&self, email: &EmailConfig, run: &RunRecord) {
+        let message_file = std::env::temp_dir().join(format!("segmented_archive_email_{}.eml", process::id()));
+        if let Err(e) = fs::write(&message_file, email_message(email, run)) {
+            warn!("Failed to write email {:?}: {}", message_file, e);
+            return;
+        }
+        let mut args = vec!["--fail".to_string(), "--silent".to_string(), "--show-error".to_string(), "--ssl-reqd".to_string(),
+            "--max-time".to_string(), NOTIFY_TIMEOUT_SECS.to_string(), "--url".to_string(), email.smtp_url.clone(),
+            "--mail-from".to_string(), email.from.clone()];
+        for to in &email.to {
+            args.extend(["--mail-rcpt".to_string(), to.clone()]);
+        }
+        args.extend(["--upload-file".to_string(), message_file.display().to_string()]);
+        let credentials = email.username.as_ref()
+            .map(|username| curl_config("user", &format!("{}:{}", username, email.password.as_deref().unwrap_or_default())));
+        if credentials.is_some() {
+            args.extend(["--config".to_string(), "-".to_string()]);
+        }
+        match run_cli(Path::new(&self.curl), &args, credentials.as_deref()) {
+            Ok(_) => info!("Emailed run summary to {}", email.to.join(", ")),
+            Err(e) => warn!("Failed to email run summary: {}", e),
+        }
+        let _ = fs::remove_file(&message_file);
+    }
+
+    fn post_json(&self, url: &str, payload: &serde_json::Value) {
+        self.request("webhook", url, Some(&payload.to_string()), Some("application/json"));
+    }
+
+    /// Request a URL (POSTing `body`, if set)
+    /// (Both are passed to curl on stdin, since the URL may hold a token)
+    fn request(&self, label: &str, url: &str, body: Option<&str>, content_type: Option<&str>) {
+        let mut args = vec!["--fail".to_string(), "--silent".to_string(), "--show-error".to_string(),
+            "--max-time".to_string(), NOTIFY_TIMEOUT_SECS.to_string(), "--retry".to_string(), "3".to_string()];
+        if let Some(content_type) = content_type {
+            args.extend(["--header".to_string(), format!("Content-Type: {}", content_type)]);
+        }
+        args.extend(["--config".to_string(), "-".to_string()]);
+        let mut config = curl_config("url", url);
+        if let Some(body) = body {
+            config.push_str(&curl_config("data-binary", body));
+        }
+        match run_cli(Path::new(&self.curl), &args, Some(&config)) {
+            Ok(_) => info!("Sent {}: {}", label, url),
+            Err(e) => warn!("Failed to send {} {}: {}", label, url, e),
+        }
+    }
+}
+
+/// A line of curl config setting `key` (Quoted, so the value is passed as is)
+fn curl_config(key: &str, value: &str) -> String {
+    let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\r', "\\r").replace('\t', "\\t");
+    format!("{} = \"{}\"\n", key, value)
+}
+
+/// `success` or `failure`
+fn status(failed: bool) -> &'static str {
+    if failed { "failure" } else { "success" }
+}
+
+/// A few lines for chat: status emoji and counts, the total size and duration, then each failure
+fn chat_message(run: &RunRecord) -> String {
+    let count = |outcome: Outcome| run.segments.iter().filter(|segment| segment.outcome == outcome).count();
+    let mut lines = vec![format!("{} {} {}: {} archived, {} skipped, {} failed",
+        if run.has_failures() { "\u{274C}" } else { "\u{2705}" }, run_label(run), if run.has_failures() { "failed" } else { "succeeded" },
+        count(Outcome::Archived), count(Outcome::Skipped), count(Outcome::Failed))];
+    lines.push(format!("{} written{}", format_bytes(run.bytes_written()),
+        run.duration_secs().map_or_else(String::new, |secs| format!(" in {}", format_duration(secs)))));
+    for segment in run.segments.iter().filter(|segment| segment.outcome == Outcome::Failed) {
+        lines.push(format!("\u{2022} {}: {}", segment.segment, segment.error.as_deref().unwrap_or("Unknown error")));
+    }
+    lines.extend(run.errors.iter().map(|error| format!("\u{2022} {}", error)));
+    lines.join("\n")
+}
+
+/// `Backup`, or `Dry run`
+fn run_label(run: &RunRecord) -> &'static str {
+    if run.dry_run { "Dry run" } else { "Backup" }
+}
+
+/// i.e. `1.5 GB`
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["bytes", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} bytes", bytes),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
+
+/// i.e. `1h 5m`, `3m 20s` or `12.5s`
+fn format_duration(secs: f64) -> String {
+    let whole = secs as u64;
+    match whole {
+        3600.. => format!("{}h {}m", whole / 3600, whole % 3600 / 60),
+        60.. => format!("{}m {}s", whole / 60, whole % 60),
+        _ => format!("{:.1}s", secs),
+    }
+}
+
+/// Email with the run's outcome in the subject, and its summary table as the body
+fn email_message(email: &EmailConfig, run: &RunRecord) -> String {
+    let count = |outcome: Outcome| run.segments.iter().filter(|segment| segment.outcome == outcome).count();
+    let subject = format!("{} {}: {} archived, {} skipped, {} failed", run_label(run),
+        if run.has_failures() { "FAILED" } else { "succeeded" }, count(Outcome::Archived), count(Outcome::Skipped), count(Outcome::Failed));
+    let headers = [
+        format!("From: {}", email.from),
+        format!("To: {}", email.to.join(", ")),
+        format!("Subject: {}", subject),
+        format!("Date: {}", Local::now().to_rfc2822()),
+        "Content-Type: text/plain; charset=utf-8".to_string(),
+    ];
+    format!("{}\r\n\r\n{}\r\n", headers.join("\r\n"), summary_table(run).replace('\n', "\r\n"))
+}
+
+/// Fixed-width table of each segment's outcome, then the run's totals and errors
+fn summary_table(run: &RunRecord) -> String {
+    let width = run.segments.iter().map(|segment| segment.segment.len()).max().unwrap_or(0).max("Segment".len());
+    let mut lines = vec![
+        format!("Started:  {}", run.started),
+        format!("Finished: {}", run.finished),
+        String::new(),
+        format!("{:<width$}  {:<8}  {:>5}  {:>15}  {:>9}", "Segment", "Outcome", "Parts", "Bytes", "Duration"),
+    ];
+    for segment in &run.segments {
+        let outcome = match segment.outcome {
+            Outcome::Archived => "archived",
+            Outcome::Skipped => "skipped",
+            Outcome::Failed => "failed",
+        };
+        let duration = segment.duration_secs.map_or_else(String::new, |secs| format!("{:.1}s", secs));
+        lines.push(format!("{:<width$}  {:<8}  {:>5}  {:>15}  {:>9}", segment.segment, outcome, segment.parts, segment.bytes_written, duration));
+    }
+    lines.push(String::new());
+    lines.push(format!("Total: {} bytes written{}", run.bytes_written(),
+        run.duration_secs().map_or_else(String::new, |secs| format!(" in {:.1}s", secs))));
+    for segment in run.segments.iter().filter(|segment| segment.outcome == Outcome::Failed) {
+        lines.push(format!("{} failed: {}", segment.segment, segment.error.as_deref().unwrap_or("Unknown error")));
+    }
+    lines.extend(run.errors.iter().map(|error| format!("Error: {}", error)));
+    lines.join("\n")
+}
+
+/// Webhook payload for a finished run
+fn run_payload(run: &RunRecord) -> serde_json::Value {
+    json!({
+        "event": "run",
+        "status": status(run.has_failures()),
+        "started": run.started,
+        "finished": run.finished,
+        "duration_secs": run.duration_secs(),
+        "bytes_written": run.bytes_written(),
+        "segments": run.segments,
+        "errors": run.errors,
+        "dry_run": run.dry_run,
+    })
+}
+
+/// Webhook payload for a finished segment
+fn segment_payload(segment: &SegmentOutcome, dry_run: bool) -> serde_json::Value {
+    json!({
+        "event": "segment",
+        "status": status(segment.outcome == Outcome::Failed),
+        "segment": segment,
+        "dry_run": dry_run,
+    })
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::collections::HashMap;
+    use crate::script_options::Script;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/notify_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[cfg(unix)]
+    fn write_script(path: &PathBuf, contents: &str) {
+        use std::os::unix::fs::PermissionsExt;
+        fs::write(path, contents).unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    /// Stands in for curl: records the arguments and config (On stdin) of each request
+    fn curl_script(test_dir: &Path) -> String {
+        format!("#!/bin/bash\necho \"$@\" >> \"{0}/args\"\ncat >> \"{0}/config\"\necho -- >> \"{0}/config\"\n", test_dir.display())
+    }
+
+    /// The URL and any body of each request recorded by curl_script
+    fn curl_requests(test_dir: &Path) -> Vec<(String, Option<String>)> {
+        let Ok(config) = fs::read_to_string(test_dir.join("config")) else {
+            return Vec::new();
+        };
+        let unescape = |value: &str| value.replace("\\\\", "\0").replace("\\n", "\n").replace("\\\"", "\"").replace('\0', "\\");
+        config.split("--\n").filter(|request| !request.is_empty()).map(|request| {
+            let value = |key: &str| request.lines()
+                .find_map(|line| line.strip_prefix(&format!("{} = \"", key)))
+                .map(|value| unescape(value.strip_suffix('"').unwrap_or(value)));
+            (value("url").unwrap_or_default(), value("data-binary"))
+        }).collect()
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_healthcheck_pings() {
+        let test_name = "healthcheck";
+        let test_dir = setup_test_dir(test_name);
+
+        let curl = test_dir.join("curl");
+        write_script(&curl, &curl_script(&test_dir));
+        let notifier = Notifier { curl, ..Notifier::new(NotifyOptions { healthcheck_url: Some("https://hc-ping.com/uuid/".to_string()), ..NotifyOptions::default() }) };
+
+        let mut run = RunRecord::start();
+        notifier.run_started(&run);
+        run.archived("docs", 150, 2);
+        notifier.run_finished(&run);
+        run.failed("photos", "Path not found");
+        notifier.run_finished(&run);
+
+        let requests = curl_requests(&test_dir);
+        assert_eq!(requests.iter().map(|(url, _)| url.as_str()).collect::<Vec<_>>(),
+            ["https://hc-ping.com/uuid/start", "https://hc-ping.com/uuid/", "https://hc-ping.com/uuid/fail"]);
+        assert!(requests[2].1.as_ref().is_some_and(|body| body.contains("\nphotos: failed (Path not found)")));
+        assert!(!fs::read_to_string(test_dir.join("args")).unwrap().contains("hc-ping.com"), "The URL is only passed on stdin");
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_webhook() {
+        let test_name = "webhook";
+        let test_dir = setup_test_dir(test_name);
+        let curl = test_dir.join("curl");
+        write_script(&curl, &curl_script(&test_dir));
+        let notifier = Notifier { curl, ..Notifier::new(NotifyOptions { webhook_url: Some("https://example.com/hook".to_string()), webhook_per_segment: true, ..NotifyOptions::default() }) };
+
+        let mut run = RunRecord::start();
+        run.begin_segment();
+        run.archived("docs", 150, 2);
+        notifier.segments_finished(&run);
+        run.failed("photos", "Path not found");
+        run.finished = chrono::Local::now().to_rfc3339();
+        notifier.run_finished(&run);
+
+        // Each segment is sent once, then the run
+        let bodies: Vec<serde_json::Value> = curl_requests(&test_dir).into_iter()
+            .map(|(_, body)| serde_json::from_str(&body.unwrap()).unwrap()).collect();
+        assert_eq!(bodies.len(), 3);
+        assert_eq!((bodies[0]["event"].as_str(), bodies[0]["status"].as_str(), bodies[0]["segment"]["parts"].as_u64()), (Some("segment"), Some("success"), Some(2)));
+        assert!(bodies[0]["segment"]["duration_secs"].is_f64());
+        assert_eq!((bodies[1]["segment"]["segment"].as_str(), bodies[1]["status"].as_str()), (Some("photos"), Some("failure")));
+        assert_eq!((bodies[2]["event"].as_str(), bodies[2]["status"].as_str(), bodies[2]["bytes_written"].as_u64()), (Some("run"), Some("failure"), Some(150)));
+        assert_eq!(bodies[2]["segments"].as_array().map(Vec::len), Some(2));
+        assert!(bodies[2]["duration_secs"].is_f64());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_dry_run_notifications() {
+        let test_name = "dry_run";
+        let test_dir = setup_test_dir(test_name);
+
+        let curl = test_dir.join("curl");
+        write_script(&curl, &curl_script(&test_dir));
+        let cli = test_dir.join("mosquitto_pub");
+        write_script(&cli, &format!("#!/bin/bash\necho \"$@\" >> \"{0}/mqtt\"\ncat > /dev/null\n", test_dir.display()));
+        let options = |dry_run| NotifyOptions {
+            healthcheck_url: Some("https://hc-ping.com/uuid".to_string()),
+            webhook_url: Some("https://example.com/hook".to_string()),
+            webhook_per_segment: true,
+            chat: Some(toml::from_str("type = \"discord\"\nwebhook_url = \"https://discord.com/api/webhooks/1\"").unwrap()),
+            mqtt: Some(toml::from_str(&format!("host = \"homeassistant.local\"\ncli = {:?}", cli)).unwrap()),
+            dry_run,
+            ..NotifyOptions::default()
+        };
+        let mut run = RunRecord::start();
+        run.dry_run = true;
+        run.archived("docs", 0, 0);
+
+        // Nothing is sent by default
+        let notifier = Notifier { curl: curl.clone(), ..Notifier::new(options(Some(DryRunScripts::Skip))) };
+        notifier.run_started(&run);
+        notifier.segments_finished(&run);
+        notifier.run_finished(&run);
+        assert!(curl_requests(&test_dir).is_empty());
+        assert!(!test_dir.join("mqtt").exists());
+
+        // Otherwise notifications are marked as a dry run, without the healthcheck or retained messages
+        let notifier = Notifier { curl, ..Notifier::new(options(Some(DryRunScripts::Run))) };
+        notifier.run_started(&run);
+        notifier.run_finished(&run);
+        let requests = curl_requests(&test_dir);
+        assert!(requests.iter().all(|(url, _)| !url.contains("hc-ping.com")), "The healthcheck is never pinged during a dry run");
+        assert_eq!(requests.iter().filter(|(url, _)| url == "https://example.com/hook").count(), 2);
+        let bodies: Vec<serde_json::Value> = requests.into_iter()
+            .filter_map(|(_, body)| body.map(|body| serde_json::from_str(&body).unwrap())).collect();
+        assert!(bodies.iter().all(|body| body["dry_run"] == true));
+        let mqtt = fs::read_to_string(test_dir.join("mqtt")).unwrap();
+        assert_eq!(mqtt.lines().count(), 3);
+        assert!(!mqtt.contains("-r"), "Dry runs shouldn't replace the retained status");
+        assert!(chat_message(&run).contains("Dry run succeeded"));
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_email() {
+        let test_name = "email";
+        let test_dir = setup_test_dir(test_name);
+
+        // Stands in for curl: records its arguments, stdin and the message it was given
+        let curl = test_dir.join("curl");
+        write_script(&curl, &format!("#!/bin/bash\necho \"$@\" > \"{0}/args\"\ncat > \"{0}/stdin\"\nwhile [ $# -gt 0 ]; do if [ \"$1\" = \"--upload-file\" ]; then cp \"$2\" \"{0}/message\"; fi; shift; done\n", test_dir.display()));
+        let email: EmailConfig = toml::from_str("smtp_url = \"smtps://smtp.example.com\"\nusername = \"backup\"\npassword = \"secret\"\nfrom = \"backup@example.com\"\nto = [\"admin@example.com\", \"ops@example.com\"]\nonly_on_failure = true").unwrap();
+        let notifier = Notifier { curl, ..Notifier::new(NotifyOptions { email: Some(email), ..NotifyOptions::default() }) };
+
+        // Only sent on failure
+        let mut run = RunRecord::start();
+        run.archived("documents", 150, 2);
+        notifier.run_finished(&run);
+        assert!(!test_dir.join("args").exists());
+
+        run.failed("photos", "Path not found");
+        notifier.run_finished(&run);
+        let args = fs::read_to_string(test_dir.join("args")).unwrap();
+        assert!(args.contains("--url smtps://smtp.example.com --mail-from backup@example.com --mail-rcpt admin@example.com --mail-rcpt ops@example.com"));
+        assert!(!args.contains("secret"));
+        assert_eq!(fs::read_to_string(test_dir.join("stdin")).unwrap(), "user = \"backup:secret\"\n");
+        let message = fs::read_to_string(test_dir.join("message")).unwrap();
+        assert!(message.contains("Subject: Backup FAILED: 1 archived, 0 skipped, 1 failed\r\n"));
+        assert!(message.contains("\r\nSegment    Outcome   Parts            Bytes   Duration\r\ndocuments  archived      2              150           \r\n"));
+        assert!(message.contains("\r\nphotos failed: Path not found\r\n"));
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_email_header_injection() {
+        let config = |from: &str, to: &str| toml::from_str::<EmailConfig>(&format!("smtp_url = \"smtps://smtp.example.com\"\nfrom = {:?}\nto = [\"admin@example.com\", {:?}]", from, to));
+        assert!(config("backup@example.com", "ops@example.com").is_ok());
+        assert!(config("backup@example.com\nBcc: someone@example.com", "ops@example.com").unwrap_err().to_string().contains("Line breaks aren't allowed"));
+        assert!(config("backup@example.com", "ops@example.com\r\nBcc: someone@example.com").is_err());
+        assert!(config("backup@example.com\r", "ops@example.com").is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_chat() {
+        let test_name = "chat";
+        let test_dir = setup_test_dir(test_name);
+
+        // Stands in for curl: records its arguments and the config on stdin
+        let curl = test_dir.join("curl");
+        write_script(&curl, &format!("#!/bin/bash\nprintf '%s\\n' \"$@\" > \"{0}/args\"\ncat > \"{0}/stdin\"\n", test_dir.display()));
+        let chat: ChatConfig = toml::from_str("type = \"telegram\"\nbot_token = \"123:abc\"\nchat_id = \"-100\"").unwrap();
+        let notifier = Notifier { curl, ..Notifier::new(NotifyOptions { chat: Some(chat), ..NotifyOptions::default() }) };
+
+        let mut run = RunRecord::start();
+        run.archived("docs", 1536, 1);
+        run.failed("photos", "Path not found");
+        run.finished = run.started.clone();
+        notifier.run_finished(&run);
+
+        let args = fs::read_to_string(test_dir.join("args")).unwrap();
+        let body = args.lines().skip_while(|arg| *arg != "--data-raw").nth(1).unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["chat_id"], "-100");
+        assert_eq!(body["text"], "\u{274C} Backup failed: 1 archived, 0 skipped, 1 failed\n1.5 KB written in 0.0s\n\u{2022} photos: Path not found");
+        assert!(!args.contains("123:abc"), "The bot token is only passed on stdin");
+        assert_eq!(fs::read_to_string(test_dir.join("stdin")).unwrap(), "url = \"https://api.telegram.org/bot123:abc/sendMessage\"\n");
+
+        assert_eq!(format_bytes(512), "512 bytes");
+        assert_eq!(format_duration(3725.0), "1h 2m");
+        assert_eq!(format_duration(200.0), "3m 20s");
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_on_failure_script() {
+        let test_name = "on_failure_script";
+        let test_dir = setup_test_dir(test_name);
+        let script = test_dir.join("on_failure.sh");
+        write_script(&script, &format!("#!/bin/bash\necho \"$1|$2\" >> \"{}/calls\"\n", test_dir.display()));
+        let hooks = Hooks { scripts: HashMap::from([(HookKind::Failure, Script::Path(script))]), ..Hooks::default() };
+        let notifier = Notifier::new(NotifyOptions { hooks: Arc::new(hooks), ..NotifyOptions::default() });
+
+        let mut run = RunRecord::start();
+        run.archived("docs", 150, 2);
+        run.failed("photos", "Path not found");
+        notifier.segments_finished(&run);
+        run.errors.push("Failed to prune superseded archives".to_string());
+        notifier.run_finished(&run);
+
+        // Each failure is reported once
+        assert_eq!(fs::read_to_string(test_dir.join("calls")).unwrap(), "photos|Path not found\n|Failed to prune superseded archives\n");
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_on_finish_script() {
+        let test_name = "on_finish_script";
+        let test_dir = setup_test_dir(test_name);
+        let script = test_dir.join("on_finish.sh");
+        write_script(&script, &format!("#!/bin/bash
+cp \"$1\" \"{0}/summary.json\"\necho \"$SEG_ARC_STATUS\" > \"{0}/status\"\n", test_dir.display()));
+        let hooks = Hooks { scripts: HashMap::from([(HookKind::RunDone, Script::Path(script))]), ..Hooks::default() };
+        let notifier = Notifier::new(NotifyOptions { hooks: Arc::new(hooks), ..NotifyOptions::default() });
+
+        let mut run = RunRecord::start();
+        run.archived("docs", 150, 2);
+        run.failed("photos", "Path not found");
+        notifier.run_finished(&run);
+
+        let summary: serde_json::Value = serde_json::from_str(&fs::read_to_string(test_dir.join("summary.json")).unwrap()).unwrap();
+        assert_eq!(summary["status"], "failure");
+        assert_eq!(summary["segments"].as_array().unwrap().iter().map(|segment| segment["outcome"].as_str().unwrap()).collect::<Vec<_>>(), ["archived", "failed"]);
+        assert_eq!(fs::read_to_string(test_dir.join("status")).unwrap(), "finished\n");
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_mqtt() {
+        let test_name = "mqtt";
+        let test_dir = setup_test_dir(test_name);
+
+        // Stands in for mosquitto_pub: records the arguments and message of each publish
+        let cli = test_dir.join("mosquitto_pub");
+        write_script(&cli, &format!("#!/bin/bash\necho \"$@\" >> \"{0}/calls\"\ncat >> \"{0}/messages\"\necho >> \"{0}/messages\"\n", test_dir.display()));
+        let mqtt: MqttConfig = toml::from_str(&format!("host = \"homeassistant.local\"\nusername = \"backup\"\ncli = {:?}", cli)).unwrap();
+        let notifier = Notifier::new(NotifyOptions { mqtt: Some(mqtt), ..NotifyOptions::default() });
+
+        let mut run = RunRecord::start();
+        notifier.run_started(&run);
+        run.archived("docs", 150, 2);
+        notifier.run_finished(&run);
+
+        assert_eq!(fs::read_to_string(test_dir.join("calls")).unwrap().lines().collect::<Vec<_>>(), [
+            "-h homeassistant.local -t segmented_archive/run -q 1 -u backup -r -s",
+            "-h homeassistant.local -t segmented_archive/segment/docs -q 1 -u backup -r -s",
+            "-h homeassistant.local -t segmented_archive/run -q 1 -u backup -r -s",
+        ]);
+        let messages: Vec<serde_json::Value> = fs::read_to_string(test_dir.join("messages")).unwrap()
+            .lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(messages.iter().map(|message| message["status"].as_str().unwrap()).collect::<Vec<_>>(), ["running", "success", "success"]);
+
+        cleanup_test_dir(test_name);
+    }
+}
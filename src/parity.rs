@@ -0,0 +1,259 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Extension appended to the archive base path for the parity file
+pub const PARITY_EXT: &str = "parity";
+/// Marks the end of a parity file (Preceded by the trailer length)
+pub const PARITY_MAGIC: &[u8; 8] = b"SEGARCP1";
+const PARITY_VERSION: u32 = 1;
+
+/// Describes the parity block and the parts it covers
+/// Stored (as TOML) at the end of the parity file
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ParityTrailer {
+    pub version: u32,
+    pub parity_size: u64,
+    pub parts: Vec<ParityPart>,
+}
+
+/// A single part covered by the parity block
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ParityPart {
+    pub name: String,
+    pub size: u64,
+    /// xxh3 of the part contents (Used to locate a damaged part)
+    pub hash: String,
+}
+
+/// Builds a single XOR parity block over every part written by a RollingWriter.
+/// Any one missing or damaged part can be rebuilt by XOR-ing the parity block with the remaining parts.
+///
+/// File layout: `[parity block][TOML trailer][trailer length: u64 LE][PARITY_MAGIC]`
+pub struct ParityWriter {
+    file: File,
+    path: PathBuf,
+    parity_size: u64,
+    part_hasher: Xxh3,
+    part_size: u64,
+    parts: Vec<ParityPart>,
+}
+
+impl ParityWriter {
+    /// Create (or truncate) a parity file at `path` (i.e. the `parity_path` of an archive)
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            parity_size: 0,
+            part_hasher: Xxh3::new(),
+            part_size: 0,
+            parts: Vec::new(),
+        })
+    }
+
+    /// XOR data (written at `offset` within the current part) into the parity block
+    pub fn update(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        // Read the existing parity bytes (Anything past the end is treated as zeroes)
+        let mut block = vec![0u8; data.len()];
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut filled = 0;
+        while filled < block.len() {
+            let read = self.file.read(&mut block[filled..])?;
+            if read == 0 {
+                break;
+            }
+            filled += read;
+        }
+
+        for (parity_byte, data_byte) in block.iter_mut().zip(data) {
+            *parity_byte ^= data_byte;
+        }
+
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(&block)?;
+
+        self.parity_size = self.parity_size.max(offset + data.len() as u64);
+        self.part_hasher.update(data);
+        self.part_size += data.len() as u64;
+        Ok(())
+    }
+
+    /// Record the part that was just completed
+    pub fn finish_part(&mut self, name: &str) {
+        self.parts.push(ParityPart {
+            name: name.to_string(),
+            size: self.part_size,
+            hash: format!("{:016x}", self.part_hasher.digest()),
+        });
+        self.part_hasher.reset();
+        self.part_size = 0;
+    }
+
+    /// Number of parts covered so far
+    pub fn part_count(&self) -> usize {
+        self.parts.len()
+    }
+
+    /// Write the trailer and close the parity file (Syncing it to disk if `sync` is set), returning its path
+    pub fn finalize(mut self, sync: bool) -> io::Result<PathBuf> {
+        let trailer = ParityTrailer {
+            version: PARITY_VERSION,
+            parity_size: self.parity_size,
+            parts: self.parts,
+        };
+        let trailer_str = toml::to_string(&trailer)
+            .map_err(|e| io::Error::other(format!("Failed to serialize parity trailer: {}", e)))?;
+
+        self.file.seek(SeekFrom::Start(self.parity_size))?;
+        self.file.write_all(trailer_str.as_bytes())?;
+        self.file.write_all(&(trailer_str.len() as u64).to_le_bytes())?;
+        self.file.write_all(PARITY_MAGIC)?;
+        self.file.flush()?;
+        if sync {
+            self.file.sync_all()?;
+        }
+        Ok(self.path)
+    }
+
+    /// Close and delete the parity file (i.e. if the archive was not split)
+    pub fn discard(self) -> io::Result<()> {
+        let ParityWriter { file, path, .. } = self;
+        drop(file);
+        std::fs::remove_file(path)
+    }
+}
+
+/// Path of the parity file for an archive base path (i.e. `docs.tar.gz` has `docs.tar.gz.parity`)
+pub fn parity_path(base_path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.{}", base_path.display(), PARITY_EXT))
+}
+
+/// Read the trailer at the end of a parity file
+pub fn read_parity_trailer(path: &Path) -> io::Result<ParityTrailer> {
+    let mut file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+    let footer_size = (8 + PARITY_MAGIC.len()) as u64;
+    if file_size < footer_size {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Parity file is too short"));
+    }
+    let mut footer = [0u8; 16];
+    file.seek(SeekFrom::Start(file_size - footer_size))?;
+    file.read_exact(&mut footer)?;
+    if &footer[8..] != PARITY_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Parity file has no trailer (Incomplete or not a parity file)"));
+    }
+    let trailer_size = u64::from_le_bytes(footer[..8].try_into().unwrap_or_default());
+    let trailer_start = (file_size - footer_size).checked_sub(trailer_size)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Parity trailer length is invalid"))?;
+    let mut trailer = String::new();
+    file.seek(SeekFrom::Start(trailer_start))?;
+    file.take(trailer_size).read_to_string(&mut trailer)?;
+    let trailer: ParityTrailer = toml::from_str(&trailer)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to parse parity trailer: {}", e)))?;
+    if trailer.parity_size != trailer_start {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Parity block size doesn't match its trailer"));
+    }
+    Ok(trailer)
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/parity_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    fn read_trailer(contents: &[u8]) -> ParityTrailer {
+        let magic_start = contents.len() - PARITY_MAGIC.len();
+        assert_eq!(&contents[magic_start..], PARITY_MAGIC);
+        let len_start = magic_start - 8;
+        let trailer_len = u64::from_le_bytes(contents[len_start..magic_start].try_into().unwrap()) as usize;
+        let trailer_str = std::str::from_utf8(&contents[len_start - trailer_len..len_start]).unwrap();
+        toml::from_str(trailer_str).unwrap()
+    }
+
+    #[test]
+    fn test_parity_xor_of_parts() {
+        let test_name = "xor";
+        let test_dir = setup_test_dir(test_name);
+        let base_path = test_dir.join("test.tar.gz");
+
+        let parts: [&[u8]; 3] = [b"abcdef", b"123456", b"xyz"];
+        let mut parity = ParityWriter::create(&parity_path(&base_path)).unwrap();
+        for (i, part) in parts.iter().enumerate() {
+            // Write each part in two chunks to exercise offset handling
+            let (first, second) = part.split_at(2);
+            parity.update(0, first).unwrap();
+            parity.update(2, second).unwrap();
+            parity.finish_part(&format!("test.tar.gz.part{:03}", i + 1));
+        }
+        assert_eq!(parity.part_count(), 3);
+        let parity_path = parity.finalize(false).unwrap();
+        assert_eq!(parity_path, test_dir.join("test.tar.gz.parity"));
+
+        let contents = fs::read(&parity_path).unwrap();
+        let trailer = read_trailer(&contents);
+        assert_eq!(read_parity_trailer(&parity_path).unwrap().parts.len(), 3);
+        assert_eq!(trailer.parity_size, 6);
+        assert_eq!(trailer.parts.len(), 3);
+        assert_eq!(trailer.parts[2].name, "test.tar.gz.part003");
+        assert_eq!(trailer.parts[2].size, 3);
+
+        // Rebuild the 2nd part from the parity block and the remaining parts
+        let mut rebuilt = contents[..6].to_vec();
+        for part in [parts[0], parts[2]] {
+            for (byte, part_byte) in rebuilt.iter_mut().zip(part) {
+                *byte ^= part_byte;
+            }
+        }
+        assert_eq!(&rebuilt[..trailer.parts[1].size as usize], parts[1]);
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_parity_discard() {
+        let test_name = "discard";
+        let test_dir = setup_test_dir(test_name);
+        let base_path = test_dir.join("test.tar.gz");
+
+        let mut parity = ParityWriter::create(&parity_path(&base_path)).unwrap();
+        parity.update(0, b"data").unwrap();
+        parity.finish_part("test.tar.gz");
+        let parity_path = test_dir.join("test.tar.gz.parity");
+        assert!(parity_path.exists());
+
+        parity.discard().unwrap();
+        assert!(!parity_path.exists());
+
+        cleanup_test_dir(test_name);
+    }
+}
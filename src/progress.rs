@@ -0,0 +1,252 @@
+use anyhow::{Result, anyhow};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+const JSON_INTERVAL: Duration = Duration::from_secs(1);
+const BAR_WIDTH: usize = 24;
+
+/// The progress bar currently drawn on stderr (Cleared while log messages are written)
+static DRAWN_BAR: Mutex<Option<String>> = Mutex::new(None);
+
+/// How progress is shown while segments are archived
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// A bar redrawn on stderr (On terminals)
+    Bar,
+    /// JSON lines on stdout (Logs are written to stderr instead)
+    Json,
+    Off,
+}
+
+/// Remove `--progress <bar|json|none>` from the command line, returning the mode it set
+///
+/// # Errors
+/// Fails if the mode is missing or unknown
+pub fn take_progress_flag(args: &mut Vec<String>) -> Result<Option<ProgressMode>> {
+    let Some(position) = args.iter().position(|arg| arg == "--progress") else {
+        return Ok(None);
+    };
+    let mode = match args.get(position + 1).map(String::as_str) {
+        Some("bar") => ProgressMode::Bar,
+        Some("json") => ProgressMode::Json,
+        Some("none") => ProgressMode::Off,
+        Some(mode) => return Err(anyhow!("Unknown progress mode: {} (Expected bar, json or none)", mode)),
+        None => return Err(anyhow!("--progress requires a mode (bar, json or none)")),
+    };
+    args.drain(position..position + 2);
+    Ok(Some(mode))
+}
+
+/// Counters of the segment being archived (Updated while archiving, and read by the progress display)
+#[derive(Debug, Default)]
+pub struct Progress {
+    files: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    part: AtomicU32,
+}
+
+/// The counters of a segment at one point in time
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressSnapshot {
+    /// Files and symlinks archived
+    pub files: u64,
+    /// Content bytes of the archived files
+    pub bytes_read: u64,
+    /// Compressed bytes written to parts
+    pub bytes_written: u64,
+    /// Number of the part being written (From 1)
+    pub part: u32,
+}
+
+impl Progress {
+    /// Count an archived file (Of `bytes` content bytes)
+    pub fn add_file(&self, bytes: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_written(&self, bytes: u64) {
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn set_part(&self, part: u32) {
+        self.part.store(part, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        ProgressSnapshot {
+            files: self.files.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            part: self.part.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Shows a segment's progress while it is archived (Until it is finished, or dropped)
+pub struct ProgressReporter {
+    stop: mpsc::Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ProgressReporter {
+    /// Start showing the progress of a segment (`total_bytes` is the size of its files, if known)
+    /// (None if progress is off)
+    pub fn start(mode: ProgressMode, segment: &str, total_bytes: Option<u64>, progress: Arc<Progress>) -> Option<Self> {
+        let (stop, stopped) = mpsc::channel();
+        let segment = segment.to_string();
+        let thread = match mode {
+            ProgressMode::Bar => thread::spawn(move || loop {
+                draw(Some(render_bar(&segment, total_bytes, &progress.snapshot())));
+                if !matches!(stopped.recv_timeout(REDRAW_INTERVAL), Err(RecvTimeoutError::Timeout)) {
+                    draw(None);
+                    return;
+                }
+            }),
+            ProgressMode::Json => thread::spawn(move || loop {
+                print_event(&segment, "archiving", total_bytes, &progress.snapshot());
+                if !matches!(stopped.recv_timeout(JSON_INTERVAL), Err(RecvTimeoutError::Timeout)) {
+                    return;
+                }
+            }),
+            ProgressMode::Off => return None,
+        };
+        Some(Self { stop, thread: Some(thread) })
+    }
+
+    /// Stop showing progress (Clearing the bar)
+    pub fn finish(mut self) {
+        self.stop_thread();
+    }
+
+    fn stop_thread(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ProgressReporter {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
+
+/// Print a progress event as a line of JSON on stdout
+pub fn print_event(segment: &str, phase: &str, total_bytes: Option<u64>, snapshot: &ProgressSnapshot) {
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{}", event_json(segment, phase, total_bytes, snapshot));
+    let _ = stdout.flush();
+}
+
+/// A progress event (i.e. `{"segment":"docs","phase":"archiving","files_done":12,...}`)
+fn event_json(segment: &str, phase: &str, total_bytes: Option<u64>, snapshot: &ProgressSnapshot) -> serde_json::Value {
+    serde_json::json!({
+        "segment": segment,
+        "phase": phase,
+        "files_done": snapshot.files,
+        "bytes_done": snapshot.bytes_read,
+        "bytes_total": total_bytes,
+        "bytes_written": snapshot.bytes_written,
+        "part": (snapshot.part > 0).then_some(snapshot.part),
+    })
+}
+
+/// Run `write` (i.e. writing a log message) with the progress bar cleared, then redraw it
+pub fn suspend<T>(write: impl FnOnce() -> T) -> T {
+    let drawn = DRAWN_BAR.lock().unwrap_or_else(|e| e.into_inner());
+    let mut stderr = io::stderr().lock();
+    if drawn.is_some() {
+        let _ = write!(stderr, "\r\x1b[2K");
+        let _ = stderr.flush();
+    }
+    let result = write();
+    if let Some(bar) = drawn.as_ref() {
+        let _ = write!(stderr, "{}", bar);
+        let _ = stderr.flush();
+    }
+    result
+}
+
+/// Replace the progress bar on stderr (None clears it)
+fn draw(bar: Option<String>) {
+    let mut drawn = DRAWN_BAR.lock().unwrap_or_else(|e| e.into_inner());
+    let mut stderr = io::stderr().lock();
+    let _ = write!(stderr, "\r\x1b[2K{}", bar.as_deref().unwrap_or_default());
+    let _ = stderr.flush();
+    *drawn = bar;
+}
+
+/// One line showing a segment's progress (i.e. `docs [#######.....] 58% | 1204 files | 2.1 GiB read | 1.4 GiB written | part 3`)
+fn render_bar(segment: &str, total_bytes: Option<u64>, snapshot: &ProgressSnapshot) -> String {
+    let mut line = segment.to_string();
+    if let Some(total_bytes) = total_bytes.filter(|total| *total > 0) {
+        let fraction = (snapshot.bytes_read as f64 / total_bytes as f64).min(1.0);
+        let filled = (fraction * BAR_WIDTH as f64).round() as usize;
+        line.push_str(&format!(" [{}{}] {:>3}%", "#".repeat(filled), ".".repeat(BAR_WIDTH - filled), (fraction * 100.0).floor()));
+    }
+    line.push_str(&format!(" | {} files | {} read | {} written", snapshot.files, format_bytes(snapshot.bytes_read), format_bytes(snapshot.bytes_written)));
+    if snapshot.part > 0 {
+        line.push_str(&format!(" | part {}", snapshot.part));
+    }
+    line
+}
+
+/// Bytes in the largest binary unit they fill (i.e. `1.5 MiB`)
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_bar() {
+        let progress = Progress::default();
+        progress.add_file(1024);
+        progress.add_file(512);
+        progress.add_written(1000);
+        progress.set_part(2);
+        let snapshot = progress.snapshot();
+        assert_eq!(snapshot, ProgressSnapshot { files: 2, bytes_read: 1536, bytes_written: 1000, part: 2 });
+
+        assert_eq!(render_bar("docs", Some(3072), &snapshot), "docs [############............]  50% | 2 files | 1.5 KiB read | 1000 B written | part 2");
+        assert_eq!(render_bar("docs", None, &ProgressSnapshot::default()), "docs | 0 files | 0 B read | 0 B written");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+
+    #[test]
+    fn test_progress_events() {
+        let snapshot = ProgressSnapshot { files: 2, bytes_read: 1536, bytes_written: 1000, part: 1 };
+        assert_eq!(event_json("docs", "archiving", Some(3072), &snapshot).to_string(),
+            r#"{"bytes_done":1536,"bytes_total":3072,"bytes_written":1000,"files_done":2,"part":1,"phase":"archiving","segment":"docs"}"#);
+        assert_eq!(event_json("docs", "hashing", None, &ProgressSnapshot::default())["part"], serde_json::Value::Null);
+
+        let mut args: Vec<String> = ["segmented_archive", "--progress", "json", "config.toml"].map(String::from).to_vec();
+        assert_eq!(take_progress_flag(&mut args).unwrap(), Some(ProgressMode::Json));
+        assert_eq!(args, ["segmented_archive", "config.toml"]);
+        assert_eq!(take_progress_flag(&mut args).unwrap(), None);
+        assert!(take_progress_flag(&mut vec!["--progress".to_string(), "xml".to_string()]).is_err());
+        assert!(take_progress_flag(&mut vec!["--progress".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::cmp::Reverse;
+use std::time::{Duration, SystemTime};
+use std::fs;
+use log::info;
+use crate::archive_reader::archive_base;
+use crate::chain::{chain_path, read_chain};
+use crate::rolling_writer::list_outputs;
+use crate::sidecar::{read_sidecar, sidecar_path};
+
+/// Extensions of archive files (Before any .part### suffix)
+const ARCHIVE_EXTENSIONS: [&str; 2] = ["tar.gz", "tar"];
+
+/// How many superseded archives to keep (An archive is kept if either rule keeps it, neither keeps none)
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Retention {
+    /// Keep this many of the newest
+    pub keep_last: Option<usize>,
+    /// Keep those modified within this many days
+    pub keep_days: Option<u64>,
+}
+
+/// An archive of a segment that is no longer its latest archive (Or part of its latest chain)
+#[derive(Debug)]
+pub struct SupersededArchive {
+    /// Archive file name (Before any .part### suffix)
+    pub name: String,
+    /// Every file written for it (Parts, listing, parity and index)
+    pub files: Vec<PathBuf>,
+    pub bytes: u64,
+    /// Latest modification time of its files
+    pub modified: SystemTime,
+}
+
+/// Result of pruning a segment's superseded archives
+#[derive(Debug, Default)]
+pub struct PruneSummary {
+    pub removed: Vec<SupersededArchive>,
+    /// Superseded archives kept by the retention rules
+    pub kept: usize,
+}
+
+/// Names of a segment's current archives: every generation of its chain (If `manifest_dir` is set),
+/// and the archive in its metadata file. None if neither is found (So nothing is known to be superseded).
+pub fn current_archives(output_dir: &Path, manifest_dir: Option<&Path>, segment: &str) -> Option<BTreeSet<String>> {
+    let mut current = BTreeSet::new();
+    if let Some(chain) = manifest_dir.and_then(|dir| read_chain(&chain_path(dir, segment)).ok()) {
+        current.extend(chain.generations.into_iter().map(|generation| generation.archive));
+    }
+    if let Ok(sidecar) = read_sidecar(&sidecar_path(output_dir, segment))
+        && let Some(part) = sidecar.parts.first() {
+        current.insert(archive_base(Path::new(&part.name)).to_string_lossy().to_string());
+    }
+    (!current.is_empty()).then_some(current)
+}
+
+/// Archives of a segment in `output_dir` that aren't `current` (Newest first)
+pub fn superseded_archives(output_dir: &Path, segment: &str, current: &BTreeSet<String>) -> Result<Vec<SupersededArchive>> {
+    let mut names = BTreeSet::new();
+    for entry in fs::read_dir(output_dir).context(format!("Failed to read output directory: {:?}", output_dir))? {
+        let file_name = entry.context("Failed to read output directory entry")?.file_name();
+        if let Some(name) = archive_name_of(&file_name.to_string_lossy(), segment)
+            && !current.contains(&name) {
+            names.insert(name);
+        }
+    }
+
+    let mut archives = Vec::new();
+    for name in names {
+        let files = list_outputs(&output_dir.join(&name))
+            .context(format!("Failed to list files of archive: {}", name))?;
+        if files.is_empty() {
+            continue;
+        }
+        let mut bytes = 0;
+        let mut modified = SystemTime::UNIX_EPOCH;
+        for file in &files {
+            let metadata = fs::metadata(file)
+                .context(format!("Failed to read metadata: {:?}", file))?;
+            bytes += metadata.len();
+            modified = modified.max(metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH));
+        }
+        archives.push(SupersededArchive { name, files, bytes, modified });
+    }
+    archives.sort_by_key(|archive| Reverse(archive.modified));
+    Ok(archives)
+}
+
+/// Delete a segment's superseded archives, besides those kept by `retention`.
+/// Segments without a metadata file or chain are left alone (Their current archive is unknown).
+pub fn prune_segment(output_dir: &Path, manifest_dir: Option<&Path>, segment: &str, retention: Retention, now: SystemTime) -> Result<PruneSummary> {
+    let Some(current) = current_archives(output_dir, manifest_dir, segment) else {
+        info!("Segment '{}' has no metadata file or chain, nothing to prune", segment);
+        return Ok(PruneSummary::default());
+    };
+    let mut summary = PruneSummary::default();
+    for (i, archive) in superseded_archives(output_dir, segment, &current)?.into_iter().enumerate() {
+        let recent = retention.keep_days.is_some_and(|days| {
+            now.duration_since(archive.modified).unwrap_or_default() < Duration::from_secs(days * 86400)
+        });
+        if retention.keep_last.is_some_and(|keep_last| i < keep_last) || recent {
+            summary.kept += 1;
+            continue;
+        }
+        for file in &archive.files {
+            fs::remove_file(file)
+                .context(format!("Failed to remove superseded archive file: {:?}", file))?;
+        }
+        info!("Removed superseded archive {} of segment '{}' ({} file(s), {} bytes)", archive.name, segment, archive.files.len(), archive.bytes);
+        summary.removed.push(archive);
+    }
+    Ok(summary)
+}
+
+/// Archive name a file was written for, if it belongs to `segment`
+/// (i.e. `docs.inc002.tar.gz.part001` is `docs.inc002.tar.gz`)
+fn archive_name_of(file_name: &str, segment: &str) -> Option<String> {
+    let rest = file_name.strip_prefix(segment)?.strip_prefix('.')?;
+    // (Increments are named `<segment>.inc###.<extension>`)
+    let (generation, rest) = match rest.strip_prefix("inc").and_then(|inc| inc.split_once('.')) {
+        Some((number, rest)) if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) => (Some(number), rest),
+        _ => (None, rest),
+    };
+    // (The longer extension first, since "tar" also starts "tar.gz")
+    let extension = ARCHIVE_EXTENSIONS.iter()
+        .find(|extension| rest.strip_prefix(**extension).is_some_and(|suffix| suffix.is_empty() || !suffix.starts_with(".gz")))?;
+    Some(match generation {
+        Some(number) => format!("{}.inc{}.{}", segment, number, extension),
+        None => format!("{}.{}", segment, extension),
+    })
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rolling_writer::PartInfo;
+    use crate::sidecar::{SegmentMetadata, write_sidecar};
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/prune_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[test]
+    fn test_archive_name_of() {
+        assert_eq!(archive_name_of("docs.tar.gz", "docs").as_deref(), Some("docs.tar.gz"));
+        assert_eq!(archive_name_of("docs.tar.gz.part002", "docs").as_deref(), Some("docs.tar.gz"));
+        assert_eq!(archive_name_of("docs.tar.part001", "docs").as_deref(), Some("docs.tar"));
+        assert_eq!(archive_name_of("docs.inc002.tar.gz.parity", "docs").as_deref(), Some("docs.inc002.tar.gz"));
+        assert_eq!(archive_name_of("docs.meta.toml", "docs"), None);
+        assert_eq!(archive_name_of("docs2.tar.gz", "docs"), None);
+        assert_eq!(archive_name_of("notes.tar.gz", "docs"), None);
+    }
+
+    #[test]
+    fn test_prune_segment() {
+        let test_name = "prune";
+        let test_dir = setup_test_dir(test_name);
+        let current = PartInfo { name: "docs.tar.gz".to_string(), path: test_dir.join("docs.tar.gz"), size: 4, checksum: None };
+        let sidecar = SegmentMetadata {
+            segment: "docs".to_string(),
+            source_path: PathBuf::from("/tmp/files/docs"),
+            hash: None,
+            created: "2024-01-01T00:00:00+00:00".to_string(),
+            file_count: 1,
+            uncompressed_bytes: 4,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            checksum_algorithm: None,
+            parts: vec![current],
+        };
+        write_sidecar(&sidecar_path(&test_dir, "docs"), &sidecar).unwrap();
+        for name in ["docs.tar.gz", "docs.inc001.tar.gz", "docs.inc002.tar.gz.part001", "docs.inc002.tar.gz.part002", "docs.tar.part001", "notes.tar.gz"] {
+            fs::write(test_dir.join(name), b"data").unwrap();
+        }
+
+        // Kept by either rule
+        let now = SystemTime::now();
+        let summary = prune_segment(&test_dir, None, "docs", Retention { keep_last: Some(1), keep_days: Some(1) }, now).unwrap();
+        assert_eq!((summary.removed.len(), summary.kept), (0, 3));
+        let summary = prune_segment(&test_dir, None, "docs", Retention { keep_last: Some(1), keep_days: None }, now + Duration::from_secs(2 * 86400)).unwrap();
+        assert_eq!((summary.removed.len(), summary.kept), (2, 1));
+
+        let summary = prune_segment(&test_dir, None, "docs", Retention::default(), now).unwrap();
+        assert_eq!(summary.removed.len(), 1);
+        let mut remaining: Vec<String> = fs::read_dir(&test_dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().to_string()).collect();
+        remaining.sort();
+        assert_eq!(remaining, ["docs.meta.toml", "docs.tar.gz", "notes.tar.gz"]);
+
+        // A segment without a metadata file is left alone
+        assert!(prune_segment(&test_dir, None, "notes", Retention::default(), now).unwrap().removed.is_empty());
+        assert!(test_dir.join("notes.tar.gz").exists());
+
+        cleanup_test_dir(test_name);
+    }
+}
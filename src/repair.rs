@@ -0,0 +1,207 @@
+use anyhow::{Context, Result, anyhow};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::Xxh3;
+use crate::archive_reader::archive_base;
+use crate::parity::{ParityPart, ParityTrailer, parity_path, read_parity_trailer};
+
+/// Extensions of archive files (Tried when the path given has none)
+const ARCHIVE_EXTENSIONS: [&str; 2] = ["tar.gz", "tar"];
+// Buffer size for rebuilding parts
+const REPAIR_BUFFER_SIZE: usize = 1048576;
+
+/// Result of checking an archive's parts against its parity file
+#[derive(Debug)]
+pub struct RepairSummary {
+    pub parity_file: PathBuf,
+    /// Parts covered by the parity file
+    pub parts: usize,
+    /// Part that was missing or damaged and has been rebuilt (None if every part was intact)
+    pub rebuilt: Option<String>,
+}
+
+/// Find the parity file of an archive, given its path, any of its parts, or its path without an extension
+pub fn find_parity_file(archive: &Path) -> Result<PathBuf> {
+    let base = archive_base(archive);
+    let mut candidates = vec![parity_path(&base)];
+    candidates.extend(ARCHIVE_EXTENSIONS.iter().map(|ext| parity_path(Path::new(&format!("{}.{}", base.display(), ext)))));
+    candidates.into_iter()
+        .find(|candidate| candidate.is_file())
+        .ok_or_else(|| anyhow!("No parity file found for {:?} (Archives need to be written with parity enabled)", archive))
+}
+
+/// Check each part covered by an archive's parity file (By size and hash), and rebuild the one missing
+/// or damaged part from the others and the parity block. The damaged part is replaced.
+///
+/// # Errors
+/// Returns an error if more than one part is missing or damaged (Parity can only rebuild one),
+/// or if the rebuilt part doesn't match its recorded hash (i.e. the parity file is damaged too)
+pub fn repair_archive(archive: &Path) -> Result<RepairSummary> {
+    let parity_file = find_parity_file(archive)?;
+    let trailer = read_parity_trailer(&parity_file)
+        .context(format!("Failed to read parity file: {:?}", parity_file))?;
+    let dir = parity_file.parent().unwrap_or(Path::new("."));
+
+    let mut damaged = Vec::new();
+    for part in &trailer.parts {
+        if !part_is_intact(&dir.join(&part.name), part)? {
+            damaged.push(part);
+        }
+    }
+    let summary = RepairSummary { parity_file: parity_file.clone(), parts: trailer.parts.len(), rebuilt: None };
+    let damaged = match damaged[..] {
+        [] => return Ok(summary),
+        [damaged] => damaged,
+        _ => return Err(anyhow!("{} parts are missing or damaged ({}), parity can only rebuild one",
+            damaged.len(), damaged.iter().map(|part| part.name.as_str()).collect::<Vec<_>>().join(", "))),
+    };
+
+    let rebuilt_path = dir.join(format!("{}.repair", damaged.name));
+    let result = rebuild_part(&parity_file, &trailer, damaged, &rebuilt_path)
+        .and_then(|_| {
+            if part_is_intact(&rebuilt_path, damaged)? {
+                Ok(())
+            } else {
+                Err(anyhow!("Rebuilt part {} doesn't match its recorded hash (The parity file or another part may be damaged)", damaged.name))
+            }
+        });
+    if let Err(e) = result {
+        let _ = fs::remove_file(&rebuilt_path);
+        return Err(e);
+    }
+    let path = dir.join(&damaged.name);
+    fs::rename(&rebuilt_path, &path)
+        .context(format!("Failed to replace part: {:?}", path))?;
+    Ok(RepairSummary { rebuilt: Some(damaged.name.clone()), ..summary })
+}
+
+/// Whether a part exists at its recorded size and hash
+fn part_is_intact(path: &Path, part: &ParityPart) -> Result<bool> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.len() == part.size => {},
+        Ok(_) => return Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e).context(format!("Failed to read part: {:?}", path)),
+    }
+    let mut file = File::open(path)
+        .context(format!("Failed to open part: {:?}", path))?;
+    let mut hasher = Xxh3::new();
+    let mut buffer = vec![0u8; REPAIR_BUFFER_SIZE];
+    loop {
+        let read = file.read(&mut buffer)
+            .context(format!("Failed to read part: {:?}", path))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:016x}", hasher.digest()) == part.hash)
+}
+
+/// Write a part rebuilt by XOR-ing the parity block with every other part (Shorter parts count as zeroes past their end)
+fn rebuild_part(parity_file: &Path, trailer: &ParityTrailer, damaged: &ParityPart, output: &Path) -> Result<()> {
+    let dir = parity_file.parent().unwrap_or(Path::new("."));
+    let mut parity = File::open(parity_file)
+        .context(format!("Failed to open parity file: {:?}", parity_file))?
+        .take(damaged.size);
+    let mut others = Vec::new();
+    for part in trailer.parts.iter().filter(|part| part.name != damaged.name) {
+        let path = dir.join(&part.name);
+        let file = File::open(&path)
+            .context(format!("Failed to open part: {:?}", path))?;
+        others.push((path, file.take(damaged.size)));
+    }
+    let mut output_file = File::create(output)
+        .context(format!("Failed to create rebuilt part: {:?}", output))?;
+
+    let mut block = vec![0u8; REPAIR_BUFFER_SIZE];
+    let mut buffer = vec![0u8; REPAIR_BUFFER_SIZE];
+    loop {
+        let len = read_full(&mut parity, &mut block)
+            .context(format!("Failed to read parity file: {:?}", parity_file))?;
+        if len == 0 {
+            break;
+        }
+        for (path, file) in &mut others {
+            let read = read_full(file, &mut buffer[..len])
+                .context(format!("Failed to read part: {:?}", path))?;
+            for (byte, other) in block.iter_mut().zip(&buffer[..read]) {
+                *byte ^= other;
+            }
+        }
+        output_file.write_all(&block[..len])
+            .context(format!("Failed to write rebuilt part: {:?}", output))?;
+    }
+    output_file.sync_all()
+        .context(format!("Failed to sync rebuilt part: {:?}", output))
+}
+
+/// Fill as much of `buffer` as the reader has left, returning the bytes read
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parity::ParityWriter;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/repair_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[test]
+    fn test_repair_archive() {
+        let test_name = "repair";
+        let test_dir = setup_test_dir(test_name);
+        let parts: [&[u8]; 3] = [b"abcdef", b"123456", b"xyz"];
+        let mut parity = ParityWriter::create(&parity_path(&test_dir.join("test.tar.gz"))).unwrap();
+        for (i, part) in parts.iter().enumerate() {
+            let name = format!("test.tar.gz.part{:03}", i + 1);
+            fs::write(test_dir.join(&name), part).unwrap();
+            parity.update(0, part).unwrap();
+            parity.finish_part(&name);
+        }
+        parity.finalize(false).unwrap();
+
+        let summary = repair_archive(&test_dir.join("test")).unwrap();
+        assert_eq!((summary.parts, summary.rebuilt), (3, None));
+
+        // A missing part, then a damaged (shorter) last part
+        fs::remove_file(test_dir.join("test.tar.gz.part002")).unwrap();
+        let summary = repair_archive(&test_dir.join("test.tar.gz.part001")).unwrap();
+        assert_eq!(summary.rebuilt.as_deref(), Some("test.tar.gz.part002"));
+        assert_eq!(fs::read(test_dir.join("test.tar.gz.part002")).unwrap(), parts[1]);
+        fs::write(test_dir.join("test.tar.gz.part003"), b"xz").unwrap();
+        assert_eq!(repair_archive(&test_dir.join("test.tar.gz")).unwrap().rebuilt.as_deref(), Some("test.tar.gz.part003"));
+        assert_eq!(fs::read(test_dir.join("test.tar.gz.part003")).unwrap(), parts[2]);
+
+        // Only one part can be rebuilt
+        fs::remove_file(test_dir.join("test.tar.gz.part001")).unwrap();
+        fs::remove_file(test_dir.join("test.tar.gz.part003")).unwrap();
+        assert!(repair_archive(&test_dir.join("test")).is_err());
+
+        cleanup_test_dir(test_name);
+    }
+}
@@ -0,0 +1,788 @@
+use anyhow::{Context, Result, anyhow};
+use std::path::{Component, Path, PathBuf};
+use std::io::Read;
+use std::collections::BTreeSet;
+use std::fs;
+use log::{error, info, warn};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use crate::archive_info::{ArchiveInfo, INFO_FILE, LEGACY_PATH_FILE, MANIFEST_ENTRY};
+use crate::archive_reader::open_archive;
+use crate::completeness::check_archive_parts;
+use crate::index::{EntryKind, find_index};
+use crate::manifest::Manifest;
+
+/// Where the restore command extracts to
+#[derive(Debug, PartialEq)]
+pub enum RestoreTarget {
+    /// Into this directory
+    Dir(PathBuf),
+    /// Back to the segment's recorded path (Its path relative to root_path under this root, if set)
+    Original(Option<PathBuf>),
+}
+
+/// What to do with an entry whose path already exists
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Fail the restore
+    #[default]
+    Refuse,
+    Overwrite,
+    /// Leave the existing file, and don't extract the entry
+    SkipExisting,
+    /// Extract the entry next to the existing file (i.e. `a.txt` as `a.restored.txt`)
+    KeepBoth,
+}
+
+/// Arguments of the restore command:
+/// `restore <archive> [--to DIR] [--original] [--include GLOB]... [--overwrite|--skip-existing|--keep-both] [--dry-run]`
+#[derive(Debug, PartialEq)]
+pub struct RestoreArgs {
+    pub archive: PathBuf,
+    /// Directory to extract into (The new root with --original)
+    pub to: Option<PathBuf>,
+    pub original: bool,
+    /// Only extract paths matching one of these (Or inside a matching directory)
+    pub include: Vec<String>,
+    pub on_conflict: ConflictPolicy,
+    pub dry_run: bool,
+}
+
+impl RestoreArgs {
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let mut archive = None;
+        let mut to = None;
+        let mut original = false;
+        let mut include = Vec::new();
+        let mut on_conflict = None;
+        let mut dry_run = false;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let policy = match arg.as_str() {
+                "--overwrite" => Some(ConflictPolicy::Overwrite),
+                "--skip-existing" => Some(ConflictPolicy::SkipExisting),
+                "--keep-both" => Some(ConflictPolicy::KeepBoth),
+                _ => None,
+            };
+            if let Some(policy) = policy {
+                if on_conflict.is_some_and(|existing| existing != policy) {
+                    return Err(anyhow!("Only one of --overwrite, --skip-existing or --keep-both can be given"));
+                }
+                on_conflict = Some(policy);
+                continue;
+            }
+            match arg.as_str() {
+                "--to" => to = Some(PathBuf::from(args.next().ok_or_else(|| anyhow!("--to requires a directory"))?)),
+                "--original" => original = true,
+                "--dry-run" => dry_run = true,
+                "--include" => include.push(args.next().ok_or_else(|| anyhow!("--include requires a pattern"))?.to_owned()),
+                flag if flag.starts_with("--") => return Err(anyhow!("Unknown restore option: {}", flag)),
+                _ if archive.is_none() => archive = Some(PathBuf::from(arg)),
+                _ => return Err(anyhow!("Unexpected restore argument: {}", arg)),
+            }
+        }
+        let archive = archive.ok_or_else(|| anyhow!(
+            "Usage: restore <archive> [--to DIR] [--original] [--include GLOB]... [--overwrite|--skip-existing|--keep-both] [--dry-run]"))?;
+        Ok(Self { archive, to, original, include, on_conflict: on_conflict.unwrap_or_default(), dry_run })
+    }
+
+    pub fn options(&self) -> Result<RestoreOptions> {
+        Ok(RestoreOptions {
+            target: self.target(),
+            include: self.include_matcher()?,
+            on_conflict: self.on_conflict,
+            dry_run: self.dry_run,
+        })
+    }
+
+    /// Where to extract to (Default: The current directory)
+    pub fn target(&self) -> RestoreTarget {
+        match (self.original, &self.to) {
+            (true, root) => RestoreTarget::Original(root.clone()),
+            (false, Some(dir)) => RestoreTarget::Dir(dir.clone()),
+            (false, None) => RestoreTarget::Dir(PathBuf::from(".")),
+        }
+    }
+
+    /// Matcher for the include patterns (None extracts everything)
+    pub fn include_matcher(&self) -> Result<Option<GlobSet>> {
+        if self.include.is_empty() {
+            return Ok(None);
+        }
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &self.include {
+            builder.add(Glob::new(pattern).context(format!("Invalid include pattern: {}", pattern))?);
+        }
+        Ok(Some(builder.build().context("Failed to build GlobSet from include patterns")?))
+    }
+}
+
+/// How to extract an archive
+#[derive(Debug)]
+pub struct RestoreOptions {
+    pub target: RestoreTarget,
+    /// Only extract entries matching this (None extracts everything)
+    pub include: Option<GlobSet>,
+    pub on_conflict: ConflictPolicy,
+    /// Only report what would be extracted, writing nothing
+    pub dry_run: bool,
+}
+
+/// Result of extracting an archive
+#[derive(Debug, Default)]
+pub struct RestoreSummary {
+    /// Entries extracted (Not counting the info entry)
+    pub entries: usize,
+    /// Entries left out by the include patterns
+    pub skipped: usize,
+    /// Size of the extracted files
+    pub bytes: u64,
+    /// Entries whose path already existed (Handled by the conflict policy)
+    pub conflicts: usize,
+    /// Extracted files checked against the archive's manifest entry
+    pub verified: usize,
+    /// Extracted files whose content doesn't match the manifest entry
+    pub mismatched: Vec<PathBuf>,
+    /// The archive's info entry (None for older archives)
+    pub info: Option<ArchiveInfo>,
+    /// Directory the entries were extracted into (None if there were none)
+    pub dest: Option<PathBuf>,
+}
+
+/// Entries written by this tool, read before the archive's files
+#[derive(Debug, Default)]
+struct ArchiveMetadata {
+    /// Restore path from the path entry of older archives
+    legacy_path: Option<String>,
+    /// File hashes to check extracted files against (Only with embed_manifest)
+    manifest: Option<Manifest>,
+}
+
+/// Extract an archive (Reassembling its parts, once they are all found) to `target`, leaving out its info entry
+/// (And any entries not matching `include`, if set).
+/// With `include`, the archive's index (If any) is used to read only the parts holding matching entries.
+/// If the archive has a manifest entry, each extracted file is hashed and checked against it.
+/// When refusing conflicts, every conflict is found before anything is extracted.
+pub fn restore_archive(archive: &Path, options: &RestoreOptions) -> Result<RestoreSummary> {
+    let parts = check_archive_parts(archive)?.parts;
+    let include = options.include.as_ref();
+    if options.on_conflict == ConflictPolicy::Refuse && !options.dry_run {
+        let conflicts = find_conflicts(&parts, options)?;
+        if !conflicts.is_empty() {
+            let paths: Vec<String> = conflicts.iter().map(|path| format!("{:?}", path)).collect();
+            return Err(anyhow!("Refusing to overwrite {} existing path(s) (Use --overwrite, --skip-existing or --keep-both): {}",
+                conflicts.len(), paths.join(", ")));
+        }
+    }
+    let mut summary = RestoreSummary::default();
+    let mut metadata = ArchiveMetadata::default();
+    let Some(index) = include.and_then(|_| find_index(&parts)) else {
+        info!("Restoring {} part(s) of {:?}", parts.len(), archive);
+        extract_entries(&parts, options, usize::MAX, &mut summary, &mut metadata)?;
+        return Ok(summary);
+    };
+
+    let wanted: Vec<usize> = index.entries.iter()
+        .filter(|entry| is_included(include, Path::new(&entry.path)))
+        .map(|entry| entry.part)
+        .collect();
+    // (Standalone parts can be read alone, otherwise reading stops after the last wanted entry)
+    let reads: Vec<&[PathBuf]> = if index.standalone {
+        let numbers: BTreeSet<usize> = wanted.iter().copied().collect();
+        numbers.into_iter().filter_map(|number| parts.get(number - 1..number)).collect()
+    } else if wanted.is_empty() {
+        Vec::new()
+    } else {
+        vec![&parts]
+    };
+    info!("Restoring {} of {} entries of {:?} (Reading {} of {} part(s), using its index)",
+        wanted.len(), index.entries.len(), archive, if index.standalone { reads.len() } else { parts.len() }, parts.len());
+    // (The manifest entry is only in part 1, so it's read even if that part holds no wanted entries)
+    if index.standalone && reads.first().is_none_or(|read| read.first() != parts.first())
+        && let Err(e) = read_metadata(&parts[..1], &mut summary, &mut metadata) {
+        warn!("Failed to read the start of {:?}, restored files won't be checked against its manifest: {:#}", parts[0], e);
+    }
+    let mut remaining = wanted.len();
+    for read in reads {
+        remaining -= extract_entries(read, options, remaining, &mut summary, &mut metadata)?;
+    }
+    summary.skipped = index.entries.len() - wanted.len();
+    Ok(summary)
+}
+
+/// Extract the included entries of `parts` into the summary's destination (Resolving it at the first one),
+/// stopping once `max_entries` have been extracted. Returns the number of included entries read.
+fn extract_entries(
+    parts: &[PathBuf],
+    options: &RestoreOptions,
+    max_entries: usize,
+    summary: &mut RestoreSummary,
+    metadata: &mut ArchiveMetadata,
+) -> Result<usize> {
+    let mut tar = open_archive(parts)?;
+    let mut included = 0;
+    for entry in tar.entries().context("Failed to read archive")? {
+        if included == max_entries {
+            break;
+        }
+        let mut entry = entry.context("Failed to read archive entry")?;
+        let path = entry.path().context("Failed to read archive entry path")?.to_path_buf();
+        if read_metadata_entry(&mut entry, &path, summary, metadata)? {
+            continue;
+        }
+        if !is_included(options.include.as_ref(), &path) {
+            summary.skipped += 1;
+            continue;
+        }
+        included += 1;
+
+        // (The info entry comes first, so the destination is known before anything is extracted)
+        let dest = match &summary.dest {
+            Some(dest) => dest,
+            None => {
+                let dest = resolve_dest(options, summary.info.as_ref(), metadata.legacy_path.as_deref(), &path, entry.header().entry_type().is_file())?;
+                if options.dry_run {
+                    info!("Would restore to {:?}", dest);
+                } else {
+                    info!("Restoring to {:?}", dest);
+                    fs::create_dir_all(&dest)
+                        .context(format!("Failed to create restore directory: {:?}", dest))?;
+                }
+                summary.dest.insert(dest)
+            }
+        };
+        // (Entries that would be written outside of dest are skipped)
+        if path.components().any(|component| !matches!(component, Component::Normal(_) | Component::CurDir)) {
+            warn!("Skipped entry outside of the restore directory: {:?}", path);
+            continue;
+        }
+        let header = entry.header();
+        let entry_type = header.entry_type();
+        let size = if entry_type.is_file() { header.size().unwrap_or(0) } else { 0 };
+        let dest_path = dest.join(&path);
+        // (An existing directory is only a conflict if the entry isn't one)
+        let conflict = fs::symlink_metadata(&dest_path)
+            .is_ok_and(|existing| !(existing.is_dir() && entry_type.is_dir()));
+        summary.conflicts += usize::from(conflict);
+        let write_path = match (conflict, options.on_conflict) {
+            (false, _) | (true, ConflictPolicy::Overwrite) => dest_path.clone(),
+            (true, ConflictPolicy::KeepBoth) => keep_both_path(&dest_path),
+            (true, ConflictPolicy::SkipExisting) => {
+                info!("{} existing {:?}", if options.dry_run { "Would skip" } else { "Skipped" }, dest_path);
+                continue;
+            }
+            (true, ConflictPolicy::Refuse) if options.dry_run => {
+                warn!("Would refuse to overwrite {:?}", dest_path);
+                continue;
+            }
+            (true, ConflictPolicy::Refuse) => return Err(anyhow!(
+                "Refusing to overwrite {:?} (Use --overwrite, --skip-existing or --keep-both)", dest_path)),
+        };
+
+        if options.dry_run {
+            match (conflict, write_path == dest_path) {
+                (false, _) => info!("Would write {:?} ({} bytes)", dest_path, size),
+                (true, true) => warn!("Would overwrite {:?} ({} bytes)", dest_path, size),
+                (true, false) => info!("Would write {:?} as {:?} ({} bytes)", dest_path, write_path, size),
+            }
+        } else if write_path != dest_path {
+            if let Some(parent) = write_path.parent() {
+                fs::create_dir_all(parent)
+                    .context(format!("Failed to create directory: {:?}", parent))?;
+            }
+            entry.unpack(&write_path).context(format!("Failed to extract {:?} as {:?}", path, write_path))?;
+            info!("Kept existing {:?}, extracted as {:?}", dest_path, write_path);
+        } else if !entry.unpack_in(dest).context(format!("Failed to extract {:?}", path))? {
+            warn!("Skipped entry outside of the restore directory: {:?}", path);
+            continue;
+        }
+
+        // Check the extracted file against the manifest entry (Symlinks by their target)
+        if !options.dry_run
+            && let Some(manifest) = &metadata.manifest {
+            let relative_path = path.to_string_lossy();
+            let matches = if entry_type.is_symlink() {
+                let target = fs::read_link(&write_path)
+                    .context(format!("Failed to read extracted symlink: {:?}", write_path))?;
+                manifest.check_file(&relative_path, target.as_os_str().as_encoded_bytes())?
+            } else if entry_type.is_file() {
+                let file = fs::File::open(&write_path)
+                    .context(format!("Failed to open extracted file: {:?}", write_path))?;
+                manifest.check_file(&relative_path, file)?
+            } else {
+                None
+            };
+            if let Some(matches) = matches {
+                summary.verified += 1;
+                if !matches {
+                    error!("Extracted file doesn't match the archive's manifest: {:?}", write_path);
+                    summary.mismatched.push(write_path);
+                }
+            }
+        }
+        summary.bytes += size;
+        summary.entries += 1;
+    }
+    Ok(included)
+}
+
+/// Every included entry whose path already exists at the destination, found without extracting anything
+/// (From the archive's index, if it has one)
+fn find_conflicts(parts: &[PathBuf], options: &RestoreOptions) -> Result<Vec<PathBuf>> {
+    let mut summary = RestoreSummary::default();
+    let mut metadata = ArchiveMetadata::default();
+    let mut entries = Vec::new();
+    if let Some(index) = find_index(parts) {
+        // (The info entry is only needed to find the original path)
+        if matches!(options.target, RestoreTarget::Original(_)) {
+            read_metadata(&parts[..1], &mut summary, &mut metadata)?;
+        }
+        entries.extend(index.entries.into_iter().map(|entry| (PathBuf::from(entry.path), entry.kind)));
+    } else {
+        let mut tar = open_archive(parts)?;
+        for entry in tar.entries().context("Failed to read archive")? {
+            let mut entry = entry.context("Failed to read archive entry")?;
+            let path = entry.path().context("Failed to read archive entry path")?.to_path_buf();
+            if read_metadata_entry(&mut entry, &path, &mut summary, &mut metadata)? {
+                continue;
+            }
+            let entry_type = entry.header().entry_type();
+            let kind = if entry_type.is_dir() { EntryKind::Dir } else if entry_type.is_symlink() { EntryKind::Link } else { EntryKind::File };
+            entries.push((path, kind));
+        }
+    }
+
+    let mut dest = None;
+    let mut conflicts = Vec::new();
+    for (path, kind) in entries {
+        if !is_included(options.include.as_ref(), &path)
+            || path.components().any(|component| !matches!(component, Component::Normal(_) | Component::CurDir)) {
+            continue;
+        }
+        let dest = match &dest {
+            Some(dest) => dest,
+            None => dest.insert(resolve_dest(options, summary.info.as_ref(), metadata.legacy_path.as_deref(), &path, kind == EntryKind::File)?),
+        };
+        let dest_path = dest.join(&path);
+        if fs::symlink_metadata(&dest_path).is_ok_and(|existing| !(existing.is_dir() && kind == EntryKind::Dir)) {
+            conflicts.push(dest_path);
+        }
+    }
+    Ok(conflicts)
+}
+
+/// Directory to extract into, resolved at the first included entry (`is_file` if it is a regular file)
+fn resolve_dest(options: &RestoreOptions, info: Option<&ArchiveInfo>, legacy_path: Option<&str>, first_entry: &Path, is_file: bool) -> Result<PathBuf> {
+    match &options.target {
+        RestoreTarget::Dir(dir) => Ok(dir.clone()),
+        RestoreTarget::Original(root) => {
+            let is_single_file = is_file && first_entry.components().count() == 1;
+            original_dest(info, legacy_path, root.as_deref(), first_entry, is_single_file)
+        }
+    }
+}
+
+/// Read the entries at the start of an archive written by this tool (Stopping at its first file)
+fn read_metadata(parts: &[PathBuf], summary: &mut RestoreSummary, metadata: &mut ArchiveMetadata) -> Result<()> {
+    let mut tar = open_archive(parts)?;
+    for entry in tar.entries().context("Failed to read archive")? {
+        let mut entry = entry.context("Failed to read archive entry")?;
+        let path = entry.path().context("Failed to read archive entry path")?.to_path_buf();
+        if !read_metadata_entry(&mut entry, &path, summary, metadata)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Read the info, manifest or path entry into the summary or metadata.
+/// Returns false if `entry` is none of those.
+fn read_metadata_entry(entry: &mut impl Read, path: &Path, summary: &mut RestoreSummary, metadata: &mut ArchiveMetadata) -> Result<bool> {
+    let name = path.to_string_lossy();
+    if ![INFO_FILE, MANIFEST_ENTRY, LEGACY_PATH_FILE].contains(&name.as_ref()) {
+        return Ok(false);
+    }
+    let mut contents = String::new();
+    entry.read_to_string(&mut contents)
+        .context(format!("Failed to read {} entry", name))?;
+    match name.as_ref() {
+        INFO_FILE => summary.info = Some(serde_json::from_str(&contents).context("Failed to parse info entry")?),
+        MANIFEST_ENTRY => metadata.manifest = Some(serde_json::from_str(&contents).context("Failed to parse manifest entry")?),
+        _ => metadata.legacy_path = Some(contents.trim_end().to_string()),
+    }
+    Ok(true)
+}
+
+/// Directory to extract into for RestoreTarget::Original, from the info entry (Or the path entry of older archives)
+/// File segments are extracted into the parent of their path. Older archives don't record whether they are one,
+/// so a first entry that is a file named like the path is taken to be one.
+fn original_dest(info: Option<&ArchiveInfo>, legacy_path: Option<&str>, root: Option<&Path>, first_entry: &Path, is_single_file: bool) -> Result<PathBuf> {
+    let (path, is_file) = match (info, legacy_path) {
+        (Some(info), _) => {
+            let path = match root {
+                Some(root) => root.join(info.path.trim_start_matches('/')),
+                None => info.source_path.clone(),
+            };
+            (path, info.is_file)
+        }
+        (None, Some(legacy_path)) => (root.unwrap_or(Path::new("/")).join(legacy_path.trim_start_matches('/')), None),
+        (None, None) => return Err(anyhow!("Archive has no info entry, so its original path is unknown (Use --to instead)")),
+    };
+    let is_file = is_file.unwrap_or_else(|| is_single_file && path.file_name() == Some(first_entry.as_os_str()));
+    if !is_file {
+        return Ok(path);
+    }
+    path.parent()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| anyhow!("Original path has no parent directory: {:?}", path))
+}
+
+/// Unused path next to `path`, to keep both files (i.e. `a.txt` as `a.restored.txt`, then `a.restored-2.txt`, ...)
+fn keep_both_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+    (1..)
+        .map(|n| {
+            let count = if n == 1 { String::new() } else { format!("-{}", n) };
+            path.with_file_name(format!("{}.restored{}{}", stem, count, extension))
+        })
+        .find(|candidate| fs::symlink_metadata(candidate).is_err())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// True if `path`, or a directory containing it, matches `include` (Always true without patterns)
+fn is_included(include: Option<&GlobSet>, path: &Path) -> bool {
+    let Some(include) = include else {
+        return true;
+    };
+    path.ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .any(|ancestor| include.is_match(ancestor))
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{ArchiveOptions, SplitMode, create_archive};
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/restore_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn extract_all(target: RestoreTarget) -> RestoreOptions {
+        RestoreOptions { target, include: None, on_conflict: ConflictPolicy::Refuse, dry_run: false }
+    }
+
+    #[test]
+    fn test_restore_args_parse() {
+        let parsed = RestoreArgs::parse(&args(&["docs.tar.gz", "--to", "/tmp/out"])).unwrap();
+        assert_eq!(parsed.target(), RestoreTarget::Dir(PathBuf::from("/tmp/out")));
+        assert_eq!(parsed.archive, PathBuf::from("docs.tar.gz"));
+        let parsed = RestoreArgs::parse(&args(&["docs", "--include", "*.txt", "--include", "sub"])).unwrap();
+        assert_eq!(parsed.include, ["*.txt", "sub"]);
+        assert_eq!(RestoreArgs::parse(&args(&["docs"])).unwrap().target(), RestoreTarget::Dir(PathBuf::from(".")));
+        assert_eq!(RestoreArgs::parse(&args(&["docs", "--original"])).unwrap().target(), RestoreTarget::Original(None));
+        assert_eq!(RestoreArgs::parse(&args(&["--original", "docs", "--to", "/mnt"])).unwrap().target(),
+            RestoreTarget::Original(Some(PathBuf::from("/mnt"))));
+
+        assert!(RestoreArgs::parse(&args(&[])).is_err());
+        assert!(RestoreArgs::parse(&args(&["docs", "--to"])).is_err());
+        assert!(RestoreArgs::parse(&args(&["docs", "--include"])).is_err());
+        assert!(RestoreArgs::parse(&args(&["docs", "--dry-run"])).unwrap().dry_run);
+        assert!(RestoreArgs::parse(&args(&["docs", "--bogus"])).is_err());
+        assert!(RestoreArgs::parse(&args(&["docs", "other"])).is_err());
+    }
+
+    #[test]
+    fn test_restore_split_archives() {
+        let test_name = "split";
+        let test_dir = setup_test_dir(test_name);
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(src_dir.join("sub")).unwrap();
+        // (Sized well above the Gzip encoder's internal buffer, which delays the size check)
+        let content: Vec<u8> = (0..100000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(src_dir.join("a.bin"), &content).unwrap();
+        fs::write(src_dir.join("sub/b.txt"), b"hello").unwrap();
+
+        for split_mode in [SplitMode::Stream, SplitMode::Independent] {
+            let archive_path = test_dir.join("docs.tar.gz");
+            let info = ArchiveInfo::new("docs", &src_dir, &None, None).unwrap();
+            // (Uncompressed, so the archive needs several parts)
+            let options = ArchiveOptions { compression_level: Some(0), max_size_bytes: Some(60000), split_mode, ..Default::default() };
+            create_archive(&src_dir, &fs::metadata(&src_dir).unwrap(), &archive_path, &[], &info, &options, None, None).unwrap();
+            assert!(test_dir.join("docs.tar.gz.part002").exists(), "{:?} archive should be split", split_mode);
+
+            let dest = test_dir.join("restored");
+            let _ = fs::remove_dir_all(&dest);
+            let summary = restore_archive(&test_dir.join("docs"), &extract_all(RestoreTarget::Dir(dest.clone()))).unwrap();
+            assert_eq!(summary.info.unwrap().segment, "docs");
+            assert_eq!(fs::read(dest.join("a.bin")).unwrap(), content, "{:?} archive should restore", split_mode);
+            assert_eq!(fs::read(dest.join("sub/b.txt")).unwrap(), b"hello");
+            assert!(!dest.join(INFO_FILE).exists(), "Info entry should not be restored");
+        }
+
+        // Only the included directory is extracted
+        let dest = test_dir.join("selective");
+        let options = RestoreArgs::parse(&args(&["docs", "--to", dest.to_str().unwrap(), "--include", "sub"])).unwrap().options().unwrap();
+        let summary = restore_archive(&test_dir.join("docs"), &options).unwrap();
+        assert_eq!(fs::read(dest.join("sub/b.txt")).unwrap(), b"hello");
+        assert!(!dest.join("a.bin").exists());
+        assert_eq!(summary.skipped, 1);
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_restore_dry_run() {
+        let test_name = "dry_run";
+        let test_dir = setup_test_dir(test_name);
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(&src_dir).unwrap();
+        fs::write(src_dir.join("a.txt"), b"hello").unwrap();
+        fs::write(src_dir.join("b.txt"), b"new").unwrap();
+        let info = ArchiveInfo::new("docs", &src_dir, &None, None).unwrap();
+        create_archive(&src_dir, &fs::metadata(&src_dir).unwrap(), &test_dir.join("docs.tar.gz"), &[], &info, &ArchiveOptions::default(), None, None).unwrap();
+
+        // Nothing is written, and existing files are reported
+        let dest = test_dir.join("restored");
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(dest.join("a.txt"), b"existing").unwrap();
+        let dry_run = |flags: &[&str]| {
+            let options = RestoreArgs::parse(&args(&[&["docs", "--to", dest.to_str().unwrap(), "--dry-run"], flags].concat()))
+                .unwrap().options().unwrap();
+            let summary = restore_archive(&test_dir.join("docs"), &options).unwrap();
+            (summary.entries, summary.bytes, summary.conflicts)
+        };
+        assert_eq!(dry_run(&[]), (1, 3, 1), "Existing files are refused by default");
+        assert_eq!(dry_run(&["--overwrite"]), (2, 8, 1));
+        assert_eq!(fs::read(dest.join("a.txt")).unwrap(), b"existing");
+        assert!(!dest.join("b.txt").exists());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_restore_conflicts() {
+        let test_name = "conflicts";
+        let test_dir = setup_test_dir(test_name);
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(&src_dir).unwrap();
+        fs::write(src_dir.join("a.txt"), b"archived").unwrap();
+        fs::write(src_dir.join("b.txt"), b"new").unwrap();
+        let info = ArchiveInfo::new("docs", &src_dir, &None, None).unwrap();
+        create_archive(&src_dir, &fs::metadata(&src_dir).unwrap(), &test_dir.join("docs.tar.gz"), &[], &info, &ArchiveOptions::default(), None, None).unwrap();
+
+        let dest = test_dir.join("restored");
+        let restore = |flags: &[&str]| {
+            let options = RestoreArgs::parse(&args(&[&["docs", "--to", dest.to_str().unwrap()], flags].concat())).unwrap().options().unwrap();
+            restore_archive(&test_dir.join("docs"), &options)
+        };
+        let reset = || {
+            let _ = fs::remove_dir_all(&dest);
+            fs::create_dir_all(&dest).unwrap();
+            fs::write(dest.join("a.txt"), b"existing").unwrap();
+        };
+
+        reset();
+        assert!(restore(&[]).unwrap_err().to_string().contains("Refusing to overwrite"));
+        assert_eq!(fs::read(dest.join("a.txt")).unwrap(), b"existing");
+
+        // Nothing is written if a later entry conflicts
+        let _ = fs::remove_dir_all(&dest);
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(dest.join("b.txt"), b"existing").unwrap();
+        let error = restore(&[]).unwrap_err().to_string();
+        assert!(error.contains("1 existing path(s)") && error.contains("b.txt"), "{}", error);
+        assert!(!dest.join("a.txt").exists(), "Earlier entries shouldn't be extracted");
+        assert_eq!(fs::read(dest.join("b.txt")).unwrap(), b"existing");
+
+        reset();
+        assert_eq!(restore(&["--skip-existing"]).unwrap().entries, 1);
+        assert_eq!(fs::read(dest.join("a.txt")).unwrap(), b"existing");
+        assert_eq!(fs::read(dest.join("b.txt")).unwrap(), b"new");
+
+        reset();
+        restore(&["--keep-both"]).unwrap();
+        restore(&["--keep-both"]).unwrap();
+        assert_eq!(fs::read(dest.join("a.txt")).unwrap(), b"existing");
+        assert_eq!(fs::read(dest.join("a.restored.txt")).unwrap(), b"archived");
+        assert_eq!(fs::read(dest.join("a.restored-2.txt")).unwrap(), b"archived");
+        assert_eq!(fs::read(dest.join("b.restored.txt")).unwrap(), b"new");
+
+        reset();
+        restore(&["--overwrite"]).unwrap();
+        assert_eq!(fs::read(dest.join("a.txt")).unwrap(), b"archived");
+
+        assert!(RestoreArgs::parse(&args(&["docs", "--overwrite", "--keep-both"])).is_err());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_restore_with_index() {
+        let test_name = "index";
+        let test_dir = setup_test_dir(test_name);
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(src_dir.join("sub")).unwrap();
+        fs::write(src_dir.join("a.bin"), (0..100000u32).map(|i| (i % 251) as u8).collect::<Vec<u8>>()).unwrap();
+        fs::write(src_dir.join("sub/b.txt"), b"hello").unwrap();
+
+        let archive_path = test_dir.join("docs.tar.gz");
+        let info = ArchiveInfo::new("docs", &src_dir, &None, None).unwrap();
+        let options = ArchiveOptions {
+            compression_level: Some(0),
+            max_size_bytes: Some(60000),
+            split_mode: SplitMode::Independent,
+            index: true,
+            ..Default::default()
+        };
+        let summary = create_archive(&src_dir, &fs::metadata(&src_dir).unwrap(), &archive_path, &[], &info, &options, None, None).unwrap();
+        let parts: Vec<PathBuf> = summary.parts.parts.iter().map(|part| part.path.clone()).collect();
+        let index = find_index(&parts).unwrap();
+        let wanted_part = index.entries.iter().find(|entry| entry.path == "sub/b.txt").unwrap().part;
+
+        // Parts without included entries are never read
+        for (_, part) in parts.iter().enumerate().filter(|(i, _)| i + 1 != wanted_part) {
+            fs::write(part, vec![0u8; fs::metadata(part).unwrap().len() as usize]).unwrap();
+        }
+        let dest = test_dir.join("selective");
+        let options = RestoreArgs::parse(&args(&["docs", "--to", dest.to_str().unwrap(), "--include", "sub"])).unwrap().options().unwrap();
+        let summary = restore_archive(&test_dir.join("docs"), &options).unwrap();
+        assert_eq!(fs::read(dest.join("sub/b.txt")).unwrap(), b"hello");
+        assert_eq!((summary.entries, summary.skipped), (1, 1));
+        assert_eq!(summary.info.unwrap().segment, "docs");
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_restore_original() {
+        let test_name = "original";
+        let test_dir = setup_test_dir(test_name);
+        let root = test_dir.join("root");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs/a.txt"), b"a").unwrap();
+        fs::write(root.join("notes.txt"), b"notes").unwrap();
+
+        for (name, source) in [("docs", root.join("docs")), ("notes", root.join("notes.txt"))] {
+            let info = ArchiveInfo::new(name, &source, &Some(root.clone()), None).unwrap();
+            create_archive(&source, &fs::metadata(&source).unwrap(), &test_dir.join(format!("{}.tar.gz", name)),
+                &[], &info, &ArchiveOptions::default(), None, None).unwrap();
+        }
+
+        // Under a new root (Relative to root_path)
+        let new_root = test_dir.join("new_root");
+        let options = extract_all(RestoreTarget::Original(Some(new_root.clone())));
+        let summary = restore_archive(&test_dir.join("docs"), &options).unwrap();
+        assert_eq!(summary.dest, Some(new_root.join("docs")));
+        assert_eq!(fs::read(new_root.join("docs/a.txt")).unwrap(), b"a");
+        restore_archive(&test_dir.join("notes"), &options).unwrap();
+        assert_eq!(fs::read(new_root.join("notes.txt")).unwrap(), b"notes", "File segments restore into their parent");
+
+        // Back to the source path
+        fs::remove_dir_all(&root).unwrap();
+        restore_archive(&test_dir.join("docs"), &extract_all(RestoreTarget::Original(None))).unwrap();
+        assert_eq!(fs::read(root.join("docs/a.txt")).unwrap(), b"a");
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_restore_checks_manifest() {
+        use crate::hasher::{HashOptions, compute_segment_hash};
+
+        let test_name = "manifest";
+        let test_dir = setup_test_dir(test_name);
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(src_dir.join("sub")).unwrap();
+        fs::write(src_dir.join("a.txt"), b"a").unwrap();
+        fs::write(src_dir.join("sub/b.txt"), b"b").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("a.txt", src_dir.join("link")).unwrap();
+        let hashed = compute_segment_hash(&src_dir, &fs::metadata(&src_dir).unwrap(), &[], None, &HashOptions::default(), None).unwrap();
+
+        let archive_path = test_dir.join("docs.tar.gz");
+        let mut info = ArchiveInfo::new("docs", &src_dir, &None, Some(hashed.hash)).unwrap();
+        info.manifest = Some(hashed.files);
+        create_archive(&src_dir, &fs::metadata(&src_dir).unwrap(), &archive_path, &[], &info, &ArchiveOptions::default(), None, None).unwrap();
+
+        let dest = test_dir.join("restored");
+        let summary = restore_archive(&archive_path, &extract_all(RestoreTarget::Dir(dest.clone()))).unwrap();
+        assert_eq!(summary.verified, if cfg!(unix) { 3 } else { 2 });
+        assert!(summary.mismatched.is_empty());
+        assert!(!dest.join(MANIFEST_ENTRY).exists(), "Manifest entry should not be restored");
+
+        // A file that doesn't match its hash is reported
+        info.manifest.as_mut().unwrap().get_mut("a.txt").unwrap().hash = "00".to_string();
+        create_archive(&src_dir, &fs::metadata(&src_dir).unwrap(), &archive_path, &[], &info, &ArchiveOptions::default(), None, None).unwrap();
+        let options = RestoreOptions { on_conflict: ConflictPolicy::Overwrite, ..extract_all(RestoreTarget::Dir(dest.clone())) };
+        let summary = restore_archive(&archive_path, &options).unwrap();
+        assert_eq!(summary.mismatched, [dest.join("a.txt")]);
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_restore_file_segment_with_manifest() {
+        use crate::hasher::{HashOptions, compute_segment_hash};
+
+        let test_name = "file_manifest";
+        let test_dir = setup_test_dir(test_name);
+        let root = test_dir.join("root");
+        fs::create_dir_all(&root).unwrap();
+        let source = root.join("notes.txt");
+        fs::write(&source, b"notes").unwrap();
+        let hashed = compute_segment_hash(&source, &fs::metadata(&source).unwrap(), &[], None, &HashOptions::default(), None).unwrap();
+
+        let mut info = ArchiveInfo::new("notes", &source, &Some(root.clone()), Some(hashed.hash)).unwrap();
+        info.manifest = Some(hashed.files);
+        create_archive(&source, &fs::metadata(&source).unwrap(), &test_dir.join("notes.tar.gz"), &[], &info, &ArchiveOptions::default(), None, None).unwrap();
+
+        // Still restored as a file into its parent, without the manifest entry
+        let new_root = test_dir.join("new_root");
+        let summary = restore_archive(&test_dir.join("notes"), &extract_all(RestoreTarget::Original(Some(new_root.clone())))).unwrap();
+        assert_eq!(summary.dest, Some(new_root.clone()));
+        assert_eq!(summary.verified, 1);
+        assert_eq!(fs::read(new_root.join("notes.txt")).unwrap(), b"notes");
+        let restored: Vec<_> = fs::read_dir(&new_root).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(restored, ["notes.txt"]);
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_original_dest_legacy() {
+        let root = Path::new("/mnt/restore");
+        assert_eq!(original_dest(None, Some("/home/docs"), Some(root), Path::new("a.txt"), true).unwrap(), root.join("home/docs"));
+        // Older file segments are detected from their only entry
+        assert_eq!(original_dest(None, Some("notes.txt"), Some(root), Path::new("notes.txt"), true).unwrap(), root);
+        assert!(original_dest(None, None, Some(root), Path::new("a.txt"), true).is_err());
+    }
+
+    #[test]
+    fn test_is_included() {
+        let include = RestoreArgs::parse(&args(&["archive", "--include", "sub/*.txt", "--include", "docs"])).unwrap()
+            .include_matcher().unwrap();
+        assert!(is_included(None, Path::new("anything")));
+        assert!(is_included(include.as_ref(), Path::new("sub/a.txt")));
+        assert!(is_included(include.as_ref(), Path::new("docs/deep/file.bin")));
+        assert!(!is_included(include.as_ref(), Path::new("sub/a.bin")));
+        assert!(!is_included(include.as_ref(), Path::new("other/docs.txt")));
+    }
+}
@@ -0,0 +1,106 @@
+use std::io;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::warn;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// How failed uploads and post-scripts are retried: exponential backoff from `delay_secs`, capped at `max_delay_secs`
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first (At least 1)
+    pub attempts: u32,
+    /// Wait before the first retry (Doubled after each failure)
+    pub delay_secs: f64,
+    /// Longest wait between attempts
+    pub max_delay_secs: f64,
+    /// Wait a random 50-100% of each delay (So parallel uploads don't retry in lockstep)
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { attempts: 3, delay_secs: 5.0, max_delay_secs: 300.0, jitter: true }
+    }
+}
+
+impl RetryPolicy {
+    /// The same policy with a different number of attempts (If set)
+    pub fn with_attempts(self, attempts: Option<u32>) -> Self {
+        Self { attempts: attempts.unwrap_or(self.attempts), ..self }
+    }
+
+    /// Wait before the retry following failed attempt number `attempt` (Starting from 1), without jitter
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.delay_secs.max(0.0) * 2f64.powi(attempt.saturating_sub(1).min(30) as i32);
+        Duration::from_secs_f64(delay.min(self.max_delay_secs.max(0.0)))
+    }
+
+    /// Call `operation` until it succeeds or every attempt has failed, logging each failure as `action`
+    ///
+    /// # Errors
+    /// Returns the last attempt's error
+    pub fn run<T>(&self, action: &str, mut operation: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let attempts = self.attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            match operation() {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < attempts => {
+                    let delay = match self.jitter {
+                        true => self.delay(attempt).mul_f64(0.5 + random_fraction() / 2.0),
+                        false => self.delay(attempt),
+                    };
+                    warn!("{} failed (Attempt {} of {}), retrying in {:.1}s: {}", action, attempt, attempts, delay.as_secs_f64(), e);
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                Err(e) if attempts > 1 => return Err(io::Error::other(format!("{} failed after {} attempts: {}", action, attempts, e))),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// A value in [0, 1) that differs between calls (Good enough for jitter, not for anything secret)
+fn random_fraction() -> f64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let thread = format!("{:?}", thread::current().id());
+    let seed = xxh3_64(&[nanos.to_le_bytes().as_slice(), thread.as_bytes()].concat());
+    (seed >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy { attempts: 4, delay_secs: 0.001, max_delay_secs: 0.003, jitter: true };
+        assert_eq!(policy.delay(1), Duration::from_millis(1));
+        assert_eq!(policy.delay(2), Duration::from_millis(2));
+        assert_eq!(policy.delay(3), Duration::from_millis(3), "Delays are capped at max_delay_secs");
+
+        // Succeeds on the last attempt
+        let mut calls = 0;
+        let result = policy.run("Test", || {
+            calls += 1;
+            if calls < 4 { Err(io::Error::other("failed")) } else { Ok(calls) }
+        });
+        assert_eq!(result.unwrap(), 4);
+
+        let mut calls = 0;
+        let result: io::Result<()> = policy.with_attempts(Some(2)).run("Test", || {
+            calls += 1;
+            Err(io::Error::other("failed"))
+        });
+        assert_eq!(calls, 2);
+        assert_eq!(result.unwrap_err().to_string(), "Test failed after 2 attempts: failed");
+
+        let policy: RetryPolicy = toml::from_str("attempts = 5").unwrap();
+        assert_eq!(policy, RetryPolicy { attempts: 5, ..RetryPolicy::default() });
+        assert!((0.0..1.0).contains(&random_fraction()));
+    }
+}
@@ -1,20 +1,136 @@
-use std::io::{self, Write, ErrorKind};
-use std::fs::{File, rename};
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::collections::HashSet;
+use std::fs::{self, File, rename};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use log::{info};
+use crate::parity::{PARITY_EXT, ParityWriter, parity_path};
+use crate::index::INDEX_EXT;
+use crate::digest::{Digest, DigestAlgorithm};
+use crate::disk_space::available_space;
+use crate::throttle::Throttle;
+use crate::progress::Progress;
+use crate::sink::{ArchiveSink, LocalSink, SinkPart};
+
+/// Callback invoked with each finalized part
+type RolloverListener = Box<dyn Fn(&FinishedPart) -> io::Result<i32>>;
+
+/// A finalized output file, as passed to the listener
+#[derive(Debug, Clone, PartialEq)]
+pub struct FinishedPart {
+    pub path: String,
+    /// Number of the part, from 1 (None for the parity file and part list)
+    pub index: Option<u32>,
+    /// Total number of parts (Only known once the last part is finalized)
+    pub count: Option<u32>,
+}
+
+impl FinishedPart {
+    pub fn new(path: impl Into<String>, index: Option<u32>, count: Option<u32>) -> Self {
+        Self { path: path.into(), index, count }
+    }
+}
+
+/// A finalized output file
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PartInfo {
+    /// File name (without directory)
+    pub name: String,
+    /// Full path (Not stored, since archives may be moved after creation)
+    #[serde(skip)]
+    pub path: PathBuf,
+    pub size: u64,
+    /// Hex digest of the part contents (If checksums are enabled)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+}
+
+/// Every part emitted by a RollingWriter, returned by finalize
+#[derive(Debug, Default, Clone)]
+pub struct PartsSummary {
+    pub parts: Vec<PartInfo>,
+}
+
+impl PartsSummary {
+    pub fn count(&self) -> usize {
+        self.parts.len()
+    }
+
+    /// Combined size of all parts in bytes
+    pub fn total_size(&self) -> u64 {
+        self.parts.iter().map(|part| part.size).sum()
+    }
+}
+
+/// Files created on disk by a RollingWriter (Kept after the writer is dropped,
+/// so a failed archive can remove its own output without touching older archives)
+#[derive(Debug, Default)]
+pub struct CreatedFiles(Mutex<Vec<PathBuf>>);
+
+impl CreatedFiles {
+    pub fn add(&self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        let mut paths = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+
+    /// Every file recorded so far (In the order they were created)
+    pub fn take(&self) -> Vec<PathBuf> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// Extension appended to base_path for the listing of a split archive's parts
+pub const PART_LIST_EXT: &str = "parts";
+
+/// Extension appended to each file while it is written (Removed once the archive is finalized)
+pub const PARTIAL_EXT: &str = "partial";
 
 /// A custom writer that wraps a file handle and manages rolling over to a new file.
 /// 
 /// NOTE: 'base_path' will be appended with .part###
+/// 
+/// Files on disk are written with a `.partial` suffix, and only renamed into place once the whole
+/// archive is finalized (So a failed run never overwrites files of the previous archive).
 pub struct RollingWriter {
-    current_file: Option<File>,
+    current_part: Option<Box<dyn SinkPart>>,
     current_path: Option<String>,
     current_size: usize,
     /// If None, all data is written to a single file without part numbering.
     max_size: Option<usize>,
     base_path: PathBuf,
     part_counter: u32,
-    rollover_listener: Option<Box<dyn Fn(&String) -> io::Result<i32>>>,
+    rollover_listener: Option<RolloverListener>,
+    /// If set, an XOR parity block is built over all parts as they are written.
+    parity: Option<ParityWriter>,
+    /// Parts that have been finalized so far
+    parts: Vec<PartInfo>,
+    /// If true, parts are only rolled over by start_new_part (max_size is not enforced)
+    manual_rollover: bool,
+    /// If set, opening more than this many parts is an error
+    max_parts: Option<u32>,
+    /// If true, each part is synced to disk before the listener is called
+    sync_parts: bool,
+    /// If set, a running checksum of each part is computed as it is written
+    checksum: Option<Digest>,
+    /// Where each part is written
+    sink: Arc<dyn ArchiveSink>,
+    /// If set, writing more than this many bytes to a part is an error (Instead of rolling over)
+    size_limit: Option<usize>,
+    /// If set, opening a part fails unless the destination has this many bytes free
+    min_free_space: Option<u64>,
+    /// If set, writes are slowed to a maximum rate
+    throttle: Option<Throttle>,
+    /// If true, a listing of all parts is written once a split archive is finalized
+    part_list: bool,
+    /// If set, the bytes written and current part are counted here
+    progress: Option<Arc<Progress>>,
+    /// If set, every file created on disk is recorded here
+    created: Option<Arc<CreatedFiles>>,
+    /// Files written under a temporary name, and the path each is renamed to once the archive is finalized
+    staged: Vec<(PathBuf, PathBuf)>,
 }
 
 impl RollingWriter {
@@ -23,49 +139,262 @@ impl RollingWriter {
     /// # Arguments
     /// * `base_path` - Base path for the output file(s)
     /// * `max_size` - Maximum size per part file in bytes. Must be >= 1 if Some.
-    ///                If None, all data is written to a single file.
+    ///   If None, all data is written to a single file.
     /// 
     /// # Errors
     /// Returns an error if `max_size` is `Some(0)` (must be at least 1 byte)
     pub fn new(base_path: PathBuf, max_size: Option<usize>) -> io::Result<Self> {
-        if let Some(size) = max_size {
-            if size == 0 {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "max_size must be at least 1 byte: 0"
-                ));
-            }
+        Self::build(base_path, max_size, Arc::new(LocalSink), Vec::new())
+    }
+
+    /// Create a writer that writes each part to `sink`
+    /// 
+    /// Unless the sink is local, parts are never renamed, stale parts are not checked and the listener
+    /// is only called for files written to disk (i.e. the parity file).
+    pub fn with_sink(base_path: PathBuf, max_size: Option<usize>, sink: Arc<dyn ArchiveSink>) -> io::Result<Self> {
+        Self::build(base_path, max_size, sink, Vec::new())
+    }
+
+    /// Continue a multi-part archive after `completed_parts` (Left by an interrupted run),
+    /// starting with the next part number
+    /// 
+    /// # Errors
+    /// Returns an error if `max_size` is None (single files cannot be continued)
+    pub fn resume(base_path: PathBuf, max_size: Option<usize>, mut completed_parts: Vec<PartInfo>) -> io::Result<Self> {
+        if max_size.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot resume an archive without max_size"
+            ));
+        }
+        let dir = output_dir(&base_path);
+        for part in completed_parts.iter_mut() {
+            part.path = dir.join(&part.name);
+        }
+        Self::build(base_path, max_size, Arc::new(LocalSink), completed_parts)
+    }
+
+    fn build(base_path: PathBuf, max_size: Option<usize>, sink: Arc<dyn ArchiveSink>, completed_parts: Vec<PartInfo>) -> io::Result<Self> {
+        if let Some(size) = max_size
+            && size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "max_size must be at least 1 byte: 0"
+            ));
         }
         
+        let staged = completed_parts.iter()
+            .map(|part| (partial_path(&part.path), part.path.clone()))
+            .collect();
         let mut writer = Self {
-            current_file: None,
+            current_part: None,
             current_path: None,
             current_size: 0,
             max_size,
             base_path,
-            part_counter: 0,
+            part_counter: completed_parts.len() as u32,
             rollover_listener: None,
+            parity: None,
+            parts: completed_parts,
+            manual_rollover: false,
+            max_parts: None,
+            sync_parts: false,
+            checksum: None,
+            sink,
+            size_limit: None,
+            min_free_space: None,
+            throttle: None,
+            part_list: false,
+            progress: None,
+            created: None,
+            staged,
         };
         writer.open_new_part()?;
         Ok(writer)
     }
 
-    /// Set a callback function to be called whenever a part is finalized
+    /// Set a callback function to be called with each file once the archive is finalized (And its files are in place)
     pub fn set_listener<F>(&mut self, callback: F)
-    where F: Fn(&String) -> io::Result<i32> + 'static {
+    where F: Fn(&FinishedPart) -> io::Result<i32> + 'static {
         self.rollover_listener = Some(Box::new(callback));
     }
 
-    /// Close out any open file part
-    pub fn finalize(&mut self) -> io::Result<()> {
-        self.finalize_current(true)
+    /// Build a parity file (`base_path.parity`) alongside the parts.
+    /// Must be called before any data is written (Parts of a resumed archive are re-read).
+    /// 
+    /// # Errors
+    /// Returns an error if `max_size` is None (a single file has nothing to rebuild from)
+    pub fn enable_parity(&mut self) -> io::Result<()> {
+        if self.max_size.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Parity requires max_size to be set"
+            ));
+        }
+        let path = partial_path(&parity_path(&self.base_path));
+        self.record_created(&path);
+        let mut parity = ParityWriter::create(&path)?;
+        for part in &self.parts {
+            let data = fs::read(partial_path(&part.path))?;
+            parity.update(0, &data)?;
+            parity.finish_part(&part.name);
+        }
+        self.parity = Some(parity);
+        Ok(())
+    }
+
+    /// Only roll over to a new part when start_new_part is called
+    /// (Used to split at boundaries chosen by the caller)
+    pub fn set_manual_rollover(&mut self) {
+        self.manual_rollover = true;
+    }
+
+    /// Abort with an error instead of opening more than `max_parts` parts
+    /// (Guards against filling the destination if a segment grows unexpectedly)
+    pub fn set_max_parts(&mut self, max_parts: u32) {
+        self.max_parts = Some(max_parts);
+    }
+
+    /// Sync each part (and the parity file) to disk before it is renamed into place
+    pub fn set_sync_parts(&mut self) {
+        self.sync_parts = true;
+    }
+
+    /// Write `base_path.parts` listing every part (`<size> <name>` per line) when a split archive is finalized
+    /// (Lets the restore side detect missing parts)
+    pub fn set_part_list(&mut self) {
+        self.part_list = true;
+    }
+
+    /// Fail any write that would grow a part past `size_limit` bytes
+    /// (For destinations that cannot accept multi-part archives)
+    pub fn set_size_limit(&mut self, size_limit: usize) {
+        self.size_limit = Some(size_limit);
+    }
+
+    /// Require at least `min_free_space` bytes free at the destination before opening each new part.
+    /// The (already open) first part is checked immediately.
+    pub fn set_min_free_space(&mut self, min_free_space: u64) -> io::Result<()> {
+        self.min_free_space = Some(min_free_space);
+        self.check_free_space()
+    }
+
+    /// Limit writes to `bytes_per_sec` (So backups don't saturate a shared or slow device)
+    pub fn set_max_write_rate(&mut self, bytes_per_sec: u64) {
+        self.throttle = Some(Throttle::new(bytes_per_sec));
+    }
+
+    /// Count the bytes written, and the part being written, in `progress`
+    pub fn set_progress(&mut self, progress: Arc<Progress>) {
+        progress.set_part(self.part_counter.max(1));
+        self.progress = Some(progress);
+    }
+
+    /// Record every file created on disk in `created` (Starting with the part already open)
+    pub fn set_created_files(&mut self, created: Arc<CreatedFiles>) {
+        if self.sink.is_local()
+            && let Some(path) = &self.current_path {
+            created.add(partial_path(Path::new(path)));
+        }
+        self.created = Some(created);
+    }
+
+    /// Checksum each part as it is written (Reported in the finalize summary).
+    /// Must be called before any data is written.
+    pub fn set_checksum(&mut self, algorithm: DigestAlgorithm) {
+        self.checksum = Some(Digest::new(algorithm));
+    }
+
+    /// Finalize the current part and open the next one
+    /// 
+    /// # Errors
+    /// Returns an error if `max_size` is None (parts are not numbered in single-file mode)
+    pub fn start_new_part(&mut self) -> io::Result<()> {
+        if self.max_size.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot start a new part without max_size"
+            ));
+        }
+        self.open_new_part()
+    }
+
+    /// Number of bytes written to the current part
+    pub fn current_size(&self) -> usize {
+        self.current_size
+    }
+
+    /// Parts that have been finalized so far
+    pub fn completed_parts(&self) -> &[PartInfo] {
+        &self.parts
+    }
+
+    /// Close out any open file part, rename every file into place, then remove stale parts left by previous runs
+    /// and pass each file to the listener
+    /// 
+    /// Returns a summary of every part written
+    pub fn finalize(&mut self) -> io::Result<PartsSummary> {
+        self.finalize_current(true)?;
+
+        // Parity is only useful if the archive was split
+        let mut extra_paths = Vec::new();
+        if let Some(parity) = self.parity.take() {
+            if parity.part_count() > 1 {
+                let path = parity_path(&self.base_path);
+                self.staged.push((parity.finalize(self.sync_parts)?, path.clone()));
+                info!("Created parity file: {:?}", path);
+                extra_paths.push(path);
+            } else {
+                parity.discard()?;
+            }
+        }
+
+        if self.part_list && self.parts.len() > 1 {
+            let list_path = self.write_part_list()?;
+            info!("Created part list: {:?}", list_path);
+            extra_paths.push(list_path);
+        }
+
+        // Only replace the previous archive once every file is complete
+        for (temp_path, path) in std::mem::take(&mut self.staged) {
+            rename(&temp_path, &path)?;
+        }
+        if self.sink.is_local() {
+            let extra_names = extra_paths.iter()
+                .filter_map(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+                .collect();
+            self.remove_stale_parts(extra_names)?;
+        }
+
+        // Pass each file on disk to the callback, with the count on the last part (Piped parts are not on disk)
+        if let Some(callback) = &self.rollover_listener {
+            let count = self.parts.len() as u32;
+            if self.sink.is_local() {
+                for (index, part) in (1..).zip(&self.parts) {
+                    callback(&FinishedPart::new(part.path.display().to_string(), Some(index), (index == count).then_some(count)))?;
+                }
+            }
+            for path in &extra_paths {
+                callback(&FinishedPart::new(path.display().to_string(), None, Some(count)))?;
+            }
+        }
+        Ok(PartsSummary { parts: std::mem::take(&mut self.parts) })
     }
 
     // --- Private methods --- //
 
     fn open_new_part(&mut self) -> io::Result<()> {
-        // Close any open file
+        // Stop before exceeding the part limit
+        if let Some(max_parts) = self.max_parts
+            && self.part_counter >= max_parts {
+            return Err(io::Error::other(format!(
+                "Exceeded max_parts ({}) for {:?}", max_parts, self.base_path
+            )));
+        }
+
+        // Close any open file (Checking free space afterwards, since the listener may remove it)
         self.finalize_current(false)?;
+        self.check_free_space()?;
         
         // Increment part number if max_size is set
         let filename = match self.max_size {
@@ -76,51 +405,173 @@ impl RollingWriter {
             }
             None => {
                 // Single-file mode: use base path directly
-                if self.current_file.is_some() {
+                if self.current_part.is_some() {
                     // This is impossible to reach as long as max_size is immutable
-                    return Err(io::Error::new(
-                        ErrorKind::Other,
+                    return Err(io::Error::other(
                         "RollingWriter internal error: attempted to open new part in single-file mode with existing file"
                     ));
                 }
                 self.base_path.display().to_string()
             }
         };
-        self.current_path = Some(filename.to_owned());
-        
-        info!("Opening new file part: {:?}", filename);
-        let new_file = File::create(filename)?;
-        self.current_file = Some(new_file);
+        let path = match self.sink.is_local() {
+            true => partial_path(Path::new(&filename)),
+            false => PathBuf::from(&filename),
+        };
+        if self.sink.is_local() {
+            self.record_created(&path);
+        }
+        self.current_part = Some(self.sink.open_part(&path)?);
+        self.current_path = Some(filename);
         self.current_size = 0;
+        if let Some(progress) = &self.progress {
+            progress.set_part(self.part_counter.max(1));
+        }
         Ok(())
     }
 
     fn finalize_current(&mut self, is_final: bool) -> io::Result<()> {
-        if let Some(mut file) = self.current_file.take() {
-            file.flush()?;
-
-            // If there is only 1 part, rename the file to match base_path
-            if is_final && self.part_counter == 1 {
-                if let Some(filename) = self.current_path.take() {
-                    info!("Renaming single part file to {:?}", self.base_path);
-                    rename(&filename, &self.base_path)?;
-                    self.current_path = Some(self.base_path.display().to_string());
+        if let Some(part) = self.current_part.take() {
+            part.finish(self.sync_parts)?;
+
+            if let Some(filename) = self.current_path.take() {
+                // Files on disk are renamed into place once the archive is finalized
+                // (If there is only 1 part, it is renamed to match base_path)
+                let mut path = PathBuf::from(&filename);
+                if self.sink.is_local() {
+                    if is_final && self.part_counter == 1 {
+                        info!("Renaming single part file to {:?}", self.base_path);
+                        path = self.base_path.clone();
+                    }
+                    self.staged.push((partial_path(Path::new(&filename)), path.clone()));
                 }
-            }
-            
-            // If a callback is set, call it passing the filename
-            if let Some(callback) = &self.rollover_listener {
-                if let Some(filename) = &self.current_path {
-                    callback(filename)?;
+
+                // Record the completed part
+                let name = path.file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| filename.to_owned());
+                if let Some(parity) = self.parity.as_mut() {
+                    parity.finish_part(&name);
                 }
+                let checksum = self.checksum.as_mut().map(|digest| digest.finish_hex());
+                self.parts.push(PartInfo { name, path, size: self.current_size as u64, checksum });
+            }
+        }
+        Ok(())
+    }
+
+    fn record_created(&self, path: impl Into<PathBuf>) {
+        if let Some(created) = &self.created {
+            created.add(path);
+        }
+    }
+
+    fn check_free_space(&self) -> io::Result<()> {
+        let Some(required) = self.min_free_space else {
+            return Ok(());
+        };
+        if !self.sink.is_local() {
+            return Ok(()); // Parts are not stored locally
+        }
+
+        let dir = output_dir(&self.base_path);
+        if let Some(available) = available_space(&dir)?
+            && available < required {
+            return Err(io::Error::new(io::ErrorKind::StorageFull, format!(
+                "Not enough free space for the next part of {:?}: {} bytes available in {:?}, {} bytes required",
+                self.base_path, available, dir, required
+            )));
+        }
+        Ok(())
+    }
+
+    /// Write the listing of every part (In order), returning its path (Once it is renamed into place)
+    fn write_part_list(&mut self) -> io::Result<PathBuf> {
+        let path = PathBuf::from(format!("{}.{}", self.base_path.display(), PART_LIST_EXT));
+        let temp_path = partial_path(&path);
+        self.record_created(&temp_path);
+        let contents: String = self.parts.iter()
+            .map(|part| format!("{} {}\n", part.size, part.name))
+            .collect();
+        let mut file = File::create(&temp_path)?;
+        file.write_all(contents.as_bytes())?;
+        if self.sync_parts {
+            file.sync_all()?;
+        }
+        self.staged.push((temp_path, path.clone()));
+        Ok(path)
+    }
+
+    /// Delete files sharing base_path's name (single file, .part###, .parity, .parts, .index.json) that were not written by this run,
+    /// so leftover parts from a previous (larger) archive cannot corrupt reassembly
+    fn remove_stale_parts(&self, extra_names: Vec<String>) -> io::Result<()> {
+        let mut current: HashSet<String> = self.parts.iter().map(|part| part.name.to_owned()).collect();
+        current.extend(extra_names);
+
+        for path in list_outputs(&self.base_path)? {
+            let is_current = path.file_name()
+                .is_some_and(|name| current.contains(name.to_string_lossy().as_ref()));
+            if !is_current {
+                info!("Removing stale part from a previous run: {:?}", path);
+                fs::remove_file(path)?;
             }
         }
         Ok(())
     }
 }
 
+/// List every existing file a RollingWriter could write for `base_path` (single file, .part###, .parity, .parts, .index.json)
+pub fn list_outputs(base_path: &Path) -> io::Result<Vec<PathBuf>> {
+    let Some(base_name) = base_path.file_name().map(|name| name.to_string_lossy().to_string()) else {
+        return Ok(Vec::new());
+    };
+    let dir = output_dir(base_path);
+
+    let mut outputs = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if is_output_name(&name, &base_name) {
+            outputs.push(dir.join(name));
+        }
+    }
+    outputs.sort();
+    Ok(outputs)
+}
+
+/// Temporary path that a file is written to, until the archive is finalized
+pub fn partial_path(path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.display(), PARTIAL_EXT))
+}
+
+/// Directory that parts of `base_path` are written to
+fn output_dir(base_path: &Path) -> PathBuf {
+    match base_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// True if `name` is a file RollingWriter could write for `base_name`
+fn is_output_name(name: &str, base_name: &str) -> bool {
+    let Some(suffix) = name.strip_prefix(base_name) else {
+        return false;
+    };
+    if suffix.is_empty() || [PARITY_EXT, PART_LIST_EXT, INDEX_EXT].iter().any(|ext| suffix == format!(".{}", ext)) {
+        return true;
+    }
+    suffix.strip_prefix(".part")
+        .is_some_and(|num| !num.is_empty() && num.chars().all(|c| c.is_ascii_digit()))
+}
+
 impl Write for RollingWriter {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(size_limit) = self.size_limit
+            && self.current_size + buf.len() > size_limit {
+            return Err(io::Error::other(format!(
+                "Archive exceeds the size limit of {} bytes: {:?}", size_limit, self.base_path
+            )));
+        }
+
         let mut bytes_written = 0usize;
         let mut bytes_remaining = buf.len();
 
@@ -128,20 +579,35 @@ impl Write for RollingWriter {
             // Calculate number of bytes to write
             let write_len = match self.max_size {
                 None => bytes_remaining, /* Ignore rollover if max_size is not set */
+                Some(_) if self.manual_rollover => bytes_remaining,
                 Some(max_size) => std::cmp::min(max_size - self.current_size, bytes_remaining),
             };
 
             // Write next block of data
             let next_write = &buf[bytes_written..(bytes_written + write_len)];
-            let written = self.current_file.as_mut()
-                .ok_or_else(|| io::Error::new(ErrorKind::Other, "No file handle available"))?
+            if let Some(throttle) = self.throttle.as_mut() {
+                throttle.consume(write_len);
+            }
+            let written = self.current_part.as_mut()
+                .ok_or_else(|| io::Error::other("No file handle available"))?
                 .write(next_write)?;
             if written != write_len {
-                return Err(io::Error::new(ErrorKind::Other, format!(
+                return Err(io::Error::other(format!(
                     "Unexpected write-size mismatch. Expected: {}, Returned: {}", write_len, written
                 )))
             }
 
+            // Fold the block into the parity file and checksum
+            if let Some(parity) = self.parity.as_mut() {
+                parity.update(self.current_size as u64, next_write)?;
+            }
+            if let Some(digest) = self.checksum.as_mut() {
+                digest.update(next_write);
+            }
+            if let Some(progress) = &self.progress {
+                progress.add_written(written as u64);
+            }
+
             // Update counters
             self.current_size += written;
             bytes_written += written;
@@ -157,19 +623,20 @@ impl Write for RollingWriter {
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        if let Some(file) = self.current_file.as_mut() {
-            file.flush()?;
+        if let Some(part) = self.current_part.as_mut() {
+            part.flush()?;
         }
         Ok(())
     }
 }
 
 
-/// --- Tests --- ///
+// --- Tests --- //
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sink::CommandSink;
     use std::fs;
     use std::io::Read;
 
@@ -183,7 +650,7 @@ mod tests {
 
     fn setup_test_dir(test_name: &str) {
         cleanup_test_dir(test_name);
-        fs::create_dir_all(&get_test_dir(test_name)).unwrap();
+        fs::create_dir_all(get_test_dir(test_name)).unwrap();
     }
 
     #[test]
@@ -319,8 +786,8 @@ mod tests {
         use std::sync::{Arc, Mutex};
         let callback_calls = Arc::new(Mutex::new(Vec::new()));
         let callback_calls_clone = callback_calls.clone();
-        writer.set_listener(move |filename| {
-            callback_calls_clone.lock().unwrap().push(filename.clone());
+        writer.set_listener(move |part| {
+            callback_calls_clone.lock().unwrap().push(part.clone());
             Ok(0)
         });
         
@@ -329,9 +796,11 @@ mod tests {
         writer.write_all(&data).unwrap();
         writer.finalize().unwrap();
         
-        // Callback should be called for each finalized part
+        // Callback should be called for each finalized part (With the count once it's known)
         let calls = callback_calls.lock().unwrap();
         assert_eq!(calls.len(), 3); // part001, part002, part003
+        assert_eq!(calls[0], FinishedPart::new(format!("{}.part001", base_path.display()), Some(1), None));
+        assert_eq!(calls[2], FinishedPart::new(format!("{}.part003", base_path.display()), Some(3), Some(3)));
         
         cleanup_test_dir(test_name);
     }
@@ -364,9 +833,9 @@ mod tests {
         let mut writer = RollingWriter::new(base_path.clone(), Some(max_size)).unwrap();
         
         // Write in multiple chunks
-        writer.write_all(&vec![0u8; 30]).unwrap();
-        writer.write_all(&vec![1u8; 30]).unwrap();
-        writer.write_all(&vec![2u8; 30]).unwrap();
+        writer.write_all(&[0u8; 30]).unwrap();
+        writer.write_all(&[1u8; 30]).unwrap();
+        writer.write_all(&[2u8; 30]).unwrap();
         writer.finalize().unwrap();
         
         // Should create 2 parts (30 + 30 + 30 = 90, but first part gets 50, second gets 40)
@@ -376,6 +845,327 @@ mod tests {
         cleanup_test_dir(test_name);
     }
 
+    #[test]
+    fn test_rolling_writer_records_parts() {
+        let test_name = "records_parts";
+        setup_test_dir(test_name);
+        
+        let base_path = get_test_dir(test_name).join("test.tar.gz");
+        let mut writer = RollingWriter::new(base_path.clone(), Some(50)).unwrap();
+        writer.write_all(&[0u8; 120]).unwrap();
+        let summary = writer.finalize().unwrap();
+        
+        assert_eq!(summary.count(), 3);
+        assert_eq!(summary.total_size(), 120);
+        let parts = &summary.parts;
+        assert_eq!(parts[0].path, get_test_dir(test_name).join("test.tar.gz.part001"));
+        assert_eq!(parts[0].name, "test.tar.gz.part001");
+        assert_eq!(parts[0].size, 50);
+        assert_eq!(parts[2].name, "test.tar.gz.part003");
+        assert_eq!(parts[2].size, 20);
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rolling_writer_checksums() {
+        let test_name = "checksums";
+        setup_test_dir(test_name);
+        
+        let base_path = get_test_dir(test_name).join("test.tar.gz");
+        let mut writer = RollingWriter::new(base_path.clone(), Some(50)).unwrap();
+        writer.set_checksum(DigestAlgorithm::Sha256);
+        let data: Vec<u8> = (0..120u8).collect();
+        writer.write_all(&data).unwrap();
+        let summary = writer.finalize().unwrap();
+        
+        // Each checksum should match a separate read of its part
+        assert_eq!(summary.count(), 3);
+        for part in &summary.parts {
+            let mut digest = Digest::new(DigestAlgorithm::Sha256);
+            digest.update(&fs::read(&part.path).unwrap());
+            assert_eq!(part.checksum, Some(digest.finish_hex()), "Checksum mismatch for {}", part.name);
+        }
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rolling_writer_manual_rollover() {
+        let test_name = "manual_rollover";
+        setup_test_dir(test_name);
+        
+        let base_path = get_test_dir(test_name).join("test.tar.gz");
+        let mut writer = RollingWriter::new(base_path.clone(), Some(50)).unwrap();
+        writer.set_manual_rollover();
+        
+        // Writes past max_size should stay in the current part
+        writer.write_all(&[0u8; 120]).unwrap();
+        assert_eq!(writer.current_size(), 120);
+        writer.start_new_part().unwrap();
+        writer.write_all(&[1u8; 10]).unwrap();
+        let parts = writer.finalize().unwrap().parts;
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].size, 120);
+        assert_eq!(parts[1].size, 10);
+        assert!(get_test_dir(test_name).join("test.tar.gz.part002").exists());
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rolling_writer_start_new_part_requires_max_size() {
+        let test_name = "new_part_no_max_size";
+        setup_test_dir(test_name);
+        
+        let base_path = get_test_dir(test_name).join("test.tar.gz");
+        let mut writer = RollingWriter::new(base_path.clone(), None).unwrap();
+        
+        let result = writer.start_new_part();
+        assert!(result.is_err(), "Starting a new part without max_size should return error");
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rolling_writer_sync_parts() {
+        let test_name = "sync_parts";
+        setup_test_dir(test_name);
+
+        let base_path = get_test_dir(test_name).join("test.tar.gz");
+        let mut writer = RollingWriter::new(base_path.clone(), Some(10)).unwrap();
+        writer.set_sync_parts();
+
+        // Listener should see each part fully written
+        writer.set_listener(|part| {
+            assert_eq!(fs::metadata(&part.path)?.len(), 10);
+            Ok(0)
+        });
+        writer.write_all(&[1u8; 20]).unwrap();
+        assert_eq!(writer.finalize().unwrap().count(), 2);
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rolling_writer_part_list() {
+        let test_name = "part_list";
+        setup_test_dir(test_name);
+
+        let base_path = get_test_dir(test_name).join("test.tar.gz");
+        let mut writer = RollingWriter::new(base_path.clone(), Some(10)).unwrap();
+        writer.set_part_list();
+        writer.write_all(&[1u8; 25]).unwrap();
+        assert_eq!(writer.finalize().unwrap().count(), 3);
+
+        let list = fs::read_to_string(get_test_dir(test_name).join("test.tar.gz.parts")).unwrap();
+        assert_eq!(list, "10 test.tar.gz.part001\n10 test.tar.gz.part002\n5 test.tar.gz.part003\n");
+
+        // A single file has nothing to list (And the old listing is stale)
+        let mut writer = RollingWriter::new(base_path.clone(), Some(10)).unwrap();
+        writer.set_part_list();
+        writer.write_all(&[1u8; 5]).unwrap();
+        writer.finalize().unwrap();
+        assert!(!get_test_dir(test_name).join("test.tar.gz.parts").exists());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rolling_writer_removes_stale_parts() {
+        let test_name = "stale_parts";
+        setup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+
+        // Leftovers from previous runs (plus unrelated files that must be kept)
+        for name in ["test.tar.gz", "test.tar.gz.part003", "test.tar.gz.part004", "test.tar.gz.parity", "test.tar.gz.partial", "other.tar.gz.part001"] {
+            fs::write(test_dir.join(name), b"old").unwrap();
+        }
+
+        let base_path = test_dir.join("test.tar.gz");
+        let mut writer = RollingWriter::new(base_path.clone(), Some(10)).unwrap();
+        writer.write_all(&[0u8; 15]).unwrap();
+        writer.finalize().unwrap();
+
+        assert!(test_dir.join("test.tar.gz.part001").exists());
+        assert!(test_dir.join("test.tar.gz.part002").exists());
+        assert!(!test_dir.join("test.tar.gz.part003").exists());
+        assert!(!test_dir.join("test.tar.gz.part004").exists());
+        assert!(!test_dir.join("test.tar.gz.parity").exists());
+        assert!(!base_path.exists());
+        assert!(test_dir.join("test.tar.gz.partial").exists());
+        assert!(test_dir.join("other.tar.gz.part001").exists());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rolling_writer_removes_stale_parts_single_file() {
+        let test_name = "stale_parts_single";
+        setup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+
+        for name in ["test.tar.gz.part001", "test.tar.gz.part002"] {
+            fs::write(test_dir.join(name), b"old").unwrap();
+        }
+
+        let base_path = test_dir.join("test.tar.gz");
+        let mut writer = RollingWriter::new(base_path.clone(), Some(100)).unwrap();
+        writer.write_all(b"new").unwrap();
+        writer.finalize().unwrap();
+
+        // Only the renamed single file should remain
+        assert_eq!(fs::read(&base_path).unwrap(), b"new");
+        assert!(!test_dir.join("test.tar.gz.part001").exists());
+        assert!(!test_dir.join("test.tar.gz.part002").exists());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rolling_writer_temporary_names() {
+        let test_name = "temporary_names";
+        setup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+
+        // The previous archive is untouched until the new one is finalized
+        let base_path = test_dir.join("test.tar.gz");
+        fs::write(&base_path, b"old").unwrap();
+        let mut writer = RollingWriter::new(base_path.clone(), None).unwrap();
+        writer.write_all(b"new").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(fs::read(&base_path).unwrap(), b"old");
+        assert_eq!(fs::read(partial_path(&base_path)).unwrap(), b"new");
+
+        writer.finalize().unwrap();
+        assert_eq!(fs::read(&base_path).unwrap(), b"new");
+        assert!(!partial_path(&base_path).exists());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rolling_writer_piped_parts() {
+        let test_name = "piped_parts";
+        setup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+
+        // Each part is piped into a separate "uploaded" file
+        let base_path = test_dir.join("test.tar.gz");
+        let command = format!("cat > {}/uploaded_{{name}}", test_dir.display());
+        let mut writer = RollingWriter::with_sink(base_path.clone(), Some(50), Arc::new(CommandSink::new(command))).unwrap();
+        let data: Vec<u8> = (0..120u8).collect();
+        writer.write_all(&data).unwrap();
+        let summary = writer.finalize().unwrap();
+
+        assert_eq!(summary.count(), 3);
+        assert_eq!(summary.parts[0].name, "test.tar.gz.part001");
+        assert_eq!(fs::read(test_dir.join("uploaded_test.tar.gz.part001")).unwrap(), &data[..50]);
+        assert_eq!(fs::read(test_dir.join("uploaded_test.tar.gz.part003")).unwrap(), &data[100..]);
+
+        // Nothing should be written to base_path
+        assert!(!base_path.exists());
+        assert!(!test_dir.join("test.tar.gz.part001").exists());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rolling_writer_piped_command_failure() {
+        let test_name = "piped_failure";
+        setup_test_dir(test_name);
+
+        let base_path = get_test_dir(test_name).join("test.tar.gz");
+        let mut writer = RollingWriter::with_sink(base_path, None, Arc::new(CommandSink::new("cat > /dev/null; exit 3"))).unwrap();
+        writer.write_all(b"data").unwrap();
+        
+        let result = writer.finalize();
+        assert!(result.is_err(), "Failing part command should return error");
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rolling_writer_size_limit() {
+        let test_name = "size_limit";
+        setup_test_dir(test_name);
+
+        let base_path = get_test_dir(test_name).join("test.tar.gz");
+        let mut writer = RollingWriter::new(base_path.clone(), None).unwrap();
+        writer.set_size_limit(10);
+
+        // Writing up to the limit is allowed
+        writer.write_all(&[0u8; 10]).unwrap();
+
+        let result = writer.write_all(&[0u8; 1]);
+        assert!(result.is_err(), "Writing past the size limit should return error");
+        assert!(result.unwrap_err().to_string().contains("size limit"));
+        assert_eq!(fs::metadata(partial_path(&base_path)).unwrap().len(), 10);
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_rolling_writer_min_free_space() {
+        let test_name = "min_free_space";
+        setup_test_dir(test_name);
+
+        let base_path = get_test_dir(test_name).join("test.tar.gz");
+        let mut writer = RollingWriter::new(base_path.clone(), Some(10)).unwrap();
+        writer.set_min_free_space(1).unwrap();
+        writer.write_all(&[0u8; 25]).unwrap();
+        writer.finalize().unwrap();
+
+        // No filesystem has this much space
+        let mut writer = RollingWriter::new(base_path, Some(10)).unwrap();
+        let result = writer.set_min_free_space(u64::MAX);
+        assert!(result.is_err(), "Insufficient free space should return error");
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::StorageFull);
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rolling_writer_max_write_rate() {
+        let test_name = "max_write_rate";
+        setup_test_dir(test_name);
+
+        let base_path = get_test_dir(test_name).join("test.tar.gz");
+        let mut writer = RollingWriter::new(base_path.clone(), Some(10000)).unwrap();
+        writer.set_max_write_rate(20000);
+
+        // 1s burst, then 0.25s for the rest
+        let start = std::time::Instant::now();
+        writer.write_all(&[0u8; 25000]).unwrap();
+        writer.finalize().unwrap();
+        assert!(start.elapsed() >= std::time::Duration::from_millis(200), "Writes should be throttled");
+        assert_eq!(fs::metadata(get_test_dir(test_name).join("test.tar.gz.part003")).unwrap().len(), 5000);
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rolling_writer_max_parts() {
+        let test_name = "max_parts";
+        setup_test_dir(test_name);
+
+        let base_path = get_test_dir(test_name).join("test.tar.gz");
+        let mut writer = RollingWriter::new(base_path.clone(), Some(10)).unwrap();
+        writer.set_max_parts(2);
+
+        // Filling 2 parts is allowed
+        writer.write_all(&[0u8; 20]).unwrap();
+
+        // Spilling into a 3rd part should fail
+        let result = writer.write_all(&[0u8; 1]);
+        assert!(result.is_err(), "Writing past max_parts should return error");
+        assert!(result.unwrap_err().to_string().contains("max_parts"));
+        assert!(!get_test_dir(test_name).join("test.tar.gz.part003").exists());
+
+        cleanup_test_dir(test_name);
+    }
+
     #[test]
     fn test_rolling_writer_max_size_zero() {
         let test_name = "max_size_zero";
@@ -469,4 +1259,79 @@ mod tests {
         
         cleanup_test_dir(test_name);
     }
+
+    #[test]
+    fn test_rolling_writer_parity_multiple_parts() {
+        let test_name = "parity_multiple";
+        setup_test_dir(test_name);
+        
+        let base_path = get_test_dir(test_name).join("test.tar.gz");
+        let mut writer = RollingWriter::new(base_path.clone(), Some(50)).unwrap();
+        writer.enable_parity().unwrap();
+
+        use std::sync::{Arc, Mutex};
+        let callback_calls = Arc::new(Mutex::new(Vec::new()));
+        let callback_calls_clone = callback_calls.clone();
+        writer.set_listener(move |part| {
+            callback_calls_clone.lock().unwrap().push(part.path.clone());
+            Ok(0)
+        });
+        
+        let data: Vec<u8> = (0..120u8).collect();
+        writer.write_all(&data).unwrap();
+        writer.finalize().unwrap();
+        
+        // Parity file should exist and be passed to the listener after the parts
+        let parity_path = get_test_dir(test_name).join("test.tar.gz.parity");
+        assert!(parity_path.exists());
+        let calls = callback_calls.lock().unwrap();
+        assert_eq!(calls.len(), 4); // part001, part002, part003, parity
+        assert_eq!(calls[3], parity_path.display().to_string());
+
+        // Parity block should be the XOR of all parts
+        let parity = fs::read(&parity_path).unwrap();
+        let mut expected = vec![0u8; 50];
+        for chunk in data.chunks(50) {
+            for (byte, data_byte) in expected.iter_mut().zip(chunk) {
+                *byte ^= data_byte;
+            }
+        }
+        assert_eq!(&parity[..50], expected.as_slice());
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rolling_writer_parity_single_part() {
+        let test_name = "parity_single";
+        setup_test_dir(test_name);
+        
+        let base_path = get_test_dir(test_name).join("test.tar.gz");
+        let mut writer = RollingWriter::new(base_path.clone(), Some(1000)).unwrap();
+        writer.enable_parity().unwrap();
+        
+        writer.write_all(b"Small data").unwrap();
+        writer.finalize().unwrap();
+        
+        // A single part has nothing to rebuild from, so no parity file is kept
+        assert!(base_path.exists());
+        assert!(!get_test_dir(test_name).join("test.tar.gz.parity").exists());
+        
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_rolling_writer_parity_requires_max_size() {
+        let test_name = "parity_no_max_size";
+        setup_test_dir(test_name);
+        
+        let base_path = get_test_dir(test_name).join("test.tar.gz");
+        let mut writer = RollingWriter::new(base_path.clone(), None).unwrap();
+        
+        let result = writer.enable_parity();
+        assert!(result.is_err(), "Parity without max_size should return error");
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        
+        cleanup_test_dir(test_name);
+    }
 }
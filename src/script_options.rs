@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use crate::hooks::HookKind;
+
+/// A script in the config: a path, or a command template
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Script {
+    /// Path to an executable (Or a `sh:` command line)
+    Path(PathBuf),
+    /// Program and its arguments, run without a shell after filling in `{placeholders}` (i.e. `["rclone", "copyto", "{part}", "remote:{segment}/{part_name}"]`)
+    Template(Vec<String>),
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Script::Path(path) => write!(f, "{}", path.display()),
+            Script::Template(template) => write!(f, "{:?}", template),
+        }
+    }
+}
+
+/// Settings for each kind of script (Shared by the global script and any set on segments)
+/// (Keyed by the script's config key, or its hook)
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptsConfig {
+    #[serde(default, alias = "segment_start")]
+    pub pre_script: ScriptOptions,
+    #[serde(default, alias = "part_finalized")]
+    pub post_script: ScriptOptions,
+    #[serde(default)]
+    pub segment_done: ScriptOptions,
+    #[serde(default, alias = "segment_skipped")]
+    pub skip_script: ScriptOptions,
+    #[serde(default, alias = "failure")]
+    pub on_failure_script: ScriptOptions,
+    #[serde(default, alias = "run_done")]
+    pub on_finish_script: ScriptOptions,
+}
+
+impl ScriptsConfig {
+    /// Settings for the script of a hook
+    pub fn get(&self, kind: HookKind) -> &ScriptOptions {
+        match kind {
+            HookKind::SegmentStart => &self.pre_script,
+            HookKind::PartFinalized => &self.post_script,
+            HookKind::SegmentDone => &self.segment_done,
+            HookKind::SegmentSkipped => &self.skip_script,
+            HookKind::RunDone => &self.on_finish_script,
+            HookKind::Failure => &self.on_failure_script,
+        }
+    }
+}
+
+/// How a script is run
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptOptions {
+    /// What each exit code means (Codes not listed follow the default rules)
+    pub exit_codes: Option<ExitCodePolicy>,
+    /// Directory the script is run in (Default: The program's working directory)
+    pub cwd: Option<PathBuf>,
+    /// Extra environment variables (Besides `SEG_ARC_*`, which are always set by the program)
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+/// Whether scripts are run (And notifications sent) during a dry run
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DryRunScripts {
+    /// Don't run any scripts
+    #[default]
+    Skip,
+    /// Run scripts as usual, with `SEG_ARC_DRY_RUN=1` set
+    Run,
+}
+
+/// What a script's exit code means
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitAction {
+    /// Success
+    Ok,
+    /// An error that is logged, and passed on (i.e. a pre_script veto)
+    Warn,
+    /// The script failed (Failing its segment)
+    Fatal,
+}
+
+/// Exit codes (Or ranges of them, i.e. `"128-255"`) to treat as each action
+/// (If a code is listed more than once, fatal takes precedence over warn, then ok)
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExitCodePolicy {
+    #[serde(default)]
+    pub ok: Vec<ExitCodes>,
+    #[serde(default)]
+    pub warn: Vec<ExitCodes>,
+    #[serde(default)]
+    pub fatal: Vec<ExitCodes>,
+}
+
+impl ExitCodePolicy {
+    /// The action for an exit code (None if it isn't listed)
+    pub fn action(&self, code: i32) -> Option<ExitAction> {
+        [(&self.fatal, ExitAction::Fatal), (&self.warn, ExitAction::Warn), (&self.ok, ExitAction::Ok)]
+            .into_iter()
+            .find(|(codes, _)| codes.iter().any(|codes| codes.contains(code)))
+            .map(|(_, action)| action)
+    }
+}
+
+/// An inclusive range of exit codes, written as a number or `"start-end"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(try_from = "RawExitCodes")]
+pub struct ExitCodes {
+    pub start: i32,
+    pub end: i32,
+}
+
+impl ExitCodes {
+    pub fn contains(&self, code: i32) -> bool {
+        (self.start..=self.end).contains(&code)
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum RawExitCodes {
+    Code(i32),
+    Range(String),
+}
+
+impl TryFrom<RawExitCodes> for ExitCodes {
+    type Error = String;
+
+    fn try_from(raw: RawExitCodes) -> Result<Self, Self::Error> {
+        let range = match raw {
+            RawExitCodes::Code(code) => return Ok(Self { start: code, end: code }),
+            RawExitCodes::Range(range) => range,
+        };
+        let parsed = match range.trim().split_once('-') {
+            Some((start, end)) => start.trim().parse().ok().zip(end.trim().parse().ok()),
+            None => range.trim().parse().ok().map(|code| (code, code)),
+        };
+        match parsed {
+            Some((start, end)) if start <= end => Ok(Self { start, end }),
+            _ => Err(format!("Invalid exit code range {:?} (Expected a code, or \"start-end\")", range)),
+        }
+    }
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code_policy() {
+        let config: ScriptsConfig = toml::from_str("post_script = { exit_codes = { fatal = [2, \"100-127\"], ok = [\"3\", 2] } }").unwrap();
+        let policy = config.post_script.exit_codes.unwrap();
+        assert_eq!(policy.action(2), Some(ExitAction::Fatal), "Fatal takes precedence");
+        assert_eq!(policy.action(100), Some(ExitAction::Fatal));
+        assert_eq!(policy.action(127), Some(ExitAction::Fatal));
+        assert_eq!(policy.action(3), Some(ExitAction::Ok));
+        assert_eq!(policy.action(1), None);
+        assert_eq!(config.pre_script, ScriptOptions::default());
+
+        assert!(toml::from_str::<ExitCodePolicy>("fatal = [\"10-2\"]").is_err());
+        assert!(toml::from_str::<ExitCodePolicy>("fatal = [\"two\"]").is_err());
+        assert!(toml::from_str::<ScriptsConfig>("post_scirpt = {}").is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use std::io;
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use log::{error, info};
+use crate::rolling_writer::FinishedPart;
+
+type SenderSlot = Arc<Mutex<Option<SyncSender<FinishedPart>>>>;
+/// Handles a finished part (i.e. uploads it, then runs the post-script), returning the script's exit code
+pub type PartHandler = Arc<dyn Fn(&FinishedPart) -> io::Result<i32> + Send + Sync>;
+
+/// Handles finished parts (Uploading them and running the post-script) in background worker threads, so archiving continues while parts upload.
+///
+/// At most `workers` parts wait in the queue, after which submitting blocks (Limits parts piling up on disk).
+/// Script failures are collected and returned by finish.
+pub struct ScriptQueue {
+    sender: SenderSlot,
+    workers: Vec<JoinHandle<()>>,
+    errors: Arc<Mutex<Vec<String>>>,
+}
+
+impl ScriptQueue {
+    /// Start `workers` threads (At least 1) that call `handler` with each submitted path
+    pub fn new(handler: PartHandler, workers: usize) -> Self {
+        let workers = workers.max(1);
+        let (sender, receiver) = sync_channel::<FinishedPart>(workers);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let errors = Arc::new(Mutex::new(Vec::new()));
+
+        let handles = (0..workers)
+            .map(|_| {
+                let receiver = receiver.clone();
+                let errors = errors.clone();
+                let handler = handler.clone();
+                thread::spawn(move || run_worker(&receiver, &errors, handler))
+            })
+            .collect();
+
+        info!("Started {} post-script worker(s)", workers);
+        Self {
+            sender: Arc::new(Mutex::new(Some(sender))),
+            workers: handles,
+            errors,
+        }
+    }
+
+    /// Listener that queues each part for the workers (Compatible with RollingWriter::set_listener)
+    pub fn submitter(&self) -> impl Fn(&FinishedPart) -> io::Result<i32> + 'static {
+        let sender = self.sender.clone();
+        move |part: &FinishedPart| {
+            let guard = sender.lock()
+                .map_err(|_| io::Error::other("Post-script queue lock poisoned"))?;
+            let sender = guard.as_ref()
+                .ok_or_else(|| io::Error::other("Post-script queue is closed"))?;
+            sender.send(part.clone())
+                .map_err(|_| io::Error::other("Post-script workers have stopped"))?;
+            Ok(0)
+        }
+    }
+
+    /// Wait for all queued scripts to complete
+    ///
+    /// # Errors
+    /// Returns an error listing every part whose script failed
+    pub fn finish(mut self) -> io::Result<()> {
+        self.close();
+        let errors = std::mem::take(&mut *self.errors.lock()
+            .map_err(|_| io::Error::other("Post-script error list lock poisoned"))?);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("Post-script failed for {} part(s): {}", errors.len(), errors.join("; "))))
+        }
+    }
+
+    // --- Private methods --- //
+
+    /// Stop accepting parts and wait for the workers to drain the queue
+    fn close(&mut self) {
+        if let Ok(mut sender) = self.sender.lock() {
+            sender.take();
+        }
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                error!("Post-script worker thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for ScriptQueue {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn run_worker(receiver: &Mutex<Receiver<FinishedPart>>, errors: &Mutex<Vec<String>>, handler: PartHandler) {
+    loop {
+        // Only hold the lock while waiting for the next part
+        let next = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        let Ok(part) = next else {
+            return; // Queue closed
+        };
+
+        if let Err(e) = handler(&part) {
+            error!("Post-script failed for {:?}: {}", part.path, e);
+            if let Ok(mut errors) = errors.lock() {
+                errors.push(format!("{}: {}", part.path, e));
+            }
+        }
+    }
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use crate::helpers::execute_script;
+    use crate::script_options::Script;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/script_queue_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[cfg(unix)]
+    fn write_script(path: &PathBuf, contents: &str) {
+        use std::os::unix::fs::PermissionsExt;
+        fs::write(path, contents).unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    fn script_handler(script_path: PathBuf) -> PartHandler {
+        let script = Script::Path(script_path);
+        Arc::new(move |part: &FinishedPart| execute_script(&script, &[&part.path], None, None))
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_script_queue_runs_all_parts() {
+        let test_name = "runs_all";
+        let test_dir = setup_test_dir(test_name);
+
+        // Script creates a marker file named after its argument
+        let script_path = test_dir.join("script.sh");
+        write_script(&script_path, &format!("#!/bin/bash\ntouch \"{}/done_$1\"\n", test_dir.display()));
+
+        let queue = ScriptQueue::new(script_handler(script_path), 2);
+        let submit = queue.submitter();
+        for i in 0..5 {
+            assert_eq!(submit(&FinishedPart::new(format!("part{}", i), Some(i + 1), None)).unwrap(), 0);
+        }
+        queue.finish().unwrap();
+
+        for i in 0..5 {
+            assert!(test_dir.join(format!("done_part{}", i)).exists(), "Script should run for part{}", i);
+        }
+
+        // Submitting after finish should fail
+        assert!(submit(&FinishedPart::new("late", None, None)).is_err());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_script_queue_reports_failures() {
+        let test_name = "failures";
+        let test_dir = setup_test_dir(test_name);
+
+        // Only "bad" parts fail (Exit codes >= 128 are treated as errors)
+        let script_path = test_dir.join("script.sh");
+        write_script(&script_path, "#!/bin/bash\nif [ \"$1\" = \"bad\" ]; then exit 200; fi\nexit 0\n");
+
+        let queue = ScriptQueue::new(script_handler(script_path), 3);
+        let submit = queue.submitter();
+        for name in ["good", "bad", "good"] {
+            submit(&FinishedPart::new(name, None, None)).unwrap();
+        }
+
+        let result = queue.finish();
+        assert!(result.is_err(), "Failed scripts should be reported by finish");
+        assert!(result.unwrap_err().to_string().contains("1 part(s)"));
+
+        cleanup_test_dir(test_name);
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use log::info;
+use crate::docker::DockerQuiesce;
+use crate::hooks::HookKind;
+use crate::script_options::Script;
+
+/// A segment in the config: its path, or a table of its path and options
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum SegmentConfig {
+    Path(PathBuf),
+    Options(Box<SegmentOptions>),
+}
+
+/// What happens to a segment when its pre_script exits with an error code
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreScriptFailure {
+    /// Record the segment as failed (The run continues with the next segment)
+    #[default]
+    Fail,
+    /// Record the segment as skipped, leaving its previous archive as its backup
+    Skip,
+}
+
+/// Options for a single segment
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SegmentOptions {
+    pub path: PathBuf,
+    /// Script run before the segment is hashed, instead of the global pre_script
+    pub pre_script: Option<Script>,
+    /// Script run on each finished part, instead of the global post_script
+    pub post_script: Option<Script>,
+    /// Script run when the segment is unchanged, instead of the global skip_script
+    pub skip_script: Option<Script>,
+    /// Scripts run on the segment's hook events, instead of the global ones
+    #[serde(default)]
+    pub hooks: HashMap<HookKind, Script>,
+    /// Shell command whose output is written to `path` before the segment is hashed (i.e. a database dump)
+    pub dump: Option<String>,
+    /// Docker containers to stop or pause while the segment is hashed and archived
+    pub docker: Option<DockerQuiesce>,
+    /// Files holding the output of commands, added to the segment's archive
+    #[serde(default)]
+    pub entries: Vec<CommandEntry>,
+}
+
+/// A file in the archive holding a command's output (Captured when the segment is archived)
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandEntry {
+    /// Path of the entry in the archive
+    pub name: String,
+    /// Shell command whose stdout is the entry's content
+    pub command: String,
+}
+
+impl CommandEntry {
+    /// Run the command, returning its stdout
+    ///
+    /// # Errors
+    /// Fails if the name isn't a relative path, or the command fails
+    pub fn capture(&self) -> io::Result<Vec<u8>> {
+        let name = Path::new(&self.name);
+        if self.name.is_empty() || !name.components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Entry name must be a relative path: {:?}", self.name)));
+        }
+        info!("Capturing entry {:?} from command: {}", self.name, self.command);
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(io::Error::other(format!("Entry command failed ({}): {}", output.status, stderr.trim())));
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// Split segments into their paths, and the options of segments that have any
+pub fn split_segments(segments: HashMap<String, SegmentConfig>) -> (HashMap<String, PathBuf>, HashMap<String, SegmentOptions>) {
+    let mut paths = HashMap::new();
+    let mut options = HashMap::new();
+    for (name, segment) in segments {
+        match segment {
+            SegmentConfig::Path(path) => {
+                paths.insert(name, path);
+            }
+            SegmentConfig::Options(segment_options) => {
+                paths.insert(name.clone(), segment_options.path.clone());
+                options.insert(name, *segment_options);
+            }
+        }
+    }
+    (paths, options)
+}
+
+/// Run a dump command, replacing the file at `path` with its stdout once it succeeds, and return the dump's size
+/// (Written to a temporary file first, so a failed dump leaves the previous one in place)
+pub fn run_dump(command: &str, path: &Path) -> io::Result<u64> {
+    if let Some(dir) = path.parent()
+        && !dir.as_os_str().is_empty() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    info!("Dumping to {:?} with command: {}", path, command);
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(File::create(&partial)?)
+        .output();
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            let _ = fs::remove_file(&partial);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(io::Error::other(format!("Dump command failed ({}): {}", output.status, stderr.trim())));
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+    };
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        info!("Dump: {}", line);
+    }
+    fs::rename(&partial, path)?;
+    Ok(fs::metadata(path)?.len())
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/segment_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Segments {
+        segments: HashMap<String, SegmentConfig>,
+    }
+
+    #[test]
+    fn test_split_segments() {
+        let config = "[segments]\ndocs = \"/home/user/Documents\"\ndb = { path = \"/srv/db\", docker = { stop = [\"postgres\"] } }";
+        let Segments { segments } = toml::from_str(config).unwrap();
+        let (paths, options) = split_segments(segments);
+        assert_eq!(paths["docs"], PathBuf::from("/home/user/Documents"));
+        assert_eq!(paths["db"], PathBuf::from("/srv/db"));
+        assert!(!options.contains_key("docs"));
+        assert_eq!(options["db"].docker.as_ref().map(|docker| docker.stop.clone()), Some(vec!["postgres".to_string()]));
+
+        let config = "[segments]\napp = { path = \"/srv/app\", pre_script = \"./quiesce.sh\", post_script = \"./upload.sh\", skip_script = \"./touch.sh\" }";
+        let Segments { segments } = toml::from_str(config).unwrap();
+        let (_, options) = split_segments(segments);
+        assert_eq!(options["app"].pre_script, Some(Script::Path(PathBuf::from("./quiesce.sh"))));
+        assert_eq!(options["app"].post_script, Some(Script::Path(PathBuf::from("./upload.sh"))));
+        assert_eq!(options["app"].skip_script, Some(Script::Path(PathBuf::from("./touch.sh"))));
+        assert!(options["app"].hooks.is_empty());
+
+        let config = "[segments]\napp = { path = \"/srv/app\", hooks = { segment_done = \"./notify.sh\" } }";
+        let Segments { segments } = toml::from_str(config).unwrap();
+        let (_, options) = split_segments(segments);
+        assert_eq!(options["app"].hooks[&HookKind::SegmentDone], Script::Path(PathBuf::from("./notify.sh")));
+        assert_eq!(toml::from_str::<HashMap<String, PreScriptFailure>>("policy = \"skip\"").unwrap()["policy"], PreScriptFailure::Skip);
+
+        // Misspelled options are an error, rather than ignored
+        assert!(toml::from_str::<Segments>("[segments]\ndb = { path = \"/srv/db\", dokcer = {} }").is_err());
+    }
+
+    #[test]
+    fn test_capture_entry() {
+        let entry = CommandEntry { name: "metadata/packages.txt".to_string(), command: "echo bash; echo curl".to_string() };
+        assert_eq!(entry.capture().unwrap(), b"bash\ncurl\n");
+
+        let entry = CommandEntry { name: "failed.txt".to_string(), command: "echo 'not found' >&2; exit 127".to_string() };
+        assert!(entry.capture().unwrap_err().to_string().contains("not found"));
+        for name in ["", "/etc/passwd", "../outside.txt"] {
+            let entry = CommandEntry { name: name.to_string(), command: "true".to_string() };
+            assert_eq!(entry.capture().unwrap_err().kind(), io::ErrorKind::InvalidInput, "{:?} is not a relative path", name);
+        }
+    }
+
+    #[test]
+    fn test_run_dump() {
+        let test_name = "run_dump";
+        cleanup_test_dir(test_name);
+        let path = get_test_dir(test_name).join("db").join("dump.sql");
+
+        assert_eq!(run_dump("echo 'CREATE TABLE t;'; echo 'Dumping' >&2", &path).unwrap(), 16);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "CREATE TABLE t;\n");
+
+        // A failed dump keeps the previous one
+        let error = run_dump("echo partial; echo 'connection refused' >&2; exit 1", &path).unwrap_err();
+        assert!(error.to_string().contains("connection refused"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "CREATE TABLE t;\n");
+        assert!(!path.with_extension("sql.partial").exists());
+
+        cleanup_test_dir(test_name);
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::fs;
+use crate::rolling_writer::PartInfo;
+use crate::digest::DigestAlgorithm;
+
+/// Extension of the metadata file written next to each archive
+pub const SIDECAR_EXT: &str = "meta.toml";
+
+/// Machine-readable description of a segment's archive
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct SegmentMetadata {
+    pub segment: String,
+    pub source_path: PathBuf,
+    /// Missing if the segment could not be hashed
+    pub hash: Option<String>,
+    /// Time the archive was created (RFC 3339)
+    pub created: String,
+    pub file_count: usize,
+    pub uncompressed_bytes: u64,
+    pub tool_version: String,
+    /// Algorithm used for each part's checksum (If checksums are enabled)
+    pub checksum_algorithm: Option<DigestAlgorithm>,
+    pub parts: Vec<PartInfo>,
+}
+
+/// Path of the metadata file for a segment
+pub fn sidecar_path(output_dir: &Path, segment: &str) -> PathBuf {
+    output_dir.join(format!("{}.{}", segment, SIDECAR_EXT))
+}
+
+/// Write segment metadata as TOML
+pub fn write_sidecar(path: &Path, metadata: &SegmentMetadata) -> Result<()> {
+    let contents = toml::to_string(metadata)
+        .context("Failed to serialize segment metadata")?;
+    fs::write(path, contents)
+        .context(format!("Failed to write metadata file: {:?}", path))
+}
+
+/// Read segment metadata written by a previous run
+pub fn read_sidecar(path: &Path) -> Result<SegmentMetadata> {
+    let contents = fs::read_to_string(path)
+        .context(format!("Failed to read metadata file: {:?}", path))?;
+    toml::from_str(&contents)
+        .context(format!("Failed to parse metadata file: {:?}", path))
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/sidecar_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[test]
+    fn test_sidecar_path() {
+        let path = sidecar_path(Path::new("/tmp/archives"), "documents");
+        assert_eq!(path, PathBuf::from("/tmp/archives/documents.meta.toml"));
+    }
+
+    #[test]
+    fn test_write_sidecar() {
+        let test_name = "write";
+        let test_dir = setup_test_dir(test_name);
+        let path = sidecar_path(&test_dir, "segment1");
+
+        let metadata = SegmentMetadata {
+            segment: "segment1".to_string(),
+            source_path: PathBuf::from("/home/user/Documents"),
+            hash: Some("0123456789abcdef".to_string()),
+            created: "2024-01-01T00:00:00+00:00".to_string(),
+            file_count: 2,
+            uncompressed_bytes: 150,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            checksum_algorithm: Some(DigestAlgorithm::Xxh3),
+            parts: vec![
+                PartInfo { name: "segment1.tar.gz.part001".to_string(), path: PathBuf::from("/tmp/segment1.tar.gz.part001"), size: 100, checksum: Some("aa".to_string()) },
+                PartInfo { name: "segment1.tar.gz.part002".to_string(), path: PathBuf::from("/tmp/segment1.tar.gz.part002"), size: 20, checksum: Some("bb".to_string()) },
+            ],
+        };
+        write_sidecar(&path, &metadata).unwrap();
+
+        // Should round-trip through TOML
+        let read = read_sidecar(&path).unwrap();
+        assert_eq!(read.segment, "segment1");
+        assert_eq!(read.hash, Some("0123456789abcdef".to_string()));
+        assert_eq!(read.file_count, 2);
+        assert_eq!(read.uncompressed_bytes, 150);
+        assert_eq!(read.parts.len(), 2);
+        assert_eq!(read.parts[1].size, 20);
+        assert_eq!(read.parts[1].checksum, Some("bb".to_string()));
+        assert_eq!(read.checksum_algorithm, Some(DigestAlgorithm::Xxh3));
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_write_sidecar_without_hash() {
+        let test_name = "no_hash";
+        let test_dir = setup_test_dir(test_name);
+        let path = sidecar_path(&test_dir, "segment1");
+
+        let metadata = SegmentMetadata {
+            segment: "segment1".to_string(),
+            source_path: PathBuf::from("/home/user/Documents"),
+            hash: None,
+            created: "2024-01-01T00:00:00+00:00".to_string(),
+            file_count: 0,
+            uncompressed_bytes: 0,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            checksum_algorithm: Some(DigestAlgorithm::Xxh3),
+            parts: vec![],
+        };
+        write_sidecar(&path, &metadata).unwrap();
+
+        let read = read_sidecar(&path).unwrap();
+        assert!(read.hash.is_none());
+
+        cleanup_test_dir(test_name);
+    }
+}
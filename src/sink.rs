@@ -0,0 +1,165 @@
+use std::io::{self, Write};
+use std::fs::File;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use log::info;
+
+/// Placeholder in a part command that is replaced with the part's file name
+pub const PART_NAME_PLACEHOLDER: &str = "{name}";
+
+/// Where the parts of an archive are written (Implement this to add a storage backend)
+pub trait ArchiveSink: Send + Sync {
+    /// Open a part for writing (`path` is its path in output_path, whether or not it is stored there)
+    fn open_part(&self, path: &Path) -> io::Result<Box<dyn SinkPart>>;
+
+    /// True if parts are stored as files at their path
+    /// (So they can be renamed, resumed, checked for free space, cleaned up and passed to the post-script)
+    fn is_local(&self) -> bool {
+        false
+    }
+}
+
+/// A part open for writing
+pub trait SinkPart: Write {
+    /// Close the part once all of it is written (Syncing it to disk if `sync` is set)
+    fn finish(self: Box<Self>, sync: bool) -> io::Result<()>;
+}
+
+/// Parts written to files in output_path
+pub struct LocalSink;
+
+impl ArchiveSink for LocalSink {
+    fn open_part(&self, path: &Path) -> io::Result<Box<dyn SinkPart>> {
+        info!("Opening new file part: {:?}", path);
+        Ok(Box::new(File::create(path)?))
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
+
+impl SinkPart for File {
+    fn finish(mut self: Box<Self>, sync: bool) -> io::Result<()> {
+        self.flush()?;
+        if sync {
+            self.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+/// Parts piped to the stdin of a shell command (`{name}` is replaced with each part's file name)
+pub struct CommandSink {
+    command: String,
+}
+
+impl CommandSink {
+    pub fn new(command: impl Into<String>) -> Self {
+        Self { command: command.into() }
+    }
+}
+
+impl ArchiveSink for CommandSink {
+    fn open_part(&self, path: &Path) -> io::Result<Box<dyn SinkPart>> {
+        let name = path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.display().to_string());
+        let command = self.command.replace(PART_NAME_PLACEHOLDER, &name);
+        info!("Piping new part {:?} to command: {}", name, command);
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        Ok(Box::new(CommandPart { child, name }))
+    }
+}
+
+/// Child process receiving a part on stdin
+struct CommandPart {
+    child: Child,
+    name: String,
+}
+
+impl CommandPart {
+    fn stdin(&mut self) -> io::Result<&mut dyn Write> {
+        self.child.stdin.as_mut()
+            .map(|stdin| stdin as &mut dyn Write)
+            .ok_or_else(|| io::Error::other("Part command stdin is closed"))
+    }
+}
+
+impl Write for CommandPart {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin()?.flush()
+    }
+}
+
+impl SinkPart for CommandPart {
+    fn finish(mut self: Box<Self>, _sync: bool) -> io::Result<()> {
+        // Close stdin so the command sees EOF, then wait for it to finish
+        drop(self.child.stdin.take());
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "Part command failed for {:?}: {}", self.name, status
+            )));
+        }
+        Ok(())
+    }
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/sink_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[test]
+    fn test_sinks() {
+        let test_name = "sinks";
+        let test_dir = setup_test_dir(test_name);
+        let path = test_dir.join("test.tar.gz.part001");
+
+        let mut part = LocalSink.open_part(&path).unwrap();
+        part.write_all(b"local").unwrap();
+        part.finish(true).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"local");
+
+        // The part's file name is substituted into the command
+        let sink = CommandSink::new(format!("cat > {}/piped.{}", test_dir.display(), PART_NAME_PLACEHOLDER));
+        assert!(!sink.is_local());
+        let mut part = sink.open_part(&path).unwrap();
+        part.write_all(b"piped").unwrap();
+        part.finish(false).unwrap();
+        assert_eq!(fs::read(test_dir.join("piped.test.tar.gz.part001")).unwrap(), b"piped");
+
+        let mut part = CommandSink::new("cat > /dev/null; exit 3").open_part(&path).unwrap();
+        part.write_all(b"failed").unwrap();
+        assert!(part.finish(false).unwrap_err().to_string().contains("Part command failed"));
+
+        cleanup_test_dir(test_name);
+    }
+}
@@ -0,0 +1,162 @@
+use std::path::Path;
+use chrono::{DateTime, Local};
+use crate::hasher::SegmentRecord;
+use crate::journal::SegmentHistory;
+use crate::sidecar::{read_sidecar, sidecar_path};
+
+/// How a segment's source compares with its last backup
+#[derive(Debug, PartialEq)]
+pub enum SourceState {
+    Unchanged,
+    Changed,
+    /// No hash was stored to compare with
+    NoHash,
+    /// The source couldn't be hashed (With the reason)
+    Unreadable(String),
+}
+
+/// State of a segment after its last run, as shown by the status command
+#[derive(Debug, PartialEq)]
+pub struct SegmentStatus {
+    pub segment: String,
+    /// Time of the last backup (RFC 3339)
+    pub last_backup: Option<String>,
+    /// Hash stored by the last backup (Or rehash)
+    pub hash: Option<String>,
+    /// Total size of the latest archive's parts
+    pub archive_bytes: u64,
+    pub parts: usize,
+    pub source: SourceState,
+    /// Outcomes of the segment in previous runs (If a journal is kept)
+    pub history: Option<SegmentHistory>,
+}
+
+impl SegmentStatus {
+    /// Read the last run of a segment from its hash file record (If any) and its metadata file in `output_dir`
+    /// (The metadata file describes the archive on disk, so its parts are preferred)
+    pub fn last_run(segment: &str, record: Option<&SegmentRecord>, output_dir: &Path) -> Self {
+        let sidecar = read_sidecar(&sidecar_path(output_dir, segment)).ok();
+        let parts = match &sidecar {
+            Some(sidecar) => &sidecar.parts,
+            None => record.map_or(&[][..], |record| &record.parts),
+        };
+        Self {
+            segment: segment.to_string(),
+            last_backup: record.and_then(|record| record.last_backup.clone())
+                .or_else(|| sidecar.as_ref().map(|sidecar| sidecar.created.clone())),
+            hash: record.map(|record| record.hash.clone())
+                .or_else(|| sidecar.as_ref().and_then(|sidecar| sidecar.hash.clone())),
+            archive_bytes: parts.iter().map(|part| part.size).sum(),
+            parts: parts.len(),
+            source: SourceState::NoHash,
+            history: None,
+        }
+    }
+
+    /// One line per segment: name, last backup, hash, archive size, then whether the source changed
+    pub fn line(&self) -> String {
+        let Some(last_backup) = &self.last_backup else {
+            return format!("{}: never backed up{}{}", self.segment, self.source_note(), self.history_note());
+        };
+        format!("{}: last backup {}, hash {}, {} bytes in {} part(s){}{}", self.segment, local_time(last_backup),
+            self.hash.as_deref().unwrap_or("(None)"), self.archive_bytes, self.parts, self.source_note(), self.history_note())
+    }
+
+    /// Totals of each outcome in the journal, the average archive size, and the latest failure
+    fn history_note(&self) -> String {
+        let Some(history) = self.history.as_ref().filter(|history| history.runs() > 0) else {
+            return String::new();
+        };
+        let mut note = format!("; {} run(s): {} archived, {} skipped, {} failed",
+            history.runs(), history.archived, history.skipped, history.failed);
+        if !history.bytes_written.is_empty() {
+            note += &format!(", {} bytes per archive on average", history.bytes_written.iter().sum::<u64>() / history.bytes_written.len() as u64);
+        }
+        if let Some((time, error)) = &history.last_failure {
+            note += &format!(", last FAILED {} ({})", local_time(time), error);
+        }
+        note
+    }
+
+    fn source_note(&self) -> String {
+        match &self.source {
+            SourceState::Unchanged => ", source unchanged".to_string(),
+            SourceState::Changed => ", source CHANGED".to_string(),
+            SourceState::NoHash => String::new(),
+            SourceState::Unreadable(reason) => format!(", source unreadable ({})", reason),
+        }
+    }
+}
+
+/// An RFC 3339 time in the local timezone (As-is if it can't be parsed)
+fn local_time(time: &str) -> String {
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|_| time.to_string())
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use crate::rolling_writer::PartInfo;
+    use crate::sidecar::{SegmentMetadata, write_sidecar};
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/status_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    #[test]
+    fn test_segment_status() {
+        let test_name = "status";
+        let test_dir = setup_test_dir(test_name);
+        let part = |name: &str, size| PartInfo { name: name.to_string(), path: test_dir.join(name), size, checksum: None };
+        let sidecar = SegmentMetadata {
+            segment: "docs".to_string(),
+            source_path: PathBuf::from("/tmp/files/docs"),
+            hash: Some("xxh3:0123456789abcdef".to_string()),
+            created: "2024-01-01T00:00:00+00:00".to_string(),
+            file_count: 2,
+            uncompressed_bytes: 300,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            checksum_algorithm: None,
+            parts: vec![part("docs.tar.gz.part001", 100), part("docs.tar.gz.part002", 50)],
+        };
+        write_sidecar(&sidecar_path(&test_dir, "docs"), &sidecar).unwrap();
+
+        // Without a hash file record, everything comes from the metadata file
+        let mut status = SegmentStatus::last_run("docs", None, &test_dir);
+        assert_eq!(status.hash.as_deref(), Some("xxh3:0123456789abcdef"));
+        assert_eq!((status.archive_bytes, status.parts), (150, 2));
+        status.source = SourceState::Changed;
+        assert!(status.line().starts_with("docs: last backup "));
+        assert!(status.line().ends_with(", hash xxh3:0123456789abcdef, 150 bytes in 2 part(s), source CHANGED"));
+
+        // The hash file's hash is the one compared on the next run
+        let record = SegmentRecord::new("xxh3:fedcba9876543210".to_string());
+        let status = SegmentStatus::last_run("docs", Some(&record), &test_dir);
+        assert_eq!(status.hash.as_deref(), Some("xxh3:fedcba9876543210"));
+        assert_eq!(status.last_backup.as_deref(), Some("2024-01-01T00:00:00+00:00"));
+
+        let mut status = SegmentStatus::last_run("other", None, &test_dir);
+        assert_eq!(status.line(), "other: never backed up");
+        status.history = Some(SegmentHistory { archived: 2, failed: 1, bytes_written: vec![100, 200], ..SegmentHistory::default() });
+        assert_eq!(status.line(), "other: never backed up; 3 run(s): 2 archived, 0 skipped, 1 failed, 150 bytes per archive on average");
+
+        cleanup_test_dir(test_name);
+    }
+}
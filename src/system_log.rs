@@ -0,0 +1,333 @@
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use log::{Level, LevelFilter, Record};
+use log4rs::append::Append;
+
+const DEFAULT_IDENTIFIER: &str = "segmented_archive";
+const SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Syslog facility of the messages (What kind of program logged them)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Facility {
+    User,
+    #[default]
+    Daemon,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl Facility {
+    fn code(&self) -> u8 {
+        match self {
+            Facility::User => 1,
+            Facility::Daemon => 3,
+            Facility::Local0 => 16,
+            Facility::Local1 => 17,
+            Facility::Local2 => 18,
+            Facility::Local3 => 19,
+            Facility::Local4 => 20,
+            Facility::Local5 => 21,
+            Facility::Local6 => 22,
+            Facility::Local7 => 23,
+        }
+    }
+}
+
+/// System log to send messages to
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SystemLogService {
+    /// The local syslog daemon (RFC 3164 messages, over `/dev/log`)
+    Syslog {
+        #[serde(default)]
+        facility: Facility,
+    },
+    /// The systemd journal (Native protocol, over `/run/systemd/journal/socket`)
+    Journald,
+    /// The Windows Application log (With `identifier` as the event source)
+    EventLog,
+}
+
+/// System log settings
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct SystemLogConfig {
+    #[serde(flatten)]
+    pub service: SystemLogService,
+    /// Least severe messages sent (Default: info)
+    pub level: Option<LevelFilter>,
+    /// Name the messages are logged under (Default: segmented_archive)
+    pub identifier: Option<String>,
+    /// Socket of the system log (Default: The service's usual socket)
+    pub socket: Option<PathBuf>,
+}
+
+impl SystemLogConfig {
+    pub fn name(&self) -> &'static str {
+        match self.service {
+            SystemLogService::Syslog { .. } => "syslog",
+            SystemLogService::Journald => "journald",
+            SystemLogService::EventLog => "the Event Log",
+        }
+    }
+}
+
+/// Appender that sends each message to the system log, with its level as the message's priority
+#[derive(Debug)]
+pub struct SystemLogAppender {
+    service: SystemLogService,
+    identifier: String,
+    sink: Sink,
+}
+
+/// Where an appender's messages are sent
+#[derive(Debug)]
+enum Sink {
+    #[cfg(unix)]
+    Socket { socket: std::os::unix::net::UnixDatagram, path: PathBuf },
+    #[cfg(windows)]
+    EventLog(event_log::EventSource),
+}
+
+impl SystemLogAppender {
+    /// # Errors
+    /// Fails if the system log isn't supported on this platform, or can't be opened (i.e. its socket doesn't exist)
+    pub fn new(config: &SystemLogConfig) -> io::Result<Self> {
+        let identifier = config.identifier.clone().unwrap_or_else(|| DEFAULT_IDENTIFIER.to_string());
+        let sink = match config.service {
+            SystemLogService::Syslog { .. } => socket_sink(config.socket.clone().unwrap_or_else(|| PathBuf::from(SYSLOG_SOCKET)), config.name())?,
+            SystemLogService::Journald => socket_sink(config.socket.clone().unwrap_or_else(|| PathBuf::from(JOURNALD_SOCKET)), config.name())?,
+            SystemLogService::EventLog => event_log_sink(&identifier)?,
+        };
+        Ok(Self { service: config.service.clone(), identifier, sink })
+    }
+
+    /// The message sent to the system log for a record
+    #[cfg_attr(not(unix), allow(dead_code))]
+    fn message(&self, record: &Record) -> Vec<u8> {
+        let severity = severity(record.level());
+        let message = record.args().to_string();
+        match self.service {
+            SystemLogService::Syslog { facility } => {
+                format!("<{}>{}[{}]: {}", facility.code() * 8 + severity, self.identifier, process::id(), message).into_bytes()
+            }
+            SystemLogService::Journald => {
+                let mut datagram = Vec::new();
+                for (field, value) in [
+                    ("PRIORITY", severity.to_string()),
+                    ("SYSLOG_IDENTIFIER", self.identifier.clone()),
+                    ("SYSLOG_PID", process::id().to_string()),
+                    ("MESSAGE", message),
+                ] {
+                    journal_field(&mut datagram, field, &value);
+                }
+                datagram
+            }
+            SystemLogService::EventLog => message.into_bytes(),
+        }
+    }
+}
+
+impl Append for SystemLogAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        match &self.sink {
+            #[cfg(unix)]
+            Sink::Socket { socket, path } => {
+                socket.send_to(&self.message(record), path)?;
+            }
+            #[cfg(windows)]
+            Sink::EventLog(source) => source.report(record.level(), &record.args().to_string())?,
+        }
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(unix)]
+fn socket_sink(path: PathBuf, name: &str) -> io::Result<Sink> {
+    if !path.exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("No {} socket at {:?}", name, path)));
+    }
+    Ok(Sink::Socket { socket: std::os::unix::net::UnixDatagram::unbound()?, path })
+}
+
+#[cfg(not(unix))]
+fn socket_sink(_path: PathBuf, name: &str) -> io::Result<Sink> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, format!("Logging to {} is only supported on Unix", name)))
+}
+
+#[cfg(windows)]
+fn event_log_sink(source: &str) -> io::Result<Sink> {
+    Ok(Sink::EventLog(event_log::EventSource::register(source)?))
+}
+
+#[cfg(not(windows))]
+fn event_log_sink(_source: &str) -> io::Result<Sink> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Logging to the Event Log is only supported on Windows"))
+}
+
+/// Syslog severity of a log level
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Append a field to a journal datagram (Values with newlines are written with their length instead of `=`)
+fn journal_field(datagram: &mut Vec<u8>, field: &str, value: &str) {
+    datagram.extend_from_slice(field.as_bytes());
+    if value.contains('\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
+
+/// Reports events to the Windows event log (Through advapi32)
+#[cfg(windows)]
+mod event_log {
+    use std::ffi::c_void;
+    use std::io;
+    use std::iter;
+    use std::ptr;
+    use log::Level;
+
+    const EVENTLOG_ERROR_TYPE: u16 = 0x0001;
+    const EVENTLOG_WARNING_TYPE: u16 = 0x0002;
+    const EVENTLOG_INFORMATION_TYPE: u16 = 0x0004;
+    /// Event ID of every message (Which sources registered by `eventcreate` or `New-EventLog` display as just the message)
+    const EVENT_ID: u32 = 1;
+
+    #[link(name = "advapi32")]
+    unsafe extern "system" {
+        fn RegisterEventSourceW(server_name: *const u16, source_name: *const u16) -> *mut c_void;
+        fn DeregisterEventSource(event_log: *mut c_void) -> i32;
+        fn ReportEventW(
+            event_log: *mut c_void,
+            event_type: u16,
+            category: u16,
+            event_id: u32,
+            user_sid: *mut c_void,
+            num_strings: u16,
+            data_size: u32,
+            strings: *const *const u16,
+            raw_data: *mut c_void,
+        ) -> i32;
+    }
+
+    /// Handle to an event source on the local machine, deregistered when dropped
+    #[derive(Debug)]
+    pub struct EventSource(*mut c_void);
+
+    // (Event log handles can be used from any thread)
+    unsafe impl Send for EventSource {}
+    unsafe impl Sync for EventSource {}
+
+    impl EventSource {
+        pub fn register(source: &str) -> io::Result<Self> {
+            let source = wide(source);
+            let handle = unsafe { RegisterEventSourceW(ptr::null(), source.as_ptr()) };
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self(handle))
+        }
+
+        /// Report a message, as an error, warning or information event
+        pub fn report(&self, level: Level, message: &str) -> io::Result<()> {
+            let event_type = match level {
+                Level::Error => EVENTLOG_ERROR_TYPE,
+                Level::Warn => EVENTLOG_WARNING_TYPE,
+                Level::Info | Level::Debug | Level::Trace => EVENTLOG_INFORMATION_TYPE,
+            };
+            let message = wide(message);
+            let strings = [message.as_ptr()];
+            let reported = unsafe {
+                ReportEventW(self.0, event_type, 0, EVENT_ID, ptr::null_mut(), 1, 0, strings.as_ptr(), ptr::null_mut())
+            };
+            if reported == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for EventSource {
+        fn drop(&mut self) {
+            unsafe { DeregisterEventSource(self.0) };
+        }
+    }
+
+    /// Null-terminated UTF-16 string
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(iter::once(0)).collect()
+    }
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    #[cfg(unix)]
+    use std::os::unix::net::UnixDatagram;
+
+    #[cfg(unix)]
+    fn receive(socket: &UnixDatagram) -> Vec<u8> {
+        let mut buffer = [0; 1024];
+        let len = socket.recv(&mut buffer).unwrap();
+        buffer[..len].to_vec()
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_system_log_appender() {
+        let test_dir = PathBuf::from("/tmp/system_log_test_appender");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+        let socket_path = test_dir.join("log.sock");
+        let receiver = UnixDatagram::bind(&socket_path).unwrap();
+
+        let config: SystemLogConfig = toml::from_str(&format!("type = \"syslog\"\nfacility = \"local3\"\nsocket = {:?}", socket_path)).unwrap();
+        let appender = SystemLogAppender::new(&config).unwrap();
+        appender.append(&Record::builder().args(format_args!("Segment 'docs' failed")).level(Level::Error).build()).unwrap();
+        assert_eq!(String::from_utf8(receive(&receiver)).unwrap(), format!("<155>segmented_archive[{}]: Segment 'docs' failed", process::id()));
+
+        let config: SystemLogConfig = toml::from_str(&format!("type = \"journald\"\nidentifier = \"backup\"\nlevel = \"warn\"\nsocket = {:?}", socket_path)).unwrap();
+        let appender = SystemLogAppender::new(&config).unwrap();
+        appender.append(&Record::builder().args(format_args!("Skipped files:\na.txt")).level(Level::Warn).build()).unwrap();
+        let mut expected = format!("PRIORITY=4\nSYSLOG_IDENTIFIER=backup\nSYSLOG_PID={}\nMESSAGE\n", process::id()).into_bytes();
+        expected.extend_from_slice(&20u64.to_le_bytes());
+        expected.extend_from_slice(b"Skipped files:\na.txt\n");
+        assert_eq!(receive(&receiver), expected);
+
+        assert_eq!(config.name(), "journald");
+        assert_eq!(config.level, Some(LevelFilter::Warn));
+        assert!(toml::from_str::<SystemLogConfig>("type = \"syslog\"\nfacility = \"kernel\"").is_err());
+
+        let config: SystemLogConfig = toml::from_str(&format!("type = \"syslog\"\nsocket = {:?}", test_dir.join("missing.sock"))).unwrap();
+        assert_eq!(SystemLogAppender::new(&config).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        let config: SystemLogConfig = toml::from_str("type = \"eventlog\"").unwrap();
+        assert_eq!(config.service, SystemLogService::EventLog);
+        assert_eq!(SystemLogAppender::new(&config).unwrap_err().kind(), io::ErrorKind::Unsupported, "Only on Windows");
+
+        let _ = fs::remove_dir_all(&test_dir);
+    }
+}
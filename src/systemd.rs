@@ -0,0 +1,120 @@
+use std::env;
+use std::io;
+use std::sync::mpsc::{RecvTimeoutError, Sender, channel};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use log::{debug, info, warn};
+
+/// Send a state update (i.e. `READY=1` or `STATUS=...`) to systemd, if running as a `Type=notify` service
+/// (Failures are only logged, since the backup doesn't depend on them)
+pub fn notify(state: &str) {
+    let Some(socket) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    match send(&socket.to_string_lossy(), state) {
+        Ok(()) => debug!("Sent to systemd: {}", state),
+        Err(e) => warn!("Failed to notify systemd ({}): {}", state, e),
+    }
+}
+
+/// Keeps the systemd watchdog fed from a background thread until dropped
+pub struct Watchdog {
+    stop: Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Start sending keepalives at half the service's `WatchdogSec` (None unless the watchdog is enabled for this process)
+    pub fn start() -> Option<Self> {
+        let interval = watchdog_interval(
+            env::var("WATCHDOG_USEC").ok().as_deref(),
+            env::var("WATCHDOG_PID").ok().as_deref(),
+            std::process::id(),
+        )?;
+        info!("Sending systemd watchdog keepalives every {:?}", interval / 2);
+        let (stop, stopped) = channel();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval / 2) {
+                notify("WATCHDOG=1");
+            }
+        });
+        Some(Self { stop, thread: Some(thread) })
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Watchdog interval from the environment systemd sets (None if it's not set, or is meant for another process)
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if let Some(pid) = pid
+        && pid.parse::<u32>().ok() != Some(own_pid) {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Write a datagram to the notify socket (A path, or an abstract name starting with `@`)
+#[cfg(unix)]
+fn send(socket: &str, state: &str) -> io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let datagram = UnixDatagram::unbound()?;
+    match socket.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            use std::os::unix::net::SocketAddr;
+            datagram.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name.as_bytes())?)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return Err(io::Error::new(io::ErrorKind::Unsupported, "Abstract sockets are only supported on Linux")),
+        None => {
+            datagram.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_socket: &str, _state: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "systemd notifications are only supported on Unix"))
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_send() {
+        use std::os::unix::net::UnixDatagram;
+
+        let path = "/tmp/systemd_test_notify.sock";
+        let _ = std::fs::remove_file(path);
+        let receiver = UnixDatagram::bind(path).unwrap();
+        send(path, "STATUS=Archiving docs").unwrap();
+        let mut buffer = [0u8; 64];
+        let len = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"STATUS=Archiving docs");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_watchdog_interval() {
+        assert_eq!(watchdog_interval(Some("30000000"), None, 42), Some(Duration::from_secs(30)));
+        assert_eq!(watchdog_interval(Some("30000000"), Some("42"), 42), Some(Duration::from_secs(30)));
+        assert_eq!(watchdog_interval(Some("30000000"), Some("7"), 42), None, "The watchdog is meant for another process");
+        assert_eq!(watchdog_interval(Some("0"), None, 42), None);
+        assert_eq!(watchdog_interval(None, None, 42), None);
+    }
+}
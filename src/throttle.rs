@@ -0,0 +1,68 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Token-bucket rate limiter (Allows bursts of up to one second's worth of bytes)
+pub struct Throttle {
+    bytes_per_sec: f64,
+    /// Bytes that can be written without waiting (Negative after an oversized write)
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Throttle {
+    /// Limit to `bytes_per_sec` (Must be at least 1)
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1) as f64;
+        Self {
+            bytes_per_sec,
+            tokens: bytes_per_sec,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Block until `bytes` can be written without exceeding the rate
+    pub fn consume(&mut self, bytes: usize) {
+        self.refill();
+        self.tokens -= bytes as f64;
+        if self.tokens < 0.0 {
+            thread::sleep(Duration::from_secs_f64(-self.tokens / self.bytes_per_sec));
+            self.refill();
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.bytes_per_sec).min(self.bytes_per_sec);
+        self.last_refill = now;
+    }
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_allows_initial_burst() {
+        let mut throttle = Throttle::new(1_000_000);
+        let start = Instant::now();
+        throttle.consume(500_000);
+        assert!(start.elapsed() < Duration::from_millis(100), "Writes within the burst should not wait");
+    }
+
+    #[test]
+    fn test_throttle_limits_rate() {
+        let mut throttle = Throttle::new(100_000);
+        let start = Instant::now();
+
+        // 1s burst + 0.5s of throttled writes
+        for _ in 0..15 {
+            throttle.consume(10_000);
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(450), "Should be throttled, took {:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(2), "Should not over-throttle, took {:?}", elapsed);
+    }
+}
@@ -0,0 +1,356 @@
+use anyhow::{Context, Result, anyhow};
+use flate2::read::MultiGzDecoder;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use crate::archive_info::{ArchiveInfo, INFO_FILE, MANIFEST_ENTRY};
+use crate::archive_reader::{latest_archive_parts, open_archive};
+use crate::chain::{chain_path, read_chain};
+use crate::completeness::check_archive_parts;
+use crate::digest::{Digest, DigestAlgorithm};
+use crate::manifest::{Manifest, manifest_path, read_manifest};
+use crate::rolling_writer::PartInfo;
+use crate::sidecar::{read_sidecar, sidecar_path};
+
+// Buffer size for reading parts during verification
+const VERIFY_BUFFER_SIZE: usize = 1048576;
+
+/// Check an existing archive's parts for corruption, using their recorded checksums if all have one,
+/// otherwise by decompressing them (Which checks the gzip CRC).
+/// Returns false if neither is possible (An uncompressed archive without checksums).
+///
+/// # Errors
+/// Returns an error describing the first part that is unreadable or corrupted
+pub fn verify_parts(
+    output_dir: &Path,
+    parts: &[PartInfo],
+    checksum_algorithm: Option<DigestAlgorithm>,
+    compressed: bool,
+) -> Result<bool> {
+    if let Some(algorithm) = checksum_algorithm
+        && parts.iter().all(|part| part.checksum.is_some()) {
+        for part in parts {
+            let path = output_dir.join(&part.name);
+            let file = fs::File::open(&path)
+                .context(format!("Failed to open part: {:?}", path))?;
+            let checksum = digest_reader(file, algorithm)
+                .context(format!("Failed to read part: {:?}", path))?;
+            if part.checksum.as_ref() != Some(&checksum) {
+                return Err(anyhow!("Checksum mismatch for part {:?}", path));
+            }
+        }
+        return Ok(true);
+    }
+
+    if !compressed {
+        return Ok(false);
+    }
+
+    // Parts are one gzip stream (Or one per part), so decompress them end to end
+    let mut reader: Box<dyn Read> = Box::new(io::empty());
+    for part in parts {
+        let path = output_dir.join(&part.name);
+        let file = fs::File::open(&path)
+            .context(format!("Failed to open part: {:?}", path))?;
+        reader = Box::new(reader.chain(file));
+    }
+    io::copy(&mut MultiGzDecoder::new(reader), &mut io::sink())
+        .context("Archive failed to decompress")?;
+    Ok(true)
+}
+
+/// Entries found by reading an archive end to end
+#[derive(Debug)]
+pub struct ArchiveContents {
+    /// The archive's info entry (None for older archives)
+    pub info: Option<ArchiveInfo>,
+    /// Paths of files and symlinks (Not counting the info entry)
+    pub files: BTreeSet<String>,
+    /// The archive's manifest entry (Only with embed_manifest)
+    pub manifest: Option<Manifest>,
+    /// Files checked against the manifest entry
+    pub hashes_checked: usize,
+    /// Files whose content doesn't match the manifest entry
+    pub mismatched: Vec<String>,
+}
+
+/// Read every entry of an archive without extracting it (Which checks the gzip CRCs and tar structure)
+pub fn read_archive_contents(parts: &[PathBuf]) -> Result<ArchiveContents> {
+    let mut tar = open_archive(parts)?;
+    let mut contents = ArchiveContents { info: None, files: BTreeSet::new(), manifest: None, hashes_checked: 0, mismatched: Vec::new() };
+    for entry in tar.entries().context("Failed to read archive")? {
+        let mut entry = entry.context("Failed to read archive entry")?;
+        let path = entry.path().context("Failed to read archive entry path")?.to_string_lossy().to_string();
+        if path == INFO_FILE {
+            let mut json = String::new();
+            entry.read_to_string(&mut json)
+                .context("Failed to read info entry")?;
+            contents.info = Some(serde_json::from_str(&json).context("Failed to parse info entry")?);
+            continue;
+        }
+        if path == MANIFEST_ENTRY {
+            let mut json = String::new();
+            entry.read_to_string(&mut json)
+                .context("Failed to read manifest entry")?;
+            contents.manifest = Some(serde_json::from_str(&json).context("Failed to parse manifest entry")?);
+            continue;
+        }
+
+        let entry_type = entry.header().entry_type();
+        let matches = match &contents.manifest {
+            Some(manifest) if entry_type.is_symlink() => {
+                let target = entry.link_name_bytes().unwrap_or_default().into_owned();
+                manifest.check_file(&path, target.as_slice())?
+            }
+            Some(manifest) if entry_type.is_file() => manifest.check_file(&path, &mut entry)?,
+            _ => None,
+        };
+        if let Some(matches) = matches {
+            contents.hashes_checked += 1;
+            if !matches {
+                contents.mismatched.push(path.clone());
+            }
+        }
+        io::copy(&mut entry, &mut io::sink())
+            .context(format!("Failed to read archive entry: {:?}", path))?;
+        if entry_type.is_file() || entry_type.is_symlink() {
+            contents.files.insert(path);
+        }
+    }
+    Ok(contents)
+}
+
+/// Result of verifying a segment's latest archive
+#[derive(Debug)]
+pub struct SegmentCheck {
+    pub parts: usize,
+    pub files: usize,
+    /// True if the archive's files were also checked against the segment's manifest
+    pub manifest_checked: bool,
+    /// Files whose content was checked against the archive's manifest entry
+    pub hashes_checked: usize,
+}
+
+/// Read a segment's latest archive end to end (After checking none of its parts are missing),
+/// checking it has the file count from its metadata file,
+/// and every file in its manifest (If `manifest_dir` is set, and its manifest describes a full archive)
+///
+/// # Errors
+/// Returns an error describing why the archive is unreadable or incomplete
+pub fn verify_segment(output_dir: &Path, manifest_dir: Option<&Path>, segment: &str) -> Result<SegmentCheck> {
+    let sidecar = read_sidecar(&sidecar_path(output_dir, segment)).ok();
+    let parts = latest_archive_parts(output_dir, segment)?;
+    check_archive_parts(&parts[0])?;
+    let contents = read_archive_contents(&parts)?;
+    if let Some(info) = &contents.info
+        && info.segment != segment {
+        return Err(anyhow!("Archive belongs to segment '{}'", info.segment));
+    }
+    if let Some(first) = contents.mismatched.first() {
+        return Err(anyhow!("{} file(s) don't match the archive's manifest entry (i.e. {:?})", contents.mismatched.len(), first));
+    }
+    if let Some(sidecar) = &sidecar
+        && sidecar.file_count != contents.files.len() {
+        return Err(anyhow!("Archive has {} file(s), but its metadata file lists {}", contents.files.len(), sidecar.file_count));
+    }
+
+    // (Increments only hold the changed files, so can't be checked against the manifest)
+    let manifest = manifest_dir
+        .filter(|dir| read_chain(&chain_path(dir, segment)).ok()
+            .and_then(|chain| chain.generations.last().map(|last| last.generation == 0))
+            .unwrap_or(true))
+        .map(|dir| manifest_path(dir, segment))
+        .filter(|path| path.exists())
+        .map(|path| read_manifest(&path))
+        .transpose()?
+        .filter(|manifest| sidecar.as_ref().is_none_or(|sidecar| sidecar.created == manifest.created));
+    if let Some(manifest) = &manifest {
+        let missing: Vec<&String> = manifest.files.keys().filter(|path| !contents.files.contains(*path)).collect();
+        if let Some(first) = missing.first() {
+            return Err(anyhow!("{} file(s) in the manifest are missing from the archive (i.e. {:?})", missing.len(), first));
+        }
+    }
+
+    Ok(SegmentCheck { parts: parts.len(), files: contents.files.len(), manifest_checked: manifest.is_some(), hashes_checked: contents.hashes_checked })
+}
+
+fn digest_reader(mut reader: impl Read, algorithm: DigestAlgorithm) -> io::Result<String> {
+    let mut digest = Digest::new(algorithm);
+    let mut buffer = vec![0u8; VERIFY_BUFFER_SIZE];
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            return Ok(digest.finish_hex());
+        }
+        digest.update(&buffer[..bytes_read]);
+    }
+}
+
+// --- Tests --- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn get_test_dir(test_name: &str) -> PathBuf {
+        PathBuf::from(format!("/tmp/verify_test_{}", test_name))
+    }
+
+    fn cleanup_test_dir(test_name: &str) {
+        let _ = fs::remove_dir_all(get_test_dir(test_name));
+    }
+
+    fn setup_test_dir(test_name: &str) -> PathBuf {
+        cleanup_test_dir(test_name);
+        let test_dir = get_test_dir(test_name);
+        fs::create_dir_all(&test_dir).unwrap();
+        test_dir
+    }
+
+    /// Gzip some data and split it into two parts
+    fn write_parts(test_dir: &Path, checksum_algorithm: Option<DigestAlgorithm>) -> Vec<PartInfo> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&(0..10000u32).flat_map(|i| i.to_le_bytes()).collect::<Vec<u8>>()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+        [first, second].iter().enumerate().map(|(i, data)| {
+            let name = format!("test.tar.gz.part{:03}", i + 1);
+            fs::write(test_dir.join(&name), data).unwrap();
+            let checksum = checksum_algorithm.map(|algorithm| digest_reader(*data, algorithm).unwrap());
+            PartInfo { path: test_dir.join(&name), name, size: data.len() as u64, checksum }
+        }).collect()
+    }
+
+    #[test]
+    fn test_verify_segment() {
+        use crate::archive_info::ArchiveInfo;
+        use crate::helpers::{ArchiveOptions, create_archive};
+        use crate::manifest::{Manifest, ManifestEntry, write_manifest};
+        use crate::sidecar::{SegmentMetadata, write_sidecar};
+
+        let test_name = "segment";
+        let test_dir = setup_test_dir(test_name);
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(&src_dir).unwrap();
+        fs::write(src_dir.join("a.txt"), b"a").unwrap();
+        fs::write(src_dir.join("b.txt"), b"b").unwrap();
+
+        let output_dir = test_dir.join("out");
+        fs::create_dir_all(&output_dir).unwrap();
+        let info = ArchiveInfo::new("docs", &src_dir, &None, None).unwrap();
+        let summary = create_archive(&src_dir, &fs::metadata(&src_dir).unwrap(), &output_dir.join("docs.tar.gz"),
+            &[], &info, &ArchiveOptions::default(), None, None).unwrap();
+        let sidecar = SegmentMetadata {
+            segment: "docs".to_string(),
+            source_path: src_dir.clone(),
+            hash: None,
+            created: info.created.clone(),
+            file_count: summary.file_count,
+            uncompressed_bytes: summary.uncompressed_bytes,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            checksum_algorithm: None,
+            parts: summary.parts.parts,
+        };
+        write_sidecar(&sidecar_path(&output_dir, "docs"), &sidecar).unwrap();
+
+        let check = verify_segment(&output_dir, None, "docs").unwrap();
+        assert_eq!(check.files, 2);
+        assert!(!check.manifest_checked);
+
+        // A manifest listing a file the archive lacks fails
+        let manifest_dir = test_dir.join("manifests");
+        let entry = ManifestEntry { hash: "00".to_string(), metadata: None };
+        let mut manifest = Manifest { segment: "docs".to_string(), hash: "xxh3:00".to_string(), created: info.created.clone(), files: Default::default() };
+        manifest.files.insert("a.txt".to_string(), entry.clone());
+        write_manifest(&manifest_path(&manifest_dir, "docs"), &manifest).unwrap();
+        assert!(verify_segment(&output_dir, Some(&manifest_dir), "docs").unwrap().manifest_checked);
+        manifest.files.insert("c.txt".to_string(), entry);
+        write_manifest(&manifest_path(&manifest_dir, "docs"), &manifest).unwrap();
+        assert!(verify_segment(&output_dir, Some(&manifest_dir), "docs").is_err());
+
+        // A corrupted archive fails
+        let archive_path = output_dir.join("docs.tar.gz");
+        let mut data = fs::read(&archive_path).unwrap();
+        let middle = data.len() / 2;
+        data[middle] ^= 0xff;
+        fs::write(&archive_path, data).unwrap();
+        assert!(verify_segment(&output_dir, None, "docs").is_err());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_verify_embedded_manifest() {
+        use crate::archive_info::ArchiveInfo;
+        use crate::hasher::{HashOptions, compute_segment_hash};
+        use crate::helpers::{ArchiveOptions, create_archive};
+
+        let test_name = "embedded";
+        let test_dir = setup_test_dir(test_name);
+        let src_dir = test_dir.join("src");
+        fs::create_dir_all(&src_dir).unwrap();
+        fs::write(src_dir.join("a.txt"), b"a").unwrap();
+        fs::write(src_dir.join("b.txt"), b"b").unwrap();
+        let hashed = compute_segment_hash(&src_dir, &fs::metadata(&src_dir).unwrap(), &[], None, &HashOptions::default(), None).unwrap();
+
+        let output_dir = test_dir.join("out");
+        fs::create_dir_all(&output_dir).unwrap();
+        let archive_path = output_dir.join("docs.tar.gz");
+        let mut info = ArchiveInfo::new("docs", &src_dir, &None, Some(hashed.hash)).unwrap();
+        info.manifest = Some(hashed.files);
+        create_archive(&src_dir, &fs::metadata(&src_dir).unwrap(), &archive_path, &[], &info, &ArchiveOptions::default(), None, None).unwrap();
+        let check = verify_segment(&output_dir, None, "docs").unwrap();
+        assert_eq!((check.files, check.hashes_checked), (2, 2));
+
+        // A file that doesn't match its hash fails
+        info.manifest.as_mut().unwrap().get_mut("b.txt").unwrap().hash = "00".to_string();
+        create_archive(&src_dir, &fs::metadata(&src_dir).unwrap(), &archive_path, &[], &info, &ArchiveOptions::default(), None, None).unwrap();
+        let contents = read_archive_contents(&[archive_path]).unwrap();
+        assert_eq!(contents.mismatched, ["b.txt"]);
+        assert!(verify_segment(&output_dir, None, "docs").unwrap_err().to_string().contains("manifest entry"));
+
+        cleanup_test_dir(test_name);
+    }
+
+    fn corrupt(path: &Path) {
+        let mut data = fs::read(path).unwrap();
+        data[0] ^= 0xff;
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn test_verify_parts_by_decompressing() {
+        let test_name = "decompress";
+        let test_dir = setup_test_dir(test_name);
+        let parts = write_parts(&test_dir, None);
+
+        assert!(verify_parts(&test_dir, &parts, None, true).unwrap());
+        assert!(!verify_parts(&test_dir, &parts, None, false).unwrap(), "Uncompressed parts without checksums can't be verified");
+
+        corrupt(&parts[1].path);
+        assert!(verify_parts(&test_dir, &parts, None, true).is_err());
+
+        cleanup_test_dir(test_name);
+    }
+
+    #[test]
+    fn test_verify_parts_by_checksum() {
+        let test_name = "checksum";
+        let test_dir = setup_test_dir(test_name);
+        let parts = write_parts(&test_dir, Some(DigestAlgorithm::Sha256));
+
+        assert!(verify_parts(&test_dir, &parts, Some(DigestAlgorithm::Sha256), false).unwrap());
+
+        corrupt(&parts[0].path);
+        let error = verify_parts(&test_dir, &parts, Some(DigestAlgorithm::Sha256), false).unwrap_err();
+        assert!(error.to_string().contains("part001"));
+
+        cleanup_test_dir(test_name);
+    }
+}
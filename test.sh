@@ -97,6 +97,7 @@ echo "hash_file = \"$TEST_DIR/logs/test.hash\"" >> "$TEST_CFG"
 echo "log_file = \"$TEST_DIR/logs/test_log_%D.log\"" >> "$TEST_CFG"
 echo "compression_level = 8" >> "$TEST_CFG"
 echo "max_size_bytes = 10485760" >> "$TEST_CFG"
+echo "embed_manifest = true" >> "$TEST_CFG"
 echo "ignore = [\"$TEST_DIR/files/test_dir_4\", \"*.ignore\"]" >> "$TEST_CFG"
 echo "" >> "$TEST_CFG"
 echo "[segments]" >> "$TEST_CFG"
@@ -149,6 +150,10 @@ echo " --- RESTORING ARCHIVES --- "
 "$SCRIPT_DIR/restore.sh" "$TEST_DIR/archives" "$TEST_DIR/restored" --stats
 echo "(You should see rsync stats above, i.e. 'Number of files,' etc.)"
 
+# File segments are restored as files, without their manifest entry
+[ -f "$TEST_DIR/restored/test_file_50.txt" ]
+[ -z "$(find "$TEST_DIR/restored" -name ".seg_arc.manifest.json")" ]
+
 # Silently re-run to rebuild archive files
 cargo run "$TEST_CFG" 2>/dev/null
 